@@ -16,11 +16,21 @@ use std::env;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
     generate_module(&Path::new("src/uci_packets.pdl").canonicalize().unwrap());
 }
 
+/// Return the minor version of the compiler used for the build,
+/// or `None` if it cannot be determined.
+fn rustc_minor_version() -> Option<u32> {
+    let rustc = env::var_os("RUSTC")?;
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    version.split(' ').nth(1)?.split('.').nth(1)?.parse().ok()
+}
+
 fn generate_module(in_file: &Path) {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let mut out_file = File::create(
@@ -31,6 +41,12 @@ fn generate_module(in_file: &Path) {
     .unwrap();
 
     println!("cargo:rerun-if-changed={}", in_file.display());
+    // The generated code optionally derives serde traits behind a
+    // `serde` feature that is not declared by this crate. The check-cfg
+    // instruction is only recognized from Rust 1.80.
+    if rustc_minor_version().is_some_and(|minor| minor >= 80) {
+        println!("cargo:rustc-check-cfg=cfg(feature, values(\"serde\"))");
+    }
 
     let mut sources = pdl_compiler::ast::SourceDatabase::new();
     let parsed_file = pdl_compiler::parser::parse_file(
@@ -118,7 +118,7 @@ class GroupId(enum.IntEnum):
     VENDOR_RESERVED_B = 0xb
     VENDOR_ANDROID = 0xc
     TEST = 0xd
-    VENDOR_RESERVED_E = 0xe
+    VENDOR_PICA = 0xe
     VENDOR_RESERVED_F = 0xf
 
     @staticmethod
@@ -171,6 +171,7 @@ class SessionConfigOpcodeId(enum.IntEnum):
     UPDATE_DT_ANCHOR_RANGING_ROUNDS = 0x8
     UPDATE_DT_TAG_RANGING_ROUNDS = 0x9
     QUERY_DATA_SIZE_IN_RANGING = 0xb
+    SET_HUS_CONTROLLER_CONFIG = 0xc
 
     @staticmethod
     def from_int(v: int) -> Union[int, 'SessionConfigOpcodeId']:
@@ -208,6 +209,26 @@ class AndroidOpcodeId(enum.IntEnum):
             raise exn
 
 
+class PicaOpcodeId(enum.IntEnum):
+    GET_DEVICE_TEMPERATURE = 0x0
+    THERMAL_STATUS = 0x1
+    GET_VENDOR_COMMANDS = 0x2
+    SET_CLIENT_ID = 0x3
+    SUSPEND = 0x4
+    RESUME = 0x5
+    LOOPBACK = 0x6
+    AUTHENTICATE = 0x7
+    SESSION_RECOVER = 0x8
+    GET_CHIP_INFO = 0x9
+
+    @staticmethod
+    def from_int(v: int) -> Union[int, 'PicaOpcodeId']:
+        try:
+            return PicaOpcodeId(v)
+        except ValueError as exn:
+            raise exn
+
+
 class Status(enum.IntEnum):
     OK = 0x0
     REJECTED = 0x1
@@ -302,7 +323,7 @@ class ResetConfig(enum.IntEnum):
         try:
             return ResetConfig(v)
         except ValueError as exn:
-            raise exn
+            return v
 
 
 class AppConfigTlvType(enum.IntEnum):
@@ -866,6 +887,7 @@ class AoaResultReqType(enum.IntEnum):
 class DeviceState(enum.IntEnum):
     DEVICE_STATE_READY = 0x1
     DEVICE_STATE_ACTIVE = 0x2
+    DEVICE_STATE_LOW_POWER = 0x3
     DEVICE_STATE_ERROR = 0xff
 
     @staticmethod
@@ -881,6 +903,7 @@ class SessionState(enum.IntEnum):
     SESSION_STATE_DEINIT = 0x1
     SESSION_STATE_ACTIVE = 0x2
     SESSION_STATE_IDLE = 0x3
+    SESSION_STATE_ERROR = 0xff
 
     @staticmethod
     def from_int(v: int) -> Union[int, 'SessionState']:
@@ -1135,6 +1158,10 @@ class ControlPacket(Packet):
             return AndroidPacket.parse(fields.copy(), payload)
         except Exception as exn:
             pass
+        try:
+            return PicaPacket.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
         return ControlPacket(**fields), span
 
     def serialize(self, payload: bytes = None) -> bytes:
@@ -1510,6 +1537,14 @@ class SessionConfigPacket(ControlPacket):
             return SessionQueryMaxDataSizeInRangingRsp.parse(fields.copy(), payload)
         except Exception as exn:
             pass
+        try:
+            return SessionSetHybridControllerConfigCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return SessionSetHybridControllerConfigRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
         return SessionConfigPacket(**fields), span
 
     def serialize(self, payload: bytes = None) -> bytes:
@@ -1643,6 +1678,114 @@ class AndroidPacket(ControlPacket):
     def size(self) -> int:
         return len(self.payload) + 3
 
+@dataclass
+class PicaPacket(ControlPacket):
+    oid: PicaOpcodeId = field(kw_only=True, default=PicaOpcodeId.GET_DEVICE_TEMPERATURE)
+
+    def __post_init__(self):
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaPacket', bytes]:
+        if fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 3:
+            raise Exception('Invalid packet size')
+        fields['oid'] = PicaOpcodeId.from_int((span[0] >> 0) & 0x3f)
+        value_ = int.from_bytes(span[1:3], byteorder='little')
+        span = span[3:]
+        payload = span
+        span = bytes([])
+        fields['payload'] = payload
+        try:
+            return PicaGetDeviceTemperatureCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaGetDeviceTemperatureRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaThermalStatusNtf.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaGetVendorCommandsCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaGetVendorCommandsRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaSetClientIdCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaSetClientIdRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaSuspendCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaSuspendRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaResumeCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaResumeRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaLoopbackCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaLoopbackRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaAuthenticateCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaAuthenticateRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaSessionRecoverCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaSessionRecoverRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaGetChipInfoCmd.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        try:
+            return PicaGetChipInfoRsp.parse(fields.copy(), payload)
+        except Exception as exn:
+            pass
+        return PicaPacket(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.oid << 0))
+        _span.extend([0] * 2)
+        _span.extend(payload or self.payload or [])
+        return ControlPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return len(self.payload) + 3
+
 @dataclass
 class CoreDeviceResetCmd(CorePacket):
     reset_config: ResetConfig = field(kw_only=True, default=ResetConfig.UWBS_RESET)
@@ -1920,6 +2063,13 @@ class CoreGetCapsInfoRsp(CorePacket):
 class ConfigParameterId(enum.IntEnum):
     DEVICE_STATE = 0x0
     LOW_POWER_MODE = 0x1
+    TX_POWER = 0xe4
+    RANGING_OFFSET = 0xe5
+    RADIAL_VELOCITY_REPORT = 0xe6
+    MIN_REPORTED_DISTANCE = 0xe7
+    MAX_REPORTED_DISTANCE = 0xe8
+    COALESCING_WINDOW = 0xe9
+    MOVEMENT_THRESHOLD = 0xea
 
     @staticmethod
     def from_int(v: int) -> Union[int, 'ConfigParameterId']:
@@ -3237,7 +3387,6 @@ class SessionUpdateControllerMulticastListRsp(SessionConfigPacket):
 @dataclass
 class ControleeStatus(Packet):
     mac_address: bytearray = field(kw_only=True, default_factory=bytearray)
-    subsession_id: int = field(kw_only=True, default=0)
     status: MulticastUpdateStatus = field(kw_only=True, default=MulticastUpdateStatus.OK_MULTICAST_LIST_UPDATE)
 
     def __post_init__(self):
@@ -3250,32 +3399,25 @@ class ControleeStatus(Packet):
             raise Exception('Invalid packet size')
         fields['mac_address'] = list(span[:2])
         span = span[2:]
-        if len(span) < 5:
+        if len(span) < 1:
             raise Exception('Invalid packet size')
-        value_ = int.from_bytes(span[0:4], byteorder='little')
-        fields['subsession_id'] = value_
-        fields['status'] = MulticastUpdateStatus.from_int(span[4])
-        span = span[5:]
+        fields['status'] = MulticastUpdateStatus.from_int(span[0])
+        span = span[1:]
         return ControleeStatus(**fields), span
 
     def serialize(self, payload: bytes = None) -> bytes:
         _span = bytearray()
         _span.extend(self.mac_address)
-        if self.subsession_id > 4294967295:
-            print(f"Invalid value for field ControleeStatus::subsession_id: {self.subsession_id} > 4294967295; the value will be truncated")
-            self.subsession_id &= 4294967295
-        _span.extend(int.to_bytes((self.subsession_id << 0), length=4, byteorder='little'))
         _span.append((self.status << 0))
         return bytes(_span)
 
     @property
     def size(self) -> int:
-        return 7
+        return 3
 
 @dataclass
 class SessionUpdateControllerMulticastListNtf(SessionConfigPacket):
     session_token: int = field(kw_only=True, default=0)
-    remaining_multicast_list_size: int = field(kw_only=True, default=0)
     controlee_status: List[ControleeStatus] = field(kw_only=True, default_factory=list)
 
     def __post_init__(self):
@@ -3287,20 +3429,19 @@ class SessionUpdateControllerMulticastListNtf(SessionConfigPacket):
     def parse(fields: dict, span: bytes) -> Tuple['SessionUpdateControllerMulticastListNtf', bytes]:
         if fields['mt'] != MessageType.NOTIFICATION or fields['oid'] != SessionConfigOpcodeId.UPDATE_CONTROLLER_MULTICAST_LIST or fields['gid'] != GroupId.SESSION_CONFIG:
             raise Exception("Invalid constraint field values")
-        if len(span) < 6:
+        if len(span) < 5:
             raise Exception('Invalid packet size')
         value_ = int.from_bytes(span[0:4], byteorder='little')
         fields['session_token'] = value_
-        fields['remaining_multicast_list_size'] = span[4]
-        controlee_status_count = span[5]
-        span = span[6:]
-        if len(span) < controlee_status_count * 7:
+        controlee_status_count = span[4]
+        span = span[5:]
+        if len(span) < controlee_status_count * 3:
             raise Exception('Invalid packet size')
         controlee_status = []
         for n in range(controlee_status_count):
-            controlee_status.append(ControleeStatus.parse_all(span[n * 7:(n + 1) * 7]))
+            controlee_status.append(ControleeStatus.parse_all(span[n * 3:(n + 1) * 3]))
         fields['controlee_status'] = controlee_status
-        span = span[controlee_status_count * 7:]
+        span = span[controlee_status_count * 3:]
         return SessionUpdateControllerMulticastListNtf(**fields), span
 
     def serialize(self, payload: bytes = None) -> bytes:
@@ -3309,10 +3450,6 @@ class SessionUpdateControllerMulticastListNtf(SessionConfigPacket):
             print(f"Invalid value for field SessionUpdateControllerMulticastListNtf::session_token: {self.session_token} > 4294967295; the value will be truncated")
             self.session_token &= 4294967295
         _span.extend(int.to_bytes((self.session_token << 0), length=4, byteorder='little'))
-        if self.remaining_multicast_list_size > 255:
-            print(f"Invalid value for field SessionUpdateControllerMulticastListNtf::remaining_multicast_list_size: {self.remaining_multicast_list_size} > 255; the value will be truncated")
-            self.remaining_multicast_list_size &= 255
-        _span.append((self.remaining_multicast_list_size << 0))
         if len(self.controlee_status) > 255:
             print(f"Invalid length for field SessionUpdateControllerMulticastListNtf::controlee_status:  {len(self.controlee_status)} > 255; the array will be truncated")
             del self.controlee_status[255:]
@@ -3323,7 +3460,7 @@ class SessionUpdateControllerMulticastListNtf(SessionConfigPacket):
 
     @property
     def size(self) -> int:
-        return sum([elt.size for elt in self.controlee_status]) + 6
+        return sum([elt.size for elt in self.controlee_status]) + 5
 
 @dataclass
 class SessionDataCreditNtf(SessionControlPacket):
@@ -3478,6 +3615,136 @@ class SessionQueryMaxDataSizeInRangingRsp(SessionConfigPacket):
     def size(self) -> int:
         return 6
 
+@dataclass
+class PhaseList(Packet):
+    session_token: int = field(kw_only=True, default=0)
+    start_slot_index: int = field(kw_only=True, default=0)
+    end_slot_index: int = field(kw_only=True, default=0)
+
+    def __post_init__(self):
+        pass
+
+    @staticmethod
+    def parse(span: bytes) -> Tuple['PhaseList', bytes]:
+        fields = {'payload': None}
+        if len(span) < 8:
+            raise Exception('Invalid packet size')
+        value_ = int.from_bytes(span[0:4], byteorder='little')
+        fields['session_token'] = value_
+        value_ = int.from_bytes(span[4:6], byteorder='little')
+        fields['start_slot_index'] = value_
+        value_ = int.from_bytes(span[6:8], byteorder='little')
+        fields['end_slot_index'] = value_
+        span = span[8:]
+        return PhaseList(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        if self.session_token > 4294967295:
+            print(f"Invalid value for field PhaseList::session_token: {self.session_token} > 4294967295; the value will be truncated")
+            self.session_token &= 4294967295
+        _span.extend(int.to_bytes((self.session_token << 0), length=4, byteorder='little'))
+        if self.start_slot_index > 65535:
+            print(f"Invalid value for field PhaseList::start_slot_index: {self.start_slot_index} > 65535; the value will be truncated")
+            self.start_slot_index &= 65535
+        _span.extend(int.to_bytes((self.start_slot_index << 0), length=2, byteorder='little'))
+        if self.end_slot_index > 65535:
+            print(f"Invalid value for field PhaseList::end_slot_index: {self.end_slot_index} > 65535; the value will be truncated")
+            self.end_slot_index &= 65535
+        _span.extend(int.to_bytes((self.end_slot_index << 0), length=2, byteorder='little'))
+        return bytes(_span)
+
+    @property
+    def size(self) -> int:
+        return 8
+
+@dataclass
+class SessionSetHybridControllerConfigCmd(SessionConfigPacket):
+    session_token: int = field(kw_only=True, default=0)
+    message_control: int = field(kw_only=True, default=0)
+    update_time: bytearray = field(kw_only=True, default_factory=bytearray)
+    phase_list: List[PhaseList] = field(kw_only=True, default_factory=list)
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = SessionConfigOpcodeId.SET_HUS_CONTROLLER_CONFIG
+        self.gid = GroupId.SESSION_CONFIG
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['SessionSetHybridControllerConfigCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != SessionConfigOpcodeId.SET_HUS_CONTROLLER_CONFIG or fields['gid'] != GroupId.SESSION_CONFIG:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 6:
+            raise Exception('Invalid packet size')
+        value_ = int.from_bytes(span[0:4], byteorder='little')
+        fields['session_token'] = value_
+        fields['message_control'] = span[4]
+        phase_list_count = span[5]
+        span = span[6:]
+        if len(span) < 8:
+            raise Exception('Invalid packet size')
+        fields['update_time'] = list(span[:8])
+        span = span[8:]
+        if len(span) < phase_list_count * 8:
+            raise Exception('Invalid packet size')
+        phase_list = []
+        for n in range(phase_list_count):
+            phase_list.append(PhaseList.parse_all(span[n * 8:(n + 1) * 8]))
+        fields['phase_list'] = phase_list
+        span = span[phase_list_count * 8:]
+        return SessionSetHybridControllerConfigCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        if self.session_token > 4294967295:
+            print(f"Invalid value for field SessionSetHybridControllerConfigCmd::session_token: {self.session_token} > 4294967295; the value will be truncated")
+            self.session_token &= 4294967295
+        _span.extend(int.to_bytes((self.session_token << 0), length=4, byteorder='little'))
+        if self.message_control > 255:
+            print(f"Invalid value for field SessionSetHybridControllerConfigCmd::message_control: {self.message_control} > 255; the value will be truncated")
+            self.message_control &= 255
+        _span.append((self.message_control << 0))
+        if len(self.phase_list) > 255:
+            print(f"Invalid length for field SessionSetHybridControllerConfigCmd::phase_list:  {len(self.phase_list)} > 255; the array will be truncated")
+            del self.phase_list[255:]
+        _span.append((len(self.phase_list) << 0))
+        _span.extend(self.update_time)
+        for _elt in self.phase_list:
+            _span.extend(_elt.serialize())
+        return SessionConfigPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return sum([elt.size for elt in self.phase_list]) + 14
+
+@dataclass
+class SessionSetHybridControllerConfigRsp(SessionConfigPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = SessionConfigOpcodeId.SET_HUS_CONTROLLER_CONFIG
+        self.gid = GroupId.SESSION_CONFIG
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['SessionSetHybridControllerConfigRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != SessionConfigOpcodeId.SET_HUS_CONTROLLER_CONFIG or fields['gid'] != GroupId.SESSION_CONFIG:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        return SessionSetHybridControllerConfigRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        return SessionConfigPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 1
+
 @dataclass
 class SessionStartCmd(SessionControlPacket):
     session_id: int = field(kw_only=True, default=0)
@@ -4068,6 +4335,54 @@ class ShortMacTwoWaySessionInfoNtf(SessionInfoNtf):
             len(self.vendor_data) * 1
         )
 
+class VendorDataTlvType(enum.IntEnum):
+    RADIAL_VELOCITY = 0x1
+    ANTENNA_PAIR_AOA = 0x2
+    TWR_TIMING = 0x3
+    STS_INDEX = 0x4
+    MOVEMENT_INDICATOR = 0x5
+
+    @staticmethod
+    def from_int(v: int) -> Union[int, 'VendorDataTlvType']:
+        try:
+            return VendorDataTlvType(v)
+        except ValueError as exn:
+            raise exn
+
+
+@dataclass
+class VendorDataTlv(Packet):
+    t: VendorDataTlvType = field(kw_only=True, default=VendorDataTlvType.RADIAL_VELOCITY)
+    v: bytearray = field(kw_only=True, default_factory=bytearray)
+
+    def __post_init__(self):
+        pass
+
+    @staticmethod
+    def parse(span: bytes) -> Tuple['VendorDataTlv', bytes]:
+        fields = {'payload': None}
+        if len(span) < 2:
+            raise Exception('Invalid packet size')
+        fields['t'] = VendorDataTlvType.from_int(span[0])
+        v_size = span[1]
+        span = span[2:]
+        if len(span) < v_size:
+            raise Exception('Invalid packet size')
+        fields['v'] = list(span[:v_size])
+        span = span[v_size:]
+        return VendorDataTlv(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.t << 0))
+        _span.append(((len(self.v) * 1) << 0))
+        _span.extend(self.v)
+        return bytes(_span)
+
+    @property
+    def size(self) -> int:
+        return len(self.v) * 1 + 2
+
 @dataclass
 class ExtendedMacTwoWaySessionInfoNtf(SessionInfoNtf):
     two_way_ranging_measurements: List[ExtendedAddressTwoWayRangingMeasurement] = field(kw_only=True, default_factory=list)
@@ -4576,6 +4891,615 @@ class AndroidSetCountryCodeRsp(AndroidPacket):
     def size(self) -> int:
         return 1
 
+@dataclass
+class PicaGetDeviceTemperatureCmd(PicaPacket):
+    
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.GET_DEVICE_TEMPERATURE
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaGetDeviceTemperatureCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.GET_DEVICE_TEMPERATURE or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        return PicaGetDeviceTemperatureCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 0
+
+@dataclass
+class PicaGetDeviceTemperatureRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+    temperature: int = field(kw_only=True, default=0)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.GET_DEVICE_TEMPERATURE
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaGetDeviceTemperatureRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.GET_DEVICE_TEMPERATURE or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 2:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        fields['temperature'] = span[1]
+        span = span[2:]
+        return PicaGetDeviceTemperatureRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        if self.temperature > 255:
+            print(f"Invalid value for field PicaGetDeviceTemperatureRsp::temperature: {self.temperature} > 255; the value will be truncated")
+            self.temperature &= 255
+        _span.append((self.temperature << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 2
+
+class ThermalState(enum.IntEnum):
+    NORMAL = 0x0
+    OVERHEATED = 0x1
+
+    @staticmethod
+    def from_int(v: int) -> Union[int, 'ThermalState']:
+        try:
+            return ThermalState(v)
+        except ValueError as exn:
+            raise exn
+
+
+@dataclass
+class PicaThermalStatusNtf(PicaPacket):
+    thermal_state: ThermalState = field(kw_only=True, default=ThermalState.NORMAL)
+    temperature: int = field(kw_only=True, default=0)
+
+    def __post_init__(self):
+        self.mt = MessageType.NOTIFICATION
+        self.oid = PicaOpcodeId.THERMAL_STATUS
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaThermalStatusNtf', bytes]:
+        if fields['mt'] != MessageType.NOTIFICATION or fields['oid'] != PicaOpcodeId.THERMAL_STATUS or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 2:
+            raise Exception('Invalid packet size')
+        fields['thermal_state'] = ThermalState.from_int(span[0])
+        fields['temperature'] = span[1]
+        span = span[2:]
+        return PicaThermalStatusNtf(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.thermal_state << 0))
+        if self.temperature > 255:
+            print(f"Invalid value for field PicaThermalStatusNtf::temperature: {self.temperature} > 255; the value will be truncated")
+            self.temperature &= 255
+        _span.append((self.temperature << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 2
+
+@dataclass
+class PicaGetVendorCommandsCmd(PicaPacket):
+    
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.GET_VENDOR_COMMANDS
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaGetVendorCommandsCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.GET_VENDOR_COMMANDS or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        return PicaGetVendorCommandsCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 0
+
+@dataclass
+class VendorCommandsTlv(Packet):
+    gid: int = field(kw_only=True, default=0)
+    oids: bytearray = field(kw_only=True, default_factory=bytearray)
+
+    def __post_init__(self):
+        pass
+
+    @staticmethod
+    def parse(span: bytes) -> Tuple['VendorCommandsTlv', bytes]:
+        fields = {'payload': None}
+        if len(span) < 2:
+            raise Exception('Invalid packet size')
+        fields['gid'] = span[0]
+        oids_size = span[1]
+        span = span[2:]
+        if len(span) < oids_size:
+            raise Exception('Invalid packet size')
+        fields['oids'] = list(span[:oids_size])
+        span = span[oids_size:]
+        return VendorCommandsTlv(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        if self.gid > 255:
+            print(f"Invalid value for field VendorCommandsTlv::gid: {self.gid} > 255; the value will be truncated")
+            self.gid &= 255
+        _span.append((self.gid << 0))
+        _span.append(((len(self.oids) * 1) << 0))
+        _span.extend(self.oids)
+        return bytes(_span)
+
+    @property
+    def size(self) -> int:
+        return len(self.oids) * 1 + 2
+
+@dataclass
+class PicaGetVendorCommandsRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+    tlvs: List[VendorCommandsTlv] = field(kw_only=True, default_factory=list)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.GET_VENDOR_COMMANDS
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaGetVendorCommandsRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.GET_VENDOR_COMMANDS or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 2:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        tlvs_count = span[1]
+        span = span[2:]
+        tlvs = []
+        for n in range(tlvs_count):
+            element, span = VendorCommandsTlv.parse(span)
+            tlvs.append(element)
+        fields['tlvs'] = tlvs
+        return PicaGetVendorCommandsRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        if len(self.tlvs) > 255:
+            print(f"Invalid length for field PicaGetVendorCommandsRsp::tlvs:  {len(self.tlvs)} > 255; the array will be truncated")
+            del self.tlvs[255:]
+        _span.append((len(self.tlvs) << 0))
+        for _elt in self.tlvs:
+            _span.extend(_elt.serialize())
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return sum([elt.size for elt in self.tlvs]) + 2
+
+@dataclass
+class PicaSetClientIdCmd(PicaPacket):
+    client_id: int = field(kw_only=True, default=0)
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.SET_CLIENT_ID
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaSetClientIdCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.SET_CLIENT_ID or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 4:
+            raise Exception('Invalid packet size')
+        value_ = int.from_bytes(span[0:4], byteorder='little')
+        fields['client_id'] = value_
+        span = span[4:]
+        return PicaSetClientIdCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        if self.client_id > 4294967295:
+            print(f"Invalid value for field PicaSetClientIdCmd::client_id: {self.client_id} > 4294967295; the value will be truncated")
+            self.client_id &= 4294967295
+        _span.extend(int.to_bytes((self.client_id << 0), length=4, byteorder='little'))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 4
+
+@dataclass
+class PicaSetClientIdRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.SET_CLIENT_ID
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaSetClientIdRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.SET_CLIENT_ID or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        return PicaSetClientIdRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 1
+
+@dataclass
+class PicaSuspendCmd(PicaPacket):
+    
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.SUSPEND
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaSuspendCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.SUSPEND or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        return PicaSuspendCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 0
+
+@dataclass
+class PicaSuspendRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.SUSPEND
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaSuspendRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.SUSPEND or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        return PicaSuspendRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 1
+
+@dataclass
+class PicaResumeCmd(PicaPacket):
+    
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.RESUME
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaResumeCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.RESUME or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        return PicaResumeCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 0
+
+@dataclass
+class PicaResumeRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.RESUME
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaResumeRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.RESUME or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        return PicaResumeRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 1
+
+@dataclass
+class PicaLoopbackCmd(PicaPacket):
+    payload: bytearray = field(kw_only=True, default_factory=bytearray)
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.LOOPBACK
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaLoopbackCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.LOOPBACK or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        fields['payload'] = list(span)
+        span = bytes()
+        return PicaLoopbackCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.extend(self.payload)
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return len(self.payload) * 1
+
+@dataclass
+class PicaLoopbackRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+    payload: bytearray = field(kw_only=True, default_factory=bytearray)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.LOOPBACK
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaLoopbackRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.LOOPBACK or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        fields['payload'] = list(span)
+        span = bytes()
+        return PicaLoopbackRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        _span.extend(self.payload)
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return len(self.payload) * 1 + 1
+
+@dataclass
+class PicaAuthenticateCmd(PicaPacket):
+    token: bytearray = field(kw_only=True, default_factory=bytearray)
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.AUTHENTICATE
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaAuthenticateCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.AUTHENTICATE or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        fields['token'] = list(span)
+        span = bytes()
+        return PicaAuthenticateCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.extend(self.token)
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return len(self.token) * 1
+
+@dataclass
+class PicaAuthenticateRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.AUTHENTICATE
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaAuthenticateRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.AUTHENTICATE or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        return PicaAuthenticateRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 1
+
+@dataclass
+class PicaSessionRecoverCmd(PicaPacket):
+    session_token: int = field(kw_only=True, default=0)
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.SESSION_RECOVER
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaSessionRecoverCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.SESSION_RECOVER or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 4:
+            raise Exception('Invalid packet size')
+        value_ = int.from_bytes(span[0:4], byteorder='little')
+        fields['session_token'] = value_
+        span = span[4:]
+        return PicaSessionRecoverCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        if self.session_token > 4294967295:
+            print(f"Invalid value for field PicaSessionRecoverCmd::session_token: {self.session_token} > 4294967295; the value will be truncated")
+            self.session_token &= 4294967295
+        _span.extend(int.to_bytes((self.session_token << 0), length=4, byteorder='little'))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 4
+
+@dataclass
+class PicaSessionRecoverRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.SESSION_RECOVER
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaSessionRecoverRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.SESSION_RECOVER or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 1:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        span = span[1:]
+        return PicaSessionRecoverRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 1
+
+@dataclass
+class PicaGetChipInfoCmd(PicaPacket):
+    
+
+    def __post_init__(self):
+        self.mt = MessageType.COMMAND
+        self.oid = PicaOpcodeId.GET_CHIP_INFO
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaGetChipInfoCmd', bytes]:
+        if fields['mt'] != MessageType.COMMAND or fields['oid'] != PicaOpcodeId.GET_CHIP_INFO or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        return PicaGetChipInfoCmd(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 0
+
+@dataclass
+class PicaGetChipInfoRsp(PicaPacket):
+    status: Status = field(kw_only=True, default=Status.OK)
+    vendor_oui: int = field(kw_only=True, default=0)
+    part_id: int = field(kw_only=True, default=0)
+    chip_version: int = field(kw_only=True, default=0)
+
+    def __post_init__(self):
+        self.mt = MessageType.RESPONSE
+        self.oid = PicaOpcodeId.GET_CHIP_INFO
+        self.gid = GroupId.VENDOR_PICA
+
+    @staticmethod
+    def parse(fields: dict, span: bytes) -> Tuple['PicaGetChipInfoRsp', bytes]:
+        if fields['mt'] != MessageType.RESPONSE or fields['oid'] != PicaOpcodeId.GET_CHIP_INFO or fields['gid'] != GroupId.VENDOR_PICA:
+            raise Exception("Invalid constraint field values")
+        if len(span) < 8:
+            raise Exception('Invalid packet size')
+        fields['status'] = Status.from_int(span[0])
+        value_ = int.from_bytes(span[1:4], byteorder='little')
+        fields['vendor_oui'] = value_
+        value_ = int.from_bytes(span[4:6], byteorder='little')
+        fields['part_id'] = value_
+        value_ = int.from_bytes(span[6:8], byteorder='little')
+        fields['chip_version'] = value_
+        span = span[8:]
+        return PicaGetChipInfoRsp(**fields), span
+
+    def serialize(self, payload: bytes = None) -> bytes:
+        _span = bytearray()
+        _span.append((self.status << 0))
+        if self.vendor_oui > 16777215:
+            print(f"Invalid value for field PicaGetChipInfoRsp::vendor_oui: {self.vendor_oui} > 16777215; the value will be truncated")
+            self.vendor_oui &= 16777215
+        _span.extend(int.to_bytes((self.vendor_oui << 0), length=3, byteorder='little'))
+        if self.part_id > 65535:
+            print(f"Invalid value for field PicaGetChipInfoRsp::part_id: {self.part_id} > 65535; the value will be truncated")
+            self.part_id &= 65535
+        _span.extend(int.to_bytes((self.part_id << 0), length=2, byteorder='little'))
+        if self.chip_version > 65535:
+            print(f"Invalid value for field PicaGetChipInfoRsp::chip_version: {self.chip_version} > 65535; the value will be truncated")
+            self.chip_version &= 65535
+        _span.extend(int.to_bytes((self.chip_version << 0), length=2, byteorder='little'))
+        return PicaPacket.serialize(self, payload = bytes(_span))
+
+    @property
+    def size(self) -> int:
+        return 8
+
 class FrameReportTlvType(enum.IntEnum):
     RSSI = 0x0
     AOA = 0x1
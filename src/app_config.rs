@@ -1,5 +1,8 @@
 use crate::packets::uci;
 use crate::MacAddress;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 /// [UCI] 8.3 Application Configuration Parameters.
 /// Sub-session Key provided for Provisioned STS for Responder specific Key mode
@@ -11,6 +14,55 @@ pub enum SubSessionKey {
     Extended([u8; 32]),
 }
 
+/// [UCI] 8.3 Application Configuration Parameters.
+/// Selection of the fields included in the ranging result reports
+/// (RESULT_REPORT_CONFIG). Fields that are not selected are reported
+/// with zero values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResultReportConfig {
+    /// b0: Time of Flight report.
+    pub tof: bool,
+    /// b1: AoA azimuth report.
+    pub aoa_azimuth: bool,
+    /// b2: AoA elevation report.
+    pub aoa_elevation: bool,
+    /// b3: AoA figure of merit report.
+    pub aoa_fom: bool,
+}
+
+impl ResultReportConfig {
+    pub fn has_aoa(&self) -> bool {
+        self.aoa_azimuth || self.aoa_elevation || self.aoa_fom
+    }
+}
+
+impl TryFrom<u8> for ResultReportConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            value & 0xf0 == 0,
+            "invalid RESULT_REPORT_CONFIG value 0x{:02x}",
+            value
+        );
+        Ok(ResultReportConfig {
+            tof: value & 0x1 != 0,
+            aoa_azimuth: value & 0x2 != 0,
+            aoa_elevation: value & 0x4 != 0,
+            aoa_fom: value & 0x8 != 0,
+        })
+    }
+}
+
+impl From<ResultReportConfig> for u8 {
+    fn from(config: ResultReportConfig) -> u8 {
+        config.tof as u8
+            | (config.aoa_azimuth as u8) << 1
+            | (config.aoa_elevation as u8) << 2
+            | (config.aoa_fom as u8) << 3
+    }
+}
+
 /// [UCI] 8.3 Application Configuration Parameters.
 /// The configuration is initially filled with default values from the
 /// specification.
@@ -41,7 +93,7 @@ pub struct AppConfig {
     pub dst_mac_address: Vec<MacAddress>,
     slot_duration: u16,
     pub ranging_duration: u32,
-    sts_index: u32,
+    pub sts_index: u32,
     mac_fcs_type: uci::MacFcsType,
     ranging_round_control: u8,
     aoa_result_req: uci::AoaResultReq,
@@ -66,16 +118,16 @@ pub struct AppConfig {
     pub schedule_mode: Option<uci::ScheduleMode>,
     key_rotation: uci::KeyRotation,
     key_rotation_rate: u8,
-    session_priority: u8,
+    pub session_priority: u8,
     pub mac_address_mode: uci::MacAddressMode,
     vendor_id: u16,
     static_sts_iv: [u8; 6],
     number_of_sts_segments: u8,
-    max_rr_retry: u16,
+    pub max_rr_retry: u16,
     uwb_initiation_time: u64,
     hopping_mode: uci::HoppingMode,
     block_stride_length: u8,
-    result_report_config: u8,
+    pub result_report_config: ResultReportConfig,
     pub in_band_termination_attempt_count: u8,
     sub_session_id: u32,
     bprf_phr_data_rate: uci::BprfPhrDataRate,
@@ -89,6 +141,14 @@ pub struct AppConfig {
     pub session_data_transfer_status_ntf_config: uci::SessionDataTransferStatusNtfConfig,
     session_time_base: [u8; 9],
     application_data_endpoint: u8,
+    /// Number of ranging rounds after the session start
+    /// reported with a low AoA figure of merit.
+    pub warmup_rounds: u8,
+    /// Types of the measurements reported in consecutive ranging rounds.
+    hybrid_report_schedule: Vec<uci::RangingMeasurementType>,
+    /// Vendor specific parameters not interpreted by the UWBS.
+    /// The raw values are preserved to be returned by GET_APP_CONFIG.
+    vendor_parameters: BTreeMap<u8, Vec<u8>>,
 }
 
 impl Default for AppConfig {
@@ -141,7 +201,7 @@ impl Default for AppConfig {
             uwb_initiation_time: 0,
             hopping_mode: uci::HoppingMode::Disable,
             block_stride_length: 0,
-            result_report_config: 0x01,
+            result_report_config: ResultReportConfig::try_from(0x01).unwrap(),
             in_band_termination_attempt_count: 1,
             sub_session_id: 0, // XX
             bprf_phr_data_rate: uci::BprfPhrDataRate::DataRate850k,
@@ -156,11 +216,21 @@ impl Default for AppConfig {
                 uci::SessionDataTransferStatusNtfConfig::Disable,
             session_time_base: [0; 9],
             application_data_endpoint: 0,
+            warmup_rounds: 0,
+            hybrid_report_schedule: vec![],
+            vendor_parameters: BTreeMap::new(),
         }
     }
 }
 
 impl AppConfig {
+    /// Duration of a ranging round, computed from the slot duration
+    /// and the number of slots per ranging round. The slot duration
+    /// is expressed in RSTU, with 1 RSTU = 1/1.2 MHz.
+    pub fn ranging_round_duration(&self) -> Duration {
+        Duration::from_nanos(self.slot_duration as u64 * self.slots_per_rr as u64 * 10_000 / 12)
+    }
+
     /// Set the APP configuration value with the selected identifier
     /// and value. Returns `Ok` if the identifier is known and the value
     /// well formatted, `Err` otherwise.
@@ -307,7 +377,7 @@ impl AppConfig {
                 self.block_stride_length = try_parse_u8(value)?
             }
             uci::AppConfigTlvType::ResultReportConfig => {
-                self.result_report_config = try_parse_u8(value)?
+                self.result_report_config = try_parse_u8(value)?.try_into()?
             }
             uci::AppConfigTlvType::InBandTerminationAttemptCount => {
                 self.in_band_termination_attempt_count = try_parse_u8(value)?
@@ -338,6 +408,24 @@ impl AppConfig {
             uci::AppConfigTlvType::ApplicationDataEndpoint => {
                 self.application_data_endpoint = try_parse_u8(value)?
             }
+            uci::AppConfigTlvType::WarmupRounds => self.warmup_rounds = try_parse_u8(value)?,
+            uci::AppConfigTlvType::HybridReportSchedule => {
+                self.hybrid_report_schedule = value
+                    .iter()
+                    .map(
+                        |value| match uci::RangingMeasurementType::try_from(*value) {
+                            Ok(
+                                report_type @ (uci::RangingMeasurementType::TwoWay
+                                | uci::RangingMeasurementType::OwrAoa),
+                            ) => Ok(report_type),
+                            _ => Err(anyhow::anyhow!(
+                                "unsupported ranging measurement type 0x{:x}",
+                                value
+                            )),
+                        },
+                    )
+                    .collect::<anyhow::Result<_>>()?
+            }
 
             uci::AppConfigTlvType::CccHopModeKey
             | uci::AppConfigTlvType::CccUwbTime0
@@ -350,9 +438,11 @@ impl AppConfig {
             | uci::AppConfigTlvType::NbOfAzimuthMeasurements
             | uci::AppConfigTlvType::NbOfElevationMeasurements
             | uci::AppConfigTlvType::EnableDiagnostics
-            | uci::AppConfigTlvType::DiagramsFrameReportsFields => {
-                log::error!("unsupported vendor config type {:?}", id);
-                anyhow::bail!("unsupported vendor config type {:?}", id)
+            | uci::AppConfigTlvType::DiagramsFrameReportsFields
+            | uci::AppConfigTlvType::VendorSpecific1(_)
+            | uci::AppConfigTlvType::VendorSpecific2(_) => {
+                log::debug!("storing uninterpreted vendor config type {:?}", id);
+                self.vendor_parameters.insert(id.into(), value.to_vec());
             }
             _ => {
                 log::error!("unsupported app config type {:?}", id);
@@ -447,7 +537,7 @@ impl AppConfig {
             }
             uci::AppConfigTlvType::HoppingMode => Ok(vec![self.hopping_mode.into()]),
             uci::AppConfigTlvType::BlockStrideLength => Ok(vec![self.block_stride_length]),
-            uci::AppConfigTlvType::ResultReportConfig => Ok(vec![self.result_report_config]),
+            uci::AppConfigTlvType::ResultReportConfig => Ok(vec![self.result_report_config.into()]),
             uci::AppConfigTlvType::InBandTerminationAttemptCount => {
                 Ok(vec![self.in_band_termination_attempt_count])
             }
@@ -473,6 +563,12 @@ impl AppConfig {
             uci::AppConfigTlvType::ApplicationDataEndpoint => {
                 Ok(vec![self.application_data_endpoint])
             }
+            uci::AppConfigTlvType::WarmupRounds => Ok(vec![self.warmup_rounds]),
+            uci::AppConfigTlvType::HybridReportSchedule => Ok(self
+                .hybrid_report_schedule
+                .iter()
+                .map(|report_type| u8::from(*report_type))
+                .collect()),
 
             uci::AppConfigTlvType::CccHopModeKey
             | uci::AppConfigTlvType::CccUwbTime0
@@ -485,10 +581,13 @@ impl AppConfig {
             | uci::AppConfigTlvType::NbOfAzimuthMeasurements
             | uci::AppConfigTlvType::NbOfElevationMeasurements
             | uci::AppConfigTlvType::EnableDiagnostics
-            | uci::AppConfigTlvType::DiagramsFrameReportsFields => {
-                log::error!("unsupported vendor config type {:?}", id);
-                anyhow::bail!("unsupported vendor config type {:?}", id)
-            }
+            | uci::AppConfigTlvType::DiagramsFrameReportsFields
+            | uci::AppConfigTlvType::VendorSpecific1(_)
+            | uci::AppConfigTlvType::VendorSpecific2(_) => self
+                .vendor_parameters
+                .get(&id.into())
+                .cloned()
+                .ok_or(anyhow::anyhow!("vendor app config not set")),
             _ => {
                 log::error!("unsupported app config type {:?}", id);
                 anyhow::bail!("unsupported app config type {:?}", id)
@@ -496,6 +595,232 @@ impl AppConfig {
         }
     }
 
+    /// Decode the APP configuration for display, indexed by parameter
+    /// name. Enumerated values are reported by name and integers by
+    /// value. Optional parameters without value are omitted, and
+    /// vendor parameters unknown to the UWBS are reported with their
+    /// identifier and value in hexadecimal.
+    pub fn decode(&self) -> BTreeMap<String, Value> {
+        fn name<T: std::fmt::Debug>(value: T) -> Value {
+            format!("{:?}", value).into()
+        }
+
+        let mut config = BTreeMap::new();
+        let mut insert = |id: uci::AppConfigTlvType, value: Value| {
+            config.insert(format!("{:?}", id), value);
+        };
+        if let Some(device_type) = self.device_type {
+            insert(uci::AppConfigTlvType::DeviceType, name(device_type));
+        }
+        if let Some(ranging_round_usage) = self.ranging_round_usage {
+            insert(
+                uci::AppConfigTlvType::RangingRoundUsage,
+                name(ranging_round_usage),
+            );
+        }
+        insert(uci::AppConfigTlvType::StsConfig, name(self.sts_config));
+        if let Some(multi_node_mode) = self.multi_node_mode {
+            insert(uci::AppConfigTlvType::MultiNodeMode, name(multi_node_mode));
+        }
+        insert(
+            uci::AppConfigTlvType::ChannelNumber,
+            name(self.channel_number),
+        );
+        insert(
+            uci::AppConfigTlvType::NumberOfControlees,
+            self.number_of_controlees.into(),
+        );
+        if let Some(device_mac_address) = self.device_mac_address {
+            insert(
+                uci::AppConfigTlvType::DeviceMacAddress,
+                device_mac_address.to_string().into(),
+            );
+        }
+        insert(
+            uci::AppConfigTlvType::DstMacAddress,
+            self.dst_mac_address
+                .iter()
+                .map(|mac_address| Value::from(mac_address.to_string()))
+                .collect(),
+        );
+        insert(
+            uci::AppConfigTlvType::SlotDuration,
+            self.slot_duration.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::RangingDuration,
+            self.ranging_duration.into(),
+        );
+        insert(uci::AppConfigTlvType::StsIndex, self.sts_index.into());
+        insert(uci::AppConfigTlvType::MacFcsType, name(self.mac_fcs_type));
+        insert(
+            uci::AppConfigTlvType::RangingRoundControl,
+            self.ranging_round_control.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::AoaResultReq,
+            name(self.aoa_result_req),
+        );
+        insert(
+            uci::AppConfigTlvType::SessionInfoNtfConfig,
+            name(self.session_info_ntf_config),
+        );
+        insert(
+            uci::AppConfigTlvType::NearProximityConfig,
+            self.near_proximity_config.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::FarProximityConfig,
+            self.far_proximity_config.into(),
+        );
+        if let Some(device_role) = self.device_role {
+            insert(uci::AppConfigTlvType::DeviceRole, name(device_role));
+        }
+        insert(
+            uci::AppConfigTlvType::RframeConfig,
+            name(self.rframe_config),
+        );
+        insert(
+            uci::AppConfigTlvType::RssiReporting,
+            name(self.rssi_reporting),
+        );
+        insert(
+            uci::AppConfigTlvType::PreambleCodeIndex,
+            self.preamble_code_index.into(),
+        );
+        insert(uci::AppConfigTlvType::SfdId, self.sfd_id.into());
+        insert(
+            uci::AppConfigTlvType::PsduDataRate,
+            name(self.psdu_data_rate),
+        );
+        insert(
+            uci::AppConfigTlvType::PreambleDuration,
+            name(self.preamble_duration),
+        );
+        insert(
+            uci::AppConfigTlvType::LinkLayerMode,
+            name(self.link_layer_mode),
+        );
+        insert(
+            uci::AppConfigTlvType::DataRepetitionCount,
+            self.data_repetition_count.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::RangingTimeStruct,
+            name(self.ranging_time_struct),
+        );
+        insert(uci::AppConfigTlvType::SlotsPerRr, self.slots_per_rr.into());
+        insert(
+            uci::AppConfigTlvType::AoaBoundConfig,
+            self.aoa_bound_config.to_vec().into(),
+        );
+        insert(uci::AppConfigTlvType::PrfMode, name(self.prf_mode));
+        insert(
+            uci::AppConfigTlvType::CapSizeRange,
+            self.cap_size_range.to_vec().into(),
+        );
+        insert(
+            uci::AppConfigTlvType::TxJitterWindowSize,
+            self.tx_jitter_window_size.into(),
+        );
+        if let Some(schedule_mode) = self.schedule_mode {
+            insert(uci::AppConfigTlvType::ScheduleMode, name(schedule_mode));
+        }
+        insert(uci::AppConfigTlvType::KeyRotation, name(self.key_rotation));
+        insert(
+            uci::AppConfigTlvType::KeyRotationRate,
+            self.key_rotation_rate.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::SessionPriority,
+            self.session_priority.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::MacAddressMode,
+            name(self.mac_address_mode),
+        );
+        insert(uci::AppConfigTlvType::VendorId, self.vendor_id.into());
+        insert(
+            uci::AppConfigTlvType::StaticStsIv,
+            hex::encode(self.static_sts_iv).into(),
+        );
+        insert(
+            uci::AppConfigTlvType::NumberOfStsSegments,
+            self.number_of_sts_segments.into(),
+        );
+        insert(uci::AppConfigTlvType::MaxRrRetry, self.max_rr_retry.into());
+        insert(
+            uci::AppConfigTlvType::UwbInitiationTime,
+            self.uwb_initiation_time.into(),
+        );
+        insert(uci::AppConfigTlvType::HoppingMode, name(self.hopping_mode));
+        insert(
+            uci::AppConfigTlvType::BlockStrideLength,
+            self.block_stride_length.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::ResultReportConfig,
+            name(self.result_report_config),
+        );
+        insert(
+            uci::AppConfigTlvType::InBandTerminationAttemptCount,
+            self.in_band_termination_attempt_count.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::SubSessionId,
+            self.sub_session_id.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::BprfPhrDataRate,
+            name(self.bprf_phr_data_rate),
+        );
+        insert(
+            uci::AppConfigTlvType::MaxNumberOfMeasurements,
+            self.max_number_of_measurements.into(),
+        );
+        insert(uci::AppConfigTlvType::StsLength, name(self.sts_length));
+        insert(
+            uci::AppConfigTlvType::MinFramesPerRr,
+            self.min_frames_per_rr.into(),
+        );
+        insert(uci::AppConfigTlvType::MtuSize, self.mtu_size.into());
+        insert(
+            uci::AppConfigTlvType::InterFrameInterval,
+            self.inter_frame_interval.into(),
+        );
+        insert(
+            uci::AppConfigTlvType::SessionKey,
+            hex::encode(&self.session_key).into(),
+        );
+        match self.sub_session_key {
+            SubSessionKey::None => (),
+            SubSessionKey::Short(key) => insert(
+                uci::AppConfigTlvType::SubSessionKey,
+                hex::encode(key).into(),
+            ),
+            SubSessionKey::Extended(key) => insert(
+                uci::AppConfigTlvType::SubSessionKey,
+                hex::encode(key).into(),
+            ),
+        }
+        insert(
+            uci::AppConfigTlvType::SessionDataTransferStatusNtfConfig,
+            name(self.session_data_transfer_status_ntf_config),
+        );
+        insert(
+            uci::AppConfigTlvType::SessionTimeBase,
+            hex::encode(self.session_time_base).into(),
+        );
+        insert(
+            uci::AppConfigTlvType::ApplicationDataEndpoint,
+            self.application_data_endpoint.into(),
+        );
+        for (id, value) in &self.vendor_parameters {
+            config.insert(format!("0x{:02x}", id), hex::encode(value).into());
+        }
+        config
+    }
+
     pub fn is_compatible_for_ranging(&self, peer_config: &Self) -> bool {
         self.device_role != peer_config.device_role
             && self.device_type != peer_config.device_type
@@ -507,6 +832,57 @@ impl AppConfig {
                 .contains(&peer_config.device_mac_address.unwrap())
     }
 
+    /// Return true if the ranging method lets the peers report the angles
+    /// of arrival they measured. The single-sided non-deferred round ends
+    /// with the response message, which only carries the reply time, and
+    /// the one-way methods do not involve any peer report.
+    pub fn reports_destination_aoa(&self) -> bool {
+        matches!(
+            self.ranging_round_usage,
+            Some(uci::RangingRoundUsage::SsTwrDeferredMode)
+                | Some(uci::RangingRoundUsage::DsTwrDeferredMode)
+                | Some(uci::RangingRoundUsage::DsTwrNonDeferredMode)
+                | Some(uci::RangingRoundUsage::EssTwrNonDeferred)
+                | Some(uci::RangingRoundUsage::AdsTwrNonDeferred)
+        )
+    }
+
+    /// Return the type of the measurements reported in the selected
+    /// ranging round, following the hybrid report schedule. When the
+    /// schedule is empty, the type follows the ranging method of the
+    /// session: OWR AoA measurements for the OWR AoA rounds, two-way
+    /// measurements otherwise.
+    pub fn report_type(&self, round: u32) -> uci::RangingMeasurementType {
+        if self.hybrid_report_schedule.is_empty() {
+            match self.ranging_round_usage {
+                Some(uci::RangingRoundUsage::OwrAoaMeasurement) => {
+                    uci::RangingMeasurementType::OwrAoa
+                }
+                _ => uci::RangingMeasurementType::TwoWay,
+            }
+        } else {
+            let index = round as usize % self.hybrid_report_schedule.len();
+            self.hybrid_report_schedule[index]
+        }
+    }
+
+    /// Return true if the STS is derived from a session key
+    /// (STS_CONFIG equal to 0x01 or 0x02).
+    pub fn is_dynamic_sts(&self) -> bool {
+        matches!(
+            self.sts_config,
+            uci::StsConfig::Dynamic | uci::StsConfig::DynamicForResponderSubSessionKey
+        )
+    }
+
+    /// Return the number of ranging rounds between two rotations of the
+    /// session key, equal to 2^KEY_ROTATION_RATE. Returns None if the
+    /// key rotation is disabled, or the STS is not dynamic.
+    pub fn key_rotation_period(&self) -> Option<u32> {
+        (self.is_dynamic_sts() && self.key_rotation == uci::KeyRotation::Enable)
+            .then(|| 1 << self.key_rotation_rate.min(31))
+    }
+
     pub fn can_start_data_transfer(&self) -> bool {
         self.device_role == Some(uci::DeviceRole::Initiator)
     }
@@ -515,3 +891,108 @@ impl AppConfig {
         self.device_role == Some(uci::DeviceRole::Responder)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_typed_parameters() {
+        let mut app_config = AppConfig::default();
+        app_config
+            .set(uci::AppConfigTlvType::RangingDuration, &[100, 0, 0, 0])
+            .unwrap();
+        app_config
+            .set(uci::AppConfigTlvType::DeviceRole, &[0x01])
+            .unwrap();
+        app_config
+            .set(uci::AppConfigTlvType::ChannelNumber, &[0x05])
+            .unwrap();
+        app_config
+            .set(uci::AppConfigTlvType::ResultReportConfig, &[0x03])
+            .unwrap();
+
+        assert_eq!(app_config.ranging_duration, 100);
+        assert_eq!(app_config.device_role, Some(uci::DeviceRole::Initiator));
+        assert_eq!(
+            app_config.channel_number,
+            uci::ChannelNumber::ChannelNumber5
+        );
+        assert_eq!(
+            app_config.result_report_config,
+            ResultReportConfig {
+                tof: true,
+                aoa_azimuth: true,
+                aoa_elevation: false,
+                aoa_fom: false,
+            }
+        );
+    }
+
+    #[test]
+    fn set_malformed_parameters() {
+        let mut app_config = AppConfig::default();
+        assert!(app_config
+            .set(uci::AppConfigTlvType::RangingDuration, &[100, 0])
+            .is_err());
+        assert!(app_config
+            .set(uci::AppConfigTlvType::ChannelNumber, &[0x05, 0x00])
+            .is_err());
+        assert!(app_config
+            .set(uci::AppConfigTlvType::ChannelNumber, &[0x07])
+            .is_err());
+        assert_eq!(app_config.ranging_duration, 200);
+    }
+
+    #[test]
+    fn vendor_parameters_round_trip() {
+        let mut app_config = AppConfig::default();
+        let id = uci::AppConfigTlvType::try_from(0xf0).unwrap();
+        assert!(app_config.get(id).is_err());
+        app_config.set(id, &[1, 2, 3]).unwrap();
+        assert_eq!(app_config.get(id).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn hybrid_report_schedule() {
+        let mut app_config = AppConfig::default();
+        assert_eq!(
+            app_config.report_type(3),
+            uci::RangingMeasurementType::TwoWay
+        );
+        // The empty schedule follows the ranging method of the session.
+        app_config
+            .set(uci::AppConfigTlvType::RangingRoundUsage, &[0x06])
+            .unwrap();
+        assert_eq!(
+            app_config.report_type(3),
+            uci::RangingMeasurementType::OwrAoa
+        );
+        assert!(app_config
+            .set(uci::AppConfigTlvType::HybridReportSchedule, &[0x01, 0x02])
+            .is_err());
+        app_config
+            .set(
+                uci::AppConfigTlvType::HybridReportSchedule,
+                &[0x01, 0x03, 0x03],
+            )
+            .unwrap();
+        assert_eq!(
+            (0..4)
+                .map(|round| app_config.report_type(round))
+                .collect::<Vec<_>>(),
+            vec![
+                uci::RangingMeasurementType::TwoWay,
+                uci::RangingMeasurementType::OwrAoa,
+                uci::RangingMeasurementType::OwrAoa,
+                uci::RangingMeasurementType::TwoWay,
+            ]
+        );
+        assert_eq!(
+            app_config
+                .get(uci::AppConfigTlvType::HybridReportSchedule)
+                .unwrap(),
+            vec![0x01, 0x03, 0x03]
+        );
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Control channel exchanging newline delimited JSON messages.
+//!
+//! Each line received on the connection is a command object selected
+//! by the `cmd` field, and is answered by a single line containing the
+//! JSON response:
+//!
+//! ```json
+//! {"cmd":"get_state"}
+//! {"status":"ok","state":{"devices":[],"obstacles":[]}}
+//! ```
+//!
+//! Malformed commands are answered with an error response, the
+//! connection is kept open.
+//!
+//! The ranging rounds can be halted with `pause_clock`, and executed
+//! on demand with `{"cmd":"step_rounds","count":1}` until `resume_clock`.
+//! The active sessions of all devices are stopped with `stop_all_ranging`.
+
+use crate::Context;
+use anyhow::Result;
+use hyper::{Body, Response};
+use pica::packets::uci;
+use pica::{MacAddress, PicaCommand, Position};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+/// Position of a device. Omitted fields default to zero.
+#[derive(Debug, Deserialize)]
+pub(crate) struct DevicePosition {
+    #[serde(default)]
+    x: i16,
+    #[serde(default)]
+    y: i16,
+    #[serde(default)]
+    z: i16,
+    #[serde(default)]
+    yaw: i16,
+    #[serde(default)]
+    pitch: i8,
+    #[serde(default)]
+    roll: i16,
+}
+
+impl From<DevicePosition> for Position {
+    fn from(position: DevicePosition) -> Self {
+        Position::new(
+            position.x,
+            position.y,
+            position.z,
+            position.yaw,
+            position.pitch,
+            position.roll,
+        )
+    }
+}
+
+/// Behavior of an injected fault, mirroring [pica::FaultBehavior].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FaultBehavior {
+    ReturnStatus(u8),
+    Drop,
+    /// Delay in milliseconds.
+    Delay(u64),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub(crate) enum Command {
+    SetPosition {
+        mac_address: MacAddress,
+        #[serde(flatten)]
+        position: DevicePosition,
+    },
+    CreateAnchor {
+        mac_address: MacAddress,
+        #[serde(flatten)]
+        position: DevicePosition,
+    },
+    GetState,
+    InjectFault {
+        mac_address: MacAddress,
+        gid: u8,
+        oid: u8,
+        behavior: FaultBehavior,
+        #[serde(default = "default_fault_count")]
+        count: usize,
+    },
+    PauseClock,
+    ResumeClock,
+    StepRounds {
+        #[serde(default = "default_step_count")]
+        count: usize,
+    },
+    StopAllRanging,
+}
+
+fn default_fault_count() -> usize {
+    1
+}
+
+fn default_step_count() -> usize {
+    1
+}
+
+fn ok() -> Value {
+    json!({ "status": "ok" })
+}
+
+fn error(reason: impl ToString) -> Value {
+    json!({ "status": "error", "reason": reason.to_string() })
+}
+
+/// Convert the response of an HTTP handler.
+fn from_http_response(response: Response<Body>) -> Value {
+    if response.status().is_success() {
+        ok()
+    } else {
+        error(
+            response
+                .status()
+                .canonical_reason()
+                .unwrap_or("unknown error"),
+        )
+    }
+}
+
+async fn inject_fault(
+    context: &Context,
+    cmd_tx: &mpsc::Sender<PicaCommand>,
+    mac_address: MacAddress,
+    gid: u8,
+    oid: u8,
+    behavior: FaultBehavior,
+    count: usize,
+) -> Result<Value> {
+    let Some(handle) = context
+        .devices
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(_, device)| device.mac_address == mac_address)
+        .map(|(handle, _)| *handle)
+    else {
+        return Ok(error(format!("device not found: {}", mac_address)));
+    };
+
+    let Ok(gid) = uci::GroupId::try_from(gid) else {
+        return Ok(error(format!("invalid gid: 0x{:x}", gid)));
+    };
+    let behavior = match behavior {
+        FaultBehavior::ReturnStatus(status) => match uci::Status::try_from(status) {
+            Ok(status) => pica::FaultBehavior::ReturnStatus(status),
+            Err(_) => return Ok(error(format!("invalid status: 0x{:x}", status))),
+        },
+        FaultBehavior::Drop => pica::FaultBehavior::Drop,
+        FaultBehavior::Delay(delay) => pica::FaultBehavior::Delay(Duration::from_millis(delay)),
+    };
+
+    cmd_tx
+        .send(PicaCommand::InjectFault(
+            handle,
+            pica::Fault {
+                gid,
+                oid,
+                behavior,
+                count,
+            },
+        ))
+        .await
+        .map_err(|_| anyhow::anyhow!("pica command stream closed"))?;
+    Ok(ok())
+}
+
+async fn step_rounds(cmd_tx: &mpsc::Sender<PicaCommand>, count: usize) -> Result<Value> {
+    let (rsp_tx, rsp_rx) = oneshot::channel();
+    cmd_tx
+        .send(PicaCommand::StepRounds(count, rsp_tx))
+        .await
+        .map_err(|_| anyhow::anyhow!("pica command stream closed"))?;
+    Ok(match rsp_rx.await? {
+        Ok(()) => ok(),
+        Err(err) => error(err),
+    })
+}
+
+async fn stop_all_ranging(cmd_tx: &mpsc::Sender<PicaCommand>) -> Result<Value> {
+    let (rsp_tx, rsp_rx) = oneshot::channel();
+    cmd_tx
+        .send(PicaCommand::StopAllRanging(rsp_tx))
+        .await
+        .map_err(|_| anyhow::anyhow!("pica command stream closed"))?;
+    let stopped = rsp_rx
+        .await?
+        .into_iter()
+        .map(|(handle, session_id)| json!({ "handle": handle, "session_id": session_id }))
+        .collect::<Vec<_>>();
+    Ok(json!({ "status": "ok", "stopped": stopped }))
+}
+
+async fn handle_command(
+    context: &Context,
+    cmd_tx: &mpsc::Sender<PicaCommand>,
+    line: &str,
+) -> Result<Value> {
+    match serde_json::from_str::<Command>(line) {
+        Ok(command) => execute(context, cmd_tx, command).await,
+        Err(err) => Ok(error(format!("invalid command: {}", err))),
+    }
+}
+
+/// Execute a command and return the JSON response.
+pub(crate) async fn execute(
+    context: &Context,
+    cmd_tx: &mpsc::Sender<PicaCommand>,
+    command: Command,
+) -> Result<Value> {
+    Ok(match command {
+        Command::SetPosition {
+            mac_address,
+            position,
+        } => from_http_response(context.http_set_position(mac_address, position.into())),
+        Command::CreateAnchor {
+            mac_address,
+            position,
+        } => from_http_response(
+            context
+                .http_create_anchor(mac_address, position.into(), cmd_tx.clone())
+                .await,
+        ),
+        Command::GetState => json!({
+            "status": "ok",
+            "state": context.get_state_with_config(cmd_tx).await
+        }),
+        Command::InjectFault {
+            mac_address,
+            gid,
+            oid,
+            behavior,
+            count,
+        } => inject_fault(context, cmd_tx, mac_address, gid, oid, behavior, count).await?,
+        Command::PauseClock => {
+            cmd_tx
+                .send(PicaCommand::PauseClock)
+                .await
+                .map_err(|_| anyhow::anyhow!("pica command stream closed"))?;
+            ok()
+        }
+        Command::ResumeClock => {
+            cmd_tx
+                .send(PicaCommand::ResumeClock)
+                .await
+                .map_err(|_| anyhow::anyhow!("pica command stream closed"))?;
+            ok()
+        }
+        Command::StepRounds { count } => step_rounds(cmd_tx, count).await?,
+        Command::StopAllRanging => stop_all_ranging(cmd_tx).await?,
+    })
+}
+
+async fn handle_connection(
+    context: Context,
+    cmd_tx: mpsc::Sender<PicaCommand>,
+    socket: TcpStream,
+) -> Result<()> {
+    let (read_half, mut write_half) = socket.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_command(&context, &cmd_tx, &line).await?;
+        write_half
+            .write_all(format!("{}\n", response).as_bytes())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Accept control connections on the selected listener.
+pub async fn serve(
+    context: Context,
+    cmd_tx: mpsc::Sender<PicaCommand>,
+    listener: TcpListener,
+) -> Result<()> {
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        log::info!("Control client addr: {}", addr);
+
+        let context = context.clone();
+        let cmd_tx = cmd_tx.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(context, cmd_tx, socket).await {
+                log::error!("Control connection error: {}", err);
+            }
+        });
+    }
+}
+
+/// Listen for control connections if a control port is selected.
+pub async fn listen(
+    context: Context,
+    cmd_tx: mpsc::Sender<PicaCommand>,
+    control_port: Option<u16>,
+) -> Result<()> {
+    let Some(control_port) = control_port else {
+        return Ok(());
+    };
+
+    let control_socket = SocketAddrV4::new(Ipv4Addr::LOCALHOST, control_port);
+    let listener = TcpListener::bind(control_socket).await?;
+    log::info!("Pica: Control channel listening on: {}", control_port);
+    serve(context, cmd_tx, listener).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn get_state() {
+        let context = Context::new();
+        let (cmd_tx, _cmd_rx) = mpsc::channel(1);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(context, cmd_tx, listener));
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let (read_half, mut write_half) = socket.into_split();
+        let mut lines = BufReader::new(read_half).lines();
+
+        // Malformed lines are answered with an error.
+        write_half.write_all(b"{\"cmd\":\n").await.unwrap();
+        let response: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["status"], "error");
+
+        // The connection is still open.
+        write_half
+            .write_all(b"{\"cmd\":\"get_state\"}\n")
+            .await
+            .unwrap();
+        let response: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            response,
+            json!({
+                "status": "ok",
+                "state": { "devices": [], "obstacles": [] }
+            })
+        );
+    }
+}
@@ -18,22 +18,31 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{body, Body, Request, Response, Server, StatusCode as HttpStatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::error::Category as SerdeErrorCategory;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::Infallible;
-use std::net::{Ipv4Addr, SocketAddrV4};
-use std::path::PathBuf;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, Semaphore};
 use tokio::try_join;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
-use env_logger::Env;
 
-use pica::{Category, MacAddress, Pica, PicaCommand, PicaCommandError, PicaEvent};
+use pica::cli::{
+    AntennaConfig, ColocatedReport, DeviceState, LogFormat, ResponseLatency, TraceFormat,
+    UciFraming,
+};
+use pica::{Category, MacAddress, Pica, PicaCommand, PicaError, PicaEvent, Position};
 
-mod position;
-use position::Position;
+mod control;
+mod obstacle;
+use obstacle::Obstacle;
+mod scene;
+use scene::Scene;
+mod script;
+use script::Script;
 
 const DEFAULT_UCI_PORT: u16 = 7000;
 const DEFAULT_WEB_PORT: u16 = 3000;
@@ -74,6 +83,9 @@ struct DeviceInformation {
     mac_address: MacAddress,
     #[serde(flatten)]
     position: Position,
+    /// Set once the position of the device is known.
+    #[serde(skip)]
+    positioned: bool,
 }
 
 /// Record information about an active device.
@@ -107,23 +119,70 @@ pub enum Event {
     },
 }
 
-/// Record the position of active devices for reference by the
-/// ranging estimator.
+/// Snapshot of the scene.
+#[derive(Serialize)]
+struct State {
+    devices: Vec<DeviceInformation>,
+    obstacles: Vec<Obstacle>,
+    /// Decoded configuration of the UCI devices, reported when
+    /// enabled with `--state-config`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    config: Vec<pica::ConfigSnapshot>,
+}
+
+/// Ranging estimations computed for pairs of devices.
+type RangingCache = HashMap<(pica::Handle, pica::Handle), Option<pica::RangingMeasurement>>;
+
+/// Record the position of active devices and the obstacle map
+/// for reference by the ranging estimator.
+/// Estimations are cached until the position of either device
+/// or the obstacle map is modified.
 #[derive(Clone)]
 struct Context {
-    devices: Arc<Mutex<HashMap<pica::Handle, DeviceInformation>>>,
+    /// Devices ordered by handle, for the state snapshots to be
+    /// reproducible.
+    devices: Arc<Mutex<BTreeMap<pica::Handle, DeviceInformation>>>,
+    obstacles: Arc<Mutex<Vec<Obstacle>>>,
+    ranging_cache: Arc<Mutex<RangingCache>>,
     events: broadcast::Sender<Event>,
+    /// Exclude the UCI devices from ranging until their position
+    /// is set.
+    require_positions: bool,
+    /// Include the decoded configuration of the UCI devices
+    /// in the state snapshots.
+    state_config: bool,
 }
 
 impl Context {
     fn new() -> Self {
         let (events, _) = broadcast::channel(1024);
         Context {
-            devices: Arc::new(Mutex::new(HashMap::new())),
+            devices: Arc::new(Mutex::new(BTreeMap::new())),
+            obstacles: Arc::new(Mutex::new(vec![])),
+            ranging_cache: Arc::new(Mutex::new(HashMap::new())),
             events,
+            require_positions: false,
+            state_config: false,
         }
     }
 
+    /// Drop the cached estimations involving any of the selected devices.
+    /// Must be called with the device lock held to prevent
+    /// concurrent estimations from caching stale values.
+    fn invalidate_ranging_cache(&self, handles: &[pica::Handle]) {
+        self.ranging_cache
+            .lock()
+            .unwrap()
+            .retain(|(left, right), _| !handles.contains(left) && !handles.contains(right));
+    }
+
+    /// Replace the obstacle map, and drop all cached estimations.
+    fn set_obstacles(&self, obstacles: Vec<Obstacle>) {
+        let mut current = self.obstacles.lock().unwrap();
+        *current = obstacles;
+        self.ranging_cache.lock().unwrap().clear();
+    }
+
     async fn handle_connection_events(
         self,
         mut events: broadcast::Receiver<PicaEvent>,
@@ -141,6 +200,7 @@ impl Context {
                             category: Category::Uci,
                             mac_address,
                             position: Default::default(),
+                            positioned: !self.require_positions,
                         },
                     );
                     self.events
@@ -157,6 +217,7 @@ impl Context {
                 }) => {
                     let mut devices = self.devices.lock().unwrap();
                     devices.remove(&handle);
+                    self.invalidate_ranging_cache(&[handle]);
                     self.events
                         .send(Event::DeviceRemoved {
                             category: Category::Uci,
@@ -164,6 +225,26 @@ impl Context {
                         })
                         .unwrap();
                 }
+                Ok(PicaEvent::MacAddressChanged {
+                    handle,
+                    previous_mac_address,
+                    mac_address,
+                }) => {
+                    let mut devices = self.devices.lock().unwrap();
+                    let Some(device) = devices.get_mut(&handle) else {
+                        continue;
+                    };
+                    device.mac_address = mac_address;
+                    let _ = self.events.send(Event::DeviceRemoved {
+                        category: Category::Uci,
+                        mac_address: previous_mac_address,
+                    });
+                    let _ = self.events.send(Event::DeviceAdded {
+                        category: Category::Uci,
+                        mac_address,
+                        position: device.position,
+                    });
+                }
                 Err(err) => anyhow::bail!(err),
             }
         }
@@ -190,10 +271,12 @@ impl Context {
 
         let mut devices = self.devices.lock().unwrap();
         let mut found_device = None;
-        for (_, device) in devices.iter_mut() {
+        for (handle, device) in devices.iter_mut() {
             if device.mac_address == mac_address {
                 device.position = position;
+                device.positioned = true;
                 found_device = Some(device.clone());
+                self.invalidate_ranging_cache(&[*handle]);
                 break;
             }
         }
@@ -205,13 +288,88 @@ impl Context {
                 .unwrap();
         };
 
-        self.events
-            .send(Event::DeviceUpdated {
-                category: device.category,
-                mac_address,
-                position,
-            })
-            .unwrap();
+        self.send_position_events(&devices, &device);
+        Response::builder()
+            .status(HttpStatusCode::OK)
+            .body("".into())
+            .unwrap()
+    }
+
+    /// Move several devices at once, identified by their MAC address.
+    /// Returns the addresses of the devices not found, the other
+    /// positions are applied regardless.
+    fn set_positions(&self, positions: Vec<(MacAddress, Position)>) -> Vec<MacAddress> {
+        let mut devices = self.devices.lock().unwrap();
+        let mut handle_positions = vec![];
+        let mut not_found = vec![];
+        for (mac_address, position) in positions {
+            match devices
+                .iter()
+                .find(|(_, device)| device.mac_address == mac_address)
+            {
+                Some((handle, _)) => handle_positions.push((*handle, position)),
+                None => not_found.push(mac_address),
+            }
+        }
+
+        self.move_devices(&mut devices, &handle_positions);
+        not_found
+    }
+
+    /// Move several devices at once, identified by their handle.
+    /// The positions are applied under a single lock of the devices,
+    /// so that no estimation observes a partial update, and the cached
+    /// estimations involving the moved devices are dropped in one pass.
+    /// Unknown handles are ignored.
+    fn move_devices(
+        &self,
+        devices: &mut BTreeMap<pica::Handle, DeviceInformation>,
+        positions: &[(pica::Handle, Position)],
+    ) {
+        let mut moved_handles = vec![];
+        for (handle, position) in positions {
+            if let Some(device) = devices.get_mut(handle) {
+                device.position = *position;
+                device.positioned = true;
+                moved_handles.push(*handle);
+            }
+        }
+
+        self.invalidate_ranging_cache(&moved_handles);
+        for handle in moved_handles {
+            self.send_position_events(devices, &devices[&handle]);
+        }
+    }
+
+    fn http_set_positions(&self, positions: Vec<(MacAddress, Position)>) -> Response<Body> {
+        log::info!("set-positions({} devices)", positions.len());
+
+        let not_found = self.set_positions(positions);
+        for mac_address in &not_found {
+            log::warn!("set-positions: device {} not found", mac_address);
+        }
+        Response::builder()
+            .status(HttpStatusCode::OK)
+            .body(
+                serde_json::json!({ "not_found": not_found })
+                    .to_string()
+                    .into(),
+            )
+            .unwrap()
+    }
+
+    /// Report the new position of a device, and the updated
+    /// estimations with every other device.
+    fn send_position_events(
+        &self,
+        devices: &BTreeMap<pica::Handle, DeviceInformation>,
+        device: &DeviceInformation,
+    ) {
+        let _ = self.events.send(Event::DeviceUpdated {
+            category: device.category,
+            mac_address: device.mac_address,
+            position: device.position,
+        });
 
         for other in devices.values() {
             if other.mac_address != device.mac_address {
@@ -224,37 +382,27 @@ impl Context {
 
                 assert!(local.0 == remote.0);
 
-                self.events
-                    .send(Event::NeighborUpdated {
-                        source_category: device.category,
-                        source_mac_address: device.mac_address,
-                        destination_category: other.category,
-                        destination_mac_address: other.mac_address,
-                        distance: local.0,
-                        azimuth: local.1,
-                        elevation: local.2,
-                    })
-                    .unwrap();
-
-                let _ = self
-                    .events
-                    .send(Event::NeighborUpdated {
-                        source_category: other.category,
-                        source_mac_address: other.mac_address,
-                        destination_category: device.category,
-                        destination_mac_address: device.mac_address,
-                        distance: remote.0,
-                        azimuth: remote.1,
-                        elevation: remote.2,
-                    })
-                    .unwrap();
+                let _ = self.events.send(Event::NeighborUpdated {
+                    source_category: device.category,
+                    source_mac_address: device.mac_address,
+                    destination_category: other.category,
+                    destination_mac_address: other.mac_address,
+                    distance: local.0,
+                    azimuth: local.1,
+                    elevation: local.2,
+                });
+
+                let _ = self.events.send(Event::NeighborUpdated {
+                    source_category: other.category,
+                    source_mac_address: other.mac_address,
+                    destination_category: device.category,
+                    destination_mac_address: device.mac_address,
+                    distance: remote.0,
+                    azimuth: remote.1,
+                    elevation: remote.2,
+                });
             }
         }
-
-        Response::builder()
-            .status(HttpStatusCode::OK)
-            .body("".into())
-            .unwrap()
     }
 
     async fn http_create_anchor(
@@ -265,7 +413,7 @@ impl Context {
     ) -> Response<Body> {
         log::info!("create-anchor({}, {})", mac_address, position);
 
-        let (rsp_tx, rsp_rx) = oneshot::channel::<Result<pica::Handle, PicaCommandError>>();
+        let (rsp_tx, rsp_rx) = oneshot::channel::<Result<pica::Handle, PicaError>>();
         cmd_tx
             .send(PicaCommand::CreateAnchor(mac_address, rsp_tx))
             .await
@@ -280,20 +428,19 @@ impl Context {
                         position,
                         mac_address,
                         category: Category::Anchor,
+                        positioned: true,
                     },
                 );
-                self.events
-                    .send(Event::DeviceAdded {
-                        category: Category::Anchor,
-                        mac_address,
-                        position,
-                    })
-                    .unwrap();
+                let _ = self.events.send(Event::DeviceAdded {
+                    category: Category::Anchor,
+                    mac_address,
+                    position,
+                });
                 HttpStatusCode::OK
             }
-            Ok(Err(PicaCommandError::DeviceAlreadyExists(_))) => HttpStatusCode::CONFLICT,
-            Ok(Err(PicaCommandError::DeviceNotFound(_))) => HttpStatusCode::NOT_FOUND,
-            Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
+            Ok(Err(PicaError::DeviceAlreadyExists(_))) => HttpStatusCode::CONFLICT,
+            Ok(Err(PicaError::DeviceNotFound(_))) => HttpStatusCode::NOT_FOUND,
+            Ok(Err(_)) | Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
         };
 
         Response::builder().status(status).body("".into()).unwrap()
@@ -306,7 +453,7 @@ impl Context {
     ) -> Response<Body> {
         log::info!("destroy-anchor({})", mac_address);
 
-        let (rsp_tx, rsp_rx) = oneshot::channel::<Result<pica::Handle, PicaCommandError>>();
+        let (rsp_tx, rsp_rx) = oneshot::channel::<Result<pica::Handle, PicaError>>();
         cmd_tx
             .send(PicaCommand::DestroyAnchor(mac_address, rsp_tx))
             .await
@@ -316,35 +463,341 @@ impl Context {
             Ok(Ok(handle)) => {
                 let mut devices = self.devices.lock().unwrap();
                 devices.remove(&handle);
-                self.events
-                    .send(Event::DeviceRemoved {
-                        category: Category::Anchor,
-                        mac_address,
-                    })
-                    .unwrap();
+                self.invalidate_ranging_cache(&[handle]);
+                let _ = self.events.send(Event::DeviceRemoved {
+                    category: Category::Anchor,
+                    mac_address,
+                });
                 HttpStatusCode::OK
             }
-            Ok(Err(PicaCommandError::DeviceAlreadyExists(_))) => HttpStatusCode::CONFLICT,
-            Ok(Err(PicaCommandError::DeviceNotFound(_))) => HttpStatusCode::NOT_FOUND,
-            Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
+            Ok(Err(PicaError::DeviceAlreadyExists(_))) => HttpStatusCode::CONFLICT,
+            Ok(Err(PicaError::DeviceNotFound(_))) => HttpStatusCode::NOT_FOUND,
+            Ok(Err(_)) | Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
         };
 
         Response::builder().status(status).body("".into()).unwrap()
     }
 
-    fn http_get_state(&self) -> Response<Body> {
-        log::info!("get-state()");
+    fn http_set_obstacles(&self, obstacles: Vec<Obstacle>) -> Response<Body> {
+        log::info!("set-obstacles({:?})", obstacles);
+
+        self.set_obstacles(obstacles);
+        Response::builder()
+            .status(HttpStatusCode::OK)
+            .body("".into())
+            .unwrap()
+    }
+
+    /// Rotate a device in place. The azimuth and elevation reported
+    /// to and by the device are updated, its coordinates are unchanged.
+    fn http_set_orientation(
+        &self,
+        mac_address: MacAddress,
+        yaw: i16,
+        pitch: i8,
+        roll: i16,
+    ) -> Response<Body> {
+        log::info!(
+            "set-orientation({}, {}, {}, {})",
+            mac_address,
+            yaw,
+            pitch,
+            roll
+        );
+
+        // The orientation is replaced under the same lock as the lookup,
+        // so that a concurrent move of the device is not overwritten.
+        let mut devices = self.devices.lock().unwrap();
+        let Some((handle, device)) = devices
+            .iter_mut()
+            .find(|(_, device)| device.mac_address == mac_address)
+        else {
+            return Response::builder()
+                .status(HttpStatusCode::NOT_FOUND)
+                .body("".into())
+                .unwrap();
+        };
+
+        device.position.set_orientation(yaw, pitch, roll);
+        device.positioned = true;
+        let device = device.clone();
+        self.invalidate_ranging_cache(&[*handle]);
+
+        self.send_position_events(&devices, &device);
+        Response::builder()
+            .status(HttpStatusCode::OK)
+            .body("".into())
+            .unwrap()
+    }
+
+    async fn http_set_temperature(
+        &self,
+        mac_address: MacAddress,
+        temperature: i8,
+        cmd_tx: mpsc::Sender<PicaCommand>,
+    ) -> Response<Body> {
+        log::info!("set-temperature({}, {})", mac_address, temperature);
+
+        let (rsp_tx, rsp_rx) = oneshot::channel::<Result<pica::Handle, PicaError>>();
+        cmd_tx
+            .send(PicaCommand::SetTemperature(
+                mac_address,
+                temperature,
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+
+        let status = match rsp_rx.await {
+            Ok(Ok(_)) => HttpStatusCode::OK,
+            Ok(Err(PicaError::DeviceNotFound(_))) => HttpStatusCode::NOT_FOUND,
+            Ok(Err(_)) | Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Response::builder().status(status).body("".into()).unwrap()
+    }
+
+    /// Set the country code of the device. Lowercase codes are normalized
+    /// to uppercase, codes that are not two ASCII letters are rejected
+    /// with the status 400.
+    async fn http_set_country_code(
+        &self,
+        mac_address: MacAddress,
+        country_code: &str,
+        cmd_tx: mpsc::Sender<PicaCommand>,
+    ) -> Response<Body> {
+        log::info!("set-country-code({}, {})", mac_address, country_code);
+
+        let Ok(country_code) = <[u8; 2]>::try_from(country_code.as_bytes()) else {
+            return Response::builder()
+                .status(HttpStatusCode::BAD_REQUEST)
+                .body(format!("Invalid country code: {}", country_code).into())
+                .unwrap();
+        };
+
+        let (rsp_tx, rsp_rx) = oneshot::channel::<Result<pica::Handle, PicaError>>();
+        cmd_tx
+            .send(PicaCommand::SetCountryCode(
+                mac_address,
+                country_code,
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+
+        let status = match rsp_rx.await {
+            Ok(Ok(_)) => HttpStatusCode::OK,
+            Ok(Err(PicaError::DeviceNotFound(_))) => HttpStatusCode::NOT_FOUND,
+            Ok(Err(PicaError::InvalidCountryCode(_))) => HttpStatusCode::BAD_REQUEST,
+            Ok(Err(_)) | Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Response::builder().status(status).body("".into()).unwrap()
+    }
+
+    async fn http_reset_device(
+        &self,
+        handle: pica::Handle,
+        cmd_tx: mpsc::Sender<PicaCommand>,
+    ) -> Response<Body> {
+        log::info!("reset-device({})", handle);
 
-        #[derive(Serialize)]
-        struct GetStateResponse {
-            devices: Vec<DeviceInformation>,
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        cmd_tx
+            .send(PicaCommand::ResetDevice { handle, rsp_tx })
+            .await
+            .unwrap();
+        device_state_response(rsp_rx.await)
+    }
+
+    /// Restrict the device roles advertised by an UCI device in the
+    /// SUPPORTED_DEVICE_ROLES capability. Sessions configured with
+    /// other roles are rejected by the device.
+    async fn http_set_device_roles(
+        &self,
+        handle: pica::Handle,
+        roles: Vec<DeviceRole>,
+        cmd_tx: mpsc::Sender<PicaCommand>,
+    ) -> Response<Body> {
+        log::info!("set-device-roles({}, {:?})", handle, roles);
+
+        let value = roles.iter().fold(0, |mask, role| {
+            mask | match role {
+                DeviceRole::Initiator => 0x01,
+                DeviceRole::Responder => 0x02,
+            }
+        });
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        cmd_tx
+            .send(PicaCommand::OverrideCapability {
+                handle,
+                id: pica::packets::uci::CapTlvType::SupportedDeviceRoles.into(),
+                value: vec![value],
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+
+        let status = match rsp_rx.await {
+            Ok(Ok(())) => HttpStatusCode::OK,
+            Ok(Err(PicaError::HandleNotFound(_))) => HttpStatusCode::NOT_FOUND,
+            Ok(Err(_)) | Err(_) => HttpStatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Response::builder().status(status).body("".into()).unwrap()
+    }
+
+    async fn http_get_device_state(
+        &self,
+        handle: pica::Handle,
+        cmd_tx: mpsc::Sender<PicaCommand>,
+    ) -> Response<Body> {
+        log::info!("get-device-state({})", handle);
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        cmd_tx
+            .send(PicaCommand::GetDeviceState { handle, rsp_tx })
+            .await
+            .unwrap();
+        device_state_response(rsp_rx.await)
+    }
+
+    /// Load the scene file, and apply the differences with the current
+    /// scene: anchors are created, destroyed, or moved to match the scene
+    /// file, and the obstacle map is replaced. Connected UCI devices are
+    /// left untouched. The current scene is preserved if the scene file
+    /// cannot be loaded.
+    async fn reload_scene(&self, path: &Path, cmd_tx: mpsc::Sender<PicaCommand>) {
+        log::info!("reload-scene({})", path.display());
+
+        let scene = match Scene::load(path) {
+            Ok(scene) => scene,
+            Err(err) => {
+                log::error!("Failed to load scene file {}: {}", path.display(), err);
+                return;
+            }
+        };
+
+        let anchors = self
+            .devices
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|device| matches!(device.category, Category::Anchor))
+            .map(|device| (device.mac_address, device.position))
+            .collect::<HashMap<_, _>>();
+
+        for mac_address in anchors.keys() {
+            if !scene
+                .anchors
+                .iter()
+                .any(|anchor| anchor.mac_address == *mac_address)
+            {
+                self.http_destroy_anchor(*mac_address, cmd_tx.clone()).await;
+            }
+        }
+
+        for anchor in scene.anchors.iter() {
+            match anchors.get(&anchor.mac_address) {
+                None => {
+                    self.http_create_anchor(anchor.mac_address, anchor.position(), cmd_tx.clone())
+                        .await;
+                }
+                Some(position) if *position != anchor.position() => {
+                    self.http_set_position(anchor.mac_address, anchor.position());
+                }
+                Some(_) => (),
+            }
+        }
+
+        self.set_obstacles(scene.obstacles);
+    }
+
+    async fn http_get_ground_truth(
+        &self,
+        session_id: u32,
+        cmd_tx: mpsc::Sender<PicaCommand>,
+    ) -> Response<Body> {
+        log::info!("get-ground-truth(0x{:x})", session_id);
+
+        let (rsp_tx, rsp_rx) = oneshot::channel::<Vec<pica::GroundTruth>>();
+        cmd_tx
+            .send(PicaCommand::GetGroundTruth(session_id, rsp_tx))
+            .await
+            .unwrap();
+
+        match rsp_rx.await {
+            Ok(ground_truth) => Response::builder()
+                .status(HttpStatusCode::OK)
+                .body(serde_json::to_string(&ground_truth).unwrap().into())
+                .unwrap(),
+            Err(_) => Response::builder()
+                .status(HttpStatusCode::INTERNAL_SERVER_ERROR)
+                .body("".into())
+                .unwrap(),
         }
+    }
+
+    async fn http_get_timers(&self, cmd_tx: mpsc::Sender<PicaCommand>) -> Response<Body> {
+        log::info!("get-timers()");
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        cmd_tx.send(PicaCommand::GetTimers(rsp_tx)).await.unwrap();
+
+        match rsp_rx.await {
+            Ok(timers) => {
+                let timers = timers.iter().map(TimerBody::from).collect::<Vec<_>>();
+                Response::builder()
+                    .status(HttpStatusCode::OK)
+                    .body(serde_json::to_string(&timers).unwrap().into())
+                    .unwrap()
+            }
+            Err(_) => Response::builder()
+                .status(HttpStatusCode::INTERNAL_SERVER_ERROR)
+                .body("".into())
+                .unwrap(),
+        }
+    }
 
+    /// Return a snapshot of the active devices and obstacle map.
+    fn get_state(&self) -> State {
         let devices = self.devices.lock().unwrap();
-        let response = GetStateResponse {
+        let obstacles = self.obstacles.lock().unwrap();
+        State {
             devices: devices.values().cloned().collect::<Vec<_>>(),
-        };
-        let body = serde_json::to_string(&response).unwrap();
+            obstacles: obstacles.clone(),
+            config: vec![],
+        }
+    }
+
+    /// Return a snapshot of the active devices and obstacle map,
+    /// completed with the decoded configuration of the UCI devices
+    /// when enabled.
+    async fn get_state_with_config(&self, cmd_tx: &mpsc::Sender<PicaCommand>) -> State {
+        let mut state = self.get_state();
+        if self.state_config {
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            if cmd_tx.send(PicaCommand::GetConfig(rsp_tx)).await.is_ok() {
+                state.config = rsp_rx.await.unwrap_or_default();
+            }
+        }
+        state
+    }
+
+    /// Write a snapshot of the state to a timestamped JSON file
+    /// in the selected directory, and return the path of the file.
+    fn dump_state(&self, dir: &Path) -> Result<PathBuf> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis();
+        let path = dir.join(format!("pica-state-{}.json", timestamp));
+        std::fs::write(&path, serde_json::to_vec_pretty(&self.get_state())?)?;
+        Ok(path)
+    }
+
+    async fn http_get_state(&self, cmd_tx: mpsc::Sender<PicaCommand>) -> Response<Body> {
+        log::info!("get-state()");
+
+        let body = serde_json::to_string(&self.get_state_with_config(&cmd_tx).await).unwrap();
         Response::builder()
             .status(HttpStatusCode::OK)
             .body(body.into())
@@ -352,24 +805,105 @@ impl Context {
     }
 }
 
+/// Build the response reporting the state of a UCI device,
+/// as a JSON object `{"state": "ready" | "active" | "low_power" | "error"}`.
+fn device_state_response(
+    result: Result<Result<pica::packets::uci::DeviceState, PicaError>, oneshot::error::RecvError>,
+) -> Response<Body> {
+    match result {
+        Ok(Ok(state)) => {
+            use pica::packets::uci::DeviceState;
+            let state = match state {
+                DeviceState::DeviceStateReady => "ready",
+                DeviceState::DeviceStateActive => "active",
+                DeviceState::DeviceStateLowPower => "low_power",
+                DeviceState::DeviceStateError => "error",
+            };
+            Response::builder()
+                .status(HttpStatusCode::OK)
+                .body(serde_json::json!({ "state": state }).to_string().into())
+                .unwrap()
+        }
+        Ok(Err(PicaError::HandleNotFound(_))) => Response::builder()
+            .status(HttpStatusCode::NOT_FOUND)
+            .body("".into())
+            .unwrap(),
+        Ok(Err(_)) | Err(_) => Response::builder()
+            .status(HttpStatusCode::INTERNAL_SERVER_ERROR)
+            .body("".into())
+            .unwrap(),
+    }
+}
+
 impl pica::RangingEstimator for Context {
     fn estimate(
         &self,
         left: &pica::Handle,
         right: &pica::Handle,
     ) -> Option<pica::RangingMeasurement> {
+        // The device lock is held for the duration of the estimation
+        // to serialize with position updates.
         let devices = self.devices.lock().ok()?;
-        let left_pos = devices.get(left)?.position;
-        let right_pos = devices.get(right)?.position;
-        let (range, azimuth, elevation) = left_pos.compute_range_azimuth_elevation(&right_pos);
-        Some(pica::RangingMeasurement {
-            range,
-            azimuth,
-            elevation,
-        })
+        if let Some(measurement) = self.ranging_cache.lock().ok()?.get(&(*left, *right)) {
+            return *measurement;
+        }
+
+        // Devices with an unknown position are out of range.
+        let position = |handle| {
+            devices
+                .get(handle)
+                .filter(|device| device.positioned)
+                .map(|device| device.position)
+        };
+        let left_pos = position(left)?;
+        let right_pos = position(right)?;
+        let obstacles = self.obstacles.lock().ok()?;
+        let measurement = if obstacles
+            .iter()
+            .any(|obstacle| obstacle.blocks(&left_pos, &right_pos))
+        {
+            None
+        } else {
+            let (range, azimuth, elevation) = left_pos.compute_range_azimuth_elevation(&right_pos);
+            Some(pica::RangingMeasurement {
+                range,
+                azimuth,
+                elevation,
+            })
+        };
+
+        self.ranging_cache
+            .lock()
+            .ok()?
+            .insert((*left, *right), measurement);
+        measurement
+    }
+
+    fn set_positions(&self, positions: &[(pica::Handle, Position)]) {
+        let mut devices = self.devices.lock().unwrap();
+        self.move_devices(&mut devices, positions);
+    }
+
+    fn position(&self, handle: &pica::Handle) -> Option<Position> {
+        self.devices
+            .lock()
+            .ok()?
+            .get(handle)
+            .filter(|device| device.positioned)
+            .map(|device| device.position)
     }
 }
 
+#[derive(Deserialize)]
+struct TemperatureBody {
+    temperature: i8,
+}
+
+#[derive(Deserialize)]
+struct CountryCodeBody {
+    country_code: String,
+}
+
 #[derive(Deserialize)]
 struct PositionBody {
     x: i16,
@@ -380,6 +914,58 @@ struct PositionBody {
     roll: i16,
 }
 
+/// Description of an active timer, with the deadline
+/// in microseconds of the clock of the devices.
+#[derive(Debug, Serialize)]
+struct TimerBody {
+    kind: &'static str,
+    handle: pica::Handle,
+    session_id: Option<u32>,
+    deadline_us: u64,
+}
+
+impl From<&pica::TimerInfo> for TimerBody {
+    fn from(timer: &pica::TimerInfo) -> Self {
+        TimerBody {
+            kind: match timer.kind {
+                pica::TimerKind::Ranging => "ranging",
+                pica::TimerKind::IdleTimeout => "idle-timeout",
+                pica::TimerKind::ResetDelay => "reset-delay",
+            },
+            handle: timer.handle,
+            session_id: timer.session_id,
+            deadline_us: timer.deadline.as_micros() as u64,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DevicePositionBody {
+    mac_address: MacAddress,
+    #[serde(flatten)]
+    position: PositionBody,
+}
+
+/// Device role advertised in the SUPPORTED_DEVICE_ROLES capability.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DeviceRole {
+    Initiator,
+    Responder,
+}
+
+#[derive(Deserialize)]
+struct DeviceRolesBody {
+    roles: Vec<DeviceRole>,
+}
+
+#[derive(Deserialize)]
+struct OrientationBody {
+    yaw: i16,
+    pitch: i8,
+    roll: i16,
+}
+
 macro_rules! position {
     ($body: ident) => {
         position!($body, false)
@@ -455,6 +1041,21 @@ async fn http_request(
         ["set-position", mac_address] => {
             context.http_set_position(mac_address!(mac_address), position!(body))
         }
+        ["set-orientation", mac_address] => {
+            match serde_json::from_slice::<OrientationBody>(&body) {
+                Ok(body) => context.http_set_orientation(
+                    mac_address!(mac_address),
+                    body.yaw,
+                    body.pitch,
+                    body.roll,
+                ),
+                Err(err) => {
+                    let reason = format!("Error while deserializing orientation: {}", err);
+                    log::error!("{}", reason);
+                    return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+                }
+            }
+        }
         ["create-anchor", mac_address] => {
             context
                 .http_create_anchor(mac_address!(mac_address), position!(body), cmd_tx)
@@ -465,7 +1066,113 @@ async fn http_request(
                 .http_destroy_anchor(mac_address!(mac_address), cmd_tx)
                 .await
         }
-        ["get-state"] => context.http_get_state(),
+        ["positions"] => match serde_json::from_slice::<Vec<DevicePositionBody>>(&body) {
+            Ok(positions) => context.http_set_positions(
+                positions
+                    .into_iter()
+                    .map(|body| {
+                        let p = body.position;
+                        (
+                            body.mac_address,
+                            Position::new(p.x, p.y, p.z, p.yaw, p.pitch, p.roll),
+                        )
+                    })
+                    .collect(),
+            ),
+            Err(err) => {
+                let reason = format!("Error while deserializing positions: {}", err);
+                log::error!("{}", reason);
+                return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+            }
+        },
+        ["set-obstacles"] => match serde_json::from_slice::<Vec<Obstacle>>(&body) {
+            Ok(obstacles) => context.http_set_obstacles(obstacles),
+            Err(err) => {
+                let reason = format!("Error while deserializing obstacles: {}", err);
+                log::error!("{}", reason);
+                return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+            }
+        },
+        ["set-temperature", mac_address] => {
+            match serde_json::from_slice::<TemperatureBody>(&body) {
+                Ok(body) => {
+                    context
+                        .http_set_temperature(mac_address!(mac_address), body.temperature, cmd_tx)
+                        .await
+                }
+                Err(err) => {
+                    let reason = format!("Error while deserializing temperature: {}", err);
+                    log::error!("{}", reason);
+                    return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+                }
+            }
+        }
+        ["set-country-code", mac_address] => {
+            match serde_json::from_slice::<CountryCodeBody>(&body) {
+                Ok(body) => {
+                    context
+                        .http_set_country_code(
+                            mac_address!(mac_address),
+                            &body.country_code,
+                            cmd_tx,
+                        )
+                        .await
+                }
+                Err(err) => {
+                    let reason = format!("Error while deserializing country code: {}", err);
+                    log::error!("{}", reason);
+                    return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+                }
+            }
+        }
+        ["get-state"] => context.http_get_state(cmd_tx).await,
+        ["devices", handle, "reset"] => match handle.parse::<pica::Handle>() {
+            Ok(handle) => context.http_reset_device(handle, cmd_tx).await,
+            Err(err) => {
+                let reason = format!("Error handle: {}", err);
+                log::error!("{}", reason);
+                return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+            }
+        },
+        ["devices", handle, "device-roles"] => {
+            let handle = match handle.parse::<pica::Handle>() {
+                Ok(handle) => handle,
+                Err(err) => {
+                    let reason = format!("Error handle: {}", err);
+                    log::error!("{}", reason);
+                    return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+                }
+            };
+            match serde_json::from_slice::<DeviceRolesBody>(&body) {
+                Ok(body) => {
+                    context
+                        .http_set_device_roles(handle, body.roles, cmd_tx)
+                        .await
+                }
+                Err(err) => {
+                    let reason = format!("Error while deserializing device roles: {}", err);
+                    log::error!("{}", reason);
+                    return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+                }
+            }
+        }
+        ["devices", handle, "state"] => match handle.parse::<pica::Handle>() {
+            Ok(handle) => context.http_get_device_state(handle, cmd_tx).await,
+            Err(err) => {
+                let reason = format!("Error handle: {}", err);
+                log::error!("{}", reason);
+                return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+            }
+        },
+        ["debug", "timers"] => context.http_get_timers(cmd_tx).await,
+        ["sessions", session_id, "truth"] => match session_id.parse::<u32>() {
+            Ok(session_id) => context.http_get_ground_truth(session_id, cmd_tx).await,
+            Err(err) => {
+                let reason = format!("Error session_id: {}", err);
+                log::error!("{}", reason);
+                return Ok(Response::builder().status(406).body(reason.into()).unwrap());
+            }
+        },
 
         _ => Response::builder()
             .status(HttpStatusCode::NOT_FOUND)
@@ -497,17 +1204,43 @@ async fn serve(context: Context, tx: mpsc::Sender<PicaCommand>, web_port: u16) -
     Ok(())
 }
 
-async fn listen(tx: mpsc::Sender<PicaCommand>, uci_port: u16) -> Result<()> {
-    let uci_socket = SocketAddrV4::new(Ipv4Addr::LOCALHOST, uci_port);
-    let uci_listener = TcpListener::bind(uci_socket).await?;
-    log::info!("Pica: Listening on: {}", uci_port);
+async fn listen(
+    tx: mpsc::Sender<PicaCommand>,
+    uci_addr: SocketAddr,
+    framing: pica::packets::uci::Framing,
+    max_connections: Option<usize>,
+    max_packet_size: Option<usize>,
+) -> Result<()> {
+    let uci_listener = TcpListener::bind(uci_addr).await?;
+    log::info!("Pica: Listening on: {}", uci_listener.local_addr()?);
+
+    // One permit is held by the packet stream of each open connection,
+    // and released when the stream is dropped.
+    let connections = Arc::new(Semaphore::new(
+        max_connections.unwrap_or(Semaphore::MAX_PERMITS),
+    ));
 
     loop {
         let (socket, addr) = uci_listener.accept().await?;
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            log::warn!("Uwb host addr: {}: connection limit reached, closing", addr);
+            continue;
+        };
         log::info!("Uwb host addr: {}", addr);
 
         let (read_half, write_half) = socket.into_split();
-        let stream = Box::pin(futures::stream::unfold(read_half, pica::packets::uci::read));
+        let stream = Box::pin(futures::stream::unfold(
+            (read_half, permit),
+            move |(read_half, permit)| async move {
+                let (packet, read_half) = pica::packets::uci::read_limited(
+                    read_half,
+                    framing,
+                    max_packet_size.unwrap_or(usize::MAX),
+                )
+                .await?;
+                Some((packet, (read_half, permit)))
+            },
+        ));
         let sink = Box::pin(futures::sink::unfold(write_half, pica::packets::uci::write));
 
         tx.send(PicaCommand::Connect(stream, sink))
@@ -516,46 +1249,1065 @@ async fn listen(tx: mpsc::Sender<PicaCommand>, uci_port: u16) -> Result<()> {
     }
 }
 
+/// Load and validate the scene file, and print a report.
+/// Returns an error describing the invalid content if any.
+fn check_scene(path: &Path) -> Result<()> {
+    let scene = Scene::load(path)
+        .map_err(|err| anyhow::anyhow!("{}: invalid scene file: {}", path.display(), err))?;
+    let errors = scene.validate();
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "{}: {} error(s) found\n{}",
+            path.display(),
+            errors.len(),
+            errors
+                .iter()
+                .map(|error| format!("  - {}", error))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    println!(
+        "{}: ok, {} anchor(s), {} obstacle(s)",
+        path.display(),
+        scene.anchors.len(),
+        scene.obstacles.len()
+    );
+    Ok(())
+}
+
+/// Load the scene file on startup, and reload it every time
+/// the process receives the SIGHUP signal.
+async fn watch_scene(
+    context: Context,
+    cmd_tx: mpsc::Sender<PicaCommand>,
+    scene: Option<PathBuf>,
+) -> Result<()> {
+    let Some(scene) = scene else {
+        return Ok(());
+    };
+
+    context.reload_scene(&scene, cmd_tx.clone()).await;
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = signal(SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            context.reload_scene(&scene, cmd_tx.clone()).await;
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "pica", about = "Virtual UWB subsystem")]
 struct Args {
-    /// Output directory for storing .pcapng traces.
-    /// If provided, .pcapng traces of client connections are automatically
-    /// saved under the name `device-{handle}.pcapng`.
-    #[arg(short, long, value_name = "DIR")]
-    pcapng_dir: Option<PathBuf>,
+    /// Output directory for storing the traces of the client connections.
+    /// If provided, the traces are automatically saved under the name
+    /// `device-{handle}.pcapng`, or `device-{handle}.bin` with the raw
+    /// trace format. `--pcapng-dir` is accepted as an alias.
+    #[arg(short = 'p', long, alias = "pcapng-dir", value_name = "DIR")]
+    trace_dir: Option<PathBuf>,
+    /// Output directory for exporting the ranging results as CSV.
+    /// If provided, the results reported to the hosts are saved under
+    /// the name `device-{handle}-session-{session_token}.csv`.
+    #[arg(long, value_name = "DIR")]
+    csv_dir: Option<PathBuf>,
+    /// Maximum size in bytes of the .pcapng traces. When exceeded, the
+    /// traces are continued in `device-{handle}.{n}.pcapng`.
+    #[arg(long, value_name = "BYTES")]
+    pcapng_max_size: Option<u64>,
+    /// pcapng file recording the traffic of all devices, in addition to
+    /// the per-device traces. Each device connection is recorded as
+    /// a separate interface named `device-{handle}`.
+    #[arg(long, value_name = "FILE")]
+    pcapng_combined: Option<PathBuf>,
+    /// Format of the traces recorded to the output directory.
+    #[arg(long, value_enum, default_value_t = TraceFormat::Pcapng)]
+    trace_format: TraceFormat,
+    /// Layout of the UCI packet headers exchanged with the hosts.
+    #[arg(long, value_enum, default_value_t = UciFraming::V2)]
+    uci_framing: UciFraming,
     /// Configure the TCP port for the UCI server.
     #[arg(short, long, value_name = "PORT", default_value_t = DEFAULT_UCI_PORT)]
     uci_port: u16,
+    /// Configure the address of the UCI server, e.g. `0.0.0.0:7000`
+    /// or `[::1]:7000`. Binding to `[::]` accepts both IPv4 and IPv6
+    /// hosts on dual-stack systems. Overrides `--uci-port`, the server
+    /// listens on the IPv4 loopback address by default.
+    #[arg(long, value_name = "ADDR")]
+    uci_bind: Option<SocketAddr>,
+    /// Pre-shared token required from the UCI hosts. The token is sent
+    /// in the Pica Authenticate command as the first packet of the
+    /// connection; other connections are closed without creating
+    /// a device. Hosts are not authenticated by default.
+    /// The token is visible to the other users of the system in the
+    /// process arguments, prefer `--auth-token-file` on shared systems.
+    #[arg(long, value_name = "TOKEN", conflicts_with = "auth_token_file")]
+    auth_token: Option<String>,
+    /// File containing the pre-shared token required from the UCI hosts.
+    /// The trailing newline of the file is not part of the token.
+    #[arg(long, value_name = "PATH")]
+    auth_token_file: Option<PathBuf>,
+    /// Maximum number of UCI connections simultaneously open, including
+    /// the connections not yet authenticated. Connections exceeding the
+    /// limit are closed immediately. Unlimited by default.
+    #[arg(long, value_name = "COUNT")]
+    max_connections: Option<usize>,
+    /// Maximum payload length in bytes of the UCI packets received from
+    /// the hosts. The payload of larger packets is discarded without being
+    /// buffered, and the packets are rejected with the status
+    /// INVALID_MESSAGE_SIZE. Unlimited by default.
+    #[arg(long, value_name = "BYTES")]
+    max_packet_size: Option<usize>,
     /// Configure the HTTP port for the web interface.
     #[arg(short, long, value_name = "PORT", default_value_t = DEFAULT_WEB_PORT)]
     web_port: u16,
+    /// Configure the TCP port for the JSON control channel.
+    /// The control channel is disabled by default.
+    #[arg(long, value_name = "PORT")]
+    control_port: Option<u16>,
+    /// Scene file describing the anchors and obstacles.
+    /// The scene file is reloaded when receiving the SIGHUP signal.
+    #[arg(long, value_name = "FILE")]
+    scene: Option<PathBuf>,
+    /// Scenario script executing control commands at scheduled times,
+    /// relative to the start of the server. Invalid scripts abort
+    /// the startup.
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+    /// Validate the scene file and exit, without starting the server.
+    /// The exit status is non-zero if the scene file is invalid.
+    #[arg(long, value_name = "FILE")]
+    check_scene: Option<PathBuf>,
+    /// Delay in milliseconds between the response to the Core Device Reset
+    /// command and the notification of the ready state.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    reset_delay: u64,
+    /// Delay in milliseconds between the connection of the host and the
+    /// notification of the initial state. Commands received in the
+    /// meantime are rejected with the status UCI_MESSAGE_RETRY.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    boot_delay: u64,
+    /// Chip identifiers reported in the Pica Get Chip Info response,
+    /// in the format `oui:part_id:chip_version`. The fields are hexadecimal
+    /// integers on 24, 16 and 16 bits respectively.
+    #[arg(long, value_name = "OUI:PART:VERSION", default_value = "0:0:0")]
+    chip_info: pica::ChipInfo,
+    /// Also report the chip identifiers in the vendor specific information
+    /// of the Core Get Device Info response.
+    #[arg(long)]
+    chip_info_in_device_info: bool,
+    /// State of the devices on initialization and after reset.
+    #[arg(long, value_enum, default_value_t = DeviceState::Ready)]
+    initial_device_state: DeviceState,
+    /// Receive antenna configuration of the devices, selecting the
+    /// angles of arrival reported in the ranging notifications.
+    #[arg(long, value_enum, default_value_t = AntennaConfig::AzimuthElevation)]
+    antenna_config: AntennaConfig,
+    /// Number of receive antenna pairs of the devices. With more than
+    /// one pair, the azimuth measured by each pair is reported in the
+    /// vendor data of the ranging notifications.
+    #[arg(long, value_name = "COUNT", default_value_t = 1)]
+    antenna_pairs: u8,
+    /// Maximum size in bytes of the payload of a reassembled
+    /// control packet.
+    #[arg(long, value_name = "BYTES", default_value_t = pica::DEFAULT_MAX_REASSEMBLY_SIZE)]
+    max_reassembly_size: usize,
+    /// Maximum number of TLVs carried by each control packet of the
+    /// Core Get Caps Info, Core Get Config, and Session Get App Config
+    /// responses. Responses are only segmented on the MTU by default.
+    #[arg(long, value_name = "COUNT")]
+    max_tlvs_per_packet: Option<usize>,
+    /// Close the connections with no inbound traffic for the selected
+    /// duration in milliseconds. Connections are never closed by default.
+    #[arg(long, value_name = "MS")]
+    idle_timeout: Option<u64>,
+    /// Maximum rate of inbound commands per device, in commands per second.
+    /// Commands exceeding the rate are rejected with the status
+    /// UCI_MESSAGE_RETRY. The rate is unlimited by default, or when
+    /// set to zero.
+    #[arg(long, value_name = "RATE")]
+    command_rate_limit: Option<u32>,
+    /// Window in milliseconds after each response during which the
+    /// devices reject new commands with the status UCI_MESSAGE_RETRY,
+    /// except the device reset. Commands are accepted at any time
+    /// by default.
+    #[arg(long, value_name = "MS")]
+    busy_window: Option<u64>,
+    /// Processing latency applied before responding to the commands with
+    /// the selected group and opcode identifiers, in the format
+    /// `gid:oid:ms`. The latency is measured on the pica clock.
+    /// Can be repeated. Responses are not delayed by default.
+    #[arg(long, value_name = "GID:OID:MS")]
+    response_latency: Vec<ResponseLatency>,
+    /// Maximum variation of the ranging interval, as a fraction of the
+    /// interval between 0 and 0.9. The interval is fixed by default.
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.)]
+    ranging_jitter: f32,
+    /// Seed of the generator drawing the ranging interval variations.
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    ranging_jitter_seed: u64,
+    /// Reply time in microseconds of the two-way ranging responders.
+    /// The timing fields derived from the reply time are reported in the
+    /// ranging notifications. The timing fields are omitted by default.
+    #[arg(long, value_name = "US")]
+    twr_reply_time: Option<u64>,
+    /// Maximum number of sessions simultaneously active on each device.
+    /// Session Start commands exceeding the limit are rejected with the
+    /// status ERROR_MAX_SESSIONS_EXCEEDED. Unlimited by default.
+    #[arg(long, value_name = "COUNT")]
+    max_active_sessions: Option<usize>,
+    /// Emit a Core Generic Error notification for the protocol errors
+    /// detected by the devices, in addition to the command responses.
+    #[arg(long)]
+    generic_error_notifications: bool,
+    /// Let the ranging offset push the reported distances below zero,
+    /// encoded in two's complement. Test-only: the distances saturate
+    /// at zero by default.
+    #[arg(long)]
+    allow_negative_distance: bool,
+    /// Report of the measurements between devices sharing the same
+    /// position, for which the angles of arrival are undefined.
+    #[arg(long, value_enum, default_value_t = ColocatedReport::AoaFailed)]
+    colocated_report: ColocatedReport,
+    /// Exclude the UCI devices from ranging until their position is set
+    /// through the web interface or the control channel. By default
+    /// the devices are placed at the origin when connected.
+    #[arg(long)]
+    require_positions: bool,
+    /// Include the configuration of the UCI devices and of their
+    /// sessions in the state snapshots, decoded into named parameters.
+    /// The state dumped on SIGUSR1 does not query the devices and
+    /// never includes the configuration.
+    #[arg(long)]
+    state_config: bool,
+    /// Format of the log records.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+/// Dump the state to a file in the selected directory when receiving
+/// the signal SIGUSR1. The state is read from the context, and not
+/// from the pica event loop which may be stuck.
+#[cfg(unix)]
+async fn dump_state_on_signal(context: Context, dir: PathBuf) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signal = signal(SignalKind::user_defined1())?;
+    while signal.recv().await.is_some() {
+        match context.dump_state(&dir) {
+            Ok(path) => log::info!("State dumped to {}", path.display()),
+            Err(err) => log::error!("Failed to dump state: {}", err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn dump_state_on_signal(_context: Context, _dir: PathBuf) -> Result<()> {
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
-    log::info!("http-server main");
     let args = Args::parse();
+    pica::cli::init_logger(args.log_format);
+    log::info!("http-server main");
+
+    if let Some(path) = args.check_scene {
+        return check_scene(&path);
+    }
     
+    let script = args.script.as_deref().map(Script::load).transpose()?;
+
+    let uci_addr = args
+        .uci_bind
+        .unwrap_or(SocketAddr::from((Ipv4Addr::LOCALHOST, args.uci_port)));
     assert_ne!(
-        args.uci_port, args.web_port,
+        uci_addr.port(),
+        args.web_port,
         "UCI port and WEB port must be different."
     );
 
-    let context = Context::new();
+    let mut context = Context::new();
+    context.require_positions = args.require_positions;
+    context.state_config = args.state_config;
+    let dump_dir = match &args.trace_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir()?,
+    };
 
-    let pica = Pica::new(Box::new(context.clone()), args.pcapng_dir);
+    let mut builder = Pica::builder()
+        .ranging_estimator(Box::new(context.clone()))
+        .trace_format(args.trace_format.into())
+        .framing(args.uci_framing.into())
+        .reset_delay(Duration::from_millis(args.reset_delay))
+        .boot_delay(Duration::from_millis(args.boot_delay))
+        .initial_device_state(args.initial_device_state.into())
+        .chip_info(args.chip_info, args.chip_info_in_device_info)
+        .antenna_config(args.antenna_config.into())
+        .antenna_pairs(args.antenna_pairs)
+        .max_reassembly_size(args.max_reassembly_size)
+        .response_latencies(pica::cli::response_latencies(&args.response_latency))
+        .ranging_jitter(args.ranging_jitter, args.ranging_jitter_seed)?
+        .generic_error_notifications(args.generic_error_notifications)
+        .allow_negative_distance(args.allow_negative_distance)
+        .colocated_report(args.colocated_report.into());
+    if let Some(trace_dir) = args.trace_dir {
+        builder = builder.pcapng_dir(trace_dir);
+    }
+    if let Some(pcapng_max_size) = args.pcapng_max_size {
+        builder = builder.pcapng_max_size(pcapng_max_size);
+    }
+    if let Some(path) = args.pcapng_combined {
+        builder = builder.pcapng_combined(path)?;
+    }
+    if let Some(max_tlvs_per_packet) = args.max_tlvs_per_packet {
+        builder = builder.max_tlvs_per_packet(max_tlvs_per_packet);
+    }
+    if let Some(idle_timeout) = args.idle_timeout {
+        builder = builder.idle_timeout(Duration::from_millis(idle_timeout));
+    }
+    if let Some(command_rate_limit) = args.command_rate_limit {
+        builder = builder.command_rate_limit(command_rate_limit);
+    }
+    if let Some(busy_window) = args.busy_window {
+        builder = builder.busy_window(Duration::from_millis(busy_window));
+    }
+    if let Some(twr_reply_time) = args.twr_reply_time {
+        builder = builder.twr_reply_time(Duration::from_micros(twr_reply_time));
+    }
+    if let Some(max_active_sessions) = args.max_active_sessions {
+        builder = builder.max_active_sessions(max_active_sessions);
+    }
+    if let Some(auth_token) =
+        pica::cli::auth_token(args.auth_token, args.auth_token_file.as_deref())?
+    {
+        builder = builder.auth_token(auth_token)?;
+    }
+    if let Some(csv_dir) = args.csv_dir {
+        builder = builder.csv_dir(csv_dir);
+    }
+    let pica = builder.build();
     let cmd_tx = pica.commands();
     let events_rx = pica.events();
+    let clock = pica.clock();
 
     try_join!(
         pica.run(),
-        listen(cmd_tx.clone(), args.uci_port),
+        listen(
+            cmd_tx.clone(),
+            uci_addr,
+            args.uci_framing.into(),
+            args.max_connections,
+            args.max_packet_size,
+        ),
         serve(context.clone(), cmd_tx.clone(), args.web_port),
+        watch_scene(context.clone(), cmd_tx.clone(), args.scene),
+        control::listen(context.clone(), cmd_tx.clone(), args.control_port),
+        script::run(context.clone(), cmd_tx.clone(), clock, script),
+        dump_state_on_signal(context.clone(), dump_dir),
         context.handle_connection_events(events_rx),
     )?;
     log::info!("http-server main after try_join!");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pica::RangingEstimator;
+
+    fn add_device(context: &Context, handle: pica::Handle, position: Position) {
+        context.devices.lock().unwrap().insert(
+            handle,
+            DeviceInformation {
+                category: Category::Anchor,
+                mac_address: MacAddress::Short([0, handle as u8]),
+                position,
+                positioned: true,
+            },
+        );
+    }
+
+    #[test]
+    fn estimate_with_obstacle() {
+        let context = Context::new();
+        add_device(&context, 0, Position::new(-100, 0, 0, 0, 0, 0));
+        add_device(&context, 1, Position::new(100, 0, 0, 0, 0, 0));
+
+        context.http_set_obstacles(vec![Obstacle {
+            x1: 0,
+            z1: -100,
+            x2: 0,
+            z2: 100,
+        }]);
+        assert!(context.estimate(&0, &1).is_none());
+        assert!(context.estimate(&1, &0).is_none());
+
+        context.http_set_obstacles(vec![]);
+        let measurement = context.estimate(&0, &1).unwrap();
+        assert_eq!(measurement.range, 200);
+    }
+
+    /// Compare the cost of the ranging rounds of 16 devices with the
+    /// estimations recomputed and cached. Run with
+    /// `cargo test --bin pica-http ranging_cache_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn ranging_cache_benchmark() {
+        const DEVICES: usize = 16;
+        const ROUNDS: u32 = 1000;
+
+        let context = Context::new();
+        for handle in 0..DEVICES {
+            add_device(
+                &context,
+                handle,
+                Position::new(100 * handle as i16, 0, 50 * (handle % 4) as i16, 0, 0, 0),
+            );
+        }
+        // Obstacles out of the way of the devices, checked for
+        // each estimation.
+        context.set_obstacles(
+            (0..8)
+                .map(|index| Obstacle {
+                    x1: 200 * index,
+                    z1: 1000,
+                    x2: 200 * index + 100,
+                    z2: 1100,
+                })
+                .collect(),
+        );
+
+        let measure = |cached: bool| {
+            let start = std::time::Instant::now();
+            for _ in 0..ROUNDS {
+                if !cached {
+                    context.ranging_cache.lock().unwrap().clear();
+                }
+                for left in 0..DEVICES {
+                    for right in (0..DEVICES).filter(|right| *right != left) {
+                        std::hint::black_box(context.estimate(&left, &right));
+                    }
+                }
+            }
+            start.elapsed() / ROUNDS
+        };
+        let uncached = measure(false);
+        let cached = measure(true);
+        println!(
+            "{} devices: {:?} per round recomputed, {:?} per round cached",
+            DEVICES, uncached, cached
+        );
+    }
+
+    #[test]
+    fn ranging_cache_invalidation() {
+        let context = Context::new();
+        add_device(&context, 0, Position::new(0, 0, 0, 0, 0, 0));
+        add_device(&context, 1, Position::new(100, 0, 0, 0, 0, 0));
+        add_device(&context, 2, Position::new(0, 0, 100, 0, 0, 0));
+
+        assert_eq!(context.estimate(&0, &1).unwrap().range, 100);
+        assert_eq!(context.estimate(&1, &2).unwrap().range, 141);
+        assert_eq!(context.ranging_cache.lock().unwrap().len(), 2);
+
+        // Moving a device only invalidates the estimations it is part of.
+        context.http_set_position(
+            MacAddress::Short([0, 0]),
+            Position::new(-100, 0, 0, 0, 0, 0),
+        );
+        {
+            let cache = context.ranging_cache.lock().unwrap();
+            assert!(!cache.contains_key(&(0, 1)));
+            assert!(cache.contains_key(&(1, 2)));
+        }
+        assert_eq!(context.estimate(&0, &1).unwrap().range, 200);
+
+        // Removed devices are dropped from the cache.
+        context.devices.lock().unwrap().remove(&2);
+        context.invalidate_ranging_cache(&[2]);
+        assert!(context.estimate(&1, &2).is_none());
+        assert!(!context.ranging_cache.lock().unwrap().contains_key(&(1, 2)));
+    }
+
+    #[test]
+    fn set_positions() {
+        let context = Context::new();
+        for handle in 0..12 {
+            add_device(&context, handle, Position::new(0, 0, 0, 0, 0, 0));
+        }
+        assert_eq!(context.estimate(&0, &1).unwrap().range, 0);
+        assert_eq!(context.estimate(&10, &11).unwrap().range, 0);
+
+        // Unknown devices are reported, the other positions are applied.
+        let mut positions = (0..10)
+            .map(|handle| {
+                (
+                    MacAddress::Short([0, handle as u8]),
+                    Position::new(100 * handle as i16, 0, 0, 0, 0, 0),
+                )
+            })
+            .collect::<Vec<_>>();
+        positions.insert(5, (MacAddress::Short([1, 0]), Position::default()));
+        assert_eq!(
+            context.set_positions(positions),
+            vec![MacAddress::Short([1, 0])]
+        );
+
+        let devices = context.devices.lock().unwrap();
+        for handle in 0..10 {
+            assert_eq!(
+                devices[&handle].position,
+                Position::new(100 * handle as i16, 0, 0, 0, 0, 0)
+            );
+        }
+        drop(devices);
+
+        // Only the estimations involving the moved devices are dropped.
+        {
+            let cache = context.ranging_cache.lock().unwrap();
+            assert!(!cache.contains_key(&(0, 1)));
+            assert!(cache.contains_key(&(10, 11)));
+        }
+        assert_eq!(context.estimate(&0, &9).unwrap().range, 900);
+
+        // Positions forwarded by the pica command loop are identified
+        // by device handle.
+        RangingEstimator::set_positions(&context, &[(0, Position::new(-100, 0, 0, 0, 0, 0))]);
+        assert_eq!(context.estimate(&0, &9).unwrap().range, 1000);
+    }
+
+    #[test]
+    fn set_orientation() {
+        let context = Context::new();
+        add_device(&context, 0, Position::new(0, 0, 0, 0, 0, 0));
+        add_device(&context, 1, Position::new(100, 0, 100, 0, 0, 0));
+        add_device(&context, 2, Position::new(100, 0, -100, 0, 0, 0));
+
+        assert_eq!(context.estimate(&0, &1).unwrap().azimuth, 45);
+        assert_eq!(context.estimate(&0, &2).unwrap().azimuth, 135);
+
+        // Rotating the device shifts the azimuth without moving the device,
+        // the azimuth wraps around at 180 degrees.
+        context.http_set_orientation(MacAddress::Short([0, 0]), 90, 0, 0);
+        let measurement = context.estimate(&0, &1).unwrap();
+        assert_eq!(measurement.range, 141);
+        assert_eq!(measurement.azimuth, 135);
+        assert_eq!(context.estimate(&0, &2).unwrap().azimuth, -135);
+    }
+
+    #[tokio::test]
+    async fn require_positions() {
+        use futures::SinkExt;
+        use pdl_runtime::Packet;
+        use pica::packets::uci::*;
+
+        let mut context = Context::new();
+        context.require_positions = true;
+        let mut pica = Pica::new(Box::new(context.clone()), None);
+        let cmd_tx = pica.commands();
+        // Device events are broadcast to the web clients.
+        let _events = context.events.subscribe();
+        tokio::spawn(context.clone().handle_connection_events(pica.events()));
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        context
+            .http_create_anchor(
+                MacAddress::Short([0, 2]),
+                Position::new(100, 0, 0, 0, 0, 0),
+                cmd_tx,
+            )
+            .await;
+
+        let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+            cfg_id,
+            v: v.to_vec(),
+        };
+        let commands = vec![
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionInitCmd {
+                session_id: 1,
+                session_type: SessionType::FiraRangingSession,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionSetAppConfigCmd {
+                session_token: 1,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                ],
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap(),
+        ];
+        for command in commands {
+            sink.send(command).await.unwrap();
+        }
+
+        async fn next_measurements(
+            stream: &mut pica::UciStream,
+        ) -> Vec<ShortAddressTwoWayRangingMeasurement> {
+            loop {
+                let packet = stream.next().await.unwrap();
+                if let Ok(ntf) = ShortMacTwoWaySessionInfoNtf::decode_full(&packet) {
+                    return ntf.two_way_ranging_measurements;
+                }
+            }
+        }
+
+        // The position of the UCI device is not set.
+        assert!(next_measurements(&mut stream).await.is_empty());
+
+        context.http_set_position(MacAddress::Short([0, 0]), Position::default());
+        let measurements = next_measurements(&mut stream).await;
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].distance, 100);
+    }
+
+    #[tokio::test]
+    async fn reset_device() {
+        use futures::SinkExt;
+        use pdl_runtime::Packet;
+        use pica::packets::uci::*;
+
+        let context = Context::new();
+        let mut pica = Pica::new(Box::new(context.clone()), None);
+        let cmd_tx = pica.commands();
+        let mut events = pica.events();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+
+        let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+            cfg_id,
+            v: v.to_vec(),
+        };
+        let commands = vec![
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionInitCmd {
+                session_id: 1,
+                session_type: SessionType::FiraRangingSession,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionSetAppConfigCmd {
+                session_token: 1,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                ],
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap(),
+        ];
+        for command in commands {
+            sink.send(command).await.unwrap();
+        }
+        loop {
+            let packet = stream.next().await.unwrap();
+            if let Ok(rsp) = SessionStartRsp::decode_full(&packet) {
+                assert_eq!(rsp.status, Status::Ok);
+                break;
+            }
+        }
+
+        async fn device_state(response: Response<Body>) -> serde_json::Value {
+            assert_eq!(response.status(), HttpStatusCode::OK);
+            let body = body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["state"].clone()
+        }
+
+        let response = context.http_get_device_state(handle, cmd_tx.clone()).await;
+        assert_eq!(device_state(response).await, "active");
+        let response = context.http_reset_device(handle, cmd_tx.clone()).await;
+        assert_eq!(device_state(response).await, "ready");
+        let response = context.http_get_device_state(handle, cmd_tx.clone()).await;
+        assert_eq!(device_state(response).await, "ready");
+
+        // Unknown devices are not found.
+        let response = context.http_reset_device(handle + 1, cmd_tx).await;
+        assert_eq!(response.status(), HttpStatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn set_country_code() {
+        let context = Context::new();
+        let mut pica = Pica::new(Box::new(context.clone()), None);
+        let cmd_tx = pica.commands();
+        let (_sink, _stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        // Lowercase codes are normalized to uppercase.
+        let mac_address = MacAddress::Short([0, 0]);
+        let response = context
+            .http_set_country_code(mac_address, "us", cmd_tx.clone())
+            .await;
+        assert_eq!(response.status(), HttpStatusCode::OK);
+
+        // Codes that are not two ASCII letters are rejected.
+        for country_code in ["U1", "USA", "É"] {
+            let response = context
+                .http_set_country_code(mac_address, country_code, cmd_tx.clone())
+                .await;
+            assert_eq!(response.status(), HttpStatusCode::BAD_REQUEST);
+        }
+
+        let response = context
+            .http_set_country_code(MacAddress::Short([0, 3]), "US", cmd_tx)
+            .await;
+        assert_eq!(response.status(), HttpStatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn get_timers() {
+        use futures::SinkExt;
+        use pdl_runtime::Packet;
+        use pica::packets::uci::*;
+
+        let context = Context::new();
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(context.clone()))
+            .idle_timeout(Duration::from_secs(10))
+            .build();
+        let cmd_tx = pica.commands();
+        let mut events = pica.events();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+
+        let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+            cfg_id,
+            v: v.to_vec(),
+        };
+        let commands = vec![
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionInitCmd {
+                session_id: 1,
+                session_type: SessionType::FiraRangingSession,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionSetAppConfigCmd {
+                session_token: 1,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                ],
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap(),
+        ];
+        for command in commands {
+            sink.send(command).await.unwrap();
+        }
+        loop {
+            let packet = stream.next().await.unwrap();
+            if let Ok(rsp) = SessionStartRsp::decode_full(&packet) {
+                assert_eq!(rsp.status, Status::Ok);
+                break;
+            }
+        }
+
+        let response = context.http_get_timers(cmd_tx).await;
+        assert_eq!(response.status(), HttpStatusCode::OK);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let mut timers = serde_json::from_slice::<Vec<serde_json::Value>>(&body).unwrap();
+        timers.sort_by_key(|timer| timer["kind"].as_str().unwrap().to_owned());
+        assert_eq!(timers.len(), 2);
+        assert_eq!(timers[0]["kind"], "idle-timeout");
+        assert_eq!(timers[0]["handle"], handle);
+        assert_eq!(timers[0]["session_id"], serde_json::Value::Null);
+        assert_eq!(timers[1]["kind"], "ranging");
+        assert_eq!(timers[1]["handle"], handle);
+        assert_eq!(timers[1]["session_id"], 1);
+    }
+
+    #[tokio::test]
+    async fn set_device_roles() {
+        use futures::SinkExt;
+        use pdl_runtime::Packet;
+        use pica::packets::uci::*;
+
+        let context = Context::new();
+        let mut pica = Pica::new(Box::new(context.clone()), None);
+        let cmd_tx = pica.commands();
+        let mut events = pica.events();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+
+        let response = context
+            .http_set_device_roles(handle, vec![super::DeviceRole::Responder], cmd_tx.clone())
+            .await;
+        assert_eq!(response.status(), HttpStatusCode::OK);
+
+        for command in [
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            CoreGetCapsInfoCmd {}.encode_to_vec().unwrap(),
+        ] {
+            sink.send(command).await.unwrap();
+        }
+        let rsp = loop {
+            let packet = stream.next().await.unwrap();
+            if let Ok(rsp) = CoreGetCapsInfoRsp::decode_full(&packet) {
+                break rsp;
+            }
+        };
+        let device_roles = rsp
+            .tlvs
+            .iter()
+            .find(|tlv| tlv.t == CapTlvType::SupportedDeviceRoles)
+            .unwrap();
+        assert_eq!(device_roles.v, vec![0x02]);
+
+        // Unknown devices are not found.
+        let response = context
+            .http_set_device_roles(handle + 1, vec![super::DeviceRole::Initiator], cmd_tx)
+            .await;
+        assert_eq!(response.status(), HttpStatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn state_config() {
+        use futures::SinkExt;
+        use pdl_runtime::Packet;
+        use pica::packets::uci::*;
+
+        let mut context = Context::new();
+        context.state_config = true;
+        let mut pica = Pica::new(Box::new(context.clone()), None);
+        let cmd_tx = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let commands = vec![
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::RangingOffset,
+                    value: vec![0xf6, 0xff],
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionInitCmd {
+                session_id: 1,
+                session_type: SessionType::FiraRangingSession,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionSetAppConfigCmd {
+                session_token: 1,
+                tlvs: vec![
+                    AppConfigTlv {
+                        cfg_id: AppConfigTlvType::DeviceRole,
+                        v: vec![0x01],
+                    },
+                    AppConfigTlv {
+                        cfg_id: AppConfigTlvType::RangingDuration,
+                        v: vec![0x2c, 0x01, 0x00, 0x00],
+                    },
+                ],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        ];
+        for command in commands {
+            sink.send(command).await.unwrap();
+        }
+        loop {
+            let packet = stream.next().await.unwrap();
+            if SessionSetAppConfigRsp::decode_full(&packet).is_ok() {
+                break;
+            }
+        }
+
+        let state = serde_json::to_value(context.get_state_with_config(&cmd_tx).await).unwrap();
+        let config = &state["config"][0];
+        assert_eq!(config["mac_address"], "00:00");
+        assert_eq!(config["config"]["RangingOffset"], -10);
+        assert_eq!(config["sessions"][0]["session_id"], 1);
+        assert_eq!(
+            config["sessions"][0]["app_config"]["DeviceRole"],
+            "Initiator"
+        );
+        assert_eq!(config["sessions"][0]["app_config"]["RangingDuration"], 300);
+
+        // The configuration is omitted unless enabled.
+        context.state_config = false;
+        let state = serde_json::to_value(context.get_state_with_config(&cmd_tx).await).unwrap();
+        assert!(state.get("config").is_none());
+    }
+
+    #[tokio::test]
+    async fn reload_scene() {
+        let context = Context::new();
+        let pica = Pica::new(Box::new(context.clone()), None);
+        let cmd_tx = pica.commands();
+        tokio::spawn(pica.run());
+
+        let path = std::env::temp_dir().join(format!("pica-scene-{}.json", std::process::id()));
+        let mac_address = MacAddress::Short([0, 1]);
+        let anchor_position = |context: &Context| {
+            context
+                .devices
+                .lock()
+                .unwrap()
+                .values()
+                .find(|device| device.mac_address == mac_address)
+                .map(|device| device.position)
+        };
+
+        std::fs::write(
+            &path,
+            r#"{ "anchors": [{ "mac_address": "00:01", "x": 100 }] }"#,
+        )
+        .unwrap();
+        context.reload_scene(&path, cmd_tx.clone()).await;
+        assert_eq!(
+            anchor_position(&context),
+            Some(Position::new(100, 0, 0, 0, 0, 0))
+        );
+
+        std::fs::write(
+            &path,
+            r#"{ "anchors": [{ "mac_address": "00:01", "x": 200 }] }"#,
+        )
+        .unwrap();
+        context.reload_scene(&path, cmd_tx.clone()).await;
+        assert_eq!(
+            anchor_position(&context),
+            Some(Position::new(200, 0, 0, 0, 0, 0))
+        );
+
+        // Parse errors leave the scene intact.
+        std::fs::write(&path, r#"{ "anchors": [{ "#).unwrap();
+        context.reload_scene(&path, cmd_tx.clone()).await;
+        assert_eq!(
+            anchor_position(&context),
+            Some(Position::new(200, 0, 0, 0, 0, 0))
+        );
+
+        std::fs::write(&path, r#"{ "anchors": [] }"#).unwrap();
+        context.reload_scene(&path, cmd_tx.clone()).await;
+        assert_eq!(anchor_position(&context), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_invalid_scene() {
+        let path = std::env::temp_dir().join(format!("pica-check-{}.json", std::process::id()));
+
+        std::fs::write(
+            &path,
+            r#"{
+                "anchors": [
+                    { "mac_address": "00:01", "pitch": 120 },
+                    { "mac_address": "00:01" }
+                ],
+                "obstacles": [{ "x1": 10, "z1": 10, "x2": 10, "z2": 10 }]
+            }"#,
+        )
+        .unwrap();
+        let report = check_scene(&path).unwrap_err().to_string();
+        assert!(report.contains("3 error(s) found"));
+        assert!(report.contains("duplicate anchor 00:01"));
+        assert!(report.contains("pitch 120 out of range"));
+        assert!(report.contains("obstacle 0: zero length wall"));
+
+        std::fs::write(&path, r#"{ "anchors": [{ "#).unwrap();
+        assert!(check_scene(&path).is_err());
+
+        std::fs::write(&path, r#"{ "anchors": [{ "mac_address": "00:01" }] }"#).unwrap();
+        assert!(check_scene(&path).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dump_state() {
+        let context = Context::new();
+        add_device(&context, 0, Position::new(100, 0, 0, 0, 0, 0));
+        let dir = std::env::temp_dir().join(format!("pica-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = context.dump_state(&dir).unwrap();
+        assert!(path.starts_with(&dir));
+        let state: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(state["devices"].as_array().unwrap().len(), 1);
+        assert_eq!(state["devices"][0]["mac_address"], "00:00");
+        assert_eq!(state["devices"][0]["x"], 100);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn state_device_order() {
+        let context = Context::new();
+        for handle in [3, 1, 4, 0, 2] {
+            add_device(&context, handle, Position::new(0, 0, 0, 0, 0, 0));
+        }
+
+        // The devices are listed by ascending handle.
+        let state = context.get_state();
+        assert_eq!(
+            state
+                .devices
+                .iter()
+                .map(|device| device.mac_address)
+                .collect::<Vec<_>>(),
+            (0..5)
+                .map(|handle| MacAddress::Short([0, handle]))
+                .collect::<Vec<_>>()
+        );
+    }
+}
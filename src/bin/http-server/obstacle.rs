@@ -0,0 +1,100 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use glam::Vec2;
+use pica::Position;
+use serde::{Deserialize, Serialize};
+
+/// Wall segment of the obstacle map.
+/// The map is defined in the horizontal plane (x, z) of the scene,
+/// the height of the walls is infinite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Obstacle {
+    pub x1: i16,
+    pub z1: i16,
+    pub x2: i16,
+    pub z2: i16,
+}
+
+/// Return the sign of the cross product (b - a) x (c - a),
+/// i.e. the side of the line (a, b) the point c lies on.
+fn orientation(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    let cross = (b - a).perp_dot(c - a);
+    if cross == 0. {
+        0.
+    } else {
+        cross.signum()
+    }
+}
+
+impl Obstacle {
+    /// Return true if the straight line between the two positions
+    /// crosses the obstacle. Devices located exactly on the wall are
+    /// considered to be in line-of-sight, the ranging line
+    /// going through one end of the wall is blocked.
+    pub fn blocks(&self, left: &Position, right: &Position) -> bool {
+        let w1 = Vec2::new(self.x1 as f32, self.z1 as f32);
+        let w2 = Vec2::new(self.x2 as f32, self.z2 as f32);
+        let a = left.ground_coordinates();
+        let b = right.ground_coordinates();
+
+        orientation(w1, w2, a) * orientation(w1, w2, b) < 0.
+            && orientation(a, b, w1) * orientation(a, b, w2) <= 0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Obstacle, Position};
+
+    const WALL: Obstacle = Obstacle {
+        x1: 0,
+        z1: -100,
+        x2: 0,
+        z2: 100,
+    };
+
+    #[test]
+    fn crossing() {
+        let position_a = Position::new(-100, 0, 0, 0, 0, 0);
+        let position_b = Position::new(100, 0, 50, 0, 0, 0);
+        assert!(WALL.blocks(&position_a, &position_b));
+        assert!(WALL.blocks(&position_b, &position_a));
+    }
+
+    #[test]
+    fn not_crossing() {
+        let position_a = Position::new(-100, 0, 0, 0, 0, 0);
+        let position_b = Position::new(100, 0, 300, 0, 0, 0);
+        let position_c = Position::new(-50, 0, 50, 0, 0, 0);
+        assert!(!WALL.blocks(&position_a, &position_b));
+        assert!(!WALL.blocks(&position_a, &position_c));
+    }
+
+    #[test]
+    fn wall_end() {
+        let position_a = Position::new(-100, 0, 100, 0, 0, 0);
+        let position_b = Position::new(100, 0, 100, 0, 0, 0);
+        assert!(WALL.blocks(&position_a, &position_b));
+    }
+
+    #[test]
+    fn device_on_wall() {
+        let position_a = Position::new(0, 0, 0, 0, 0, 0);
+        let position_b = Position::new(100, 0, 0, 0, 0, 0);
+        let position_c = Position::new(-100, 0, 0, 0, 0, 0);
+        assert!(!WALL.blocks(&position_a, &position_b));
+        assert!(!WALL.blocks(&position_c, &position_a));
+    }
+}
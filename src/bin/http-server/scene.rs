@@ -0,0 +1,111 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::obstacle::Obstacle;
+use anyhow::Result;
+use pica::{MacAddress, Position};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Anchor declared in the scene file.
+/// The position defaults to the origin of the scene.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SceneAnchor {
+    pub mac_address: MacAddress,
+    #[serde(default)]
+    x: i16,
+    #[serde(default)]
+    y: i16,
+    #[serde(default)]
+    z: i16,
+    #[serde(default)]
+    yaw: i16,
+    #[serde(default)]
+    pitch: i8,
+    #[serde(default)]
+    roll: i16,
+}
+
+impl SceneAnchor {
+    pub fn position(&self) -> Position {
+        Position::new(self.x, self.y, self.z, self.yaw, self.pitch, self.roll)
+    }
+}
+
+/// Content of the scene file, in JSON format:
+///
+/// ```json
+/// {
+///   "anchors": [
+///     { "mac_address": "00:01", "x": 100, "y": 0, "z": 50 }
+///   ],
+///   "obstacles": [
+///     { "x1": 0, "z1": -100, "x2": 0, "z2": 100 }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Scene {
+    #[serde(default)]
+    pub anchors: Vec<SceneAnchor>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Scene> {
+        let content = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Validate the content of the scene, and return the list
+    /// of errors found. The scene is valid if the list is empty.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let mut mac_addresses = HashSet::new();
+
+        for anchor in self.anchors.iter() {
+            if !mac_addresses.insert(anchor.mac_address) {
+                errors.push(format!("duplicate anchor {}", anchor.mac_address));
+            }
+            if !(-180..=180).contains(&anchor.yaw) {
+                errors.push(format!(
+                    "anchor {}: yaw {} out of range [-180, 180]",
+                    anchor.mac_address, anchor.yaw
+                ));
+            }
+            if !(-90..=90).contains(&anchor.pitch) {
+                errors.push(format!(
+                    "anchor {}: pitch {} out of range [-90, 90]",
+                    anchor.mac_address, anchor.pitch
+                ));
+            }
+            if !(-180..=180).contains(&anchor.roll) {
+                errors.push(format!(
+                    "anchor {}: roll {} out of range [-180, 180]",
+                    anchor.mac_address, anchor.roll
+                ));
+            }
+        }
+
+        for (index, obstacle) in self.obstacles.iter().enumerate() {
+            if (obstacle.x1, obstacle.z1) == (obstacle.x2, obstacle.z2) {
+                errors.push(format!("obstacle {}: zero length wall", index));
+            }
+        }
+
+        errors
+    }
+}
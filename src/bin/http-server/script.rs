@@ -0,0 +1,272 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scenario scripts executing control commands on a timeline.
+//!
+//! Each action is a command of the control channel, scheduled at the
+//! time `at` in milliseconds after the start of the script:
+//!
+//! ```json
+//! {
+//!   "actions": [
+//!     { "at": 0, "cmd": "create_anchor", "mac_address": "00:01" },
+//!     { "at": 1000, "cmd": "set_position", "mac_address": "00:01", "x": 100 },
+//!     { "at": 2000, "cmd": "inject_fault", "mac_address": "00:01",
+//!       "gid": 1, "oid": 3, "behavior": "drop" },
+//!     { "at": 3000, "cmd": "stop_all_ranging" }
+//!   ]
+//! }
+//! ```
+//!
+//! The actions are scheduled on the pica clock driving the ranging
+//! rounds: the script is suspended while the clock is paused with
+//! `pause_clock`, and resumes with the ranging rounds.
+
+use crate::control::{self, Command};
+use crate::Context;
+use anyhow::Result;
+use pica::{Clock, PicaCommand};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Command scheduled in the script.
+#[derive(Debug, Deserialize)]
+pub struct ScriptAction {
+    /// Time of the action in milliseconds after the start of the script.
+    pub at: u64,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+/// Content of the script file, in JSON format.
+#[derive(Debug, Deserialize)]
+pub struct Script {
+    pub actions: Vec<ScriptAction>,
+}
+
+impl Script {
+    /// Load and validate the script file. Returns an error describing
+    /// the invalid content if any.
+    pub fn load(path: &Path) -> Result<Script> {
+        let content = std::fs::read(path)?;
+        let script: Script = serde_json::from_slice(&content)
+            .map_err(|err| anyhow::anyhow!("{}: invalid script file: {}", path.display(), err))?;
+        let errors = script.validate();
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "{}: {} error(s) found\n{}",
+                path.display(),
+                errors.len(),
+                errors
+                    .iter()
+                    .map(|error| format!("  - {}", error))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        Ok(script)
+    }
+
+    /// Validate the content of the script, and return the list
+    /// of errors found. The script is valid if the list is empty.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for (index, pair) in self.actions.windows(2).enumerate() {
+            if pair[1].at < pair[0].at {
+                errors.push(format!(
+                    "action {}: scheduled at {} ms, before the previous action at {} ms",
+                    index + 1,
+                    pair[1].at,
+                    pair[0].at
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Execute the actions of the script at their scheduled time, measured
+/// on the selected clock. Failed actions are logged, and do not interrupt
+/// the script.
+pub async fn run(
+    context: Context,
+    cmd_tx: mpsc::Sender<PicaCommand>,
+    clock: Arc<dyn Clock>,
+    script: Option<Script>,
+) -> Result<()> {
+    let Some(script) = script else {
+        return Ok(());
+    };
+
+    let start = clock.now();
+    for action in script.actions {
+        clock
+            .sleep_until(start + Duration::from_millis(action.at))
+            .await;
+        log::info!("script: {} ms: {:?}", action.at, action.command);
+        let response = control::execute(&context, &cmd_tx, action.command).await?;
+        if response["status"] != "ok" {
+            log::warn!("script: {} ms: action failed: {}", action.at, response);
+        }
+    }
+
+    log::info!("script: completed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pica::{MacAddress, Position, SystemClock};
+    use std::sync::Mutex;
+    use tokio::time;
+
+    fn parse(script: &str) -> Script {
+        serde_json::from_str(script).unwrap()
+    }
+
+    #[test]
+    fn validate_action_order() {
+        let script = parse(
+            r#"{ "actions": [
+                { "at": 1000, "cmd": "pause_clock" },
+                { "at": 500, "cmd": "resume_clock" }
+            ] }"#,
+        );
+        assert_eq!(script.validate().len(), 1);
+
+        assert!(serde_json::from_str::<Script>(
+            r#"{ "actions": [{ "at": 0, "cmd": "unknown_command" }] }"#
+        )
+        .is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_script() {
+        let context = Context::new();
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let mac_address = MacAddress::Short([0, 1]);
+
+        // Record the virtual time at which the commands reach pica.
+        let start = time::Instant::now();
+        let received = Arc::new(Mutex::new(vec![]));
+        tokio::spawn({
+            let received = received.clone();
+            async move {
+                while let Some(cmd) = cmd_rx.recv().await {
+                    let name = match cmd {
+                        PicaCommand::CreateAnchor(_, rsp_tx) => {
+                            let _ = rsp_tx.send(Ok(0));
+                            "create_anchor"
+                        }
+                        PicaCommand::InjectFault(0, _) => "inject_fault",
+                        PicaCommand::StopAllRanging(rsp_tx) => {
+                            let _ = rsp_tx.send(vec![]);
+                            "stop_all_ranging"
+                        }
+                        _ => "unexpected",
+                    };
+                    received.lock().unwrap().push((name, start.elapsed()));
+                }
+            }
+        });
+
+        let script = parse(
+            r#"{ "actions": [
+                { "at": 0, "cmd": "create_anchor", "mac_address": "00:01" },
+                { "at": 1000, "cmd": "set_position", "mac_address": "00:01", "x": 100 },
+                { "at": 2000, "cmd": "inject_fault", "mac_address": "00:01",
+                  "gid": 1, "oid": 3, "behavior": "drop" },
+                { "at": 3000, "cmd": "stop_all_ranging" }
+            ] }"#,
+        );
+        let task = tokio::spawn(run(
+            context.clone(),
+            cmd_tx,
+            Arc::new(SystemClock::new()),
+            Some(script),
+        ));
+
+        let anchor_position = || {
+            context
+                .devices
+                .lock()
+                .unwrap()
+                .values()
+                .find(|device| device.mac_address == mac_address)
+                .map(|device| device.position)
+        };
+
+        time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(anchor_position(), Some(Position::new(0, 0, 0, 0, 0, 0)));
+        time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(anchor_position(), Some(Position::new(100, 0, 0, 0, 0, 0)));
+
+        task.await.unwrap().unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                ("create_anchor", Duration::ZERO),
+                ("inject_fault", Duration::from_secs(2)),
+                ("stop_all_ranging", Duration::from_secs(3)),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pause_script() {
+        let context = Context::new();
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(1);
+        let clock = Arc::new(SystemClock::new());
+
+        // Record the virtual time at which the commands reach pica.
+        let start = time::Instant::now();
+        let received = Arc::new(Mutex::new(vec![]));
+        tokio::spawn({
+            let received = received.clone();
+            async move {
+                while let Some(cmd) = cmd_rx.recv().await {
+                    if let PicaCommand::StopAllRanging(rsp_tx) = cmd {
+                        let _ = rsp_tx.send(vec![]);
+                    }
+                    received.lock().unwrap().push(start.elapsed());
+                }
+            }
+        });
+
+        let script = parse(
+            r#"{ "actions": [
+                { "at": 1000, "cmd": "stop_all_ranging" },
+                { "at": 2000, "cmd": "stop_all_ranging" }
+            ] }"#,
+        );
+        let task = tokio::spawn(run(context, cmd_tx, clock.clone(), Some(script)));
+
+        // The script is suspended while the clock is paused.
+        time::sleep(Duration::from_millis(500)).await;
+        clock.pause();
+        time::sleep(Duration::from_secs(3)).await;
+        assert!(received.lock().unwrap().is_empty());
+        clock.resume();
+
+        task.await.unwrap().unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![Duration::from_millis(4000), Duration::from_millis(5000)]
+        );
+    }
+}
@@ -14,28 +14,58 @@
 
 use anyhow::Result;
 use clap::Parser;
-use env_logger::Env;
-use pica::{Pica, PicaCommand};
-use std::net::{Ipv4Addr, SocketAddrV4};
+use pica::cli::{
+    AntennaConfig, ColocatedReport, DeviceState, LogFormat, ResponseLatency, TraceFormat,
+    UciFraming,
+};
+use pica::{NullRangingEstimator, Pica, PicaCommand};
+use std::net::{Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Semaphore};
 use tokio::try_join;
 
 const DEFAULT_UCI_PORT: u16 = 7000;
 
-async fn accept_incoming(cmd_tx: mpsc::Sender<PicaCommand>, uci_port: u16) -> Result<()> {
-    let uci_socket = SocketAddrV4::new(Ipv4Addr::LOCALHOST, uci_port);
-    let uci_listener = TcpListener::bind(uci_socket).await?;
+async fn accept_incoming(
+    cmd_tx: mpsc::Sender<PicaCommand>,
+    uci_listener: TcpListener,
+    framing: pica::packets::uci::Framing,
+    max_connections: Option<usize>,
+    max_packet_size: Option<usize>,
+) -> Result<()> {
     log::info!("? main");
-    log::info!("Pica: Listening on: {}", uci_port);
+    log::info!("Pica: Listening on: {}", uci_listener.local_addr()?);
+
+    // One permit is held by the packet stream of each open connection,
+    // and released when the stream is dropped.
+    let connections = Arc::new(Semaphore::new(
+        max_connections.unwrap_or(Semaphore::MAX_PERMITS),
+    ));
 
     loop {
         let (socket, addr) = uci_listener.accept().await?;
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            log::warn!("Uwb host addr: {}: connection limit reached, closing", addr);
+            continue;
+        };
         log::info!("Uwb host addr: {}", addr);
 
         let (read_half, write_half) = socket.into_split();
-        let stream = Box::pin(futures::stream::unfold(read_half, pica::packets::uci::read));
+        let stream = Box::pin(futures::stream::unfold(
+            (read_half, permit),
+            move |(read_half, permit)| async move {
+                let (packet, read_half) = pica::packets::uci::read_limited(
+                    read_half,
+                    framing,
+                    max_packet_size.unwrap_or(usize::MAX),
+                )
+                .await?;
+                Some((packet, (read_half, permit)))
+            },
+        ));
         let sink = Box::pin(futures::sink::unfold(write_half, pica::packets::uci::write));
 
         cmd_tx
@@ -48,14 +78,177 @@ async fn accept_incoming(cmd_tx: mpsc::Sender<PicaCommand>, uci_port: u16) -> Re
 #[derive(Parser, Debug)]
 #[command(name = "pica", about = "Virtual UWB subsystem")]
 struct Args {
-    /// Output directory for storing .pcapng traces.
-    /// If provided, .pcapng traces of client connections are automatically
-    /// saved under the name `device-{handle}.pcapng`.
-    #[arg(short, long, value_name = "PCAPNG_DIR")]
-    pcapng_dir: Option<PathBuf>,
+    /// Output directory for storing the traces of the client connections.
+    /// If provided, the traces are automatically saved under the name
+    /// `device-{handle}.pcapng`, or `device-{handle}.bin` with the raw
+    /// trace format. `--pcapng-dir` is accepted as an alias.
+    #[arg(short = 'p', long, alias = "pcapng-dir", value_name = "DIR")]
+    trace_dir: Option<PathBuf>,
+    /// Output directory for exporting the ranging results as CSV.
+    /// If provided, the results reported to the hosts are saved under
+    /// the name `device-{handle}-session-{session_token}.csv`.
+    #[arg(long, value_name = "DIR")]
+    csv_dir: Option<PathBuf>,
+    /// Maximum size in bytes of the .pcapng traces. When exceeded, the
+    /// traces are continued in `device-{handle}.{n}.pcapng`.
+    #[arg(long, value_name = "BYTES")]
+    pcapng_max_size: Option<u64>,
+    /// pcapng file recording the traffic of all devices, in addition to
+    /// the per-device traces. Each device connection is recorded as
+    /// a separate interface named `device-{handle}`.
+    #[arg(long, value_name = "FILE")]
+    pcapng_combined: Option<PathBuf>,
+    /// Format of the traces recorded to the output directory.
+    #[arg(long, value_enum, default_value_t = TraceFormat::Pcapng)]
+    trace_format: TraceFormat,
+    /// Layout of the UCI packet headers exchanged with the hosts.
+    #[arg(long, value_enum, default_value_t = UciFraming::V2)]
+    uci_framing: UciFraming,
     /// Configure the TCP port for the UCI server.
     #[arg(short, long, value_name = "UCI_PORT", default_value_t = DEFAULT_UCI_PORT)]
     uci_port: u16,
+    /// Configure the address of the UCI server, e.g. `0.0.0.0:7000`
+    /// or `[::1]:7000`. Binding to `[::]` accepts both IPv4 and IPv6
+    /// hosts on dual-stack systems. Overrides `--uci-port`, the server
+    /// listens on the IPv4 loopback address by default.
+    #[arg(long, value_name = "ADDR")]
+    uci_bind: Option<SocketAddr>,
+    /// Pre-shared token required from the UCI hosts. The token is sent
+    /// in the Pica Authenticate command as the first packet of the
+    /// connection; other connections are closed without creating
+    /// a device. Hosts are not authenticated by default.
+    /// The token is visible to the other users of the system in the
+    /// process arguments, prefer `--auth-token-file` on shared systems.
+    #[arg(long, value_name = "TOKEN", conflicts_with = "auth_token_file")]
+    auth_token: Option<String>,
+    /// File containing the pre-shared token required from the UCI hosts.
+    /// The trailing newline of the file is not part of the token.
+    #[arg(long, value_name = "PATH")]
+    auth_token_file: Option<PathBuf>,
+    /// Maximum number of UCI connections simultaneously open, including
+    /// the connections not yet authenticated. Connections exceeding the
+    /// limit are closed immediately. Unlimited by default.
+    #[arg(long, value_name = "COUNT")]
+    max_connections: Option<usize>,
+    /// Maximum payload length in bytes of the UCI packets received from
+    /// the hosts. The payload of larger packets is discarded without being
+    /// buffered, and the packets are rejected with the status
+    /// INVALID_MESSAGE_SIZE. Unlimited by default.
+    #[arg(long, value_name = "BYTES")]
+    max_packet_size: Option<usize>,
+    /// Delay in milliseconds between the response to the Core Device Reset
+    /// command and the notification of the ready state.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    reset_delay: u64,
+    /// Delay in milliseconds between the connection of the host and the
+    /// notification of the initial state. Commands received in the
+    /// meantime are rejected with the status UCI_MESSAGE_RETRY.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    boot_delay: u64,
+    /// Chip identifiers reported in the Pica Get Chip Info response,
+    /// in the format `oui:part_id:chip_version`. The fields are hexadecimal
+    /// integers on 24, 16 and 16 bits respectively.
+    #[arg(long, value_name = "OUI:PART:VERSION", default_value = "0:0:0")]
+    chip_info: pica::ChipInfo,
+    /// Also report the chip identifiers in the vendor specific information
+    /// of the Core Get Device Info response.
+    #[arg(long)]
+    chip_info_in_device_info: bool,
+    /// State of the devices on initialization and after reset.
+    #[arg(long, value_enum, default_value_t = DeviceState::Ready)]
+    initial_device_state: DeviceState,
+    /// Receive antenna configuration of the devices, selecting the
+    /// angles of arrival reported in the ranging notifications.
+    #[arg(long, value_enum, default_value_t = AntennaConfig::AzimuthElevation)]
+    antenna_config: AntennaConfig,
+    /// Number of receive antenna pairs of the devices. With more than
+    /// one pair, the azimuth measured by each pair is reported in the
+    /// vendor data of the ranging notifications.
+    #[arg(long, value_name = "COUNT", default_value_t = 1)]
+    antenna_pairs: u8,
+    /// Maximum size in bytes of the payload of a reassembled
+    /// control packet.
+    #[arg(long, value_name = "BYTES", default_value_t = pica::DEFAULT_MAX_REASSEMBLY_SIZE)]
+    max_reassembly_size: usize,
+    /// Maximum number of TLVs carried by each control packet of the
+    /// Core Get Caps Info, Core Get Config, and Session Get App Config
+    /// responses. Responses are only segmented on the MTU by default.
+    #[arg(long, value_name = "COUNT")]
+    max_tlvs_per_packet: Option<usize>,
+    /// Close the connections with no inbound traffic for the selected
+    /// duration in milliseconds. Connections are never closed by default.
+    #[arg(long, value_name = "MS")]
+    idle_timeout: Option<u64>,
+    /// Maximum rate of inbound commands per device, in commands per second.
+    /// Commands exceeding the rate are rejected with the status
+    /// UCI_MESSAGE_RETRY. The rate is unlimited by default, or when
+    /// set to zero.
+    #[arg(long, value_name = "RATE")]
+    command_rate_limit: Option<u32>,
+    /// Window in milliseconds after each response during which the
+    /// devices reject new commands with the status UCI_MESSAGE_RETRY,
+    /// except the device reset. Commands are accepted at any time
+    /// by default.
+    #[arg(long, value_name = "MS")]
+    busy_window: Option<u64>,
+    /// Processing latency applied before responding to the commands with
+    /// the selected group and opcode identifiers, in the format
+    /// `gid:oid:ms`. The latency is measured on the pica clock.
+    /// Can be repeated. Responses are not delayed by default.
+    #[arg(long, value_name = "GID:OID:MS")]
+    response_latency: Vec<ResponseLatency>,
+    /// Maximum variation of the ranging interval, as a fraction of the
+    /// interval between 0 and 0.9. The interval is fixed by default.
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.)]
+    ranging_jitter: f32,
+    /// Seed of the generator drawing the ranging interval variations.
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    ranging_jitter_seed: u64,
+    /// Reply time in microseconds of the two-way ranging responders.
+    /// The timing fields derived from the reply time are reported in the
+    /// ranging notifications. The timing fields are omitted by default.
+    #[arg(long, value_name = "US")]
+    twr_reply_time: Option<u64>,
+    /// Maximum number of sessions simultaneously active on each device.
+    /// Session Start commands exceeding the limit are rejected with the
+    /// status ERROR_MAX_SESSIONS_EXCEEDED. Unlimited by default.
+    #[arg(long, value_name = "COUNT")]
+    max_active_sessions: Option<usize>,
+    /// Emit a Core Generic Error notification for the protocol errors
+    /// detected by the devices, in addition to the command responses.
+    #[arg(long)]
+    generic_error_notifications: bool,
+    /// Let the ranging offset push the reported distances below zero,
+    /// encoded in two's complement. Test-only: the distances saturate
+    /// at zero by default.
+    #[arg(long)]
+    allow_negative_distance: bool,
+    /// Report of the measurements between devices sharing the same
+    /// position, for which the angles of arrival are undefined.
+    #[arg(long, value_enum, default_value_t = ColocatedReport::AoaFailed)]
+    colocated_report: ColocatedReport,
+    /// Format of the log records.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Replay the commands recorded in a .pcapng trace, and compare the
+    /// generated responses against the recorded responses. Exits with
+    /// an error if any response differs.
+    #[arg(long, value_name = "PCAPNG_FILE")]
+    replay_verify: Option<PathBuf>,
+    /// Bytes ignored when comparing the replayed responses with the
+    /// selected group and opcode identifiers, in the format
+    /// `gid:oid:offset:length`. The offset is relative to the start
+    /// of the payload. Can be repeated.
+    #[arg(long, value_name = "MASK")]
+    replay_mask: Vec<pica::replay::Mask>,
+    /// Delay in milliseconds for receiving each replayed response.
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    replay_timeout: u64,
+    /// Disable the mock ranging estimator. The positions of the devices
+    /// cannot be set with this binary, and the ranging rounds produce
+    /// no measurements.
+    #[arg(long)]
+    require_positions: bool,
 }
 
 struct MockRangingEstimator();
@@ -73,17 +266,211 @@ impl pica::RangingEstimator for MockRangingEstimator {
     }
 }
 
+/// Replay a .pcapng trace, and fail if any of the recorded responses
+/// is not reproduced.
+async fn replay_verify(
+    pica: Pica,
+    path: &PathBuf,
+    masks: &[pica::replay::Mask],
+    timeout: Duration,
+) -> Result<()> {
+    let mismatches = pica::replay::verify(pica, path, masks, timeout).await?;
+    if !mismatches.is_empty() {
+        let report = mismatches
+            .iter()
+            .map(|mismatch| mismatch.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        anyhow::bail!(
+            "{}: {} response(s) differ\n{}",
+            path.display(),
+            mismatches.len(),
+            report
+        );
+    }
+    log::info!("{}: all responses match", path.display());
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
-    log::info!("bin main");
     let args = Args::parse();
+    pica::cli::init_logger(args.log_format);
+    log::info!("bin main");
+
+    let ranging_estimator: Box<dyn pica::RangingEstimator> = if args.require_positions {
+        Box::new(NullRangingEstimator())
+    } else {
+        Box::new(MockRangingEstimator())
+    };
+    let mut builder = Pica::builder()
+        .ranging_estimator(ranging_estimator)
+        .trace_format(args.trace_format.into())
+        .framing(args.uci_framing.into())
+        .reset_delay(Duration::from_millis(args.reset_delay))
+        .boot_delay(Duration::from_millis(args.boot_delay))
+        .initial_device_state(args.initial_device_state.into())
+        .chip_info(args.chip_info, args.chip_info_in_device_info)
+        .antenna_config(args.antenna_config.into())
+        .antenna_pairs(args.antenna_pairs)
+        .max_reassembly_size(args.max_reassembly_size)
+        .response_latencies(pica::cli::response_latencies(&args.response_latency))
+        .ranging_jitter(args.ranging_jitter, args.ranging_jitter_seed)?
+        .generic_error_notifications(args.generic_error_notifications)
+        .allow_negative_distance(args.allow_negative_distance)
+        .colocated_report(args.colocated_report.into());
+    if let Some(trace_dir) = args.trace_dir {
+        builder = builder.pcapng_dir(trace_dir);
+    }
+    if let Some(pcapng_max_size) = args.pcapng_max_size {
+        builder = builder.pcapng_max_size(pcapng_max_size);
+    }
+    if let Some(path) = args.pcapng_combined {
+        builder = builder.pcapng_combined(path)?;
+    }
+    if let Some(max_tlvs_per_packet) = args.max_tlvs_per_packet {
+        builder = builder.max_tlvs_per_packet(max_tlvs_per_packet);
+    }
+    if let Some(idle_timeout) = args.idle_timeout {
+        builder = builder.idle_timeout(Duration::from_millis(idle_timeout));
+    }
+    if let Some(command_rate_limit) = args.command_rate_limit {
+        builder = builder.command_rate_limit(command_rate_limit);
+    }
+    if let Some(busy_window) = args.busy_window {
+        builder = builder.busy_window(Duration::from_millis(busy_window));
+    }
+    if let Some(twr_reply_time) = args.twr_reply_time {
+        builder = builder.twr_reply_time(Duration::from_micros(twr_reply_time));
+    }
+    if let Some(max_active_sessions) = args.max_active_sessions {
+        builder = builder.max_active_sessions(max_active_sessions);
+    }
+    if let Some(auth_token) =
+        pica::cli::auth_token(args.auth_token, args.auth_token_file.as_deref())?
+    {
+        builder = builder.auth_token(auth_token)?;
+    }
+    if let Some(csv_dir) = args.csv_dir {
+        builder = builder.csv_dir(csv_dir);
+    }
+    let pica = builder.build();
+
+    if let Some(path) = &args.replay_verify {
+        let timeout = Duration::from_millis(args.replay_timeout);
+        return replay_verify(pica, path, &args.replay_mask, timeout).await;
+    }
 
-    let pica = Pica::new(Box::new(MockRangingEstimator()), args.pcapng_dir);
     let commands = pica.commands();
+    let uci_addr = args
+        .uci_bind
+        .unwrap_or(SocketAddr::from((Ipv4Addr::LOCALHOST, args.uci_port)));
+    let uci_listener = TcpListener::bind(uci_addr).await?;
 
-    try_join!(accept_incoming(commands.clone(), args.uci_port), pica.run(),)?;
+    try_join!(
+        accept_incoming(
+            commands.clone(),
+            uci_listener,
+            args.uci_framing.into(),
+            args.max_connections,
+            args.max_packet_size,
+        ),
+        pica.run(),
+    )?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdl_runtime::Packet;
+    use pica::packets::uci::{self, *};
+    use std::net::Ipv6Addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Send a Core Device Reset command and return the response status.
+    async fn device_reset(socket: &mut TcpStream) -> uci::Status {
+        let mut cmd = CoreDeviceResetCmd {
+            reset_config: ResetConfig::UwbsReset,
+        }
+        .encode_to_vec()
+        .unwrap();
+        // The payload length is not filled in by the encoder.
+        cmd[3] = (cmd.len() - HEADER_SIZE) as u8;
+        socket.write_all(&cmd).await.unwrap();
+
+        // Skip the notifications received before the response.
+        let rsp = loop {
+            let mut packet = vec![0; HEADER_SIZE];
+            socket.read_exact(&mut packet).await.unwrap();
+            packet.resize(HEADER_SIZE + packet[3] as usize, 0);
+            socket.read_exact(&mut packet[HEADER_SIZE..]).await.unwrap();
+            if let Ok(rsp) = CoreDeviceResetRsp::decode_full(&packet) {
+                break rsp;
+            }
+        };
+        rsp.status
+    }
+
+    #[tokio::test]
+    async fn ipv6_listener() {
+        let pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let uci_listener = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+        let uci_addr = uci_listener.local_addr().unwrap();
+        tokio::spawn(accept_incoming(
+            pica.commands(),
+            uci_listener,
+            pica::packets::uci::Framing::V2,
+            None,
+            None,
+        ));
+        tokio::spawn(pica.run());
+
+        let mut socket = TcpStream::connect(uci_addr).await.unwrap();
+        assert_eq!(device_reset(&mut socket).await, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn max_connections() {
+        let pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let uci_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let uci_addr = uci_listener.local_addr().unwrap();
+        tokio::spawn(accept_incoming(
+            pica.commands(),
+            uci_listener,
+            pica::packets::uci::Framing::V2,
+            Some(2),
+            None,
+        ));
+        tokio::spawn(pica.run());
+
+        let mut first = TcpStream::connect(uci_addr).await.unwrap();
+        let mut second = TcpStream::connect(uci_addr).await.unwrap();
+        assert_eq!(device_reset(&mut first).await, uci::Status::Ok);
+        assert_eq!(device_reset(&mut second).await, uci::Status::Ok);
+
+        // The connections past the limit are closed without a device.
+        for _ in 0..3 {
+            let mut excess = TcpStream::connect(uci_addr).await.unwrap();
+            let mut buf = [0; 1];
+            let read = tokio::time::timeout(Duration::from_secs(1), excess.read(&mut buf))
+                .await
+                .expect("excess connection not closed");
+            assert!(matches!(read, Ok(0) | Err(_)));
+        }
+
+        // Closing a connection makes room for a new one.
+        drop(first);
+        let mut third = loop {
+            let mut socket = TcpStream::connect(uci_addr).await.unwrap();
+            let mut buf = [0; 1];
+            match tokio::time::timeout(Duration::from_millis(100), socket.peek(&mut buf)).await {
+                Ok(Ok(0) | Err(_)) => tokio::time::sleep(Duration::from_millis(10)).await,
+                _ => break socket,
+            }
+        };
+        assert_eq!(device_reset(&mut third).await, uci::Status::Ok);
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Command line options shared by the pica binaries.
+//! The module is not part of the library API, and may change
+//! without notice.
+
+use clap::ValueEnum;
+use env_logger::Env;
+use log::kv::{self, VisitSource};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Format of the log records.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable text, one line per record.
+    Text,
+    /// JSON object, one line per record.
+    Json,
+}
+
+/// Receive antenna configuration of the devices.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum AntennaConfig {
+    /// Single antenna, no angle of arrival.
+    Single,
+    /// Two antennas, azimuth only.
+    Azimuth,
+    /// Three or more antennas, azimuth and elevation.
+    AzimuthElevation,
+}
+
+impl From<AntennaConfig> for crate::AntennaConfig {
+    fn from(antenna_config: AntennaConfig) -> Self {
+        match antenna_config {
+            AntennaConfig::Single => crate::AntennaConfig::Single,
+            AntennaConfig::Azimuth => crate::AntennaConfig::Azimuth,
+            AntennaConfig::AzimuthElevation => crate::AntennaConfig::AzimuthElevation,
+        }
+    }
+}
+
+/// Format of the recorded traces.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum TraceFormat {
+    /// pcapng file, saved as `device-{handle}.pcapng`.
+    Pcapng,
+    /// Concatenation of the UCI packets prefixed with their length as
+    /// a little-endian u16, saved as `device-{handle}.bin`.
+    Raw,
+}
+
+impl From<TraceFormat> for crate::TraceFormat {
+    fn from(trace_format: TraceFormat) -> Self {
+        match trace_format {
+            TraceFormat::Pcapng => crate::TraceFormat::Pcapng,
+            TraceFormat::Raw => crate::TraceFormat::Raw,
+        }
+    }
+}
+
+/// Layout of the UCI packet headers.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum UciFraming {
+    /// UCI 1.x framing, reporting the UCI version 1.1.
+    V1,
+    /// UCI 2.0 framing with extended payload lengths, reporting
+    /// the UCI version 2.0.
+    V2,
+}
+
+impl From<UciFraming> for crate::packets::uci::Framing {
+    fn from(framing: UciFraming) -> Self {
+        match framing {
+            UciFraming::V1 => crate::packets::uci::Framing::V1,
+            UciFraming::V2 => crate::packets::uci::Framing::V2,
+        }
+    }
+}
+
+/// Report of the measurements between devices sharing the same position.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ColocatedReport {
+    /// Valid distance, angles of arrival with a zero figure of merit.
+    AoaFailed,
+    /// Measurement reported with the status FAILED when the angles
+    /// of arrival are requested.
+    Failed,
+}
+
+impl From<ColocatedReport> for crate::ColocatedReport {
+    fn from(colocated_report: ColocatedReport) -> Self {
+        match colocated_report {
+            ColocatedReport::AoaFailed => crate::ColocatedReport::AoaFailed,
+            ColocatedReport::Failed => crate::ColocatedReport::Failed,
+        }
+    }
+}
+
+/// Device state entered on initialization and after reset.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DeviceState {
+    Ready,
+    Active,
+    Error,
+}
+
+impl From<DeviceState> for crate::packets::uci::DeviceState {
+    fn from(state: DeviceState) -> Self {
+        match state {
+            DeviceState::Ready => crate::packets::uci::DeviceState::DeviceStateReady,
+            DeviceState::Active => crate::packets::uci::DeviceState::DeviceStateActive,
+            DeviceState::Error => crate::packets::uci::DeviceState::DeviceStateError,
+        }
+    }
+}
+
+/// Processing latency applied before responding to the commands
+/// with the selected group and opcode identifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseLatency {
+    pub gid: u8,
+    pub oid: u8,
+    pub latency: Duration,
+}
+
+/// Parse a response latency from the format `gid:oid:ms`.
+/// The fields are decimal integers, or hexadecimal integers
+/// prefixed with `0x`.
+impl FromStr for ResponseLatency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        use crate::replay::parse_integer;
+        let fields = s.split(':').collect::<Vec<_>>();
+        let [gid, oid, latency] = fields[..] else {
+            anyhow::bail!("expected gid:oid:ms, got {}", s);
+        };
+        Ok(ResponseLatency {
+            gid: parse_integer(gid)?,
+            oid: parse_integer(oid)?,
+            latency: Duration::from_millis(parse_integer(latency)?),
+        })
+    }
+}
+
+/// Index the response latencies by group and opcode identifiers.
+pub fn response_latencies(latencies: &[ResponseLatency]) -> HashMap<(u8, u8), Duration> {
+    latencies
+        .iter()
+        .map(|latency| ((latency.gid, latency.oid), latency.latency))
+        .collect()
+}
+
+/// Return the authentication token of the UCI hosts, passed on the
+/// command line or read from the selected file. The trailing newline
+/// of the file is not part of the token. Empty tokens are rejected.
+pub fn auth_token(token: Option<String>, path: Option<&Path>) -> std::io::Result<Option<Vec<u8>>> {
+    let token = match (token, path) {
+        (Some(token), _) => token.into_bytes(),
+        (None, Some(path)) => {
+            let mut token = std::fs::read(path)?;
+            if token.ends_with(b"\n") {
+                token.pop();
+                if token.ends_with(b"\r") {
+                    token.pop();
+                }
+            }
+            token
+        }
+        (None, None) => return Ok(None),
+    };
+    if token.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "empty authentication token",
+        ));
+    }
+    Ok(Some(token))
+}
+
+/// Install the global logger, writing the records in the selected format.
+/// The log level defaults to debug and is overridden by `RUST_LOG`.
+pub fn init_logger(log_format: LogFormat) {
+    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or("debug"));
+    if log_format == LogFormat::Json {
+        logger.format(|buf, record| {
+            let timestamp = buf.timestamp_millis().to_string();
+            write_json_record(buf, &timestamp, record)
+        });
+    }
+    logger.init();
+}
+
+/// Collect the structured fields of a log record as JSON values.
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_bool() {
+            value.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.entry(key.as_str()).or_insert(value);
+        Ok(())
+    }
+}
+
+/// Write a log record as a JSON object on a single line.
+/// The structured fields of the record, such as the handle, command
+/// and status of the UCI commands, are reported as top-level keys.
+fn write_json_record(
+    out: &mut impl Write,
+    timestamp: &str,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let mut object = serde_json::Map::new();
+    object.insert("timestamp".into(), timestamp.into());
+    object.insert("level".into(), record.level().as_str().into());
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+    let _ = record.key_values().visit(&mut JsonFields(&mut object));
+    writeln!(out, "{}", serde_json::Value::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_response_latency() {
+        assert_eq!(
+            "0x0:0x4:100".parse::<ResponseLatency>().unwrap(),
+            ResponseLatency {
+                gid: 0,
+                oid: 4,
+                latency: Duration::from_millis(100),
+            }
+        );
+        assert!("0:4".parse::<ResponseLatency>().is_err());
+        assert!("0:4:ms".parse::<ResponseLatency>().is_err());
+    }
+
+    #[test]
+    fn read_auth_token() {
+        let path = std::env::temp_dir().join(format!("pica-auth-token-{}", std::process::id()));
+        std::fs::write(&path, "secret\n").unwrap();
+        assert_eq!(
+            auth_token(None, Some(&path)).unwrap(),
+            Some(b"secret".to_vec())
+        );
+        assert_eq!(
+            auth_token(Some("token".to_owned()), None).unwrap(),
+            Some(b"token".to_vec())
+        );
+        assert_eq!(auth_token(None, None).unwrap(), None);
+        assert!(auth_token(Some(String::new()), None).is_err());
+        std::fs::write(&path, "\n").unwrap();
+        assert!(auth_token(None, Some(&path)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(auth_token(None, Some(&path)).is_err());
+    }
+
+    #[test]
+    fn json_log_record() {
+        let fields = [
+            ("handle", kv::Value::from(1)),
+            ("command", kv::Value::from("0x2")),
+            ("status", kv::Value::from("Ok")),
+            // Fields cannot override the record attributes.
+            ("level", kv::Value::from("INFO")),
+        ];
+        let mut out = vec![];
+        write_json_record(
+            &mut out,
+            "2024-01-01T00:00:00.000Z",
+            &log::Record::builder()
+                .args(format_args!("command 0x2 status Ok"))
+                .level(log::Level::Debug)
+                .target("pica::device")
+                .key_values(&fields)
+                .build(),
+        )
+        .unwrap();
+
+        let line = String::from_utf8(out).unwrap();
+        assert_eq!(line.lines().count(), 1);
+        let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["timestamp"], "2024-01-01T00:00:00.000Z");
+        assert_eq!(record["level"], "DEBUG");
+        assert_eq!(record["target"], "pica::device");
+        assert_eq!(record["message"], "command 0x2 status Ok");
+        assert_eq!(record["handle"], 1);
+        assert_eq!(record["command"], "0x2");
+        assert_eq!(record["status"], "Ok");
+    }
+}
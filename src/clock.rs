@@ -0,0 +1,306 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Future completing when a clock reaches a deadline.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Trait matching the capabilities of the time source of the UWBS.
+/// The clock is shared by all the devices of the Pica context.
+pub trait Clock: Send + Sync {
+    /// Return the time elapsed since the clock epoch.
+    fn now(&self) -> Duration;
+
+    /// Advance the clock by the selected duration. The default is a no-op
+    /// for clocks that cannot be set.
+    fn advance(&self, _duration: Duration) {}
+
+    /// Freeze the clock until [Clock::resume] is called. The sleeping
+    /// tasks are not woken up while the clock is paused, unless the clock
+    /// is advanced. The default is a no-op for clocks whose time is
+    /// set manually, which only change when advanced.
+    fn pause(&self) {}
+
+    /// Resume the clock after [Clock::pause]. The time spent paused
+    /// is not accounted for.
+    fn resume(&self) {}
+
+    /// Return a future completing when the clock reaches the
+    /// selected time, measured from the clock epoch.
+    fn sleep_until(&self, deadline: Duration) -> Sleep;
+
+    /// Return a future completing when the selected duration
+    /// has elapsed on the clock.
+    fn sleep(&self, duration: Duration) -> Sleep {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+/// Clock following the monotonic system time.
+/// The epoch is the creation time of the clock. The time is read
+/// from the tokio runtime, which can be paused in tests.
+/// The clock stops following the system time while paused.
+pub struct SystemClock {
+    epoch: Instant,
+    offset: watch::Sender<Offset>,
+}
+
+/// Offset of a system clock from the system time.
+#[derive(Clone, Copy, Debug, Default)]
+struct Offset {
+    /// System time at which the clock was paused, if paused.
+    paused_at: Option<Instant>,
+    /// Time spent paused, subtracted from the system time.
+    paused: Duration,
+    /// Time the clock was advanced by, added to the system time.
+    advanced: Duration,
+}
+
+impl Offset {
+    /// Return the time of the clock at the selected system time.
+    fn time(&self, epoch: Instant, now: Instant) -> Duration {
+        let now = self.paused_at.unwrap_or(now);
+        now.saturating_duration_since(epoch + self.paused) + self.advanced
+    }
+
+    /// Return the system time at which the clock reaches the selected
+    /// time, or None while the clock is paused before the selected time.
+    fn instant(&self, epoch: Instant, time: Duration) -> Option<Instant> {
+        match self.paused_at {
+            Some(paused_at) if self.time(epoch, paused_at) >= time => Some(paused_at),
+            Some(_) => None,
+            None => Some(
+                (epoch + self.paused + time)
+                    .checked_sub(self.advanced)
+                    .unwrap_or(epoch),
+            ),
+        }
+    }
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            epoch: Instant::now(),
+            offset: watch::channel(Offset::default()).0,
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.offset.borrow().time(self.epoch, Instant::now())
+    }
+
+    fn advance(&self, duration: Duration) {
+        self.offset
+            .send_modify(|offset| offset.advanced += duration);
+    }
+
+    fn pause(&self) {
+        self.offset.send_modify(|offset| {
+            offset.paused_at.get_or_insert_with(Instant::now);
+        });
+    }
+
+    fn resume(&self) {
+        self.offset.send_modify(|offset| {
+            if let Some(paused_at) = offset.paused_at.take() {
+                offset.paused += paused_at.elapsed();
+            }
+        });
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> Sleep {
+        let epoch = self.epoch;
+        let mut offset = self.offset.subscribe();
+        Box::pin(async move {
+            loop {
+                let instant = offset.borrow_and_update().instant(epoch, deadline);
+                match instant {
+                    Some(instant) => tokio::select! {
+                        _ = tokio::time::sleep_until(instant) => return,
+                        // The offset is final once the clock is dropped.
+                        result = offset.changed() => if result.is_err() {
+                            return tokio::time::sleep_until(instant).await;
+                        },
+                    },
+                    // The deadline is never reached once the clock
+                    // is dropped while paused.
+                    None => {
+                        if offset.changed().await.is_err() {
+                            std::future::pending::<()>().await;
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Clock whose time is set manually.
+/// Used to produce deterministic timestamps in tests. The sleeping
+/// tasks are woken up when the time is changed.
+pub struct MockClock {
+    now: watch::Sender<Duration>,
+}
+
+impl MockClock {
+    pub fn new(now: Duration) -> Self {
+        MockClock {
+            now: watch::channel(now).0,
+        }
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.send_modify(|current| *current = now);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.borrow()
+    }
+
+    fn advance(&self, duration: Duration) {
+        MockClock::advance(self, duration)
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> Sleep {
+        let mut now = self.now.subscribe();
+        Box::pin(async move {
+            while *now.borrow_and_update() < deadline {
+                // The deadline is never reached once the clock is dropped.
+                if now.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            }
+        })
+    }
+}
+
+/// Drift of the clock of a device relative to the shared clock,
+/// in parts per million. The local time of the device diverges
+/// linearly from the shared time, starting from the time the drift
+/// was last changed. The local time matches the shared time
+/// exactly when no drift is configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClockDrift {
+    ppm: f64,
+    origin: Duration,
+    local_origin: Duration,
+}
+
+impl ClockDrift {
+    /// Return the drift in parts per million.
+    pub fn ppm(&self) -> f64 {
+        self.ppm
+    }
+
+    /// Return the rate of the local clock relative to the shared clock.
+    pub fn rate(&self) -> f64 {
+        1. + self.ppm * 1e-6
+    }
+
+    /// Change the drift at the shared time `now`. The local time
+    /// stays continuous across the change.
+    pub fn set(&mut self, ppm: f64, now: Duration) {
+        self.local_origin = self.local_time(now);
+        self.origin = now;
+        self.ppm = ppm;
+    }
+
+    /// Convert the shared time `now` to the local time of the device.
+    /// The local time saturates at the maximum duration.
+    pub fn local_time(&self, now: Duration) -> Duration {
+        let elapsed = now.saturating_sub(self.origin);
+        let elapsed = if self.ppm == 0. {
+            elapsed
+        } else {
+            Duration::try_from_secs_f64(elapsed.as_secs_f64() * self.rate())
+                .unwrap_or(Duration::MAX)
+        };
+        self.local_origin.saturating_add(elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_drift_saturation() {
+        let mut drift = ClockDrift::default();
+        drift.set(999_999., Duration::ZERO);
+        assert_eq!(
+            drift.local_time(Duration::from_secs(10)),
+            Duration::from_secs_f64(10. * drift.rate())
+        );
+        assert_eq!(drift.local_time(Duration::MAX), Duration::MAX);
+
+        drift.set(-200., Duration::MAX);
+        assert_eq!(drift.local_time(Duration::MAX), Duration::MAX);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn system_clock_pause() {
+        let clock = SystemClock::new();
+        let mut sleep = clock.sleep(Duration::from_millis(100));
+
+        // The clock is frozen while paused.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        clock.pause();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(clock.now(), Duration::from_millis(50));
+        assert!(futures::poll!(&mut sleep).is_pending());
+
+        // Deadlines reached by advancing the paused clock expire.
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(clock.now(), Duration::from_millis(90));
+        let advanced = clock.sleep_until(Duration::from_millis(80));
+        assert!(tokio::time::timeout(Duration::from_millis(1), advanced)
+            .await
+            .is_ok());
+        assert!(futures::poll!(&mut sleep).is_pending());
+
+        // The time resumes from the advanced time.
+        clock.resume();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(futures::poll!(&mut sleep).is_pending());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(futures::poll!(&mut sleep).is_ready());
+        assert_eq!(clock.now(), Duration::from_millis(105));
+    }
+}
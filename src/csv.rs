@@ -0,0 +1,156 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of the ranging results reported to the hosts as CSV files.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use pdl_runtime::Packet;
+
+use crate::packets::uci::{self, *};
+use crate::{Clock, Handle, MacAddress};
+
+/// Columns of the CSV files.
+pub const HEADER: &str =
+    "timestamp_us,round_index,peer_address,distance,azimuth,elevation,status,rssi";
+
+/// Recorder of the ranging results reported to the host of a device
+/// connection. The results of each session are saved to a separate file
+/// named `device-{handle}-session-{session_token}.csv`. The file of a
+/// session is closed when the session leaves the active state, and
+/// reopened in append mode when the session is restarted.
+pub struct Recorder {
+    dir: PathBuf,
+    handle: Handle,
+    clock: Arc<dyn Clock>,
+    files: HashMap<u32, BufWriter<File>>,
+}
+
+/// Row of the CSV files, for one measurement of a ranging notification.
+/// The distance and RSSI are not reported by the one-way AoA measurements.
+struct Row {
+    peer_address: u16,
+    distance: Option<u16>,
+    azimuth: u16,
+    elevation: u16,
+    status: uci::Status,
+    rssi: Option<u8>,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path, handle: Handle, clock: Arc<dyn Clock>) -> Self {
+        Recorder {
+            dir: dir.to_path_buf(),
+            handle,
+            clock,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Record the ranging results carried by a packet sent to the host.
+    /// Packets other than the ranging and session status notifications
+    /// are ignored.
+    pub fn record(&mut self, packet: &[u8]) -> std::io::Result<()> {
+        if let Ok(ntf) = SessionStatusNtf::decode_full(packet) {
+            // Close the file of the sessions leaving the active state.
+            if ntf.session_state != SessionState::SessionStateActive {
+                if let Some(mut file) = self.files.remove(&ntf.session_token) {
+                    file.flush()?;
+                }
+            }
+            return Ok(());
+        }
+
+        let Ok(ntf) = SessionInfoNtf::decode_full(packet) else {
+            return Ok(());
+        };
+        let rows = match ntf.specialize() {
+            Ok(SessionInfoNtfChild::ShortMacTwoWaySessionInfoNtf(ntf)) => ntf
+                .two_way_ranging_measurements
+                .iter()
+                .map(|measurement| Row {
+                    peer_address: measurement.mac_address,
+                    distance: Some(measurement.distance),
+                    azimuth: measurement.aoa_azimuth,
+                    elevation: measurement.aoa_elevation,
+                    status: measurement.status,
+                    rssi: Some(measurement.rssi),
+                })
+                .collect::<Vec<_>>(),
+            Ok(SessionInfoNtfChild::ShortMacOwrAoaSessionInfoNtf(ntf)) => ntf
+                .owr_aoa_ranging_measurements
+                .iter()
+                .map(|measurement| Row {
+                    peer_address: measurement.mac_address,
+                    distance: None,
+                    azimuth: measurement.aoa_azimuth,
+                    elevation: measurement.aoa_elevation,
+                    status: measurement.status,
+                    rssi: None,
+                })
+                .collect::<Vec<_>>(),
+            _ => return Ok(()),
+        };
+
+        let timestamp = self.clock.now().as_micros();
+        let file = self.open(ntf.session_token)?;
+        for row in rows {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{}",
+                timestamp,
+                ntf.sequence_number,
+                MacAddress::Short(row.peer_address.to_le_bytes()),
+                row.distance.map(|d| d.to_string()).unwrap_or_default(),
+                row.azimuth as i16,
+                row.elevation as i16,
+                u8::from(row.status),
+                row.rssi.map(|r| r.to_string()).unwrap_or_default(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Flush the rows buffered for all sessions.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Return the file of the selected session, opened on first use.
+    /// The header is written to new files only.
+    fn open(&mut self, session_token: u32) -> std::io::Result<&mut BufWriter<File>> {
+        if !self.files.contains_key(&session_token) {
+            let path = self.dir.join(format!(
+                "device-{}-session-{}.csv",
+                self.handle, session_token
+            ));
+            log::debug!("Recording ranging results to file {}", path.display());
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let is_empty = file.metadata()?.len() == 0;
+            let mut file = BufWriter::new(file);
+            if is_empty {
+                writeln!(file, "{}", HEADER)?;
+            }
+            self.files.insert(session_token, file);
+        }
+        Ok(self.files.get_mut(&session_token).unwrap())
+    }
+}
@@ -12,19 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::noise::Rng;
 use crate::packets::uci::{self, *};
 use crate::MacAddress;
-use crate::PicaCommand;
+use crate::{Clock, ClockDrift, SystemClock};
+use crate::{ConfigSnapshot, SessionConfigSnapshot};
+use crate::{Fault, FaultBehavior};
+use crate::{PicaCommand, PicaError};
+use crate::{Timer, TimerKind, Timers};
 
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use pdl_runtime::Packet;
+use pdl_runtime::{DecodeError, Packet};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time;
 
-use super::app_config::SubSessionKey;
-use super::session::Session;
+use super::app_config::{ResultReportConfig, SubSessionKey};
+use super::session::{Session, SessionCommand, MAX_DATA_SIZE};
 use super::UciPacket;
 
 pub const MAX_DEVICE: usize = 4;
@@ -35,9 +43,283 @@ const MAC_VERSION: u16 = 0x3001; // Version 1.3.0
 const PHY_VERSION: u16 = 0x3001; // Version 1.3.0
 const TEST_VERSION: u16 = 0x1001; // Version 1.1
 
+/// Versions reported in the Core Get Device Info response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Versions {
+    pub uci_version: u16,
+    pub mac_version: u16,
+    pub phy_version: u16,
+    pub uci_test_version: u16,
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        Versions {
+            uci_version: UCI_VERSION,
+            mac_version: MAC_VERSION,
+            phy_version: PHY_VERSION,
+            uci_test_version: TEST_VERSION,
+        }
+    }
+}
+
+/// Largest vendor OUI, encoded on 24 bits.
+const MAX_VENDOR_OUI: u32 = 0xff_ffff;
+
+/// Chip identifiers reported in the Pica Get Chip Info response.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChipInfo {
+    vendor_oui: u32,
+    /// Part identifier of the chip.
+    pub part_id: u16,
+    /// Version of the chip.
+    pub chip_version: u16,
+}
+
+impl ChipInfo {
+    /// Create the chip identifiers, returning None if the IEEE
+    /// Organizationally Unique Identifier of the vendor exceeds 24 bits.
+    pub fn new(vendor_oui: u32, part_id: u16, chip_version: u16) -> Option<Self> {
+        (vendor_oui <= MAX_VENDOR_OUI).then_some(ChipInfo {
+            vendor_oui,
+            part_id,
+            chip_version,
+        })
+    }
+
+    /// Return the IEEE Organizationally Unique Identifier of the vendor.
+    pub fn vendor_oui(&self) -> u32 {
+        self.vendor_oui
+    }
+
+    /// Encode the identifiers with the layout of the
+    /// Pica Get Chip Info response, after the status.
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.vendor_oui.to_le_bytes()[..3].to_vec();
+        bytes.extend_from_slice(&self.part_id.to_le_bytes());
+        bytes.extend_from_slice(&self.chip_version.to_le_bytes());
+        bytes
+    }
+}
+
+/// Parse the chip identifiers from the format `oui:part_id:chip_version`.
+/// The fields are hexadecimal integers, optionally prefixed with `0x`.
+impl std::str::FromStr for ChipInfo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        fn parse_hex<T: num_traits::Num>(value: &str) -> anyhow::Result<T> {
+            T::from_str_radix(value.strip_prefix("0x").unwrap_or(value), 16)
+                .map_err(|_| anyhow::anyhow!("invalid hexadecimal integer: {}", value))
+        }
+
+        let fields = s.split(':').collect::<Vec<_>>();
+        let [vendor_oui, part_id, chip_version] = fields[..] else {
+            anyhow::bail!("expected oui:part_id:chip_version, got {}", s);
+        };
+        let vendor_oui = parse_hex(vendor_oui)?;
+        ChipInfo::new(vendor_oui, parse_hex(part_id)?, parse_hex(chip_version)?)
+            .ok_or_else(|| anyhow::anyhow!("vendor OUI exceeds 24 bits: 0x{:x}", vendor_oui))
+    }
+}
+
+/// Receive antenna configuration of the device.
+/// The configuration selects the angles of arrival the device is able
+/// to measure; unsupported angles are not advertised in the capabilities
+/// and are omitted from the ranging reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AntennaConfig {
+    /// Single receive antenna, the angle of arrival is not measured.
+    Single,
+    /// Two receive antennas, measuring the azimuth only.
+    Azimuth,
+    /// Three or more receive antennas, measuring both
+    /// the azimuth and the elevation.
+    #[default]
+    AzimuthElevation,
+}
+
+impl AntennaConfig {
+    pub fn supports_azimuth(&self) -> bool {
+        *self != AntennaConfig::Single
+    }
+
+    pub fn supports_elevation(&self) -> bool {
+        *self == AntennaConfig::AzimuthElevation
+    }
+
+    /// Restrict the SUPPORTED_AOA capability bitmask
+    /// to the angles measured by the antennas.
+    fn supported_aoa(&self, supported_aoa: u8) -> u8 {
+        match self {
+            AntennaConfig::Single => supported_aoa & !0xf,
+            AntennaConfig::Azimuth => supported_aoa & !0x4,
+            AntennaConfig::AzimuthElevation => supported_aoa,
+        }
+    }
+}
+
+/// Options selected on device creation, preserved across device resets.
+#[derive(Clone)]
+pub struct DeviceOptions {
+    /// Time source for the UWBS timestamp.
+    pub clock: Arc<dyn Clock>,
+    /// Delay between the Core Device Reset response and the
+    /// notification of the ready state.
+    pub reset_delay: Duration,
+    /// Delay between the connection of the host and the notification
+    /// of the initial state, simulating the boot time of the UWBS.
+    /// Commands received during the boot are rejected with the status
+    /// UCI_MESSAGE_RETRY.
+    pub boot_delay: Duration,
+    /// State entered on initialization and after the Core Device Reset
+    /// command. Defaults to ready, other states are used to simulate
+    /// faulty controllers.
+    pub initial_state: DeviceState,
+    /// Versions reported in the Core Get Device Info response.
+    pub versions: Versions,
+    /// Chip identifiers reported in the Pica Get Chip Info response.
+    pub chip_info: ChipInfo,
+    /// Report the chip identifiers in the vendor specific information
+    /// of the Core Get Device Info response, with the layout of the
+    /// Pica Get Chip Info response. No vendor specific information
+    /// is reported by default.
+    pub chip_info_in_device_info: bool,
+    /// Receive antenna configuration.
+    pub antenna_config: AntennaConfig,
+    /// Number of receive antenna pairs measuring the azimuth.
+    /// Devices with more than one pair report the angle of arrival
+    /// measured by each pair in the ranging notifications.
+    pub antenna_pairs: u8,
+    /// Maximum rate of inbound commands, in commands per second.
+    /// Commands exceeding the rate are rejected with the status
+    /// UCI_MESSAGE_RETRY. The rate is unlimited by default,
+    /// or when set to zero.
+    pub command_rate_limit: Option<u32>,
+    /// Processing window opened by each response, during which new
+    /// commands are rejected with the status UCI_MESSAGE_RETRY.
+    /// Commands are accepted at any time by default.
+    pub busy_window: Option<Duration>,
+    /// Maximum variation of the ranging interval, as a fraction of the
+    /// interval in the range [0, 0.9]. The interval of each ranging round
+    /// is drawn uniformly in the band. The interval is fixed by default.
+    pub ranging_jitter: f32,
+    /// Seed of the generator drawing the ranging interval variations.
+    pub ranging_jitter_seed: u64,
+    /// Processing delay of the responder between the reception of the
+    /// ranging poll and the transmission of the response, from which the
+    /// two-way ranging timing fields are derived. The timing fields are
+    /// not reported by default.
+    pub twr_reply_time: Option<Duration>,
+    /// Maximum number of sessions simultaneously active on the device.
+    /// Session Start commands exceeding the limit are rejected with the
+    /// status ERROR_MAX_SESSIONS_EXCEEDED. The number of active sessions
+    /// is only limited by the number of sessions by default.
+    pub max_active_sessions: Option<usize>,
+    /// Processing latency applied before sending the response to the
+    /// commands, identified by their group and opcode identifiers.
+    /// The latency is measured on the device clock. Responses are sent
+    /// without delay by default.
+    pub response_latencies: HashMap<(u8, u8), Duration>,
+    /// Capabilities reported in the Core Get Caps Info response in place
+    /// of the default values, identified by their raw TLV type. Types
+    /// unknown to pica are reported verbatim.
+    pub caps_overrides: Vec<(u8, Vec<u8>)>,
+    /// Emit a Core Generic Error notification for the protocol errors
+    /// detected by the device, in addition to the command response.
+    pub generic_error_notifications: bool,
+    /// Report the distances pushed below zero by the ranging offset as
+    /// negative values in two's complement, instead of saturating at zero.
+    /// Test-only escape hatch exercising the sanity checks of the host.
+    pub allow_negative_distance: bool,
+    /// Registry of the timers armed by the device tasks.
+    pub timers: Timers,
+}
+
+impl Default for DeviceOptions {
+    fn default() -> Self {
+        DeviceOptions {
+            clock: Arc::new(SystemClock::new()),
+            reset_delay: Duration::ZERO,
+            boot_delay: Duration::ZERO,
+            initial_state: DeviceState::DeviceStateReady,
+            versions: Default::default(),
+            chip_info: Default::default(),
+            chip_info_in_device_info: false,
+            antenna_config: Default::default(),
+            antenna_pairs: 1,
+            command_rate_limit: None,
+            busy_window: None,
+            ranging_jitter: 0.,
+            ranging_jitter_seed: 0,
+            twr_reply_time: None,
+            max_active_sessions: None,
+            response_latencies: HashMap::new(),
+            caps_overrides: vec![],
+            generic_error_notifications: false,
+            allow_negative_distance: false,
+            timers: Default::default(),
+        }
+    }
+}
+
+/// Token bucket limiting the rate of inbound commands.
+/// The bucket holds up to one second worth of commands.
+struct RateLimiter {
+    rate: u32,
+    tokens: f64,
+    last_refill: Duration,
+}
+
+impl RateLimiter {
+    fn new(rate: u32, now: Duration) -> Self {
+        RateLimiter {
+            rate,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Consume a token if available, return false if the
+    /// rate is exceeded.
+    fn try_acquire(&mut self, now: Duration) -> bool {
+        let elapsed = now.saturating_sub(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 /// cf. [UCI] 8.3 Table 29
 pub const MAX_NUMBER_OF_CONTROLEES: usize = 8;
 
+/// Initial temperature of the UWBS, in degrees Celsius.
+const DEFAULT_TEMPERATURE: i8 = 25;
+/// Temperature above which the UWBS reports the overheated
+/// thermal state, in degrees Celsius.
+const THERMAL_WARNING_THRESHOLD: i8 = 85;
+
+/// Maximum size of the value of a device configuration parameter.
+/// Larger values are rejected by the Core Set Config command,
+/// whatever the parameter.
+const MAX_CONFIG_VALUE_SIZE: usize = 16;
+
+/// First identifier of the vendor specific device configuration
+/// parameters. The parameters of the range not interpreted by the
+/// UWBS are stored as is, whatever the length of their value.
+const VENDOR_CONFIG_PARAMETER_ID: u8 = 0xe0;
+
+/// Upper bound of the ranging interval jitter, keeping the
+/// jittered interval strictly positive.
+const MAX_RANGING_JITTER: f32 = 0.9;
+/// Shortest interval between two jittered ranging rounds.
+const MIN_JITTERED_RANGING_INTERVAL: Duration = Duration::from_millis(1);
+
 // Capabilities are vendor defined
 // Android compliant: FIRA-287 UCI_Generic_Specification controlee capabilities_r4
 // Android parses capabilities, according to these definitions:
@@ -77,6 +359,168 @@ pub const DEFAULT_CAPS_INFO: &[(CapTlvType, &[u8])] = &[
     ),
 ];
 
+/// Return false if the value of an app config parameter is not
+/// supported by the device capabilities. Only the parameters
+/// constrained by a capability are checked.
+fn supports_app_config(caps: &[(u8, Vec<u8>)], id: AppConfigTlvType, value: &[u8]) -> bool {
+    let capability = |cap_id: CapTlvType| {
+        caps.iter()
+            .find(|(id, _)| *id == u8::from(cap_id))
+            .and_then(|(_, value)| value.first().copied())
+            .unwrap_or(0)
+    };
+    match (id, value) {
+        (AppConfigTlvType::ChannelNumber, [channel]) => {
+            channel_bit(*channel) & capability(CapTlvType::SupportedChannels) != 0
+        }
+        // Preamble code indices 9-12 are used with BPRF,
+        // 25-32 with HPRF.
+        (AppConfigTlvType::PreambleCodeIndex, [9..=12]) => {
+            capability(CapTlvType::SupportedBprfParameterSets) != 0
+        }
+        (AppConfigTlvType::PreambleCodeIndex, [25..=32]) => {
+            capability(CapTlvType::SupportedHprfParameterSets) != 0
+        }
+        (AppConfigTlvType::PreambleCodeIndex, _) => false,
+        (AppConfigTlvType::DeviceRole, [role @ (0x00 | 0x01)]) => {
+            device_role_bit(*role) & capability(CapTlvType::SupportedDeviceRoles) != 0
+        }
+        // Bitmask of SUPPORTED_AOA:
+        //   b0, b1: azimuth 90 or 180 degrees
+        //   b2: elevation
+        //   b3: figure of merit
+        (AppConfigTlvType::ResultReportConfig, [value]) => {
+            let supported_aoa = capability(CapTlvType::SupportedAoa);
+            ResultReportConfig::try_from(*value).is_ok_and(|report_config| {
+                (!report_config.aoa_azimuth || supported_aoa & 0x3 != 0)
+                    && (!report_config.aoa_elevation || supported_aoa & 0x4 != 0)
+                    && (!report_config.aoa_fom || supported_aoa & 0x8 != 0)
+            })
+        }
+        (AppConfigTlvType::RangingRoundUsage, [usage]) => {
+            let supported_methods = caps
+                .iter()
+                .find(|(id, _)| *id == u8::from(CapTlvType::SupportedRangingMethod))
+                .map(|(_, value)| {
+                    value
+                        .iter()
+                        .take(2)
+                        .rev()
+                        .fold(0, |mask, byte| (mask << 8) | *byte as u16)
+                })
+                .unwrap_or(0);
+            ranging_method_bit(*usage) & supported_methods != 0
+        }
+        _ => true,
+    }
+}
+
+/// Return the bit of the ranging round usage in the SUPPORTED_RANGING_METHOD
+/// bitmask:
+///   b0: OWR UL-TDoA, b1: SS-TWR deferred, b2: DS-TWR deferred,
+///   b3: SS-TWR non-deferred, b4: DS-TWR non-deferred, b5: OWR DL-TDoA,
+///   b6: OWR for AoA measurement, b7: eSS-TWR non-deferred,
+///   b8: aDS-TWR non-deferred
+fn ranging_method_bit(usage: u8) -> u16 {
+    match usage {
+        0x00..=0x08 => 1 << usage,
+        _ => 0,
+    }
+}
+
+/// Return the bit of the responder and initiator roles in the
+/// SUPPORTED_DEVICE_ROLES bitmask:
+///   b0: initiator, b1: responder
+fn device_role_bit(role: u8) -> u8 {
+    match role {
+        0x00 => 0x02,
+        0x01 => 0x01,
+        _ => 0,
+    }
+}
+
+/// Return the bit of the channel in the SUPPORTED_CHANNELS bitmask:
+///   b0: channel 5, b1: channel 6, b2: channel 8, b3: channel 9,
+///   b4: channel 10, b5: channel 12, b6: channel 13, b7: channel 14
+fn channel_bit(channel: u8) -> u8 {
+    match channel {
+        5 => 0x01,
+        6 => 0x02,
+        8 => 0x04,
+        9 => 0x08,
+        10 => 0x10,
+        12 => 0x20,
+        13 => 0x40,
+        14 => 0x80,
+        _ => 0,
+    }
+}
+
+/// Channels permitted in the regulatory region of the country code,
+/// as a SUPPORTED_CHANNELS bitmask. The channels are not restricted
+/// when the country code is unset or not present in the table.
+const SUPPORTED_CHANNELS_TABLE: &[([u8; 2], u8)] = &[
+    (*b"JP", 0x08), // Channel 9
+    (*b"KR", 0x09), // Channels 5 and 9
+    (*b"CN", 0x08), // Channel 9
+];
+
+/// Return the mask of the channels permitted for the country code.
+fn channel_mask(country_code: [u8; 2]) -> u8 {
+    SUPPORTED_CHANNELS_TABLE
+        .iter()
+        .find(|(code, _)| *code == country_code)
+        .map(|(_, mask)| *mask)
+        .unwrap_or(0xff)
+}
+
+/// Country code set by the host when the country is unknown.
+/// The default regulatory limits apply.
+const UNKNOWN_COUNTRY_CODE: [u8; 2] = *b"00";
+
+/// Validate an ISO 3166-1 alpha-2 country code. Lowercase letters are
+/// normalized to uppercase, codes containing other characters than
+/// ASCII letters are rejected, except for the unknown country code.
+fn normalize_country_code(country_code: [u8; 2]) -> Option<[u8; 2]> {
+    if country_code == UNKNOWN_COUNTRY_CODE {
+        return Some(country_code);
+    }
+    country_code
+        .iter()
+        .all(u8::is_ascii_alphabetic)
+        .then(|| country_code.map(|c| c.to_ascii_uppercase()))
+}
+
+/// Maximum transmit power (EIRP) in dBm permitted in the
+/// regulatory region of the country code.
+const MAX_TX_POWER_TABLE: &[([u8; 2], i8)] = &[
+    (*b"US", -14),
+    (*b"CA", -14),
+    (*b"GB", -14),
+    (*b"FR", -14),
+    (*b"DE", -14),
+    (*b"JP", -16),
+    (*b"KR", -16),
+    (*b"CN", -18),
+];
+
+/// Return the maximum transmit power in dBm for the country code.
+/// The most conservative limit applies when the country code is unset
+/// or not present in the regulatory table.
+fn max_tx_power(country_code: [u8; 2]) -> i8 {
+    MAX_TX_POWER_TABLE
+        .iter()
+        .find(|(code, _)| *code == country_code)
+        .map(|(_, max_tx_power)| *max_tx_power)
+        .unwrap_or_else(|| {
+            MAX_TX_POWER_TABLE
+                .iter()
+                .map(|(_, max_tx_power)| *max_tx_power)
+                .min()
+                .unwrap()
+        })
+}
+
 /// [UCI] 8.2 Device Configuration Parameters
 pub struct DeviceConfig {
     device_state: DeviceState,
@@ -84,6 +528,29 @@ pub struct DeviceConfig {
     //   0x00 = Disable low power mode
     //   0x01 = Enable low power mode (default)
     low_power_mode: bool,
+    // Vendor specific: transmit power in dBm, bounded by the maximum
+    // transmit power of the current country code.
+    // The parameter has no value until set by the Host.
+    tx_power: Option<i8>,
+    // Vendor specific: offset in centimeters added to the measured
+    // distances, modelling the antenna delays.
+    ranging_offset: i16,
+    // Vendor specific: report the radial velocity of the peers
+    // in the ranging notifications.
+    radial_velocity_report: bool,
+    // Vendor specific: bounds in centimeters of the distances
+    // reported by the device.
+    min_reported_distance: Option<u16>,
+    max_reported_distance: Option<u16>,
+    // Vendor specific: number of ranging rounds coalesced
+    // in each ranging notification.
+    coalescing_window: u8,
+    // Vendor specific: speed threshold in centimeters per second
+    // above which the peers are reported as mobile.
+    movement_threshold: Option<u16>,
+    // Vendor specific parameters not interpreted by the UWBS.
+    // The raw values are preserved to be returned by CORE_GET_CONFIG.
+    vendor_parameters: BTreeMap<u8, Vec<u8>>,
 }
 
 // [UCI] 6.3.1 Setting the Configuration
@@ -94,7 +561,50 @@ impl Default for DeviceConfig {
         DeviceConfig {
             device_state: DeviceState::DeviceStateError,
             low_power_mode: true,
+            tx_power: None,
+            ranging_offset: 0,
+            radial_velocity_report: false,
+            min_reported_distance: None,
+            max_reported_distance: None,
+            coalescing_window: 0,
+            movement_threshold: None,
+            vendor_parameters: BTreeMap::new(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    /// Decode the configuration parameters for display, indexed by
+    /// parameter name. Parameters without value are omitted.
+    fn decode(&self) -> BTreeMap<String, Value> {
+        fn name(id: ConfigParameterId) -> String {
+            format!("{:?}", id)
+        }
+
+        let mut config = BTreeMap::new();
+        config.insert(
+            name(ConfigParameterId::DeviceState),
+            format!("{:?}", self.device_state).into(),
+        );
+        config.insert(
+            name(ConfigParameterId::LowPowerMode),
+            self.low_power_mode.into(),
+        );
+        if let Some(tx_power) = self.tx_power {
+            config.insert(name(ConfigParameterId::TxPower), tx_power.into());
+        }
+        config.insert(
+            name(ConfigParameterId::RangingOffset),
+            self.ranging_offset.into(),
+        );
+        config.insert(
+            name(ConfigParameterId::RadialVelocityReport),
+            self.radial_velocity_report.into(),
+        );
+        for (id, value) in &self.vendor_parameters {
+            config.insert(format!("0x{:02x}", id), hex::encode(value).into());
         }
+        config
     }
 }
 
@@ -109,11 +619,31 @@ pub struct Device {
     config: DeviceConfig,
     /// [UCI] 5. UWBS Device State Machine
     state: DeviceState,
+    options: DeviceOptions,
     sessions: HashMap<u32, Session>,
     pub tx: mpsc::UnboundedSender<UciPacket>,
     pica_tx: mpsc::Sender<PicaCommand>,
     country_code: [u8; 2],
     pub n_active_sessions: usize,
+    /// Faults injected in the command handler.
+    /// Faults are preserved across device resets.
+    faults: Vec<Fault>,
+    /// Simulated temperature of the UWBS, in degrees Celsius.
+    /// The temperature is preserved across device resets.
+    temperature: i8,
+    /// Drift of the clock of the UWBS relative to the shared clock.
+    /// The drift is preserved across device resets.
+    clock_drift: ClockDrift,
+    /// Limiter for the rate of inbound commands.
+    /// The limiter is preserved across device resets.
+    rate_limiter: Option<RateLimiter>,
+    /// Time until which new commands are rejected, opened by the
+    /// previous response when the busy window is configured.
+    busy_until: Duration,
+    /// State announced by the delayed device status notification,
+    /// cleared when the notification is sent. Commands are rejected
+    /// until the notification is sent.
+    pending_state: Arc<Mutex<Option<DeviceState>>>,
 }
 
 impl Device {
@@ -122,43 +652,127 @@ impl Device {
         mac_address: MacAddress,
         tx: mpsc::UnboundedSender<UciPacket>,
         pica_tx: mpsc::Sender<PicaCommand>,
+        options: DeviceOptions,
     ) -> Self {
+        let rate_limiter = options
+            .command_rate_limit
+            .filter(|rate| *rate > 0)
+            .map(|rate| RateLimiter::new(rate, options.clock.now()));
         Device {
             handle,
             mac_address,
             is_reset: false,
             config: Default::default(),
             state: DeviceState::DeviceStateError, // Will be overwitten
+            options,
             sessions: Default::default(),
             tx,
             pica_tx,
             country_code: Default::default(),
             n_active_sessions: 0,
+            faults: vec![],
+            temperature: DEFAULT_TEMPERATURE,
+            clock_drift: Default::default(),
+            rate_limiter,
+            busy_until: Duration::ZERO,
+            pending_state: Default::default(),
         }
     }
 
     pub fn set_state(&mut self, device_state: DeviceState) {
+        self.set_state_with_delay(device_state, Duration::ZERO)
+    }
+
+    /// Change the device state, and send the status notification
+    /// after the selected delay.
+    fn set_state_with_delay(&mut self, device_state: DeviceState, delay: Duration) {
         // No transition: ignore
         if device_state == self.state {
             return;
         }
 
-        // Send status notification
         self.state = device_state;
+        self.send_device_status_ntf(delay);
+    }
+
+    /// Send the status notification for the current device state
+    /// after the selected delay. The device is busy until the
+    /// notification is sent when the delay is not zero.
+    fn send_device_status_ntf(&self, delay: Duration) {
+        let device_state = self.state;
         let tx = self.tx.clone();
+        let pending_state = self.pending_state.clone();
+        if !delay.is_zero() {
+            *pending_state.lock().unwrap() = Some(device_state);
+        }
+        let timer = (!delay.is_zero()).then(|| {
+            self.options.timers.register(
+                TimerKind::ResetDelay,
+                self.handle,
+                None,
+                self.options.clock.clone(),
+                delay,
+            )
+        });
+        // The delay is measured on the shared clock, up to the
+        // deadline of the registered timer.
+        let sleep = match &timer {
+            Some(timer) => timer.sleep(),
+            None => self.options.clock.sleep(delay),
+        };
         tokio::spawn(async move {
+            let _timer = timer;
+            // The notification is sent after the response to the command.
             time::sleep(Duration::from_millis(5)).await;
-            tx.send(
+            sleep.await;
+            // The pending state is cleared when the device is reset
+            // again in the meantime, the notification is then dropped.
+            let mut pending_state = pending_state.lock().unwrap();
+            if !delay.is_zero() && pending_state.take().is_none() {
+                return;
+            }
+            // The host connection may be closed in the meantime,
+            // the device is then disconnected by the connection task.
+            let _ = tx.send(
                 CoreDeviceStatusNtf { device_state }
                     .encode_to_vec()
                     .unwrap(),
-            )
-            .unwrap()
+            );
         });
     }
 
     pub fn init(&mut self) {
-        self.set_state(DeviceState::DeviceStateReady);
+        self.state = self.options.initial_state;
+        // The device is busy until the end of the boot, when the
+        // pending device status notification is sent.
+        self.send_device_status_ntf(self.options.boot_delay);
+    }
+
+    pub fn state(&self) -> DeviceState {
+        self.state
+    }
+
+    /// Return true if the delayed device status notification
+    /// has not been sent yet.
+    fn is_state_pending(&self) -> bool {
+        self.pending_state.lock().unwrap().is_some()
+    }
+
+    /// Return true if the device is suspended with the Pica Suspend
+    /// command. The active sessions of a suspended device do not range.
+    pub fn is_suspended(&self) -> bool {
+        self.state == DeviceState::DeviceStateLowPower
+    }
+
+    /// Reset the device on behalf of the host, as when receiving the
+    /// Core Device Reset command. No response is sent to the host.
+    /// Returns the state of the device after the reset.
+    pub fn reset(&mut self) -> DeviceState {
+        self.stop_ranging_tasks();
+        self.core_device_reset(CoreDeviceResetCmd {
+            reset_config: ResetConfig::UwbsReset,
+        });
+        self.state
     }
 
     pub fn session(&self, session_id: u32) -> Option<&Session> {
@@ -169,10 +783,225 @@ impl Device {
         self.sessions.get_mut(&session_id)
     }
 
+    /// Decode the device configuration, and the APP configuration
+    /// of the device sessions, for display.
+    pub fn config_snapshot(&self) -> ConfigSnapshot {
+        let mut sessions = self
+            .sessions
+            .iter()
+            .map(|(session_id, session)| SessionConfigSnapshot {
+                session_id: *session_id,
+                app_config: session.app_config.decode(),
+            })
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|session| session.session_id);
+        ConfigSnapshot {
+            mac_address: self.mac_address,
+            config: self.config.decode(),
+            sessions,
+        }
+    }
+
+    /// Return the identifiers of the active sessions, in increasing order.
+    pub fn active_session_ids(&self) -> Vec<u32> {
+        let mut session_ids = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.session_state() == SessionState::SessionStateActive)
+            .map(|(session_id, _)| *session_id)
+            .collect::<Vec<_>>();
+        session_ids.sort();
+        session_ids
+    }
+
+    pub fn antenna_config(&self) -> AntennaConfig {
+        self.options.antenna_config
+    }
+
+    pub fn antenna_pairs(&self) -> u8 {
+        self.options.antenna_pairs
+    }
+
+    /// Offset in centimeters added to the distances measured
+    /// by the device.
+    pub fn ranging_offset(&self) -> i16 {
+        self.config.ranging_offset
+    }
+
+    /// Return true if the ranging offset can push the distances
+    /// reported by the device below zero.
+    pub fn allow_negative_distance(&self) -> bool {
+        self.options.allow_negative_distance
+    }
+
+    /// Reply time used to derive the two-way ranging timing fields
+    /// reported by the device, if enabled.
+    pub fn twr_reply_time(&self) -> Option<Duration> {
+        self.options.twr_reply_time
+    }
+
+    /// Bounds in centimeters of the distances reported by the device.
+    /// Shorter distances are clamped to the minimum, longer distances
+    /// are reported as unavailable.
+    pub fn reported_distance_limits(&self) -> (Option<u16>, Option<u16>) {
+        (
+            self.config.min_reported_distance,
+            self.config.max_reported_distance,
+        )
+    }
+
+    /// Number of ranging rounds coalesced in each ranging notification.
+    /// Every round is reported when the window is 0 or 1.
+    pub fn coalescing_window(&self) -> u8 {
+        self.config.coalescing_window
+    }
+
+    /// Speed threshold in centimeters per second above which the peers
+    /// are reported as mobile. The movement indicator is not reported
+    /// until the threshold is configured.
+    pub fn movement_threshold(&self) -> Option<u16> {
+        self.config.movement_threshold
+    }
+
+    /// Return true if the radial velocity of the peers is reported
+    /// in the ranging notifications.
+    pub fn radial_velocity_report(&self) -> bool {
+        self.config.radial_velocity_report
+    }
+
+    /// Return true if a session with the selected identifier
+    /// can be attached to the device.
+    pub fn can_attach_session(&self, session_id: u32) -> bool {
+        self.sessions.len() < MAX_SESSION && !self.sessions.contains_key(&session_id)
+    }
+
+    /// Detach the selected session from the device.
+    /// The ranging task is stopped if the session is active.
+    pub fn detach_session(&mut self, session_id: u32) -> Option<Session> {
+        let mut session = self.sessions.remove(&session_id)?;
+        if session.state == SessionState::SessionStateActive {
+            session.stop_ranging_task();
+            self.n_active_sessions -= 1;
+            if self.n_active_sessions == 0 {
+                self.set_state(DeviceState::DeviceStateReady);
+            }
+        }
+        Some(session)
+    }
+
+    /// Stop the ranging tasks of all the sessions of the device.
+    pub fn stop_ranging_tasks(&mut self) {
+        self.sessions
+            .values_mut()
+            .for_each(|session| session.stop_ranging_task());
+    }
+
+    /// Attach a session detached from another device.
+    /// The ranging task is restarted if the session is active.
+    pub fn attach_session(&mut self, mut session: Session) {
+        let session_id = session.id();
+        session.rebind(self.handle, self.tx.clone());
+        if session.state == SessionState::SessionStateActive {
+            let ranging_interval =
+                time::Duration::from_millis(session.app_config.ranging_duration as u64);
+            session.ranging_task = Some(spawn_ranging_task(
+                self.pica_tx.clone(),
+                self.handle,
+                session_id,
+                ranging_interval,
+                self.options.ranging_jitter,
+                self.ranging_jitter_rng(session_id),
+                self.ranging_timer(session_id),
+            ));
+            self.n_active_sessions += 1;
+            self.set_state(DeviceState::DeviceStateActive);
+        }
+        assert!(self.sessions.insert(session_id, session).is_none());
+    }
+
+    /// Register the timer of the ranging task of the selected session.
+    fn ranging_timer(&self, session_id: u32) -> Timer {
+        self.options.timers.register(
+            TimerKind::Ranging,
+            self.handle,
+            Some(session_id),
+            self.options.clock.clone(),
+            Duration::ZERO,
+        )
+    }
+
+    /// Return the generator drawing the ranging interval variations
+    /// of the selected session. The sequence only depends on the
+    /// configured seed and on the device and session identifiers.
+    fn ranging_jitter_rng(&self, session_id: u32) -> Rng {
+        Rng::new(
+            self.options.ranging_jitter_seed ^ ((self.handle as u64) << 32) ^ session_id as u64,
+        )
+    }
+
+    /// Stop an active session on behalf of the UWBS, and report the
+    /// selected reason code to the host.
+    pub fn stop_session(&mut self, session_id: u32, reason_code: ReasonCode) {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+        session.stop_ranging_task();
+        session.set_state(SessionState::SessionStateIdle, reason_code);
+        self.n_active_sessions = self.n_active_sessions.saturating_sub(1);
+        if self.n_active_sessions == 0 {
+            self.set_state(DeviceState::DeviceStateReady);
+        }
+    }
+
+    /// Override the reason code reported when the host stops the selected
+    /// session, or restore the reason of the state changes requested by
+    /// the host. Returns false if the session does not exist.
+    pub fn set_stop_reason(&mut self, session_id: u32, reason_code: Option<ReasonCode>) -> bool {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return false;
+        };
+        session.stop_reason = reason_code;
+        true
+    }
+
+    /// Change the state of a session on behalf of the UWBS, bypassing the
+    /// session state machine, and report the change to the host.
+    /// Sessions leaving the active state stop ranging, sessions entering
+    /// the active state do not start ranging.
+    /// Returns false if the session does not exist.
+    pub fn inject_session_status(
+        &mut self,
+        session_id: u32,
+        session_state: SessionState,
+        reason_code: ReasonCode,
+    ) -> bool {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return false;
+        };
+        let was_active = session.session_state() == SessionState::SessionStateActive;
+        let is_active = session_state == SessionState::SessionStateActive;
+        if was_active && !is_active {
+            session.stop_ranging_task();
+        }
+        session.inject_state(session_state, reason_code);
+
+        if was_active && !is_active {
+            self.n_active_sessions = self.n_active_sessions.saturating_sub(1);
+            if self.n_active_sessions == 0 {
+                self.set_state(DeviceState::DeviceStateReady);
+            }
+        } else if !was_active && is_active {
+            self.n_active_sessions += 1;
+            self.set_state(DeviceState::DeviceStateActive);
+        }
+        true
+    }
+
     pub fn can_start_ranging(&self, peer_session: &Session, session_id: u32) -> bool {
         match self.session(session_id) {
             Some(session) => {
-                session.session_state() == SessionState::SessionStateActive
+                !self.is_suspended()
+                    && session.session_state() == SessionState::SessionStateActive
                     && session
                         .app_config
                         .is_compatible_for_ranging(&peer_session.app_config)
@@ -203,6 +1032,85 @@ impl Device {
         }
     }
 
+    pub fn inject_fault(&mut self, fault: Fault) {
+        if fault.count > 0 {
+            self.faults.push(fault)
+        }
+    }
+
+    /// Return the behavior of the first injected fault matching the
+    /// group and opcode identifiers, and consume one occurrence of the fault.
+    fn take_fault(&mut self, gid: u8, oid: u8) -> Option<FaultBehavior> {
+        let index = self
+            .faults
+            .iter()
+            .position(|fault| u8::from(fault.gid) == gid && fault.oid == oid)?;
+        let fault = &mut self.faults[index];
+        let behavior = fault.behavior;
+        fault.count -= 1;
+        if fault.count == 0 {
+            self.faults.remove(index);
+        }
+        Some(behavior)
+    }
+
+    /// Update the country code of the UWBS, after normalization to
+    /// uppercase. The transmit power is bounded by the limit of the new
+    /// country code. Returns false if the country code is invalid,
+    /// in which case the current country code is left unchanged.
+    pub fn set_country_code(&mut self, country_code: [u8; 2]) -> bool {
+        let Some(country_code) = normalize_country_code(country_code) else {
+            return false;
+        };
+        self.country_code = country_code;
+        self.config.tx_power = self
+            .config
+            .tx_power
+            .map(|tx_power| tx_power.min(max_tx_power(country_code)));
+        true
+    }
+
+    /// Update the temperature of the UWBS. The thermal status notification
+    /// is sent when the temperature crosses the thermal warning threshold.
+    pub fn set_temperature(&mut self, temperature: i8) {
+        let was_overheated = self.temperature > THERMAL_WARNING_THRESHOLD;
+        let is_overheated = temperature > THERMAL_WARNING_THRESHOLD;
+        self.temperature = temperature;
+
+        if was_overheated != is_overheated {
+            let thermal_state = if is_overheated {
+                ThermalState::Overheated
+            } else {
+                ThermalState::Normal
+            };
+            log::info!(
+                "[{}] Thermal state changed to {:?}",
+                self.handle,
+                thermal_state
+            );
+            self.send_control(PicaThermalStatusNtf {
+                thermal_state,
+                temperature: temperature as u8,
+            })
+        }
+    }
+
+    pub fn clock_drift(&self) -> ClockDrift {
+        self.clock_drift
+    }
+
+    /// Change the drift of the clock of the UWBS, in parts per million.
+    /// The UWBS timestamp remains continuous across the change.
+    pub fn set_clock_drift(&mut self, ppm: f64) {
+        self.clock_drift.set(ppm, self.options.clock.now());
+    }
+
+    /// Return the time of the UWBS clock, derived from the
+    /// shared clock and the drift of the device.
+    pub fn uwbs_time(&self) -> Duration {
+        self.clock_drift.local_time(self.options.clock.now())
+    }
+
     // Send a response or notification to the Host.
     fn send_raw_control(&mut self, packet: Vec<u8>) {
         let _ = self.tx.send(packet);
@@ -213,6 +1121,15 @@ impl Device {
         self.send_raw_control(packet.encode_to_vec().unwrap());
     }
 
+    // Send a Core Generic Error notification to the Host,
+    // if enabled in the device options.
+    fn send_generic_error(&mut self, status: uci::Status) {
+        if self.options.generic_error_notifications {
+            log::debug!("[{}] GenericError status={:?}", self.handle, status);
+            self.send_control(CoreGenericErrorNtf { status });
+        }
+    }
+
     // The fira norm specify to send a response, then reset, then
     // send a notification once the reset is done
     fn core_device_reset(&mut self, cmd: CoreDeviceResetCmd) -> CoreDeviceResetRsp {
@@ -220,14 +1137,44 @@ impl Device {
         log::debug!("[{}] DeviceReset", self.handle);
         log::debug!("  reset_config={:?}", reset_config);
 
+        // Unsupported reset configurations are rejected,
+        // the device state is left unchanged.
+        match reset_config {
+            ResetConfig::UwbsReset => (),
+            ResetConfig::Rfu(_) => {
+                log::warn!(
+                    "[{}] Unsupported reset config {:?}",
+                    self.handle,
+                    reset_config
+                );
+                return CoreDeviceResetRsp {
+                    status: uci::Status::InvalidParam,
+                };
+            }
+        }
+
+        // Drop the notification still pending from a previous reset.
+        self.pending_state.lock().unwrap().take();
+        let faults = std::mem::take(&mut self.faults);
+        let temperature = self.temperature;
+        let clock_drift = self.clock_drift;
+        let rate_limiter = self.rate_limiter.take();
         *self = Device::new(
             self.handle,
             self.mac_address,
             self.tx.clone(),
             self.pica_tx.clone(),
+            self.options.clone(),
         );
+        self.faults = faults;
+        self.temperature = temperature;
+        self.clock_drift = clock_drift;
+        self.rate_limiter = rate_limiter;
         self.is_reset = true;
-        self.init();
+        // The device reports no state, and rejects commands as busy,
+        // until the configured reset delay has elapsed.
+        self.state = self.options.initial_state;
+        self.send_device_status_ntf(self.options.reset_delay);
 
         CoreDeviceResetRsp {
             status: uci::Status::Ok,
@@ -237,37 +1184,102 @@ impl Device {
     fn core_get_device_info(&self, _cmd: CoreGetDeviceInfoCmd) -> CoreGetDeviceInfoRsp {
         // TODO: Implement a fancy build time state machine instead of crash at runtime
         log::debug!("[{}] GetDeviceInfo", self.handle);
-        assert_eq!(self.state, DeviceState::DeviceStateReady);
         CoreGetDeviceInfoRsp {
             status: uci::Status::Ok,
-            uci_version: UCI_VERSION,
-            mac_version: MAC_VERSION,
-            phy_version: PHY_VERSION,
-            uci_test_version: TEST_VERSION,
-            vendor_spec_info: Vec::new(),
+            uci_version: self.options.versions.uci_version,
+            mac_version: self.options.versions.mac_version,
+            phy_version: self.options.versions.phy_version,
+            uci_test_version: self.options.versions.uci_test_version,
+            vendor_spec_info: if self.options.chip_info_in_device_info {
+                self.options.chip_info.to_bytes()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Replace the value of the selected capability, or add the
+    /// capability if not reported by default. The override is preserved
+    /// across device resets.
+    pub fn override_capability(&mut self, id: u8, value: Vec<u8>) {
+        let caps_overrides = &mut self.options.caps_overrides;
+        match caps_overrides.iter_mut().find(|(cap_id, _)| *cap_id == id) {
+            Some((_, cap_value)) => *cap_value = value,
+            None => caps_overrides.push((id, value)),
+        }
+    }
+
+    /// Return the capabilities advertised by the device,
+    /// identified by their raw TLV type.
+    fn caps_info(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut caps: Vec<(u8, Vec<u8>)> = DEFAULT_CAPS_INFO
+            .iter()
+            .map(|(id, value)| {
+                let value = match id {
+                    // The supported angles of arrival depend on the antennas.
+                    CapTlvType::SupportedAoa => value
+                        .iter()
+                        .map(|aoa| self.options.antenna_config.supported_aoa(*aoa))
+                        .collect(),
+                    // The supported channels depend on the country code.
+                    CapTlvType::SupportedChannels => value
+                        .iter()
+                        .map(|channels| channels & channel_mask(self.country_code))
+                        .collect(),
+                    _ => (*value).into(),
+                };
+                (u8::from(id), value)
+            })
+            .collect();
+
+        // The maximum transmit power depends on the country code.
+        caps.push((
+            CapTlvType::SupportedMaxTxPower.into(),
+            vec![max_tx_power(self.country_code) as u8],
+        ));
+
+        for (id, value) in &self.options.caps_overrides {
+            match caps.iter_mut().find(|(cap_id, _)| cap_id == id) {
+                Some((_, cap_value)) => cap_value.clone_from(value),
+                None => caps.push((*id, value.clone())),
+            }
         }
+
+        caps
     }
 
     pub fn core_get_caps_info(&self, _cmd: CoreGetCapsInfoCmd) -> CoreGetCapsInfoRsp {
         log::debug!("[{}] GetCapsInfo", self.handle);
 
-        let caps = DEFAULT_CAPS_INFO
-            .iter()
+        // All the 8-bit TLV types are representable, types not
+        // defined in the specification are mapped to the RFU and
+        // vendor specific ranges.
+        let tlvs = self
+            .caps_info()
+            .into_iter()
             .map(|(id, value)| CapTlv {
-                t: *id,
-                v: (*value).into(),
+                t: CapTlvType::try_from(id).unwrap(),
+                v: value,
             })
             .collect();
 
         CoreGetCapsInfoRsp {
             status: uci::Status::Ok,
-            tlvs: caps,
+            tlvs,
         }
     }
 
     pub fn core_set_config(&mut self, cmd: CoreSetConfigCmd) -> CoreSetConfigRsp {
         log::debug!("[{}] SetConfig", self.handle);
-        assert_eq!(self.state, DeviceState::DeviceStateReady); // UCI 6.3
+
+        // [UCI] 6.3 The configuration can only be modified
+        // in the ready state.
+        if self.state != DeviceState::DeviceStateReady {
+            return CoreSetConfigRsp {
+                status: uci::Status::Rejected,
+                parameters: vec![],
+            };
+        }
 
         // [UCI] 6.3.1 Setting the Configuration
         // The UWBS shall respond with CORE_SET_CONFIG_RSP setting the Status
@@ -276,7 +1288,23 @@ impl Device {
         // available in the UWBS. All other configuration parameters should
         // have been set to the new values within the UWBS.
         let mut invalid_parameters = vec![];
+        // The distance bounds are checked against each other
+        // once all the parameters are parsed.
+        let mut min_reported_distance = None;
+        let mut max_reported_distance = None;
         for parameter in cmd.parameters {
+            if parameter.value.len() > MAX_CONFIG_VALUE_SIZE {
+                log::warn!(
+                    "config parameter {:?} value exceeds {} bytes",
+                    parameter.id,
+                    MAX_CONFIG_VALUE_SIZE
+                );
+                invalid_parameters.push(uci::ConfigParameterStatus {
+                    id: parameter.id,
+                    status: uci::Status::InvalidParam,
+                });
+                continue;
+            }
             match parameter.id {
                 uci::ConfigParameterId::DeviceState => {
                     invalid_parameters.push(uci::ConfigParameterStatus {
@@ -284,8 +1312,72 @@ impl Device {
                         status: uci::Status::ReadOnly,
                     })
                 }
-                uci::ConfigParameterId::LowPowerMode => {
-                    self.config.low_power_mode = parameter.value.first().copied().unwrap_or(1) != 0;
+                uci::ConfigParameterId::LowPowerMode => match parameter.value[..] {
+                    [enabled @ (0 | 1)] => self.config.low_power_mode = enabled != 0,
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::TxPower => match parameter.value[..] {
+                    [tx_power] if tx_power as i8 <= max_tx_power(self.country_code) => {
+                        self.config.tx_power = Some(tx_power as i8)
+                    }
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::RangingOffset => match parameter.value[..] {
+                    [lsb, msb] => self.config.ranging_offset = i16::from_le_bytes([lsb, msb]),
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::RadialVelocityReport => match parameter.value[..] {
+                    [enabled @ (0 | 1)] => self.config.radial_velocity_report = enabled != 0,
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::MinReportedDistance => match parameter.value[..] {
+                    [lsb, msb] => min_reported_distance = Some(u16::from_le_bytes([lsb, msb])),
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::MaxReportedDistance => match parameter.value[..] {
+                    [lsb, msb] => max_reported_distance = Some(u16::from_le_bytes([lsb, msb])),
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::CoalescingWindow => match parameter.value[..] {
+                    [window] => self.config.coalescing_window = window,
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::MovementThreshold => match parameter.value[..] {
+                    [lsb, msb] => {
+                        self.config.movement_threshold = Some(u16::from_le_bytes([lsb, msb]))
+                    }
+                    _ => invalid_parameters.push(uci::ConfigParameterStatus {
+                        id: parameter.id,
+                        status: uci::Status::InvalidParam,
+                    }),
+                },
+                uci::ConfigParameterId::Rfu(id) if *id >= VENDOR_CONFIG_PARAMETER_ID => {
+                    log::debug!(
+                        "storing uninterpreted vendor config parameter 0x{:02x}",
+                        *id
+                    );
+                    self.config.vendor_parameters.insert(*id, parameter.value);
                 }
                 uci::ConfigParameterId::Rfu(id) => {
                     log::warn!("unknown config parameter id 0x{:02x}", *id);
@@ -297,6 +1389,41 @@ impl Device {
             }
         }
 
+        // The minimum distance cannot exceed the maximum distance,
+        // the bounds are left unchanged otherwise.
+        match (
+            min_reported_distance.or(self.config.min_reported_distance),
+            max_reported_distance.or(self.config.max_reported_distance),
+        ) {
+            (Some(min), Some(max)) if min > max => {
+                for (id, value) in [
+                    (
+                        uci::ConfigParameterId::MinReportedDistance,
+                        min_reported_distance,
+                    ),
+                    (
+                        uci::ConfigParameterId::MaxReportedDistance,
+                        max_reported_distance,
+                    ),
+                ] {
+                    if value.is_some() {
+                        invalid_parameters.push(uci::ConfigParameterStatus {
+                            id,
+                            status: uci::Status::InvalidParam,
+                        })
+                    }
+                }
+            }
+            _ => {
+                if min_reported_distance.is_some() {
+                    self.config.min_reported_distance = min_reported_distance;
+                }
+                if max_reported_distance.is_some() {
+                    self.config.max_reported_distance = max_reported_distance;
+                }
+            }
+        }
+
         CoreSetConfigRsp {
             status: if invalid_parameters.is_empty() {
                 uci::Status::Ok
@@ -317,6 +1444,9 @@ impl Device {
         // Device Configuration Parameter Type with Length field is zero. In
         // this case, the CORE_GET_CONFIG_RSP shall not include any parameter(s)
         // that are available in the UWBS.
+        //
+        // Parameters known to the UWBS but without a stored value are
+        // available, and returned with the Length field set to zero.
         let mut valid_parameters = vec![];
         let mut invalid_parameters = vec![];
         for id in cmd.parameter_ids {
@@ -329,8 +1459,60 @@ impl Device {
                     id,
                     value: vec![self.config.low_power_mode.into()],
                 }),
+                ConfigParameterId::TxPower => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: self
+                        .config
+                        .tx_power
+                        .map(|tx_power| vec![tx_power as u8])
+                        .unwrap_or_default(),
+                }),
+                ConfigParameterId::RangingOffset => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: self.config.ranging_offset.to_le_bytes().to_vec(),
+                }),
+                ConfigParameterId::RadialVelocityReport => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: vec![self.config.radial_velocity_report.into()],
+                }),
+                ConfigParameterId::MinReportedDistance => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: self
+                        .config
+                        .min_reported_distance
+                        .map(|distance| distance.to_le_bytes().to_vec())
+                        .unwrap_or_default(),
+                }),
+                ConfigParameterId::MaxReportedDistance => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: self
+                        .config
+                        .max_reported_distance
+                        .map(|distance| distance.to_le_bytes().to_vec())
+                        .unwrap_or_default(),
+                }),
+                ConfigParameterId::CoalescingWindow => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: vec![self.config.coalescing_window],
+                }),
+                ConfigParameterId::MovementThreshold => valid_parameters.push(ConfigParameter {
+                    id,
+                    value: self
+                        .config
+                        .movement_threshold
+                        .map(|threshold| threshold.to_le_bytes().to_vec())
+                        .unwrap_or_default(),
+                }),
+                // Vendor specific parameters are available once set.
                 ConfigParameterId::Rfu(_) => {
-                    invalid_parameters.push(ConfigParameter { id, value: vec![] })
+                    let value = self.config.vendor_parameters.get(&u8::from(id));
+                    match value {
+                        Some(value) => valid_parameters.push(ConfigParameter {
+                            id,
+                            value: value.clone(),
+                        }),
+                        None => invalid_parameters.push(ConfigParameter { id, value: vec![] }),
+                    }
                 }
             }
         }
@@ -348,6 +1530,17 @@ impl Device {
         }
     }
 
+    fn core_query_uwbs_timestamp(&self, _cmd: CoreQueryTimeStampCmd) -> CoreQueryTimeStampRsp {
+        log::debug!("[{}] QueryUwbsTimestamp", self.handle);
+
+        // [UCI] 7.4.3 Query UWBS Timestamp
+        // The UWBS timestamp is reported in microseconds.
+        CoreQueryTimeStampRsp {
+            status: uci::Status::Ok,
+            timeStamp: self.uwbs_time().as_micros() as u64,
+        }
+    }
+
     fn session_init(&mut self, cmd: SessionInitCmd) -> SessionInitRsp {
         let session_id = cmd.session_id;
         let session_type = cmd.session_type;
@@ -380,20 +1573,22 @@ impl Device {
         log::debug!("[{}] Session deinit", self.handle);
         log::debug!("  session_id=0x{:x}", session_id);
 
-        let status = match self.sessions.get_mut(&session_id) {
-            Some(session) => {
-                if session.state == SessionState::SessionStateActive {
-                    self.n_active_sessions -= 1;
-                    if self.n_active_sessions == 0 {
-                        self.set_state(DeviceState::DeviceStateReady);
-                    }
-                }
-                self.sessions.remove(&session_id);
-                uci::Status::Ok
-            }
-            None => uci::Status::ErrorSessionNotExist,
+        let Some(session) = self.sessions.get(&session_id) else {
+            return SessionDeinitRsp {
+                status: PicaError::SessionNotFound(session_id).into(),
+            };
         };
-        SessionDeinitRsp { status }
+
+        if session.state == SessionState::SessionStateActive {
+            self.n_active_sessions -= 1;
+            if self.n_active_sessions == 0 {
+                self.set_state(DeviceState::DeviceStateReady);
+            }
+        }
+        self.sessions.remove(&session_id);
+        SessionDeinitRsp {
+            status: uci::Status::Ok,
+        }
     }
 
     fn session_get_count(&self, _cmd: SessionGetCountCmd) -> SessionGetCountRsp {
@@ -405,6 +1600,35 @@ impl Device {
         }
     }
 
+    /// Return the maximum size of the application data of a data message
+    /// for a data transfer session. The command is rejected for other
+    /// session types.
+    fn session_query_max_data_size(
+        &self,
+        cmd: SessionQueryMaxDataSizeInRangingCmd,
+    ) -> Result<SessionQueryMaxDataSizeInRangingRsp, PicaError> {
+        let session_handle = cmd.session_token;
+
+        log::debug!(
+            "[{}:0x{:x}] Session Query Max Data Size",
+            self.handle,
+            session_handle
+        );
+
+        let session = self
+            .sessions
+            .get(&session_handle)
+            .ok_or(PicaError::SessionNotFound(session_handle))?;
+        if session.session_type != SessionType::FiraRangingAndInBandDataSession {
+            return Err(PicaError::SessionTypeNotSupported(session_handle));
+        }
+
+        Ok(SessionQueryMaxDataSizeInRangingRsp {
+            session_token: session_handle,
+            max_data_size: MAX_DATA_SIZE as u16,
+        })
+    }
+
     fn session_set_app_config(&mut self, cmd: SessionSetAppConfigCmd) -> SessionSetAppConfigRsp {
         let session_handle = cmd.session_token;
 
@@ -414,10 +1638,11 @@ impl Device {
             session_handle
         );
 
+        let caps = self.caps_info();
         let Some(session) = self.sessions.get_mut(&session_handle) else {
             return SessionSetAppConfigRsp {
                 cfg_status: Vec::new(),
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_handle).into(),
             };
         };
 
@@ -426,83 +1651,119 @@ impl Device {
                 || session.session_type == SessionType::FiraRangingAndInBandDataSession
         );
 
+        if let Err(status) = session.check_state(SessionCommand::SetAppConfig) {
+            return SessionSetAppConfigRsp {
+                status,
+                cfg_status: vec![],
+            };
+        }
+
         if session.state == SessionState::SessionStateActive {
             const IMMUTABLE_PARAMETERS: &[AppConfigTlvType] = &[AppConfigTlvType::AoaResultReq];
-            if cmd
+            let immutable_parameters = cmd
                 .tlvs
                 .iter()
-                .any(|cfg| IMMUTABLE_PARAMETERS.contains(&cfg.cfg_id))
-            {
+                .filter(|cfg| IMMUTABLE_PARAMETERS.contains(&cfg.cfg_id))
+                .map(|cfg| AppConfigStatus {
+                    cfg_id: cfg.cfg_id,
+                    status: uci::Status::ErrorSessionActive,
+                })
+                .collect::<Vec<_>>();
+            if !immutable_parameters.is_empty() {
                 return SessionSetAppConfigRsp {
                     status: uci::Status::ErrorSessionActive,
-                    cfg_status: vec![],
+                    cfg_status: immutable_parameters,
                 };
             }
         }
 
-        let (status, invalid_parameters) = if session.state != SessionState::SessionStateInit
-            && session.state != SessionState::SessionStateActive
-        {
-            (uci::Status::Rejected, Vec::new())
-        } else {
-            let mut app_config = session.app_config.clone();
-            let mut invalid_parameters = vec![];
-            for cfg in cmd.tlvs {
-                match app_config.set(cfg.cfg_id, &cfg.v) {
-                    Ok(_) => (),
-                    Err(_) => invalid_parameters.push(AppConfigStatus {
-                        cfg_id: cfg.cfg_id,
-                        status: uci::Status::InvalidParam,
-                    }),
-                }
+        let mut app_config = session.app_config.clone();
+        let mut invalid_parameters = vec![];
+        for cfg in cmd.tlvs {
+            match app_config.set(cfg.cfg_id, &cfg.v) {
+                Ok(_) if supports_app_config(&caps, cfg.cfg_id, &cfg.v) => (),
+                Ok(_) | Err(_) => invalid_parameters.push(AppConfigStatus {
+                    cfg_id: cfg.cfg_id,
+                    status: uci::Status::InvalidParam,
+                }),
             }
+        }
 
-            // [UCI] 7.5.1 Configuration of a Session
-            // This section defines the mandatory APP Configuration Parameters to be applied
-            // by the Host for FiRa defined UWB Session types. The Host shall apply these
-            // mandatory configurations to move the Session State from SESSION_STATE_INIT
-            // to SESSION_STATE_IDLE.
-            //
-            // - DEVICE_ROLE
-            // - MULTI_NODE_MODE
-            // - RANGING_ROUND_USAGE
-            // - DEVICE_MAC_ADDRESS
-            // - DEVICE_TYPE (see Note1)
-            // - SCHEDULE_MODE
-            if app_config.device_role.is_none()
-                || app_config.multi_node_mode.is_none()
-                || app_config.ranging_round_usage.is_none()
-                || app_config.device_mac_address.is_none()
-                || app_config.schedule_mode.is_none()
-            {
-                log::error!(
-                    "[{}:0x{:x}] missing mandatory APP config parameters",
-                    self.handle,
-                    session_handle
-                );
-                return SessionSetAppConfigRsp {
-                    status: uci::Status::Rejected,
-                    cfg_status: vec![],
-                };
-            }
+        // The invalid parameters are reported individually, so that
+        // the host can correct only the offending TLVs.
+        if !invalid_parameters.is_empty() {
+            return SessionSetAppConfigRsp {
+                status: uci::Status::InvalidParam,
+                cfg_status: invalid_parameters,
+            };
+        }
 
-            if invalid_parameters.is_empty() {
-                session.app_config = app_config;
-                if session.state == SessionState::SessionStateInit {
-                    session.set_state(
-                        SessionState::SessionStateIdle,
-                        ReasonCode::StateChangeWithSessionManagementCommands,
-                    );
-                }
-                (uci::Status::Ok, invalid_parameters)
-            } else {
-                (uci::Status::InvalidParam, invalid_parameters)
-            }
-        };
+        // [UCI] 7.5.1 Configuration of a Session
+        // This section defines the mandatory APP Configuration Parameters to be applied
+        // by the Host for FiRa defined UWB Session types. The Host shall apply these
+        // mandatory configurations to move the Session State from SESSION_STATE_INIT
+        // to SESSION_STATE_IDLE.
+        //
+        // - DEVICE_ROLE
+        // - MULTI_NODE_MODE
+        // - RANGING_ROUND_USAGE
+        // - DEVICE_MAC_ADDRESS
+        // - DEVICE_TYPE (see Note1)
+        // - SCHEDULE_MODE
+        //
+        // The missing parameters are reported individually.
+        let missing_parameters = [
+            (
+                AppConfigTlvType::DeviceRole,
+                app_config.device_role.is_none(),
+            ),
+            (
+                AppConfigTlvType::MultiNodeMode,
+                app_config.multi_node_mode.is_none(),
+            ),
+            (
+                AppConfigTlvType::RangingRoundUsage,
+                app_config.ranging_round_usage.is_none(),
+            ),
+            (
+                AppConfigTlvType::DeviceMacAddress,
+                app_config.device_mac_address.is_none(),
+            ),
+            (
+                AppConfigTlvType::ScheduleMode,
+                app_config.schedule_mode.is_none(),
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(cfg_id, _)| AppConfigStatus {
+            cfg_id,
+            status: uci::Status::Rejected,
+        })
+        .collect::<Vec<_>>();
+        if !missing_parameters.is_empty() {
+            log::error!(
+                "[{}:0x{:x}] missing mandatory APP config parameters",
+                self.handle,
+                session_handle
+            );
+            return SessionSetAppConfigRsp {
+                status: uci::Status::Rejected,
+                cfg_status: missing_parameters,
+            };
+        }
+
+        session.app_config = app_config;
+        if session.state == SessionState::SessionStateInit {
+            session.set_state(
+                SessionState::SessionStateIdle,
+                ReasonCode::StateChangeWithSessionManagementCommands,
+            );
+        }
 
         SessionSetAppConfigRsp {
-            status,
-            cfg_status: invalid_parameters,
+            status: uci::Status::Ok,
+            cfg_status: vec![],
         }
     }
 
@@ -518,7 +1779,7 @@ impl Device {
         let Some(session) = self.sessions.get(&session_handle) else {
             return SessionGetAppConfigRsp {
                 tlvs: vec![],
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_handle).into(),
             };
         };
 
@@ -559,7 +1820,7 @@ impl Device {
         let Some(session) = self.sessions.get(&session_handle) else {
             return SessionGetStateRsp {
                 session_state: SessionState::SessionStateInit,
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_handle).into(),
             };
         };
 
@@ -583,13 +1844,15 @@ impl Device {
 
         let Some(session) = self.sessions.get_mut(&session_handle) else {
             return SessionUpdateControllerMulticastListRsp {
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_handle).into(),
             };
         };
 
-        if (session.state != SessionState::SessionStateActive
-            && session.state != SessionState::SessionStateIdle)
-            || session.app_config.device_type != Some(DeviceType::Controller)
+        if let Err(status) = session.check_state(SessionCommand::UpdateControllerMulticastList) {
+            return SessionUpdateControllerMulticastListRsp { status };
+        }
+
+        if session.app_config.device_type != Some(DeviceType::Controller)
             || session.app_config.multi_node_mode != Some(MultiNodeMode::OneToMany)
         {
             return SessionUpdateControllerMulticastListRsp {
@@ -736,15 +1999,14 @@ impl Device {
                         // sent before the response.
                         // TODO(#84) remove the sleep.
                         time::sleep(Duration::from_millis(5)).await;
-                        tx.send(
+                        let _ = tx.send(
                             SessionUpdateControllerMulticastListNtf {
                                 controlee_status,
                                 session_token: session_handle,
                             }
                             .encode_to_vec()
                             .unwrap(),
-                        )
-                        .unwrap()
+                        );
                     });
                 }
             }
@@ -763,20 +2025,83 @@ impl Device {
         SessionUpdateControllerMulticastListRsp { status }
     }
 
+    fn session_set_hybrid_controller_config(
+        &mut self,
+        cmd: SessionSetHybridControllerConfigCmd,
+    ) -> SessionSetHybridControllerConfigRsp {
+        let session_handle = cmd.session_token;
+
+        log::debug!(
+            "[{}:0x{:x}] Session Set Hybrid Controller Config",
+            self.handle,
+            session_handle
+        );
+
+        let Some(session) = self.sessions.get(&session_handle) else {
+            return SessionSetHybridControllerConfigRsp {
+                status: PicaError::SessionNotFound(session_handle).into(),
+            };
+        };
+
+        if let Err(status) = session.check_state(SessionCommand::SetHybridConfig) {
+            return SessionSetHybridControllerConfigRsp { status };
+        }
+
+        // The primary sessions of the phases must exist,
+        // and be configured but not started.
+        for phase in &cmd.phase_list {
+            let status: uci::Status = match self.sessions.get(&phase.session_token) {
+                None => PicaError::SessionNotFound(phase.session_token).into(),
+                Some(_) if phase.session_token == session_handle => uci::Status::InvalidParam,
+                Some(session) if session.state != SessionState::SessionStateIdle => {
+                    uci::Status::Rejected
+                }
+                Some(_) => continue,
+            };
+            log::warn!(
+                "[{}:0x{:x}] Invalid hybrid session phase 0x{:x}",
+                self.handle,
+                session_handle,
+                phase.session_token
+            );
+            return SessionSetHybridControllerConfigRsp { status };
+        }
+
+        self.session_mut(session_handle).unwrap().phases = cmd.phase_list;
+        SessionSetHybridControllerConfigRsp {
+            status: uci::Status::Ok,
+        }
+    }
+
     fn session_start(&mut self, cmd: SessionStartCmd) -> SessionStartRsp {
         let session_id = cmd.session_id;
 
         log::debug!("[{}:0x{:x}] Session Start", self.handle, session_id);
 
+        let jitter_rng = self.ranging_jitter_rng(session_id);
+        let ranging_timer = self.ranging_timer(session_id);
         let Some(session) = self.sessions.get_mut(&session_id) else {
             return SessionStartRsp {
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_id).into(),
             };
         };
 
-        if session.state != SessionState::SessionStateIdle {
+        if let Err(status) = session.check_state(SessionCommand::Start) {
+            return SessionStartRsp { status };
+        }
+
+        if self
+            .options
+            .max_active_sessions
+            .is_some_and(|max_active_sessions| self.n_active_sessions >= max_active_sessions)
+        {
+            log::warn!(
+                "[{}:0x{:x}] Session Start rejected: maximum number of active sessions reached",
+                self.handle,
+                session_id
+            );
             return SessionStartRsp {
-                status: uci::Status::ErrorSessionNotConfigured,
+                status: uci::Status::ErrorMaxSessionsExceeded,
             };
         }
 
@@ -785,16 +2110,19 @@ impl Device {
         let ranging_interval =
             time::Duration::from_millis(session.app_config.ranging_duration as u64);
 
-        let tx = self.pica_tx.clone();
-        let handle = self.handle;
-        session.ranging_task = Some(tokio::spawn(async move {
-            loop {
-                time::sleep(ranging_interval).await;
-                tx.send(PicaCommand::Ranging(handle, session_id))
-                    .await
-                    .unwrap();
-            }
-        }));
+        session.ranging_task = Some(spawn_ranging_task(
+            self.pica_tx.clone(),
+            self.handle,
+            session_id,
+            ranging_interval,
+            self.options.ranging_jitter,
+            jitter_rng,
+            ranging_timer,
+        ));
+        session.warmup_rounds = session.app_config.warmup_rounds;
+        session.failed_rounds = 0;
+        session.sts_index = session.app_config.sts_index;
+        session.key_rotation_rounds = 0;
 
         session.set_state(
             SessionState::SessionStateActive,
@@ -816,21 +2144,19 @@ impl Device {
 
         let Some(session) = self.sessions.get_mut(&session_id) else {
             return SessionStopRsp {
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_id).into(),
             };
         };
 
-        if session.state != SessionState::SessionStateActive {
-            return SessionStopRsp {
-                status: uci::Status::ErrorSessionActive,
-            };
+        if let Err(status) = session.check_state(SessionCommand::Stop) {
+            return SessionStopRsp { status };
         }
 
         session.stop_ranging_task();
-        session.set_state(
-            SessionState::SessionStateIdle,
-            ReasonCode::StateChangeWithSessionManagementCommands,
-        );
+        let reason_code = session
+            .stop_reason
+            .unwrap_or(ReasonCode::StateChangeWithSessionManagementCommands);
+        session.set_state(SessionState::SessionStateIdle, reason_code);
 
         self.n_active_sessions -= 1;
         if self.n_active_sessions == 0 {
@@ -856,7 +2182,7 @@ impl Device {
 
         let Some(session) = self.sessions.get(&session_id) else {
             return SessionGetRangingCountRsp {
-                status: uci::Status::ErrorSessionNotExist,
+                status: PicaError::SessionNotFound(session_id).into(),
                 count: 0,
             };
         };
@@ -876,10 +2202,12 @@ impl Device {
         log::debug!("[{}] Set country code", self.handle);
         log::debug!("  country_code={},{}", country_code[0], country_code[1]);
 
-        self.country_code = country_code;
-        AndroidSetCountryCodeRsp {
-            status: uci::Status::Ok,
-        }
+        let status = if self.set_country_code(country_code) {
+            uci::Status::Ok
+        } else {
+            uci::Status::InvalidParam
+        };
+        AndroidSetCountryCodeRsp { status }
     }
 
     fn android_get_power_stats(
@@ -900,6 +2228,166 @@ impl Device {
         }
     }
 
+    fn pica_get_device_temperature(
+        &self,
+        _cmd: PicaGetDeviceTemperatureCmd,
+    ) -> PicaGetDeviceTemperatureRsp {
+        log::debug!("[{}] Get device temperature", self.handle);
+
+        PicaGetDeviceTemperatureRsp {
+            status: uci::Status::Ok,
+            temperature: self.temperature as u8,
+        }
+    }
+
+    fn pica_loopback(&self, cmd: PicaLoopbackCmd) -> PicaLoopbackRsp {
+        log::debug!("[{}] Loopback", self.handle);
+        log::debug!("  payload={:?}", cmd.payload);
+
+        PicaLoopbackRsp {
+            status: uci::Status::Ok,
+            payload: cmd.payload,
+        }
+    }
+
+    fn pica_suspend(&mut self, _cmd: PicaSuspendCmd) -> PicaSuspendRsp {
+        log::debug!("[{}] Suspend", self.handle);
+
+        if self.is_suspended() {
+            return PicaSuspendRsp {
+                status: uci::Status::Rejected,
+            };
+        }
+
+        // The active sessions are paused, and keep their state
+        // until the device is resumed.
+        self.stop_ranging_tasks();
+        self.set_state(DeviceState::DeviceStateLowPower);
+        PicaSuspendRsp {
+            status: uci::Status::Ok,
+        }
+    }
+
+    fn pica_resume(&mut self, _cmd: PicaResumeCmd) -> PicaResumeRsp {
+        log::debug!("[{}] Resume", self.handle);
+
+        if !self.is_suspended() {
+            return PicaResumeRsp {
+                status: uci::Status::Rejected,
+            };
+        }
+
+        for session_id in self.active_session_ids() {
+            let jitter_rng = self.ranging_jitter_rng(session_id);
+            let ranging_timer = self.ranging_timer(session_id);
+            let session = self.sessions.get_mut(&session_id).unwrap();
+            let ranging_interval =
+                time::Duration::from_millis(session.app_config.ranging_duration as u64);
+            session.ranging_task = Some(spawn_ranging_task(
+                self.pica_tx.clone(),
+                self.handle,
+                session_id,
+                ranging_interval,
+                self.options.ranging_jitter,
+                jitter_rng,
+                ranging_timer,
+            ));
+        }
+        self.set_state(if self.n_active_sessions > 0 {
+            DeviceState::DeviceStateActive
+        } else {
+            DeviceState::DeviceStateReady
+        });
+        PicaResumeRsp {
+            status: uci::Status::Ok,
+        }
+    }
+
+    fn pica_session_recover(&mut self, cmd: PicaSessionRecoverCmd) -> PicaSessionRecoverRsp {
+        let session_id = cmd.session_token;
+
+        log::debug!("[{}:0x{:x}] Session Recover", self.handle, session_id);
+
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return PicaSessionRecoverRsp {
+                status: PicaError::SessionNotFound(session_id).into(),
+            };
+        };
+
+        if let Err(status) = session.check_state(SessionCommand::Recover) {
+            return PicaSessionRecoverRsp { status };
+        }
+
+        // The application configuration is preserved, the session
+        // can be restarted without being configured again. Sessions
+        // that were never configured return to the initialized state:
+        // the configuration is only applied once the mandatory
+        // parameters are set.
+        let state = if session.app_config.device_mac_address.is_some() {
+            SessionState::SessionStateIdle
+        } else {
+            SessionState::SessionStateInit
+        };
+        session.set_state(state, ReasonCode::StateChangeWithSessionManagementCommands);
+        PicaSessionRecoverRsp {
+            status: uci::Status::Ok,
+        }
+    }
+
+    fn pica_get_vendor_commands(&self, _cmd: PicaGetVendorCommandsCmd) -> PicaGetVendorCommandsRsp {
+        log::debug!("[{}] Get vendor commands", self.handle);
+
+        let mut tlvs: Vec<VendorCommandsTlv> = vec![];
+        for (gid, oid, _) in VENDOR_COMMANDS {
+            let gid = u8::from(*gid);
+            match tlvs.iter_mut().find(|tlv| tlv.gid == gid) {
+                Some(tlv) => tlv.oids.push(*oid),
+                None => tlvs.push(VendorCommandsTlv {
+                    gid,
+                    oids: vec![*oid],
+                }),
+            }
+        }
+
+        PicaGetVendorCommandsRsp {
+            status: uci::Status::Ok,
+            tlvs,
+        }
+    }
+
+    fn pica_get_chip_info(&self, _cmd: PicaGetChipInfoCmd) -> PicaGetChipInfoRsp {
+        log::debug!("[{}] Get chip info", self.handle);
+
+        let chip_info = self.options.chip_info;
+        PicaGetChipInfoRsp {
+            status: uci::Status::Ok,
+            vendor_oui: chip_info.vendor_oui,
+            part_id: chip_info.part_id,
+            chip_version: chip_info.chip_version,
+        }
+    }
+
+    fn pica_set_client_id(&self, cmd: PicaSetClientIdCmd) -> PicaSetClientIdRsp {
+        log::debug!("[{}] Set client id", self.handle);
+        log::debug!("  client_id=0x{:x}", cmd.client_id);
+
+        // The client id is only accepted as the first packet
+        // of the connection, before the device is created.
+        PicaSetClientIdRsp {
+            status: uci::Status::Rejected,
+        }
+    }
+
+    fn pica_authenticate(&self, _cmd: PicaAuthenticateCmd) -> PicaAuthenticateRsp {
+        log::debug!("[{}] Authenticate", self.handle);
+
+        // The token is only accepted as the first packet
+        // of the connection, before the device is created.
+        PicaAuthenticateRsp {
+            status: uci::Status::Rejected,
+        }
+    }
+
     pub fn data_message_snd(&mut self, data: DataPacket) -> ControlPacket {
         log::debug!("[{}] data_message_send", self.handle);
         match data
@@ -939,8 +2427,30 @@ impl Device {
         }
     }
 
-    fn receive_command(&mut self, cmd: ControlPacket) -> ControlPacket {
-        use AndroidPacketChild::*;
+    /// Dispatch a vendor command to its handler in [VENDOR_COMMANDS].
+    fn receive_vendor_command(
+        &mut self,
+        gid: GroupId,
+        oid: u8,
+        cmd: ControlPacket,
+    ) -> Result<ControlPacket, uci::Status> {
+        match VENDOR_COMMANDS
+            .iter()
+            .find(|(vendor_gid, vendor_oid, _)| (*vendor_gid, *vendor_oid) == (gid, oid))
+        {
+            Some((_, _, handler)) => Ok(handler(self, cmd)),
+            None => {
+                log::error!("Unsupported vendor gid {:?} oid {:?}", gid, oid);
+                Err(uci::Status::UnknownOid)
+            }
+        }
+    }
+
+    /// Dispatch a command to its handler, and return the response.
+    /// Commands that cannot be parsed are rejected with the status
+    /// STATUS_UNKNOWN_OID if the opcode identifier is not known,
+    /// and STATUS_SYNTAX_ERROR otherwise.
+    fn receive_command(&mut self, cmd: ControlPacket) -> Result<ControlPacket, uci::Status> {
         use ControlPacketChild::*;
         use CorePacketChild::*;
         use SessionConfigPacketChild::*;
@@ -950,29 +2460,37 @@ impl Device {
         // Reset command. The controller responds with Device Status
         // Notification with DEVICE_STATE_ERROR otherwise.
         if !self.is_reset && !cmd.is_core_device_reset_cmd() {
-            return uci::CoreDeviceStatusNtf {
+            return Ok(uci::CoreDeviceStatusNtf {
                 device_state: DeviceState::DeviceStateError,
             }
             .try_into()
-            .unwrap();
+            .unwrap());
         }
 
-        match cmd
-            .specialize()
-            .expect("Failed to parse Control packet child")
-        {
-            CorePacket(cmd) => match cmd.specialize().expect("Failed to parse Core packet child") {
+        let decode_status = |err: DecodeError| {
+            log::error!("Failed to parse command: {}", err);
+            match err {
+                DecodeError::InvalidEnumValueError { field: "oid", .. } => uci::Status::UnknownOid,
+                _ => uci::Status::SyntaxError,
+            }
+        };
+
+        Ok(match cmd.specialize().map_err(decode_status)? {
+            CorePacket(cmd) => match cmd.specialize().map_err(decode_status)? {
                 CoreDeviceResetCmd(cmd) => self.core_device_reset(cmd).try_into().unwrap(),
                 CoreGetDeviceInfoCmd(cmd) => self.core_get_device_info(cmd).try_into().unwrap(),
                 CoreGetCapsInfoCmd(cmd) => self.core_get_caps_info(cmd).try_into().unwrap(),
                 CoreSetConfigCmd(cmd) => self.core_set_config(cmd).try_into().unwrap(),
                 CoreGetConfigCmd(cmd) => self.core_get_config(cmd).try_into().unwrap(),
-                _ => unimplemented!("Unsupported Core oid {:?}", cmd.oid),
+                CoreQueryTimeStampCmd(cmd) => {
+                    self.core_query_uwbs_timestamp(cmd).try_into().unwrap()
+                }
+                _ => {
+                    log::error!("Unsupported Core oid {:?}", cmd.oid);
+                    return Err(uci::Status::UnknownOid);
+                }
             },
-            SessionConfigPacket(cmd) => match cmd
-                .specialize()
-                .expect("Failed to parse Session Config packet child")
-            {
+            SessionConfigPacket(cmd) => match cmd.specialize().map_err(decode_status)? {
                 SessionInitCmd(cmd) => self.session_init(cmd).try_into().unwrap(),
                 SessionDeinitCmd(cmd) => self.session_deinit(cmd).try_into().unwrap(),
                 SessionGetCountCmd(cmd) => self.session_get_count(cmd).try_into().unwrap(),
@@ -983,31 +2501,44 @@ impl Device {
                     .session_update_controller_multicast_list(cmd)
                     .try_into()
                     .unwrap(),
-                _ => unimplemented!("Unsupported Session Config oid {:?}", cmd.oid),
+                SessionSetHybridControllerConfigCmd(cmd) => self
+                    .session_set_hybrid_controller_config(cmd)
+                    .try_into()
+                    .unwrap(),
+                SessionQueryMaxDataSizeInRangingCmd(cmd) => {
+                    match self.session_query_max_data_size(cmd) {
+                        Ok(rsp) => rsp.try_into().unwrap(),
+                        // The response has no status field.
+                        Err(err) => ControlPacket::decode_full(&status_response(
+                            GroupId::SessionConfig.into(),
+                            SessionConfigOpcodeId::QueryDataSizeInRanging.into(),
+                            err.into(),
+                        ))
+                        .unwrap(),
+                    }
+                }
+                _ => {
+                    log::error!("Unsupported Session Config oid {:?}", cmd.oid);
+                    return Err(uci::Status::UnknownOid);
+                }
             },
-            SessionControlPacket(cmd) => match cmd
-                .specialize()
-                .expect("Failed to parse Session Control packet child")
-            {
+            SessionControlPacket(cmd) => match cmd.specialize().map_err(decode_status)? {
                 SessionStartCmd(cmd) => self.session_start(cmd).try_into().unwrap(),
                 SessionStopCmd(cmd) => self.session_stop(cmd).try_into().unwrap(),
                 SessionGetRangingCountCmd(cmd) => {
                     self.session_get_ranging_count(cmd).try_into().unwrap()
                 }
-                _ => unimplemented!("Unsupported Session Control oid {:?}", cmd.oid),
-            },
-            AndroidPacket(cmd) => match cmd
-                .specialize()
-                .expect("Failed to parse Android packet child")
-            {
-                AndroidSetCountryCodeCmd(cmd) => {
-                    self.android_set_country_code(cmd).try_into().unwrap()
-                }
-                AndroidGetPowerStatsCmd(cmd) => {
-                    self.android_get_power_stats(cmd).try_into().unwrap()
-                }
-                _ => unimplemented!("Unsupported Android oid {:?}", cmd.oid),
+                _ => {
+                    log::error!("Unsupported Session Control oid {:?}", cmd.oid);
+                    return Err(uci::Status::UnknownOid);
+                }
             },
+            AndroidPacket(vendor_cmd) => {
+                self.receive_vendor_command(GroupId::VendorAndroid, vendor_cmd.oid as u8, cmd)?
+            }
+            PicaPacket(vendor_cmd) => {
+                self.receive_vendor_command(GroupId::VendorPica, vendor_cmd.oid as u8, cmd)?
+            }
             ControlPacketChild::None
                 if matches!(
                     cmd.mt,
@@ -1024,18 +2555,9 @@ impl Device {
                 // value of STATUS_UNKNOWN_GID/STATUS_UNKNOWN_OID respectively
                 // and no additional fields.
                 log::error!("Unsupported gid {:?}", cmd.gid);
-                ControlPacket {
-                    mt: uci::MessageType::Response,
-                    gid: cmd.gid,
-                    payload: vec![
-                        cmd.payload[0],
-                        cmd.payload[1],
-                        0x1,
-                        uci::Status::UnknownGid.into(),
-                    ],
-                }
+                return Err(uci::Status::UnknownGid);
             }
-        }
+        })
     }
 
     pub fn receive_packet(&mut self, packet: Vec<u8>) {
@@ -1046,54 +2568,340 @@ impl Device {
                     let notification = self.data_message_snd(packet);
                     self.send_control(notification)
                 }
-                Err(err) => log::error!("failed to parse incoming Data packet: {}", err),
+                Err(err) => {
+                    log::error!("failed to parse incoming Data packet: {}", err);
+                    self.send_generic_error(uci::Status::SyntaxError)
+                }
             },
             MessageType::Command => {
+                let group_id = packet[0] & 0xf;
+                let opcode_id = packet[1] & 0x3f;
+                let mut delay = None;
+
+                match self.take_fault(group_id, opcode_id) {
+                    Some(FaultBehavior::ReturnStatus(status)) => {
+                        log::info!(
+                            "[{}] Injected fault: returning status {:?} for gid 0x{:x} oid 0x{:x}",
+                            self.handle,
+                            status,
+                            group_id,
+                            opcode_id
+                        );
+                        return self.send_raw_control(status_response(group_id, opcode_id, status));
+                    }
+                    Some(FaultBehavior::Drop) => {
+                        log::info!(
+                            "[{}] Injected fault: dropping command gid 0x{:x} oid 0x{:x}",
+                            self.handle,
+                            group_id,
+                            opcode_id
+                        );
+                        return;
+                    }
+                    Some(FaultBehavior::Delay(duration)) => delay = Some(duration),
+                    None => (),
+                }
+
+                if let Some(latency) = self.options.response_latencies.get(&(group_id, opcode_id)) {
+                    delay = Some(delay.unwrap_or_default() + *latency);
+                }
+
+                // The Core Device Reset command is accepted within the
+                // busy window, but not before the pending device status
+                // notification is sent.
+                let device_reset = (group_id, opcode_id)
+                    == (GroupId::Core.into(), CoreOpcodeId::DeviceReset.into());
+                let now = self.options.clock.now();
+                if (now < self.busy_until && !device_reset) || self.is_state_pending() {
+                    log::warn!(
+                        "[{}] Command gid 0x{:x} oid 0x{:x} rejected: device busy",
+                        self.handle,
+                        group_id,
+                        opcode_id
+                    );
+                    return self.send_raw_control(status_response(
+                        group_id,
+                        opcode_id,
+                        uci::Status::UciMessageRetry,
+                    ));
+                }
+
+                // Commands rejected while the device is busy do not
+                // consume the tokens of the rate limiter.
+                if let Some(rate_limiter) = &mut self.rate_limiter {
+                    if !rate_limiter.try_acquire(now) {
+                        log::warn!(
+                            "[{}] Command gid 0x{:x} oid 0x{:x} rejected: rate limit exceeded",
+                            self.handle,
+                            group_id,
+                            opcode_id
+                        );
+                        return self.send_raw_control(status_response(
+                            group_id,
+                            opcode_id,
+                            uci::Status::UciMessageRetry,
+                        ));
+                    }
+                }
+
+                // [UCI] 5. The UWBS in error state only accepts
+                // the Core Device Reset command.
+                if self.is_reset && self.state == DeviceState::DeviceStateError && !device_reset {
+                    log::warn!(
+                        "[{}] Command gid 0x{:x} oid 0x{:x} rejected in error state",
+                        self.handle,
+                        group_id,
+                        opcode_id
+                    );
+                    return self.send_raw_control(status_response(
+                        group_id,
+                        opcode_id,
+                        uci::Status::Rejected,
+                    ));
+                }
+
+                // The suspended UWBS rejects the session commands
+                // until resumed with the Pica Resume command.
+                if self.is_suspended()
+                    && (group_id == GroupId::SessionConfig.into()
+                        || group_id == GroupId::SessionControl.into())
+                {
+                    log::warn!(
+                        "[{}] Command gid 0x{:x} oid 0x{:x} rejected in low power state",
+                        self.handle,
+                        group_id,
+                        opcode_id
+                    );
+                    return self.send_raw_control(status_response(
+                        group_id,
+                        opcode_id,
+                        uci::Status::Rejected,
+                    ));
+                }
+
                 match ControlPacket::decode_full(&packet) {
                     // Parsing error. Determine what error response should be
                     // returned to the host:
                     // - response and notifications are ignored, no response
                     // - if the group id is not known, STATUS_UNKNOWN_GID,
-                    // - otherwise, and to simplify the code, STATUS_UNKNOWN_OID is
-                    //      always returned. That means that malformed commands
-                    //      get the same status code, instead of
-                    //      STATUS_SYNTAX_ERROR.
+                    // - otherwise STATUS_UNKNOWN_OID.
+                    // Errors in the command fields are detected when the
+                    // command is dispatched.
                     Err(_) => {
-                        let group_id = packet[0] & 0xf;
-                        let opcode_id = packet[1] & 0x3f;
-
                         let status = if GroupId::try_from(group_id).is_ok() {
                             uci::Status::UnknownOid
                         } else {
                             uci::Status::UnknownGid
                         };
-                        // The PDL generated code cannot be used to generate
-                        // responses with invalid group identifiers.
-                        let response = vec![
-                            (u8::from(MessageType::Response) << 5) | group_id,
-                            opcode_id,
-                            0,
-                            1,
-                            status.into(),
-                        ];
-                        self.send_raw_control(response)
+                        self.send_raw_control(status_response(group_id, opcode_id, status));
+                        self.send_generic_error(status)
                     }
 
                     // Parsing success, ignore non command packets.
                     Ok(cmd) => {
-                        let response = self.receive_command(cmd);
-                        self.send_control(response)
+                        let response = match self.receive_command(cmd) {
+                            Ok(response) => response.encode_to_vec().unwrap(),
+                            Err(status) => {
+                                self.send_raw_control(status_response(group_id, opcode_id, status));
+                                return self.send_generic_error(status);
+                            }
+                        };
+                        // The status is the first field of all responses.
+                        if let (MessageType::Response, Some(status)) = (
+                            parse_message_type(response[0]),
+                            response
+                                .get(HEADER_SIZE)
+                                .and_then(|status| uci::Status::try_from(*status).ok()),
+                        ) {
+                            log::debug!(
+                                handle = self.handle,
+                                command:% = format_args!("0x{:x}{:02x}", group_id, opcode_id),
+                                status:? = status;
+                                "[{}] Command 0x{:x}{:02x} status {:?}",
+                                self.handle,
+                                group_id,
+                                opcode_id,
+                                status
+                            );
+                        }
+                        if let Some(busy_window) = self.options.busy_window {
+                            self.busy_until = now + delay.unwrap_or_default() + busy_window;
+                        }
+                        match delay {
+                            Some(duration) => {
+                                log::info!(
+                                    "[{}] Injected fault: delaying response by {:?}",
+                                    self.handle,
+                                    duration
+                                );
+                                // The delay is measured on the shared clock.
+                                let sleep = self.options.clock.sleep(duration);
+                                let tx = self.tx.clone();
+                                tokio::spawn(async move {
+                                    sleep.await;
+                                    let _ = tx.send(response);
+                                });
+                            }
+                            None => self.send_raw_control(response),
+                        }
                     }
                 }
             }
 
             // Message types for notifications and responses ignored
             // by the controller.
-            _ => log::warn!("received unexpected packet of MT {:?}", mt),
+            _ => {
+                log::warn!("received unexpected packet of MT {:?}", mt);
+                self.send_generic_error(uci::Status::SyntaxError)
+            }
         }
     }
 }
 
+/// Build a response with the same group and opcode identifiers as the
+/// command, followed by the status field and no additional fields.
+/// The PDL generated code cannot be used to generate responses with
+/// invalid group identifiers.
+pub(crate) fn status_response(group_id: u8, opcode_id: u8, status: uci::Status) -> UciPacket {
+    vec![
+        (u8::from(MessageType::Response) << 5) | group_id,
+        opcode_id,
+        0,
+        1,
+        status.into(),
+    ]
+}
+
+/// Handler of a vendor command.
+type VendorCommandHandler = fn(&mut Device, ControlPacket) -> ControlPacket;
+
+/// Vendor commands implemented by the device, with their group and
+/// opcode identifiers. The commands reported in the response to the
+/// Pica Get Vendor Commands command are listed from this table.
+const VENDOR_COMMANDS: &[(GroupId, u8, VendorCommandHandler)] = &[
+    (
+        GroupId::VendorAndroid,
+        AndroidOpcodeId::GetPowerStats as u8,
+        |device, cmd| {
+            let cmd = AndroidPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.android_get_power_stats(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorAndroid,
+        AndroidOpcodeId::SetCountryCode as u8,
+        |device, cmd| {
+            let cmd = AndroidPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.android_set_country_code(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::GetDeviceTemperature as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_get_device_temperature(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::GetVendorCommands as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_get_vendor_commands(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::SetClientId as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_set_client_id(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::Suspend as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_suspend(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::Resume as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_resume(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::Loopback as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_loopback(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::Authenticate as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_authenticate(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::SessionRecover as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_session_recover(cmd).try_into().unwrap()
+        },
+    ),
+    (
+        GroupId::VendorPica,
+        PicaOpcodeId::GetChipInfo as u8,
+        |device, cmd| {
+            let cmd = PicaPacket::try_from(&cmd).unwrap().try_into().unwrap();
+            device.pica_get_chip_info(cmd).try_into().unwrap()
+        },
+    ),
+];
+
+/// Spawn the task periodically triggering the ranging rounds
+/// of the selected device and session. The interval of each round
+/// varies uniformly by up to `jitter` times the ranging interval.
+/// The timer is rearmed for each round, and unregistered when
+/// the task is aborted.
+fn spawn_ranging_task(
+    tx: mpsc::Sender<PicaCommand>,
+    handle: usize,
+    session_id: u32,
+    ranging_interval: time::Duration,
+    jitter: f32,
+    mut rng: Rng,
+    timer: Timer,
+) -> JoinHandle<()> {
+    let jitter = jitter.clamp(0., MAX_RANGING_JITTER);
+    tokio::spawn(async move {
+        loop {
+            let interval = if jitter > 0. {
+                let factor = 1. + jitter * (2. * rng.uniform() - 1.);
+                ranging_interval
+                    .mul_f32(factor)
+                    .max(MIN_JITTERED_RANGING_INTERVAL)
+            } else {
+                ranging_interval
+            };
+            timer.rearm(interval).await;
+            tx.send(PicaCommand::Ranging(handle, session_id))
+                .await
+                .unwrap();
+        }
+    })
+}
+
 struct Controlee {
     short_address: MacAddress,
     #[allow(dead_code)]
@@ -1131,3 +2939,1565 @@ impl From<&uci::Controlee_V2_0_32_Byte_Version> for Controlee {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+
+    fn new_device(clock: Arc<dyn Clock>) -> (Device, mpsc::UnboundedReceiver<UciPacket>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (pica_tx, _) = mpsc::channel(1);
+        let mut device = Device::new(
+            0,
+            MacAddress::Short([0, 0]),
+            tx,
+            pica_tx,
+            DeviceOptions {
+                clock,
+                ..Default::default()
+            },
+        );
+        device.init();
+        (device, rx)
+    }
+
+    fn send_command(
+        device: &mut Device,
+        rx: &mut mpsc::UnboundedReceiver<UciPacket>,
+        cmd: impl Packet,
+    ) -> UciPacket {
+        device.receive_packet(cmd.encode_to_vec().unwrap());
+        rx.try_recv().unwrap()
+    }
+
+    fn reset(device: &mut Device, rx: &mut mpsc::UnboundedReceiver<UciPacket>) {
+        let rsp = send_command(
+            device,
+            rx,
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            },
+        );
+        assert_eq!(
+            CoreDeviceResetRsp::decode_full(&rsp).unwrap().status,
+            uci::Status::Ok
+        );
+    }
+
+    #[tokio::test]
+    async fn query_uwbs_timestamp() {
+        let clock = Arc::new(MockClock::new(Duration::from_micros(1234)));
+        let (mut device, mut rx) = new_device(clock.clone());
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(&mut device, &mut rx, CoreQueryTimeStampCmd {});
+        let rsp = CoreQueryTimeStampRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(rsp.timeStamp, 1234);
+
+        clock.advance(Duration::from_secs(2));
+        let rsp = send_command(&mut device, &mut rx, CoreQueryTimeStampCmd {});
+        let rsp = CoreQueryTimeStampRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.timeStamp, 2_001_234);
+    }
+
+    #[tokio::test]
+    async fn clock_drift() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1)));
+        let (mut device, mut rx) = new_device(clock.clone());
+        reset(&mut device, &mut rx);
+
+        let query_timestamp = |device: &mut Device, rx: &mut mpsc::UnboundedReceiver<UciPacket>| {
+            let rsp = send_command(device, rx, CoreQueryTimeStampCmd {});
+            CoreQueryTimeStampRsp::decode_full(&rsp).unwrap().timeStamp
+        };
+
+        // The UWBS clock runs 100 ppm faster than the shared clock
+        // from the time the drift is set.
+        device.set_clock_drift(100.);
+        assert_eq!(query_timestamp(&mut device, &mut rx), 1_000_000);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(query_timestamp(&mut device, &mut rx), 11_001_000);
+
+        // Resetting the device or changing the drift preserves
+        // the accumulated offset.
+        reset(&mut device, &mut rx);
+        assert_eq!(query_timestamp(&mut device, &mut rx), 11_001_000);
+        device.set_clock_drift(-50.);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(query_timestamp(&mut device, &mut rx), 21_000_500);
+        device.set_clock_drift(0.);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(query_timestamp(&mut device, &mut rx), 31_000_500);
+    }
+
+    #[tokio::test]
+    async fn inject_fault_return_status() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        device.inject_fault(Fault {
+            gid: GroupId::Core,
+            oid: CoreOpcodeId::SetConfig.into(),
+            behavior: FaultBehavior::ReturnStatus(uci::Status::Rejected),
+            count: 1,
+        });
+
+        let cmd = || CoreSetConfigCmd {
+            parameters: vec![ConfigParameter {
+                id: ConfigParameterId::LowPowerMode,
+                value: vec![0],
+            }],
+        };
+
+        let rsp = send_command(&mut device, &mut rx, cmd());
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::Core.into(),
+                CoreOpcodeId::SetConfig.into(),
+                uci::Status::Rejected
+            )
+        );
+
+        let rsp = send_command(&mut device, &mut rx, cmd());
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn session_start_in_init_state() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let rsp = send_command(&mut device, &mut rx, SessionStartCmd { session_id });
+        let rsp = SessionStartRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::ErrorSessionNotConfigured);
+
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionGetStateCmd {
+                session_token: session_id,
+            },
+        );
+        let rsp = SessionGetStateRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(rsp.session_state, SessionState::SessionStateInit);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reset_delay() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (pica_tx, _) = mpsc::channel(1);
+        let clock = Arc::new(MockClock::default());
+        let reset_delay = Duration::from_millis(100);
+        let mut device = Device::new(
+            0,
+            MacAddress::Short([0, 0]),
+            tx,
+            pica_tx,
+            DeviceOptions {
+                clock: clock.clone(),
+                reset_delay,
+                ..Default::default()
+            },
+        );
+        device.init();
+
+        // Discard the status notification sent on device creation.
+        time::sleep(Duration::from_millis(10)).await;
+        while rx.try_recv().is_ok() {}
+
+        reset(&mut device, &mut rx);
+
+        // Commands received before the notification are rejected
+        // as busy, and do not observe the new state.
+        clock.advance(reset_delay / 2);
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(rx.try_recv().is_err());
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::Core.into(),
+                CoreOpcodeId::GetDeviceInfo.into(),
+                uci::Status::UciMessageRetry
+            )
+        );
+
+        clock.advance(reset_delay / 2);
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn boot_delay() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (pica_tx, _) = mpsc::channel(1);
+        let clock = Arc::new(MockClock::default());
+        let boot_delay = Duration::from_millis(100);
+        let mut device = Device::new(
+            0,
+            MacAddress::Short([0, 0]),
+            tx,
+            pica_tx,
+            DeviceOptions {
+                clock: clock.clone(),
+                boot_delay,
+                ..Default::default()
+            },
+        );
+        device.init();
+
+        // Commands received during the boot are rejected,
+        // and no notification is sent.
+        clock.advance(boot_delay / 2);
+        let reset_cmd = CoreDeviceResetCmd {
+            reset_config: ResetConfig::UwbsReset,
+        };
+        let rsp = send_command(&mut device, &mut rx, reset_cmd.clone());
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::Core.into(),
+                CoreOpcodeId::DeviceReset.into(),
+                uci::Status::UciMessageRetry
+            )
+        );
+        // The boot delay is measured on the device clock only.
+        assert!(time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .is_err());
+
+        clock.advance(boot_delay / 2);
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+        let rsp = send_command(&mut device, &mut rx, reset_cmd);
+        let rsp = CoreDeviceResetRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn response_latency() {
+        let clock = Arc::new(MockClock::default());
+        let (mut device, mut rx) = new_device(clock.clone());
+        device.options.response_latencies.insert(
+            (GroupId::Core.into(), CoreOpcodeId::SetConfig.into()),
+            Duration::from_millis(100),
+        );
+        reset(&mut device, &mut rx);
+        time::sleep(Duration::from_millis(10)).await;
+        while rx.try_recv().is_ok() {}
+
+        device.receive_packet(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::LowPowerMode,
+                    value: vec![0],
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        );
+
+        // The response is sent once the latency has elapsed
+        // on the device clock.
+        clock.advance(Duration::from_millis(90));
+        time::sleep(Duration::from_millis(200)).await;
+        assert!(rx.try_recv().is_err());
+        clock.advance(Duration::from_millis(10));
+        time::sleep(Duration::from_millis(10)).await;
+        let rsp = CoreSetConfigRsp::decode_full(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // Other commands are not delayed.
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        assert!(CoreGetDeviceInfoRsp::decode_full(&rsp).is_ok());
+    }
+
+    #[tokio::test]
+    async fn command_rate_limit() {
+        let clock = Arc::new(MockClock::default());
+        let (mut device, mut rx) = new_device(clock.clone());
+        device.rate_limiter = Some(RateLimiter::new(2, clock.now()));
+        reset(&mut device, &mut rx);
+
+        let mut statuses = vec![];
+        for _ in 0..8 {
+            let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+            statuses.push(uci::Status::try_from(rsp[4]).unwrap());
+        }
+        // The reset command consumed one of the two available tokens.
+        assert_eq!(statuses[0], uci::Status::Ok);
+        assert!(statuses[1..]
+            .iter()
+            .all(|status| *status == uci::Status::UciMessageRetry));
+
+        // Tokens are replenished as time passes.
+        clock.advance(Duration::from_secs(1));
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn zero_command_rate_limit() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (pica_tx, _) = mpsc::channel(1);
+        let mut device = Device::new(
+            0,
+            MacAddress::Short([0, 0]),
+            tx,
+            pica_tx,
+            DeviceOptions {
+                clock: Arc::new(MockClock::default()),
+                command_rate_limit: Some(0),
+                ..Default::default()
+            },
+        );
+        device.init();
+        reset(&mut device, &mut rx);
+
+        // A zero rate disables the limit.
+        for _ in 0..8 {
+            let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+            let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+            assert_eq!(rsp.status, uci::Status::Ok);
+        }
+    }
+
+    #[tokio::test]
+    async fn busy_window() {
+        let clock = Arc::new(MockClock::default());
+        let (mut device, mut rx) = new_device(clock.clone());
+        device.options.busy_window = Some(Duration::from_millis(10));
+        reset(&mut device, &mut rx);
+        clock.advance(Duration::from_millis(10));
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // The second command is received within the window.
+        clock.advance(Duration::from_millis(5));
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::Core.into(),
+                CoreOpcodeId::GetDeviceInfo.into(),
+                uci::Status::UciMessageRetry
+            )
+        );
+
+        // The rejected command does not extend the window.
+        clock.advance(Duration::from_millis(5));
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // The device can be reset within the window.
+        clock.advance(Duration::from_millis(5));
+        reset(&mut device, &mut rx);
+    }
+
+    #[tokio::test]
+    async fn busy_window_rate_limit() {
+        let clock = Arc::new(MockClock::default());
+        let (mut device, mut rx) = new_device(clock.clone());
+        device.options.busy_window = Some(Duration::from_millis(10));
+        device.rate_limiter = Some(RateLimiter::new(2, clock.now()));
+        reset(&mut device, &mut rx);
+
+        // The commands rejected within the window do not consume
+        // the token left after the reset command.
+        for _ in 0..4 {
+            let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+            assert_eq!(
+                rsp,
+                status_response(
+                    GroupId::Core.into(),
+                    CoreOpcodeId::GetDeviceInfo.into(),
+                    uci::Status::UciMessageRetry
+                )
+            );
+        }
+        clock.advance(Duration::from_millis(10));
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    async fn ranging_intervals(seed: u64) -> Vec<Duration> {
+        let (tx, mut rx) = mpsc::channel(1);
+        let timer = Timers::default().register(
+            TimerKind::Ranging,
+            0,
+            Some(1),
+            Arc::new(SystemClock::new()),
+            Duration::ZERO,
+        );
+        let task = spawn_ranging_task(
+            tx,
+            0,
+            1,
+            Duration::from_millis(200),
+            0.25,
+            Rng::new(seed),
+            timer,
+        );
+        let mut intervals = vec![];
+        let mut last = time::Instant::now();
+        for _ in 0..20 {
+            assert!(matches!(rx.recv().await, Some(PicaCommand::Ranging(0, 1))));
+            let now = time::Instant::now();
+            intervals.push(now - last);
+            last = now;
+        }
+        task.abort();
+        intervals
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ranging_jitter() {
+        let intervals = ranging_intervals(42).await;
+        assert!(intervals
+            .iter()
+            .all(|interval| *interval >= Duration::from_millis(150)
+                && *interval <= Duration::from_millis(250)));
+        assert!(intervals.iter().any(|interval| *interval != intervals[0]));
+
+        // The intervals are reproducible with the same seed.
+        assert_eq!(ranging_intervals(42).await, intervals);
+    }
+
+    #[tokio::test]
+    async fn antenna_config_caps() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        device.options.antenna_config = AntennaConfig::Azimuth;
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetCapsInfoCmd {});
+        let rsp = CoreGetCapsInfoRsp::decode_full(&rsp).unwrap();
+        let supported_aoa = rsp
+            .tlvs
+            .iter()
+            .find(|tlv| tlv.t == CapTlvType::SupportedAoa)
+            .unwrap();
+        assert_eq!(supported_aoa.v, vec![0xfb]);
+    }
+
+    #[tokio::test]
+    async fn unsupported_aoa_reports() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        device.options.antenna_config = AntennaConfig::Single;
+        reset(&mut device, &mut rx);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        while rx.try_recv().is_ok() {}
+
+        let set_app_config = |report_config: u8| {
+            let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+                cfg_id,
+                v: v.to_vec(),
+            };
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                    tlv(AppConfigTlvType::ResultReportConfig, &[report_config]),
+                ],
+            }
+        };
+
+        // The azimuth is not measured with a single antenna.
+        let rsp = send_command(&mut device, &mut rx, set_app_config(0x03));
+        let rsp = SessionSetAppConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.cfg_status,
+            vec![AppConfigStatus {
+                cfg_id: AppConfigTlvType::ResultReportConfig,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+
+        device.receive_packet(set_app_config(0x01).encode_to_vec().unwrap());
+        let rsp = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|packet| SessionSetAppConfigRsp::decode_full(&packet).ok())
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn country_code_channels() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        // Only channel 9 is permitted in Japan.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            AndroidSetCountryCodeCmd {
+                country_code: *b"JP",
+            },
+        );
+        let rsp = AndroidSetCountryCodeRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetCapsInfoCmd {});
+        let rsp = CoreGetCapsInfoRsp::decode_full(&rsp).unwrap();
+        let supported_channels = rsp
+            .tlvs
+            .iter()
+            .find(|tlv| tlv.t == CapTlvType::SupportedChannels)
+            .unwrap();
+        assert_eq!(supported_channels.v, vec![0x08]);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        while rx.try_recv().is_ok() {}
+
+        let set_app_config = |channel: u8| {
+            let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+                cfg_id,
+                v: v.to_vec(),
+            };
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                    tlv(AppConfigTlvType::ChannelNumber, &[channel]),
+                ],
+            }
+        };
+
+        // Channel 5 is generally supported, but masked by the country code.
+        let rsp = send_command(&mut device, &mut rx, set_app_config(5));
+        let rsp = SessionSetAppConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.cfg_status,
+            vec![AppConfigStatus {
+                cfg_id: AppConfigTlvType::ChannelNumber,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+
+        // The session state changes before the response is sent.
+        device.receive_packet(set_app_config(9).encode_to_vec().unwrap());
+        let rsp = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|packet| SessionSetAppConfigRsp::decode_full(&packet).ok())
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn set_app_config_status() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        while rx.try_recv().is_ok() {}
+
+        let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+            cfg_id,
+            v: v.to_vec(),
+        };
+
+        // Only the invalid parameter is reported, even though the
+        // mandatory parameters are missing.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::ChannelNumber, &[0x09, 0x00]),
+                ],
+            },
+        );
+        let rsp = SessionSetAppConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.cfg_status,
+            vec![AppConfigStatus {
+                cfg_id: AppConfigTlvType::ChannelNumber,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+
+        // The missing mandatory parameters are reported individually.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                ],
+            },
+        );
+        let rsp = SessionSetAppConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Rejected);
+        assert_eq!(
+            rsp.cfg_status,
+            [
+                AppConfigTlvType::RangingRoundUsage,
+                AppConfigTlvType::DeviceMacAddress,
+                AppConfigTlvType::ScheduleMode,
+            ]
+            .map(|cfg_id| AppConfigStatus {
+                cfg_id,
+                status: uci::Status::Rejected,
+            })
+        );
+
+        // All valid parameters are reported with an empty list.
+        device.receive_packet(
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                    tlv(AppConfigTlvType::ChannelNumber, &[0x09]),
+                ],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        );
+        let rsp = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|packet| SessionSetAppConfigRsp::decode_full(&packet).ok())
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert!(rsp.cfg_status.is_empty());
+    }
+
+    #[tokio::test]
+    async fn supported_ranging_method() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        // DS-TWR non-deferred only.
+        device.override_capability(CapTlvType::SupportedRangingMethod.into(), vec![0x10]);
+        reset(&mut device, &mut rx);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        while rx.try_recv().is_ok() {}
+
+        let set_app_config = |ranging_round_usage: u8| {
+            let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+                cfg_id,
+                v: v.to_vec(),
+            };
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[ranging_round_usage]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                ],
+            }
+        };
+
+        // DS-TWR deferred is not supported.
+        let rsp = send_command(&mut device, &mut rx, set_app_config(0x02));
+        let rsp = SessionSetAppConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.cfg_status,
+            vec![AppConfigStatus {
+                cfg_id: AppConfigTlvType::RangingRoundUsage,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+
+        device.receive_packet(set_app_config(0x04).encode_to_vec().unwrap());
+        let rsp = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|packet| SessionSetAppConfigRsp::decode_full(&packet).ok())
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[test]
+    fn ranging_method_bits() {
+        assert_eq!(ranging_method_bit(0x00), 0x001);
+        assert_eq!(ranging_method_bit(0x04), 0x010);
+        assert_eq!(ranging_method_bit(0x08), 0x100);
+        assert_eq!(ranging_method_bit(0x09), 0);
+    }
+
+    #[tokio::test]
+    async fn supported_device_roles() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        // Responder only.
+        device.override_capability(CapTlvType::SupportedDeviceRoles.into(), vec![0x02]);
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetCapsInfoCmd {});
+        let rsp = CoreGetCapsInfoRsp::decode_full(&rsp).unwrap();
+        let device_roles = rsp
+            .tlvs
+            .iter()
+            .find(|tlv| tlv.t == CapTlvType::SupportedDeviceRoles)
+            .unwrap();
+        assert_eq!(device_roles.v, vec![0x02]);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        while rx.try_recv().is_ok() {}
+
+        let set_app_config = |device_type: u8, device_role: u8| {
+            let tlv = |cfg_id, v: &[u8]| AppConfigTlv {
+                cfg_id,
+                v: v.to_vec(),
+            };
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: vec![
+                    tlv(AppConfigTlvType::DeviceType, &[device_type]),
+                    tlv(AppConfigTlvType::DeviceRole, &[device_role]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x02]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                ],
+            }
+        };
+
+        // The controller initiator role is not supported.
+        let rsp = send_command(&mut device, &mut rx, set_app_config(0x01, 0x01));
+        let rsp = SessionSetAppConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.cfg_status,
+            vec![AppConfigStatus {
+                cfg_id: AppConfigTlvType::DeviceRole,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+
+        device.receive_packet(set_app_config(0x00, 0x00).encode_to_vec().unwrap());
+        let rsp = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|packet| SessionSetAppConfigRsp::decode_full(&packet).ok())
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn country_code_validation() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let mut set_country_code = |country_code: [u8; 2]| {
+            let rsp = send_command(
+                &mut device,
+                &mut rx,
+                AndroidSetCountryCodeCmd { country_code },
+            );
+            let status = AndroidSetCountryCodeRsp::decode_full(&rsp).unwrap().status;
+            (status, device.country_code)
+        };
+
+        // Lowercase codes are normalized to uppercase.
+        assert_eq!(set_country_code(*b"jp"), (uci::Status::Ok, *b"JP"));
+        assert_eq!(set_country_code(*b"us"), (uci::Status::Ok, *b"US"));
+
+        // Codes with other characters than letters are rejected,
+        // and the country code is left unchanged.
+        assert_eq!(
+            set_country_code(*b"U1"),
+            (uci::Status::InvalidParam, *b"US")
+        );
+        assert_eq!(
+            set_country_code([0x55, 0xff]),
+            (uci::Status::InvalidParam, *b"US")
+        );
+
+        // The unknown country code is accepted, with the default limits.
+        assert_eq!(set_country_code(*b"00"), (uci::Status::Ok, *b"00"));
+        assert_eq!(channel_mask(device.country_code), 0xff);
+        assert_eq!(max_tx_power(device.country_code), max_tx_power([0, 0]));
+    }
+
+    #[tokio::test]
+    async fn country_code_max_tx_power() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            AndroidSetCountryCodeCmd {
+                country_code: *b"US",
+            },
+        );
+        let rsp = AndroidSetCountryCodeRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetCapsInfoCmd {});
+        let rsp = CoreGetCapsInfoRsp::decode_full(&rsp).unwrap();
+        let max_tx_power = rsp
+            .tlvs
+            .iter()
+            .find(|tlv| tlv.t == CapTlvType::SupportedMaxTxPower)
+            .unwrap();
+        assert_eq!(max_tx_power.v, vec![-14i8 as u8]);
+
+        let set_tx_power = |tx_power: i8| CoreSetConfigCmd {
+            parameters: vec![ConfigParameter {
+                id: ConfigParameterId::TxPower,
+                value: vec![tx_power as u8],
+            }],
+        };
+
+        let rsp = send_command(&mut device, &mut rx, set_tx_power(-10));
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.parameters,
+            vec![ConfigParameterStatus {
+                id: ConfigParameterId::TxPower,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+
+        let rsp = send_command(&mut device, &mut rx, set_tx_power(-14));
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn reported_distance_limits() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let set_limits = |limits: &[(ConfigParameterId, u16)]| CoreSetConfigCmd {
+            parameters: limits
+                .iter()
+                .map(|(id, distance)| ConfigParameter {
+                    id: *id,
+                    value: distance.to_le_bytes().to_vec(),
+                })
+                .collect(),
+        };
+
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            set_limits(&[
+                (ConfigParameterId::MinReportedDistance, 20),
+                (ConfigParameterId::MaxReportedDistance, 1000),
+            ]),
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(device.reported_distance_limits(), (Some(20), Some(1000)));
+
+        // The minimum cannot exceed the maximum, including
+        // the maximum previously set.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            set_limits(&[(ConfigParameterId::MinReportedDistance, 1500)]),
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.parameters,
+            vec![ConfigParameterStatus {
+                id: ConfigParameterId::MinReportedDistance,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+        assert_eq!(device.reported_distance_limits(), (Some(20), Some(1000)));
+
+        // Both bounds can be moved in the same command.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            set_limits(&[
+                (ConfigParameterId::MinReportedDistance, 1500),
+                (ConfigParameterId::MaxReportedDistance, 2000),
+            ]),
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(device.reported_distance_limits(), (Some(1500), Some(2000)));
+    }
+
+    #[tokio::test]
+    async fn set_config_oversized_value() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let vendor_id = ConfigParameterId::try_from(0xf0).unwrap();
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreSetConfigCmd {
+                parameters: vec![
+                    ConfigParameter {
+                        id: vendor_id,
+                        value: vec![0; MAX_CONFIG_VALUE_SIZE + 1],
+                    },
+                    ConfigParameter {
+                        id: ConfigParameterId::RangingOffset,
+                        value: vec![0x10, 0x00],
+                    },
+                ],
+            },
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.parameters,
+            vec![ConfigParameterStatus {
+                id: vendor_id,
+                status: uci::Status::InvalidParam,
+            }]
+        );
+        assert!(device.config.vendor_parameters.is_empty());
+        assert_eq!(device.ranging_offset(), 0x10);
+
+        // Values of exactly the maximum size are accepted.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: vendor_id,
+                    value: vec![0xa5; MAX_CONFIG_VALUE_SIZE],
+                }],
+            },
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreGetConfigCmd {
+                parameter_ids: vec![vendor_id],
+            },
+        );
+        let rsp = CoreGetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(
+            rsp.parameters,
+            vec![ConfigParameter {
+                id: vendor_id,
+                value: vec![0xa5; MAX_CONFIG_VALUE_SIZE],
+            }]
+        );
+
+        // Parameters of fixed size reject the values of other sizes.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::LowPowerMode,
+                    value: vec![0; 2],
+                }],
+            },
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert!(device.config.low_power_mode);
+    }
+
+    #[tokio::test]
+    async fn get_config_unset_and_invalid_parameters() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::LowPowerMode,
+                    value: vec![0],
+                }],
+            },
+        );
+        let rsp = CoreSetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // Set and unset parameters.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreGetConfigCmd {
+                parameter_ids: vec![ConfigParameterId::LowPowerMode, ConfigParameterId::TxPower],
+            },
+        );
+        let rsp = CoreGetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(
+            rsp.parameters,
+            vec![
+                ConfigParameter {
+                    id: ConfigParameterId::LowPowerMode,
+                    value: vec![0],
+                },
+                ConfigParameter {
+                    id: ConfigParameterId::TxPower,
+                    value: vec![],
+                },
+            ]
+        );
+
+        // Set, unset, and invalid parameters.
+        let invalid_id = ConfigParameterId::try_from(0x42).unwrap();
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreGetConfigCmd {
+                parameter_ids: vec![
+                    ConfigParameterId::LowPowerMode,
+                    ConfigParameterId::TxPower,
+                    invalid_id,
+                ],
+            },
+        );
+        let rsp = CoreGetConfigRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+        assert_eq!(
+            rsp.parameters,
+            vec![ConfigParameter {
+                id: invalid_id,
+                value: vec![],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_vendor_commands() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(&mut device, &mut rx, PicaGetVendorCommandsCmd {});
+        let rsp = PicaGetVendorCommandsRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let mut reported = rsp
+            .tlvs
+            .iter()
+            .flat_map(|tlv| tlv.oids.iter().map(|oid| (tlv.gid, *oid)))
+            .collect::<Vec<_>>();
+        let mut registered = VENDOR_COMMANDS
+            .iter()
+            .map(|(gid, oid, _)| (u8::from(*gid), *oid))
+            .collect::<Vec<_>>();
+        reported.sort();
+        registered.sort();
+        assert_eq!(reported, registered);
+        assert!(reported.contains(&(0xc, AndroidOpcodeId::SetCountryCode as u8)));
+        assert!(reported.contains(&(0xe, PicaOpcodeId::GetDeviceTemperature as u8)));
+
+        // Each group is reported once.
+        let mut gids = rsp.tlvs.iter().map(|tlv| tlv.gid).collect::<Vec<_>>();
+        gids.dedup();
+        assert_eq!(gids, vec![0xc, 0xe]);
+    }
+
+    #[tokio::test]
+    async fn get_chip_info() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (pica_tx, _) = mpsc::channel(1);
+        let chip_info = ChipInfo::new(0x3c5ab4, 0x1234, 0x0201).unwrap();
+        let mut device = Device::new(
+            0,
+            MacAddress::Short([0, 0]),
+            tx,
+            pica_tx,
+            DeviceOptions {
+                clock: Arc::new(MockClock::default()),
+                chip_info,
+                chip_info_in_device_info: true,
+                ..Default::default()
+            },
+        );
+        device.init();
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(&mut device, &mut rx, PicaGetChipInfoCmd {});
+        let rsp = PicaGetChipInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(rsp.vendor_oui, 0x3c5ab4);
+        assert_eq!(rsp.part_id, 0x1234);
+        assert_eq!(rsp.chip_version, 0x0201);
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(
+            rsp.vendor_spec_info,
+            vec![0xb4, 0x5a, 0x3c, 0x34, 0x12, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn parse_chip_info() {
+        assert_eq!(
+            "0x3c5ab4:1234:0201".parse::<ChipInfo>().unwrap(),
+            ChipInfo::new(0x3c5ab4, 0x1234, 0x0201).unwrap()
+        );
+        assert!(ChipInfo::new(0x1000000, 0, 0).is_none());
+        assert!("1000000:0:0".parse::<ChipInfo>().is_err());
+        assert!("0:10000:0".parse::<ChipInfo>().is_err());
+        assert!("0:0".parse::<ChipInfo>().is_err());
+    }
+
+    #[tokio::test]
+    async fn thermal_warning() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let rsp = send_command(&mut device, &mut rx, PicaGetDeviceTemperatureCmd {});
+        let rsp = PicaGetDeviceTemperatureRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.temperature as i8, DEFAULT_TEMPERATURE);
+
+        device.set_temperature(90);
+        let ntf = PicaThermalStatusNtf::decode_full(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(ntf.thermal_state, ThermalState::Overheated);
+        assert_eq!(ntf.temperature as i8, 90);
+
+        // No notification while the thermal state is unchanged.
+        device.set_temperature(95);
+        assert!(rx.try_recv().is_err());
+
+        let rsp = send_command(&mut device, &mut rx, PicaGetDeviceTemperatureCmd {});
+        let rsp = PicaGetDeviceTemperatureRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.temperature as i8, 95);
+
+        device.set_temperature(40);
+        let ntf = PicaThermalStatusNtf::decode_full(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(ntf.thermal_state, ThermalState::Normal);
+        assert_eq!(ntf.temperature as i8, 40);
+    }
+
+    #[tokio::test]
+    async fn data_credit() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingAndInBandDataSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        device
+            .session_mut(session_id)
+            .unwrap()
+            .app_config
+            .session_data_transfer_status_ntf_config = SessionDataTransferStatusNtfConfig::Enable;
+
+        let data = |data_sequence_number, pbf| DataMessageSnd {
+            session_handle: session_id,
+            destination_address: 0,
+            data_sequence_number,
+            pbf,
+            application_data: vec![1, 2, 3],
+        };
+
+        // The credit is taken by the last data packet of the message.
+        let ntf = send_command(
+            &mut device,
+            &mut rx,
+            data(1, PacketBoundaryFlag::NotComplete),
+        );
+        let ntf = SessionDataCreditNtf::decode_full(&ntf).unwrap();
+        assert_eq!(ntf.credit_availability, CreditAvailability::CreditAvailable);
+        let ntf = send_command(&mut device, &mut rx, data(1, PacketBoundaryFlag::Complete));
+        let ntf = SessionDataCreditNtf::decode_full(&ntf).unwrap();
+        assert_eq!(
+            ntf.credit_availability,
+            CreditAvailability::CreditNotAvailable
+        );
+        assert_eq!(
+            device.session(session_id).unwrap().data().as_ref(),
+            &[1, 2, 3, 1, 2, 3]
+        );
+
+        // Sending with zero credit is rejected.
+        let ntf = send_command(&mut device, &mut rx, data(2, PacketBoundaryFlag::Complete));
+        let ntf = SessionDataTransferStatusNtf::decode_full(&ntf).unwrap();
+        assert_eq!(
+            ntf.status,
+            DataTransferNtfStatusCode::UciDataTransferStatusErrorNoCreditAvailable
+        );
+        assert_eq!(ntf.uci_sequence_number, 2);
+
+        device
+            .session_mut(session_id)
+            .unwrap()
+            .complete_data_transfer(true);
+        let ntf = SessionDataTransferStatusNtf::decode_full(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(
+            ntf.status,
+            DataTransferNtfStatusCode::UciDataTransferStatusOk
+        );
+        assert_eq!(ntf.uci_sequence_number, 1);
+        let ntf = SessionDataCreditNtf::decode_full(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(ntf.credit_availability, CreditAvailability::CreditAvailable);
+    }
+
+    #[tokio::test]
+    async fn data_reassembly() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        let session_id = 0x1234;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id,
+                session_type: SessionType::FiraRangingAndInBandDataSession,
+            },
+        );
+        let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // Send a data message segmented in data packets carrying
+        // the selected number of bytes of application data each.
+        let mut send_data = |size: usize, fragment_size: usize| {
+            let fragments = size.div_ceil(fragment_size);
+            (0..fragments)
+                .map(|index| {
+                    send_command(
+                        &mut device,
+                        &mut rx,
+                        DataMessageSnd {
+                            session_handle: session_id,
+                            destination_address: 0,
+                            data_sequence_number: 1,
+                            pbf: if index + 1 < fragments {
+                                PacketBoundaryFlag::NotComplete
+                            } else {
+                                PacketBoundaryFlag::Complete
+                            },
+                            application_data: vec![
+                                0;
+                                fragment_size.min(size - index * fragment_size)
+                            ],
+                        },
+                    )
+                })
+                .last()
+                .unwrap()
+        };
+
+        // The maximum data size applies to the reassembled message.
+        let ntf = send_data(MAX_DATA_SIZE + 1, 1000);
+        let ntf = SessionDataTransferStatusNtf::decode_full(&ntf).unwrap();
+        assert_eq!(
+            ntf.status,
+            DataTransferNtfStatusCode::UciDataTransferStatusErrorRejected
+        );
+
+        // Messages larger than a data packet are accepted.
+        let ntf = send_data(
+            2 * MAX_DATA_PACKET_PAYLOAD_SIZE,
+            MAX_DATA_PACKET_PAYLOAD_SIZE,
+        );
+        let ntf = SessionDataCreditNtf::decode_full(&ntf).unwrap();
+        assert_eq!(
+            ntf.credit_availability,
+            CreditAvailability::CreditNotAvailable
+        );
+        assert_eq!(
+            device.session(session_id).unwrap().data().len(),
+            2 * MAX_DATA_PACKET_PAYLOAD_SIZE
+        );
+    }
+
+    #[tokio::test]
+    async fn query_max_data_size() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        reset(&mut device, &mut rx);
+
+        for (session_id, session_type) in [
+            (0x1234, SessionType::FiraRangingAndInBandDataSession),
+            (0x5678, SessionType::FiraRangingSession),
+        ] {
+            let rsp = send_command(
+                &mut device,
+                &mut rx,
+                SessionInitCmd {
+                    session_id,
+                    session_type,
+                },
+            );
+            let rsp = SessionInitRsp::decode_full(&rsp).unwrap();
+            assert_eq!(rsp.status, uci::Status::Ok);
+        }
+
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionQueryMaxDataSizeInRangingCmd {
+                session_token: 0x9abc,
+            },
+        );
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::SessionConfig.into(),
+                SessionConfigOpcodeId::QueryDataSizeInRanging.into(),
+                uci::Status::ErrorSessionNotExist
+            )
+        );
+
+        // The query is rejected for sessions without data transfer.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionQueryMaxDataSizeInRangingCmd {
+                session_token: 0x5678,
+            },
+        );
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::SessionConfig.into(),
+                SessionConfigOpcodeId::QueryDataSizeInRanging.into(),
+                uci::Status::Rejected
+            )
+        );
+
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionQueryMaxDataSizeInRangingCmd {
+                session_token: 0x1234,
+            },
+        );
+        let rsp = SessionQueryMaxDataSizeInRangingRsp::decode_full(&rsp).unwrap();
+        let max_data_size = rsp.max_data_size as usize;
+
+        let data = |size| DataMessageSnd {
+            session_handle: 0x1234,
+            destination_address: 0,
+            data_sequence_number: 1,
+            pbf: PacketBoundaryFlag::Complete,
+            application_data: vec![0; size],
+        };
+
+        let ntf = send_command(&mut device, &mut rx, data(max_data_size + 1));
+        let ntf = SessionDataTransferStatusNtf::decode_full(&ntf).unwrap();
+        assert_eq!(
+            ntf.status,
+            DataTransferNtfStatusCode::UciDataTransferStatusErrorRejected
+        );
+
+        let ntf = send_command(&mut device, &mut rx, data(max_data_size));
+        assert!(SessionDataCreditNtf::decode_full(&ntf).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn initial_error_state() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (pica_tx, _) = mpsc::channel(1);
+        let mut device = Device::new(
+            0,
+            MacAddress::Short([0, 0]),
+            tx,
+            pica_tx,
+            DeviceOptions {
+                clock: Arc::new(MockClock::default()),
+                initial_state: DeviceState::DeviceStateError,
+                ..Default::default()
+            },
+        );
+        device.init();
+
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateError);
+
+        // The device remains in error state after reset.
+        reset(&mut device, &mut rx);
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateError);
+
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::Core.into(),
+                CoreOpcodeId::GetDeviceInfo.into(),
+                uci::Status::Rejected
+            )
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn suspend() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+        reset(&mut device, &mut rx);
+        rx.recv().await.unwrap();
+
+        // Only a suspended device can be resumed.
+        let rsp = send_command(&mut device, &mut rx, PicaResumeCmd {});
+        let rsp = PicaResumeRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Rejected);
+
+        let rsp = send_command(&mut device, &mut rx, PicaSuspendCmd {});
+        let rsp = PicaSuspendRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateLowPower);
+
+        // Session commands are rejected while suspended,
+        // core commands are still accepted.
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            SessionInitCmd {
+                session_id: 1,
+                session_type: SessionType::FiraRangingSession,
+            },
+        );
+        assert_eq!(
+            rsp,
+            status_response(
+                GroupId::SessionConfig.into(),
+                SessionConfigOpcodeId::Init.into(),
+                uci::Status::Rejected
+            )
+        );
+        let rsp = send_command(&mut device, &mut rx, CoreGetDeviceInfoCmd {});
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let rsp = send_command(&mut device, &mut rx, PicaResumeCmd {});
+        let rsp = PicaResumeRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+
+        // The device leaves the low power state when reset.
+        let rsp = send_command(&mut device, &mut rx, PicaSuspendCmd {});
+        let rsp = PicaSuspendRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        rx.recv().await.unwrap();
+        reset(&mut device, &mut rx);
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+        assert!(!device.is_suspended());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reset_with_invalid_config() {
+        let (mut device, mut rx) = new_device(Arc::new(MockClock::default()));
+        let ntf = CoreDeviceStatusNtf::decode_full(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+        reset(&mut device, &mut rx);
+        rx.recv().await.unwrap();
+
+        device.config.low_power_mode = false;
+        let rsp = send_command(
+            &mut device,
+            &mut rx,
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::try_from(0x01).unwrap(),
+            },
+        );
+        let rsp = CoreDeviceResetRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::InvalidParam);
+
+        // The device is not reset.
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        assert!(!device.config.low_power_mode);
+        assert_eq!(device.state, DeviceState::DeviceStateReady);
+    }
+}
@@ -15,46 +15,178 @@
 use anyhow::Result;
 use pdl_runtime::Packet;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time;
 
+// Shared with the binaries, not part of the library API.
+#[doc(hidden)]
+pub mod cli;
+mod csv;
 pub mod packets;
 mod pcapng;
+pub mod replay;
+#[cfg(any(test, feature = "test-client"))]
+pub mod test_client;
+mod trace;
+pub use trace::TraceFormat;
+use trace::TraceWriter;
 
 use packets::uci::{self, *};
 
 mod device;
-use device::{Device, MAX_DEVICE, MAX_SESSION};
+pub use device::{AntennaConfig, ChipInfo, Versions};
+use device::{Device, DeviceOptions, MAX_DEVICE, MAX_SESSION};
 
 mod session;
 
 mod mac_address;
 pub use mac_address::MacAddress;
 
+mod position;
+pub use position::Position;
+
 mod app_config;
 pub use app_config::AppConfig;
+use app_config::ResultReportConfig;
+
+mod clock;
+pub use clock::{Clock, ClockDrift, MockClock, Sleep, SystemClock};
+
+mod noise;
+mod scheduler;
+mod timers;
+pub use noise::NoiseModel;
+use noise::PairRngs;
+use scheduler::Scheduler;
+use timers::{Timer, Timers};
+pub use timers::{TimerInfo, TimerKind};
 
 pub type UciPacket = Vec<u8>;
 pub type UciStream = Pin<Box<dyn futures::stream::Stream<Item = Vec<u8>> + Send>>;
 pub type UciSink = Pin<Box<dyn futures::sink::Sink<Vec<u8>, Error = anyhow::Error> + Send>>;
 
+/// Direction of a UCI packet on a device connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Packet sent by the host to the device.
+    Inbound,
+    /// Packet sent by the device to the host.
+    Outbound,
+}
+
+/// Hook applied to the UCI packets exchanged with the hosts, before
+/// reassembly for inbound packets and after segmentation for outbound
+/// packets. The returned packet replaces the original packet,
+/// the packet is dropped if the hook returns None.
+pub type PacketTransform = dyn Fn(Direction, UciPacket) -> Option<UciPacket> + Send + Sync;
+
+/// Sender to the notification sink registered for a device, if any.
+type NotificationSender = Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<UciPacket>>>>;
+
+/// Return true for the session status and ranging notifications,
+/// which are routed to the notification sink of the device when
+/// one is registered.
+fn is_routed_notification(packet: &[u8]) -> bool {
+    let gid = packet[0] & 0xf;
+    let oid = packet[1] & 0x3f;
+    parse_message_type(packet[0]) == MessageType::Notification
+        && ((gid, oid)
+            == (
+                GroupId::SessionConfig.into(),
+                SessionConfigOpcodeId::Status.into(),
+            )
+            || (gid, oid)
+                == (
+                    GroupId::SessionControl.into(),
+                    SessionControlOpcodeId::Start.into(),
+                ))
+}
+
 /// Handle allocated for created devices or anchors.
-/// The handle is unique across the lifetime of the Pica context
-/// and callers may assume that one handle is never reused.
+/// Handles allocated in connection order are unique across the lifetime
+/// of the Pica context and never reused. The exception are the handles
+/// reserved for client ids (see [`PicaBuilder::client_handles`]),
+/// which are assigned again to the same client when it reconnects.
 pub type Handle = usize;
 
 /// Ranging measurement produced by a ranging estimator.
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize)]
 pub struct RangingMeasurement {
     pub range: u16,
     pub azimuth: i16,
     pub elevation: i8,
 }
 
+/// Noise-free measurement computed by the ranging estimator
+/// for a device and one of its peers in the last ranging round.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct GroundTruth {
+    pub mac_address: MacAddress,
+    pub peer_mac_address: MacAddress,
+    #[serde(flatten)]
+    pub measurement: RangingMeasurement,
+}
+
+/// Configuration of a device decoded for display. The parameters
+/// are indexed by name, with values interpreted according to the
+/// type of the parameter.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConfigSnapshot {
+    pub mac_address: MacAddress,
+    pub config: BTreeMap<String, serde_json::Value>,
+    pub sessions: Vec<SessionConfigSnapshot>,
+}
+
+/// APP configuration of a session decoded for display.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionConfigSnapshot {
+    pub session_id: u32,
+    pub app_config: BTreeMap<String, serde_json::Value>,
+}
+
+/// Report of the measurements between devices sharing the same
+/// position, for which the distance is zero and the angles of
+/// arrival are undefined.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColocatedReport {
+    /// The distance is reported with a valid status, the angles of
+    /// arrival are reported as zero with a zero figure of merit.
+    #[default]
+    AoaFailed,
+    /// The measurement is reported with the status FAILED when
+    /// the angles of arrival are requested.
+    Failed,
+}
+
+/// Measurement generated for a device and one of its peers,
+/// reported to the observer installed with [Pica::on_measurement].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MeasurementEvent {
+    pub session_id: u32,
+    /// Index of the ranging round in the session, reported as the
+    /// sequence number of the ranging notification.
+    pub round_index: u32,
+    pub mac_address: MacAddress,
+    pub peer_mac_address: MacAddress,
+    /// Noise-free measurement computed by the ranging estimator.
+    pub truth: RangingMeasurement,
+    /// Measurement after applying the ranging offset and the noise,
+    /// before the reporting filters: the reported distance limits and
+    /// the coalescing of the rounds may alter or omit the measurement
+    /// in the ranging notification.
+    pub measurement: RangingMeasurement,
+}
+
+/// Observer of the measurements generated in the ranging rounds.
+pub type MeasurementObserver = dyn FnMut(MeasurementEvent) + Send;
+
 /// Trait matching the capabilities of a ranging estimator.
 /// The estimator manages the position of the devices, and chooses
 /// the algorithm used to generate the ranging measurements.
@@ -66,6 +198,19 @@ pub trait RangingEstimator: Send + Sync {
     /// Return `None` if the measurement could not be estimated, e.g. because
     /// the devices are out of range.
     fn estimate(&self, left: &Handle, right: &Handle) -> Option<RangingMeasurement>;
+
+    /// Move the devices identified by their respective handle. All the
+    /// positions are applied at once, before the next estimation.
+    /// The default implementation ignores the positions, for estimators
+    /// not managing the position of the devices.
+    fn set_positions(&self, _positions: &[(Handle, Position)]) {}
+
+    /// Return the position of the device identified by its handle
+    /// in the world frame. The default implementation returns `None`,
+    /// for estimators not managing the position of the devices.
+    fn position(&self, _handle: &Handle) -> Option<Position> {
+        None
+    }
 }
 
 /// Pica emulation environment.
@@ -73,56 +218,311 @@ pub trait RangingEstimator: Send + Sync {
 /// from the same physical space.
 pub struct Pica {
     counter: usize,
-    devices: HashMap<Handle, Device>,
+    /// Devices ordered by handle, for the reports to be
+    /// reproducible across runs.
+    devices: BTreeMap<Handle, Device>,
     anchors: HashMap<MacAddress, Anchor>,
     command_rx: Option<mpsc::Receiver<PicaCommand>>,
     command_tx: mpsc::Sender<PicaCommand>,
     event_tx: broadcast::Sender<PicaEvent>,
     ranging_estimator: Box<dyn RangingEstimator>,
     pcapng_dir: Option<PathBuf>,
+    pcapng_max_size: Option<u64>,
+    trace_format: TraceFormat,
+    /// pcapng file recording the traffic of all devices,
+    /// with one interface per device connection.
+    pcapng_combined: Option<Arc<pcapng::CombinedFile>>,
+    csv_dir: Option<PathBuf>,
+    device_options: DeviceOptions,
+    max_devices: Option<usize>,
+    framing: Framing,
+    mtu: usize,
+    data_mtu: usize,
+    max_tlvs_per_packet: Option<usize>,
+    max_reassembly_size: usize,
+    idle_timeout: Option<Duration>,
+    packet_transform: Option<Arc<PacketTransform>>,
+    noise_model: NoiseModel,
+    colocated_report: ColocatedReport,
+    /// Handles assigned to the clients identified by the
+    /// connection handshake.
+    client_handles: HashMap<u32, Handle>,
+    /// Token required in the authentication sent by the host
+    /// as the first packet of the connection.
+    auth_token: Option<Vec<u8>>,
+    /// Generators of the noise applied to the measurements
+    /// of each device pair.
+    rngs: PairRngs,
+    /// Scheduler of the ranging rounds of each device.
+    schedulers: HashMap<Handle, Scheduler>,
+    /// Observer of the generated measurements.
+    measurement_observer: Option<Box<MeasurementObserver>>,
+    /// Noise-free measurements of the last ranging round and
+    /// the time of the round, indexed by device and session.
+    ground_truth: HashMap<(Handle, u32), (time::Instant, Vec<GroundTruth>)>,
+    /// Positions of the peers in the world frame over the last
+    /// [MOVEMENT_WINDOW] ranging rounds, indexed by device and session.
+    peer_positions: HashMap<(Handle, u32), VecDeque<(time::Instant, Vec<PeerPosition>)>>,
+    /// Notification sinks registered for the connected devices.
+    notification_txs: HashMap<Handle, NotificationSender>,
+    /// Trace files of the connected devices, shared with the writers
+    /// of the notification sinks.
+    trace_files: HashMap<Handle, Arc<trace::Tee>>,
+    /// Set while the ranging rounds are halted. Rounds are then only
+    /// executed on demand with [PicaCommand::StepRounds].
+    clock_paused: bool,
 }
 
+/// Default limit for the size of the payload of reassembled
+/// control packets.
+pub const DEFAULT_MAX_REASSEMBLY_SIZE: usize = 64 * 1024;
+
+/// Delay after which connections are closed when the host has not sent
+/// the authentication command.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay after which connections are closed when the host has not sent
+/// its first packet, when client handles are configured.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors reported by the pica commands and handlers.
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum PicaCommandError {
+pub enum PicaError {
     #[error("Device already exists: {0}")]
     DeviceAlreadyExists(MacAddress),
     #[error("Device not found: {0}")]
     DeviceNotFound(MacAddress),
+    #[error("Device handle not found: {0}")]
+    HandleNotFound(Handle),
+    #[error("Session not found: 0x{0:x}")]
+    SessionNotFound(u32),
+    #[error("Session cannot be attached to device: {0}")]
+    SessionNotAttachable(MacAddress),
+    #[error("Capability value exceeds 255 bytes: 0x{0:x}")]
+    InvalidCapabilityLength(u8),
+    #[error("Maximum number of devices reached")]
+    MaxDevicesExceeded,
+    #[error("Session type not supported by the command: 0x{0:x}")]
+    SessionTypeNotSupported(u32),
+    #[error("Clock is not paused")]
+    ClockNotPaused,
+    #[error("Clock drift is out of range")]
+    InvalidClockDrift,
+    #[error("Ranging jitter is not finite")]
+    InvalidRangingJitter,
+    #[error("Authentication token is empty")]
+    EmptyAuthToken,
+    #[error("Invalid country code: {0:?}")]
+    InvalidCountryCode([u8; 2]),
+}
+
+/// Former name of [`PicaError`].
+#[deprecated(note = "renamed to PicaError")]
+pub type PicaCommandError = PicaError;
+
+/// Status reported to the host for the errors of the UCI commands.
+impl From<PicaError> for uci::Status {
+    fn from(err: PicaError) -> Self {
+        match err {
+            PicaError::SessionNotFound(_) => uci::Status::ErrorSessionNotExist,
+            PicaError::DeviceNotFound(_)
+            | PicaError::HandleNotFound(_)
+            | PicaError::InvalidCapabilityLength(_)
+            | PicaError::InvalidClockDrift
+            | PicaError::InvalidRangingJitter
+            | PicaError::EmptyAuthToken
+            | PicaError::InvalidCountryCode(_) => uci::Status::InvalidParam,
+            PicaError::DeviceAlreadyExists(_)
+            | PicaError::SessionNotAttachable(_)
+            | PicaError::MaxDevicesExceeded
+            | PicaError::SessionTypeNotSupported(_)
+            | PicaError::ClockNotPaused => uci::Status::Rejected,
+        }
+    }
 }
 
 pub enum PicaCommand {
     // Connect a new device.
     Connect(UciStream, UciSink),
+    // Connect a new device after the connection handshake, with the
+    // client id received in the handshake packet if any.
+    ConnectClient(Option<u32>, UciStream, UciSink),
     // Disconnect the selected device.
     Disconnect(usize),
     // Execute ranging command for selected device and session.
     Ranging(usize, u32),
+    // Service the next ranging round scheduled for the selected device.
+    RangingRound(usize),
     // Send an in-band request to stop ranging to a peer controlee identified by address and session id.
     StopRanging(MacAddress, u32),
     // UCI packet received for the selected device.
     UciPacket(usize, Vec<u8>),
     // Create Anchor
-    CreateAnchor(
-        MacAddress,
-        oneshot::Sender<Result<Handle, PicaCommandError>>,
-    ),
+    CreateAnchor(MacAddress, oneshot::Sender<Result<Handle, PicaError>>),
     // Destroy Anchor
-    DestroyAnchor(
+    DestroyAnchor(MacAddress, oneshot::Sender<Result<Handle, PicaError>>),
+    // Inject a fault in the command handler of the selected device.
+    InjectFault(Handle, Fault),
+    // Set the temperature of the UWBS, in degrees Celsius.
+    SetTemperature(MacAddress, i8, oneshot::Sender<Result<Handle, PicaError>>),
+    // Set the country code of the UWBS, as an ISO 3166-1 alpha-2 code,
+    // or "00" when the country is unknown. Lowercase codes are normalized
+    // to uppercase.
+    SetCountryCode(
         MacAddress,
-        oneshot::Sender<Result<Handle, PicaCommandError>>,
+        [u8; 2],
+        oneshot::Sender<Result<Handle, PicaError>>,
     ),
+    // Get the noise-free measurements of the last ranging round
+    // of the selected session, for all participating devices.
+    GetGroundTruth(u32, oneshot::Sender<Vec<GroundTruth>>),
+    // Move a session between two devices, identified by their MAC
+    // address. Active sessions keep ranging from the destination device.
+    MoveSession {
+        session_id: u32,
+        from: MacAddress,
+        to: MacAddress,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    },
+    // Assign the MAC address of the selected device, replacing the
+    // address derived from the device handle.
+    SetMacAddress {
+        handle: Handle,
+        address: MacAddress,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    },
+    // Override a capability reported by the selected device, identified
+    // by its raw TLV type. Types unknown to pica are reported verbatim.
+    OverrideCapability {
+        handle: Handle,
+        id: u8,
+        value: Vec<u8>,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    },
+    // Get the decoded configuration of all devices.
+    GetConfig(oneshot::Sender<Vec<ConfigSnapshot>>),
+    // Route the session status and ranging notifications of the selected
+    // device to a secondary sink. Command responses and the other packets
+    // remain on the device connection.
+    SetNotificationSink {
+        handle: Handle,
+        sink: UciSink,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    },
+    // Send a session status notification on behalf of the selected device,
+    // and update the state of the session accordingly.
+    InjectSessionStatus {
+        handle: Handle,
+        session_id: u32,
+        state: uci::SessionState,
+        reason: uci::ReasonCode,
+    },
+    // Reset the selected device, as when receiving the Core Device Reset
+    // command, and return the state of the device after the reset.
+    ResetDevice {
+        handle: Handle,
+        rsp_tx: oneshot::Sender<Result<uci::DeviceState, PicaError>>,
+    },
+    // Get the state of the selected device.
+    GetDeviceState {
+        handle: Handle,
+        rsp_tx: oneshot::Sender<Result<uci::DeviceState, PicaError>>,
+    },
+    // Halt the ranging rounds of all devices, and pause the shared clock.
+    // The ranging timers keep running, but their rounds are dropped until
+    // the clock is resumed. The timers measured on the shared clock, such
+    // as the idle timeouts and the reset and boot delays, are frozen.
+    PauseClock,
+    // Resume the ranging rounds of all devices, and the shared clock.
+    ResumeClock,
+    // Execute the selected number of ranging rounds for all active
+    // sessions while the clock is paused. The rounds of each device are
+    // ordered by the scheduler. The clock is advanced by the longest
+    // ranging interval after each round.
+    StepRounds(usize, oneshot::Sender<Result<(), PicaError>>),
+    // Stop the active sessions of all devices, and return the stopped
+    // sessions identified by their device handle and session id.
+    StopAllRanging(oneshot::Sender<Vec<(Handle, u32)>>),
+    // Set the drift of the clock of the selected device relative to the
+    // shared clock, in parts per million. The drift applies to the UWBS
+    // timestamp and to the timing fields reported by the device.
+    // Drifts of one million ppm or more, in absolute value, are rejected.
+    SetClockDrift {
+        handle: Handle,
+        ppm: f64,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    },
+    // Return the description of the active timers, for debugging.
+    GetTimers(oneshot::Sender<Vec<TimerInfo>>),
+    // Override the reason code reported when the host stops the selected
+    // session. The reason of the state changes requested by the host is
+    // restored when the reason is None.
+    SetStopReason {
+        handle: Handle,
+        session_id: u32,
+        reason: Option<uci::ReasonCode>,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    },
+    // Move several devices or anchors at once, identified by their handle.
+    // The positions are forwarded to the ranging estimator in one batch.
+    // Unknown handles are reported without discarding the other positions.
+    SetPositions(Vec<(Handle, Position)>, oneshot::Sender<Vec<PicaError>>),
+}
+
+/// Behavior of the device when receiving a command targeted
+/// by an injected fault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultBehavior {
+    /// Respond with the selected status code, without executing the command.
+    ReturnStatus(uci::Status),
+    /// Drop the command without responding.
+    Drop,
+    /// Execute the command, but delay the response.
+    Delay(Duration),
+}
+
+/// Fault injected in the command handler of a device.
+/// The fault applies to the next `count` commands matching the
+/// group and opcode identifiers.
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+    pub gid: uci::GroupId,
+    pub oid: u8,
+    pub behavior: FaultBehavior,
+    pub count: usize,
 }
 
 impl Display for PicaCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let cmd = match self {
             PicaCommand::Connect(_, _) => "Connect",
+            PicaCommand::ConnectClient(_, _, _) => "ConnectClient",
             PicaCommand::Disconnect(_) => "Disconnect",
             PicaCommand::Ranging(_, _) => "Ranging",
+            PicaCommand::RangingRound(_) => "RangingRound",
             PicaCommand::StopRanging(_, _) => "StopRanging",
             PicaCommand::UciPacket(_, _) => "UciPacket",
             PicaCommand::CreateAnchor(_, _) => "CreateAnchor",
             PicaCommand::DestroyAnchor(_, _) => "DestroyAnchor",
+            PicaCommand::InjectFault(_, _) => "InjectFault",
+            PicaCommand::SetTemperature(_, _, _) => "SetTemperature",
+            PicaCommand::SetCountryCode(_, _, _) => "SetCountryCode",
+            PicaCommand::GetGroundTruth(_, _) => "GetGroundTruth",
+            PicaCommand::MoveSession { .. } => "MoveSession",
+            PicaCommand::SetMacAddress { .. } => "SetMacAddress",
+            PicaCommand::OverrideCapability { .. } => "OverrideCapability",
+            PicaCommand::GetConfig(_) => "GetConfig",
+            PicaCommand::SetNotificationSink { .. } => "SetNotificationSink",
+            PicaCommand::InjectSessionStatus { .. } => "InjectSessionStatus",
+            PicaCommand::ResetDevice { .. } => "ResetDevice",
+            PicaCommand::GetDeviceState { .. } => "GetDeviceState",
+            PicaCommand::PauseClock => "PauseClock",
+            PicaCommand::ResumeClock => "ResumeClock",
+            PicaCommand::StepRounds(_, _) => "StepRounds",
+            PicaCommand::StopAllRanging(_) => "StopAllRanging",
+            PicaCommand::GetTimers(_) => "GetTimers",
+            PicaCommand::SetStopReason { .. } => "SetStopReason",
+            PicaCommand::SetClockDrift { .. } => "SetClockDrift",
         };
         write!(f, "{}", cmd)
     }
@@ -141,6 +541,12 @@ pub enum PicaEvent {
         handle: Handle,
         mac_address: MacAddress,
     },
+    // The MAC address of a UCI connection was assigned
+    MacAddressChanged {
+        handle: Handle,
+        previous_mac_address: MacAddress,
+        mac_address: MacAddress,
+    },
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -156,25 +562,261 @@ struct Anchor {
     mac_address: MacAddress,
 }
 
+/// Add the ranging offset of the measuring device to the distance
+/// of a measurement. The distance saturates at zero, unless negative
+/// distances are allowed: the distance is then encoded in two's complement.
+fn with_ranging_offset(
+    measurement: RangingMeasurement,
+    offset: i16,
+    allow_negative: bool,
+) -> RangingMeasurement {
+    let range = measurement.range as i32 + offset as i32;
+    RangingMeasurement {
+        range: if allow_negative && range < 0 {
+            range as i16 as u16
+        } else {
+            range.clamp(0, u16::MAX as i32) as u16
+        },
+        ..measurement
+    }
+}
+
+/// Apply the bounds of the distances reported by the measuring device.
+/// Distances shorter than the minimum are clamped to the minimum,
+/// the measurement is unavailable for distances longer than the maximum.
+/// Negative distances are compared in two's complement when allowed.
+fn with_reported_distance_limits(
+    measurement: RangingMeasurement,
+    (min, max): (Option<u16>, Option<u16>),
+    allow_negative: bool,
+) -> Option<RangingMeasurement> {
+    let range = if allow_negative {
+        measurement.range as i16 as i32
+    } else {
+        measurement.range as i32
+    };
+    if max.is_some_and(|max| range > max as i32) {
+        return None;
+    }
+    match min {
+        Some(min) if range < min as i32 => Some(RangingMeasurement {
+            range: min,
+            ..measurement
+        }),
+        _ => Some(measurement),
+    }
+}
+
+/// Position of a peer in the world frame, recorded for
+/// a ranging round.
+struct PeerPosition {
+    peer_mac_address: MacAddress,
+    position: Position,
+}
+
+/// Compute the rate of change per second of a quantity between the
+/// records of a peer in a previous ranging round and in the current
+/// round. The rate is unknown when the peer was not ranged with in
+/// both rounds.
+fn peer_rate<T>(
+    previous: Option<&(time::Instant, Vec<T>)>,
+    now: time::Instant,
+    current: &[T],
+    is_peer: impl Fn(&T) -> bool,
+    change: impl Fn(&T, &T) -> f32,
+) -> Option<f32> {
+    let (previous_instant, previous_records) = previous?;
+    let elapsed = now.duration_since(*previous_instant).as_secs_f32();
+    let previous_record = previous_records.iter().find(|&record| is_peer(record))?;
+    let record = current.iter().find(|&record| is_peer(record))?;
+    (elapsed > 0.).then(|| change(previous_record, record) / elapsed)
+}
+
+/// Compute the radial velocity in centimeters per second of a peer
+/// from the ground truth distances of two consecutive ranging rounds.
+/// The velocity is positive when the devices move apart, and zero
+/// when the peer was not ranged with in both rounds.
+fn radial_velocity(
+    previous: Option<&(time::Instant, Vec<GroundTruth>)>,
+    now: time::Instant,
+    ground_truth: &[GroundTruth],
+    peer_mac_address: MacAddress,
+) -> i16 {
+    peer_rate(
+        previous,
+        now,
+        ground_truth,
+        |truth| truth.peer_mac_address == peer_mac_address,
+        |previous, truth| truth.measurement.range as f32 - previous.measurement.range as f32,
+    )
+    .map_or(0, |velocity| velocity.round() as i16)
+}
+
+/// Compute the speed in centimeters per second of a peer from its
+/// positions in the world frame at the start of the movement window and
+/// in the current round. The rotations of the device do not contribute
+/// to the speed, and a peer oscillating around a point is reported with
+/// a low speed. The speed is zero when the peer was not ranged with in
+/// both rounds.
+fn peer_speed(
+    previous: Option<&(time::Instant, Vec<PeerPosition>)>,
+    now: time::Instant,
+    positions: &[PeerPosition],
+    peer_mac_address: MacAddress,
+) -> f32 {
+    peer_rate(
+        previous,
+        now,
+        positions,
+        |position| position.peer_mac_address == peer_mac_address,
+        |previous, position| previous.position.distance(&position.position),
+    )
+    .unwrap_or(0.)
+}
+
+/// Number of previous ranging rounds over which the speed of the peers
+/// is measured. The movement window spans an even number of rounds
+/// for the oscillations at half the ranging rate to cancel out.
+const MOVEMENT_WINDOW: usize = 2;
+
+/// Figure of merit of the AoA measurements reported during
+/// the warmup phase of a session.
+const WARMUP_AOA_FOM: u8 = 10;
+
+/// Spacing in centimeters between the centers of two adjacent
+/// receive antenna pairs.
+const ANTENNA_PAIR_SPACING: f32 = 5.;
+
+/// Bound of the clock drift of the devices, in parts per million,
+/// excluded. The local clock of the devices runs at a rate between
+/// zero and twice the rate of the shared clock.
+const MAX_CLOCK_DRIFT_PPM: f64 = 1e6;
+
+/// Compute the azimuth in degrees measured by each receive antenna
+/// pair of a device, from the distance and azimuth of the peer measured
+/// at the center of the device. The pairs are aligned on the lateral
+/// axis of the device, and see the peer under slightly different angles.
+fn antenna_pair_azimuths(range: u16, azimuth: i16, antenna_pairs: u8) -> Vec<i16> {
+    if range == 0 {
+        return vec![azimuth; antenna_pairs as usize];
+    }
+    let azimuth = (azimuth as f32).to_radians();
+    let x = range as f32 * azimuth.cos();
+    let y = range as f32 * azimuth.sin();
+    (0..antenna_pairs)
+        .map(|pair| {
+            let offset = (pair as f32 - (antenna_pairs - 1) as f32 / 2.) * ANTENNA_PAIR_SPACING;
+            (y - offset).atan2(x).to_degrees().round() as i16
+        })
+        .collect()
+}
+
+/// Speed of light in centimeters per picosecond.
+const SPEED_OF_LIGHT: f64 = 0.029_979_245_8;
+
+/// Compute the single-sided two-way ranging timing fields T_round and
+/// T_reply, in picoseconds, for the reported distance in centimeters,
+/// such that T_round = T_reply + 2 * ToF.
+/// The time of flight is rounded to the picosecond, so that the distance
+/// computed back from the timing fields matches the reported distance.
+fn twr_timing(distance: u16, reply_time: Duration) -> (u32, u32) {
+    let time_of_flight = (distance as f64 / SPEED_OF_LIGHT).round() as u32;
+    let reply_time = reply_time
+        .as_nanos()
+        .saturating_mul(1000)
+        .min((u32::MAX - 2 * time_of_flight) as u128) as u32;
+    (reply_time + 2 * time_of_flight, reply_time)
+}
+
+/// Encode the TLVs of the vendor data of the ranging notifications,
+/// from the records of the value, e.g. one record per measurement.
+/// The records are split across several TLVs of the same type when
+/// they exceed the maximum length of the value, a record is never
+/// split. Records exceeding the maximum length alone are omitted.
+fn make_vendor_data_tlvs(
+    t: VendorDataTlvType,
+    records: impl IntoIterator<Item = Vec<u8>>,
+) -> Vec<u8> {
+    let encode = |v: Vec<u8>| {
+        VendorDataTlv { t, v }
+            .encode_to_vec()
+            .unwrap_or_else(|err| {
+                log::warn!("Failed to encode vendor data TLV {:?}: {}", t, err);
+                vec![]
+            })
+    };
+    let mut tlvs = vec![];
+    let mut v = vec![];
+    for record in records {
+        if !v.is_empty() && v.len() + record.len() > u8::MAX as usize {
+            tlvs.extend(encode(std::mem::take(&mut v)));
+        }
+        v.extend(record);
+    }
+    tlvs.extend(encode(v));
+    tlvs
+}
+
+/// Build the ranging measurement reported to the host.
+/// Only the fields selected by the RESULT_REPORT_CONFIG app config
+/// are reported, the other fields are left to zero.
+#[allow(clippy::too_many_arguments)]
 fn make_measurement(
     mac_address: &MacAddress,
     local: RangingMeasurement,
     remote: RangingMeasurement,
+    colocated: bool,
+    report_config: &ResultReportConfig,
+    aoa_fom: u8,
+    local_antenna_config: AntennaConfig,
+    remote_antenna_config: AntennaConfig,
+    colocated_report: ColocatedReport,
 ) -> ShortAddressTwoWayRangingMeasurement {
+    // The angle of arrival is undefined when the devices are at the
+    // same position, regardless of the reported distance.
+    let aoa_available = !colocated;
+    // Angles not measured by the antennas are omitted.
+    let local_azimuth = report_config.aoa_azimuth && local_antenna_config.supports_azimuth();
+    let local_elevation = report_config.aoa_elevation && local_antenna_config.supports_elevation();
+    let remote_azimuth = report_config.aoa_azimuth && remote_antenna_config.supports_azimuth();
+    let remote_elevation =
+        report_config.aoa_elevation && remote_antenna_config.supports_elevation();
+    let angle = |enabled: bool, angle: u16| {
+        if enabled && aoa_available {
+            angle
+        } else {
+            0
+        }
+    };
+    let fom = |enabled: bool| {
+        if enabled && report_config.aoa_fom && aoa_available {
+            aoa_fom
+        } else {
+            0
+        }
+    };
+
     if let MacAddress::Short(address) = mac_address {
         ShortAddressTwoWayRangingMeasurement {
             mac_address: u16::from_le_bytes(*address),
-            status: uci::Status::Ok,
+            status: if aoa_available
+                || !report_config.has_aoa()
+                || colocated_report == ColocatedReport::AoaFailed
+            {
+                uci::Status::Ok
+            } else {
+                uci::Status::Failed
+            },
             nlos: 0, // in Line Of Sight
-            distance: local.range,
-            aoa_azimuth: local.azimuth as u16,
-            aoa_azimuth_fom: 100, // Yup, pretty sure about this
-            aoa_elevation: local.elevation as u16,
-            aoa_elevation_fom: 100, // Yup, pretty sure about this
-            aoa_destination_azimuth: remote.azimuth as u16,
-            aoa_destination_azimuth_fom: 100,
-            aoa_destination_elevation: remote.elevation as u16,
-            aoa_destination_elevation_fom: 100,
+            distance: if report_config.tof { local.range } else { 0 },
+            aoa_azimuth: angle(local_azimuth, local.azimuth as u16),
+            aoa_azimuth_fom: fom(local_azimuth),
+            aoa_elevation: angle(local_elevation, local.elevation as u16),
+            aoa_elevation_fom: fom(local_elevation),
+            aoa_destination_azimuth: angle(remote_azimuth, remote.azimuth as u16),
+            aoa_destination_azimuth_fom: fom(remote_azimuth),
+            aoa_destination_elevation: angle(remote_elevation, remote.elevation as u16),
+            aoa_destination_elevation_fom: fom(remote_elevation),
             slot_index: 0,
             rssi: u8::MAX,
         }
@@ -183,22 +825,664 @@ fn make_measurement(
     }
 }
 
-impl Pica {
-    pub fn new(ranging_estimator: Box<dyn RangingEstimator>, pcapng_dir: Option<PathBuf>) -> Self {
+/// Circular mean of angles in degrees.
+fn circular_mean(angles: impl Iterator<Item = i16>) -> i16 {
+    let (sin, cos) = angles.fold((0., 0.), |(sin, cos), angle| {
+        let angle = (angle as f64).to_radians();
+        (sin + angle.sin(), cos + angle.cos())
+    });
+    f64::atan2(sin, cos).to_degrees().round() as i16
+}
+
+/// Average the measurements of the rounds of a coalescing window,
+/// for each peer. Distances, figures of merit and RSSI are averaged,
+/// the angles of arrival are averaged on the circle. Only the valid
+/// measurements are averaged, peers without valid measurements in
+/// the window are omitted.
+fn coalesce_measurements<'a>(
+    measurements: impl Iterator<Item = &'a ShortAddressTwoWayRangingMeasurement>,
+) -> Vec<ShortAddressTwoWayRangingMeasurement> {
+    let mut peers: Vec<Vec<&ShortAddressTwoWayRangingMeasurement>> = vec![];
+    for measurement in measurements.filter(|measurement| measurement.status == uci::Status::Ok) {
+        match peers
+            .iter_mut()
+            .find(|peer| peer[0].mac_address == measurement.mac_address)
+        {
+            Some(peer) => peer.push(measurement),
+            None => peers.push(vec![measurement]),
+        }
+    }
+
+    peers
+        .into_iter()
+        .map(|peer| {
+            let count = peer.len() as u32;
+            let mean = |value: fn(&ShortAddressTwoWayRangingMeasurement) -> u16| {
+                let sum = peer.iter().map(|m| value(m) as u32).sum::<u32>();
+                ((sum + count / 2) / count) as u16
+            };
+            let angle = |value: fn(&ShortAddressTwoWayRangingMeasurement) -> u16| {
+                circular_mean(peer.iter().map(|m| value(m) as i16)) as u16
+            };
+            ShortAddressTwoWayRangingMeasurement {
+                distance: mean(|m| m.distance),
+                aoa_azimuth: angle(|m| m.aoa_azimuth),
+                aoa_azimuth_fom: mean(|m| m.aoa_azimuth_fom as u16) as u8,
+                aoa_elevation: angle(|m| m.aoa_elevation),
+                aoa_elevation_fom: mean(|m| m.aoa_elevation_fom as u16) as u8,
+                aoa_destination_azimuth: angle(|m| m.aoa_destination_azimuth),
+                aoa_destination_azimuth_fom: mean(|m| m.aoa_destination_azimuth_fom as u16) as u8,
+                aoa_destination_elevation: angle(|m| m.aoa_destination_elevation),
+                aoa_destination_elevation_fom: mean(|m| m.aoa_destination_elevation_fom as u16)
+                    as u8,
+                rssi: mean(|m| m.rssi as u16) as u8,
+                ..peer[peer.len() - 1].clone()
+            }
+        })
+        .collect()
+}
+
+/// Build the ranging measurement reported for a peer device
+/// that could not be ranged with. Returns None for peers with an
+/// extended address, which cannot be reported in the short address
+/// measurement list.
+fn make_unavailable_measurement(
+    mac_address: &MacAddress,
+) -> Option<ShortAddressTwoWayRangingMeasurement> {
+    match mac_address {
+        MacAddress::Short(address) => Some(ShortAddressTwoWayRangingMeasurement {
+            mac_address: u16::from_le_bytes(*address),
+            status: uci::Status::RangingRxTimeout,
+            nlos: 0,
+            distance: 0,
+            aoa_azimuth: 0,
+            aoa_azimuth_fom: 0,
+            aoa_elevation: 0,
+            aoa_elevation_fom: 0,
+            aoa_destination_azimuth: 0,
+            aoa_destination_azimuth_fom: 0,
+            aoa_destination_elevation: 0,
+            aoa_destination_elevation_fom: 0,
+            slot_index: 0,
+            rssi: 0,
+        }),
+        MacAddress::Extended(_) => {
+            log::warn!("Extended address {} is not supported", mac_address);
+            None
+        }
+    }
+}
+
+/// Peer of a device in a ranging round: an anchor listed in the
+/// destination addresses of the session, or a device with a
+/// compatible session.
+struct RangingPeer {
+    handle: Handle,
+    /// Address of the peer in the session.
+    mac_address: MacAddress,
+    /// Address of the peer device, selecting the random number
+    /// generator of the pair.
+    device_mac_address: MacAddress,
+    ranging_offset: i16,
+    allow_negative_distance: bool,
+    antenna_config: AntennaConfig,
+}
+
+/// Result of the ranging of a device with one of its peers.
+struct PeerRanging {
+    ground_truth: GroundTruth,
+    position: Option<PeerPosition>,
+    measurement: Option<ShortAddressTwoWayRangingMeasurement>,
+}
+
+/// Range a device with one of its peers in a round of the session.
+/// The ranging offsets and the noise are applied to the measurements
+/// of both devices, and the measurement of the device is reported to
+/// the observer before the reported distance limits. Returns None
+/// when the ranging estimator has no measurement for the pair.
+#[allow(clippy::too_many_arguments)]
+fn range_peer(
+    ranging_estimator: &dyn RangingEstimator,
+    noise_model: &NoiseModel,
+    rngs: &mut PairRngs,
+    measurement_observer: &mut Option<Box<MeasurementObserver>>,
+    colocated_report: ColocatedReport,
+    device: &Device,
+    session_id: u32,
+    aoa_fom: u8,
+    peer: &RangingPeer,
+) -> Option<PeerRanging> {
+    let session = device.session(session_id)?;
+    let local = ranging_estimator.estimate(&device.handle, &peer.handle)?;
+    let remote = ranging_estimator.estimate(&peer.handle, &device.handle)?;
+    let ground_truth = GroundTruth {
+        mac_address: device.mac_address,
+        peer_mac_address: peer.mac_address,
+        measurement: local,
+    };
+    let position = ranging_estimator
+        .position(&peer.handle)
+        .map(|position| PeerPosition {
+            peer_mac_address: peer.mac_address,
+            position,
+        });
+    let truth = local;
+    let local = with_ranging_offset(
+        local,
+        device.ranging_offset(),
+        device.allow_negative_distance(),
+    );
+    let remote = with_ranging_offset(remote, peer.ranging_offset, peer.allow_negative_distance);
+    let rng = rngs.get(&device.mac_address, &peer.device_mac_address);
+    let local = noise_model.apply(rng, local);
+    let remote = noise_model.apply(rng, remote);
+    if let Some(observer) = measurement_observer {
+        observer(MeasurementEvent {
+            session_id,
+            round_index: session.sequence_number,
+            mac_address: device.mac_address,
+            peer_mac_address: peer.mac_address,
+            truth,
+            measurement: local,
+        });
+    }
+    // Distances out of the reported bounds are unavailable.
+    let measurement = match with_reported_distance_limits(
+        local,
+        device.reported_distance_limits(),
+        device.allow_negative_distance(),
+    ) {
+        Some(local) => Some(make_measurement(
+            &peer.mac_address,
+            local,
+            remote,
+            truth.range == 0,
+            &session.app_config.result_report_config,
+            aoa_fom,
+            device.antenna_config(),
+            peer.antenna_config,
+            colocated_report,
+        )),
+        None => make_unavailable_measurement(&peer.mac_address),
+    };
+    Some(PeerRanging {
+        ground_truth,
+        position,
+        measurement,
+    })
+}
+
+/// Compare the authentication token sent by the host with the expected
+/// token. The comparison time does not depend on the content of the
+/// tokens, only on their length.
+fn tokens_match(token: &[u8], expected: &[u8]) -> bool {
+    token.len() == expected.len()
+        && token
+            .iter()
+            .zip(expected)
+            .fold(0, |acc, (left, right)| acc | (left ^ right))
+            == 0
+}
+
+/// Build the one-way AoA measurement reported in place of the two-way
+/// ranging measurement, in the rounds of hybrid sessions scheduled
+/// to report the angle of arrival only.
+fn make_owr_aoa_measurement(
+    measurement: &ShortAddressTwoWayRangingMeasurement,
+    frame_sequence_number: u8,
+) -> ShortAddressOwrAoaRangingMeasurement {
+    ShortAddressOwrAoaRangingMeasurement {
+        mac_address: measurement.mac_address,
+        status: measurement.status,
+        nlos: measurement.nlos,
+        frame_sequence_number,
+        block_index: 0,
+        aoa_azimuth: measurement.aoa_azimuth,
+        aoa_azimuth_fom: measurement.aoa_azimuth_fom,
+        aoa_elevation: measurement.aoa_elevation,
+        aoa_elevation_fom: measurement.aoa_elevation_fom,
+    }
+}
+
+/// Builder for the Pica emulation environment.
+/// All options are initialized with default values, the ranging estimator
+/// defaults to an estimator producing no measurements.
+pub struct PicaBuilder {
+    ranging_estimator: Box<dyn RangingEstimator>,
+    pcapng_dir: Option<PathBuf>,
+    pcapng_max_size: Option<u64>,
+    trace_format: TraceFormat,
+    pcapng_combined: Option<Arc<pcapng::CombinedFile>>,
+    csv_dir: Option<PathBuf>,
+    device_options: DeviceOptions,
+    max_devices: Option<usize>,
+    framing: Framing,
+    mtu: usize,
+    data_mtu: usize,
+    max_tlvs_per_packet: Option<usize>,
+    max_reassembly_size: usize,
+    idle_timeout: Option<Duration>,
+    packet_transform: Option<Arc<PacketTransform>>,
+    noise_model: NoiseModel,
+    noise_seed: u64,
+    colocated_report: ColocatedReport,
+    client_handles: HashMap<u32, Handle>,
+    auth_token: Option<Vec<u8>>,
+}
+
+/// Ranging estimator for devices with unknown positions,
+/// producing no measurements.
+pub struct NullRangingEstimator();
+
+impl RangingEstimator for NullRangingEstimator {
+    fn estimate(&self, _left: &Handle, _right: &Handle) -> Option<RangingMeasurement> {
+        None
+    }
+}
+
+impl Default for PicaBuilder {
+    fn default() -> Self {
+        PicaBuilder {
+            ranging_estimator: Box::new(NullRangingEstimator()),
+            pcapng_dir: None,
+            pcapng_max_size: None,
+            trace_format: TraceFormat::Pcapng,
+            pcapng_combined: None,
+            csv_dir: None,
+            device_options: Default::default(),
+            max_devices: None,
+            framing: Framing::V2,
+            mtu: MAX_CTRL_PACKET_PAYLOAD_SIZE,
+            data_mtu: MAX_DATA_PACKET_PAYLOAD_SIZE,
+            max_tlvs_per_packet: None,
+            max_reassembly_size: DEFAULT_MAX_REASSEMBLY_SIZE,
+            idle_timeout: None,
+            packet_transform: None,
+            noise_model: Default::default(),
+            noise_seed: 0,
+            colocated_report: Default::default(),
+            client_handles: Default::default(),
+            auth_token: None,
+        }
+    }
+}
+
+impl PicaBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Select the estimator used to generate the ranging measurements.
+    pub fn ranging_estimator(mut self, ranging_estimator: Box<dyn RangingEstimator>) -> Self {
+        self.ranging_estimator = ranging_estimator;
+        self
+    }
+
+    /// Record the traces of the device connections to pcapng files
+    /// saved in the selected directory.
+    pub fn pcapng_dir(mut self, pcapng_dir: impl Into<PathBuf>) -> Self {
+        self.pcapng_dir = Some(pcapng_dir.into());
+        self
+    }
+
+    /// Configure the maximum size of the pcapng files. The traces are
+    /// rotated into `device-{handle}.{n}.pcapng` when the limit is exceeded.
+    pub fn pcapng_max_size(mut self, pcapng_max_size: u64) -> Self {
+        self.pcapng_max_size = Some(pcapng_max_size);
+        self
+    }
+
+    /// Select the format of the traces recorded to the directory
+    /// configured with [PicaBuilder::pcapng_dir]. Raw traces are saved
+    /// as `device-{handle}.bin` and are never rotated.
+    pub fn trace_format(mut self, trace_format: TraceFormat) -> Self {
+        self.trace_format = trace_format;
+        self
+    }
+
+    /// Record the traffic of all devices to a single pcapng file, in
+    /// addition to the per-device traces. Each device connection is
+    /// described as a separate interface, appended to the file when the
+    /// device is connected. The file is created immediately.
+    pub fn pcapng_combined(mut self, path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        log::debug!("Recording combined pcapng to file {}", path.display());
+        self.pcapng_combined = Some(Arc::new(pcapng::CombinedFile::create(path)?));
+        Ok(self)
+    }
+
+    /// Export the ranging results reported to the hosts to CSV files
+    /// saved as `device-{handle}-session-{session_token}.csv` in the
+    /// selected directory, one file per device and session.
+    pub fn csv_dir(mut self, csv_dir: impl Into<PathBuf>) -> Self {
+        self.csv_dir = Some(csv_dir.into());
+        self
+    }
+
+    /// Limit the number of devices connected at the same time.
+    /// Connections exceeding the limit are closed.
+    pub fn max_devices(mut self, max_devices: usize) -> Self {
+        self.max_devices = Some(max_devices);
+        self
+    }
+
+    /// Select the versions reported by the devices in the
+    /// Core Get Device Info response.
+    pub fn versions(mut self, versions: Versions) -> Self {
+        self.device_options.versions = versions;
+        self
+    }
+
+    /// Select the chip identifiers reported by the devices in the
+    /// Pica Get Chip Info response, and optionally in the vendor specific
+    /// information of the Core Get Device Info response.
+    pub fn chip_info(mut self, chip_info: ChipInfo, in_device_info: bool) -> Self {
+        self.device_options.chip_info = chip_info;
+        self.device_options.chip_info_in_device_info = in_device_info;
+        self
+    }
+
+    /// Select the layout of the UCI packet headers exchanged with the
+    /// host, and the matching UCI version reported by the devices.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self.device_options.versions.uci_version = framing.uci_version();
+        self
+    }
+
+    /// Configure the maximum payload size of the control packets sent
+    /// to the host. Larger packets are segmented. The value is capped
+    /// to the maximum control packet payload size of 255 bytes, or
+    /// 65535 bytes with the extended payload length of the UCI 2.0
+    /// framing. Payloads exceeding 255 bytes always use the extended
+    /// payload length.
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu.clamp(1, MAX_EXTENDED_CTRL_PACKET_PAYLOAD_SIZE);
+        self
+    }
+
+    /// Configure the maximum payload size of the data packets sent
+    /// to the host. Larger packets are segmented. The value is capped
+    /// to the maximum data packet payload size of 1024 bytes.
+    pub fn data_mtu(mut self, data_mtu: usize) -> Self {
+        self.data_mtu = data_mtu.clamp(1, MAX_DATA_PACKET_PAYLOAD_SIZE);
+        self
+    }
+
+    /// Limit the number of TLVs carried by each control packet of the
+    /// Core Get Caps Info, Core Get Config, and Session Get App Config
+    /// responses. Responses listing more TLVs are segmented on TLV
+    /// boundaries, even when fitting in the MTU.
+    pub fn max_tlvs_per_packet(mut self, max_tlvs_per_packet: usize) -> Self {
+        self.max_tlvs_per_packet = Some(max_tlvs_per_packet.max(1));
+        self
+    }
+
+    /// Select the clock used as time source by the devices.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.device_options.clock = clock;
+        self
+    }
+
+    /// Configure the delay between the response to the Core Device Reset
+    /// command and the notification of the ready state.
+    pub fn reset_delay(mut self, reset_delay: Duration) -> Self {
+        self.device_options.reset_delay = reset_delay;
+        self
+    }
+
+    /// Configure the boot time of the devices, between the connection
+    /// of the host and the notification of the initial state. Commands
+    /// received during the boot are rejected with the status
+    /// UCI_MESSAGE_RETRY.
+    pub fn boot_delay(mut self, boot_delay: Duration) -> Self {
+        self.device_options.boot_delay = boot_delay;
+        self
+    }
+
+    /// Configure the state entered by the devices on initialization
+    /// and after the Core Device Reset command. Devices configured in
+    /// the error state reject all commands except Core Device Reset.
+    pub fn initial_device_state(mut self, initial_device_state: DeviceState) -> Self {
+        self.device_options.initial_state = initial_device_state;
+        self
+    }
+
+    /// Configure the receive antennas of the devices, selecting the
+    /// angles of arrival reported in the ranging notifications.
+    pub fn antenna_config(mut self, antenna_config: AntennaConfig) -> Self {
+        self.device_options.antenna_config = antenna_config;
+        self
+    }
+
+    /// Configure the number of receive antenna pairs of the devices.
+    /// With more than one pair, the azimuth measured by each pair is
+    /// reported in the vendor data of the ranging notifications.
+    pub fn antenna_pairs(mut self, antenna_pairs: u8) -> Self {
+        self.device_options.antenna_pairs = antenna_pairs.max(1);
+        self
+    }
+
+    /// Configure the maximum size of the payload of a reassembled control
+    /// packet. Commands exceeding the limit are discarded and rejected
+    /// with the status INVALID_MESSAGE_SIZE.
+    pub fn max_reassembly_size(mut self, max_reassembly_size: usize) -> Self {
+        self.max_reassembly_size = max_reassembly_size;
+        self
+    }
+
+    /// Delay the response to the commands with the selected group and
+    /// opcode identifiers by the selected processing latency.
+    pub fn response_latency(mut self, gid: GroupId, oid: u8, latency: Duration) -> Self {
+        self.device_options
+            .response_latencies
+            .insert((gid.into(), oid), latency);
+        self
+    }
+
+    /// Replace the processing latencies applied before responding to the
+    /// commands, indexed by group and opcode identifiers.
+    pub fn response_latencies(mut self, response_latencies: HashMap<(u8, u8), Duration>) -> Self {
+        self.device_options.response_latencies = response_latencies;
+        self
+    }
+
+    /// Limit the number of sessions simultaneously active on each device.
+    /// Session Start commands exceeding the limit are rejected with the
+    /// status ERROR_MAX_SESSIONS_EXCEEDED.
+    pub fn max_active_sessions(mut self, max_active_sessions: usize) -> Self {
+        self.device_options.max_active_sessions = Some(max_active_sessions);
+        self
+    }
+
+    /// Emit a Core Generic Error notification for the protocol errors
+    /// detected by the devices: malformed packets, unknown group or
+    /// opcode identifiers, and oversized control packets. The
+    /// notifications are disabled by default.
+    pub fn generic_error_notifications(mut self, enabled: bool) -> Self {
+        self.device_options.generic_error_notifications = enabled;
+        self
+    }
+
+    /// Let the ranging offset push the reported distances below zero,
+    /// encoded in two's complement. Test-only escape hatch exercising
+    /// the sanity checks of the host. The distances saturate at zero
+    /// by default.
+    pub fn allow_negative_distance(mut self, enabled: bool) -> Self {
+        self.device_options.allow_negative_distance = enabled;
+        self
+    }
+
+    /// Limit the rate of inbound commands of each device, in commands
+    /// per second. Commands exceeding the rate are rejected with the
+    /// status UCI_MESSAGE_RETRY. A rate of zero disables the limit.
+    pub fn command_rate_limit(mut self, command_rate_limit: u32) -> Self {
+        self.device_options.command_rate_limit = Some(command_rate_limit);
+        self
+    }
+
+    /// Reject the commands received by each device within the selected
+    /// window after the previous response, with the status
+    /// UCI_MESSAGE_RETRY. The Core Device Reset command is accepted
+    /// within the window.
+    pub fn busy_window(mut self, busy_window: Duration) -> Self {
+        self.device_options.busy_window = Some(busy_window);
+        self
+    }
+
+    /// Vary the interval of each ranging round uniformly by up to
+    /// `jitter` times the ranging interval. The variations are drawn
+    /// from a generator seeded with `seed`, making them reproducible.
+    /// Jitters that are not finite are rejected.
+    pub fn ranging_jitter(mut self, jitter: f32, seed: u64) -> Result<Self, PicaError> {
+        if !jitter.is_finite() {
+            return Err(PicaError::InvalidRangingJitter);
+        }
+        self.device_options.ranging_jitter = jitter;
+        self.device_options.ranging_jitter_seed = seed;
+        Ok(self)
+    }
+
+    /// Report the two-way ranging timing fields in the ranging
+    /// notifications, derived from the selected reply time of the
+    /// responder and the reported distances.
+    pub fn twr_reply_time(mut self, twr_reply_time: Duration) -> Self {
+        self.device_options.twr_reply_time = Some(twr_reply_time);
+        self
+    }
+
+    /// Close the connections with no inbound traffic for the selected
+    /// duration, and remove the associated devices. Notifications sent
+    /// by the device do not count as activity.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Install a hook observing and transforming the UCI packets
+    /// exchanged with the hosts.
+    pub fn packet_transform(mut self, packet_transform: Box<PacketTransform>) -> Self {
+        self.packet_transform = Some(packet_transform.into());
+        self
+    }
+
+    /// Select the noise applied to the ranging measurements.
+    pub fn noise_model(mut self, noise_model: NoiseModel) -> Self {
+        self.noise_model = noise_model;
+        self
+    }
+
+    /// Select the global seed of the noise generators. The generator
+    /// of each device pair is derived from this seed and the mac
+    /// addresses of the pair.
+    pub fn noise_seed(mut self, noise_seed: u64) -> Self {
+        self.noise_seed = noise_seed;
+        self
+    }
+
+    /// Select the report of the measurements between devices
+    /// sharing the same position.
+    pub fn colocated_report(mut self, colocated_report: ColocatedReport) -> Self {
+        self.colocated_report = colocated_report;
+        self
+    }
+
+    /// Assign fixed handles to the clients identified by their client id.
+    /// The client id is sent by the host in the Pica Set Client Id command
+    /// as the first packet of the connection; handles are allocated in
+    /// connection order when the handshake is absent.
+    pub fn client_handles(mut self, client_handles: HashMap<u32, Handle>) -> Self {
+        self.client_handles = client_handles;
+        self
+    }
+
+    /// Require the hosts to authenticate with the selected token.
+    /// The token is sent by the host in the Pica Authenticate command
+    /// as the first packet of the connection, ahead of the client id
+    /// handshake; other connections are closed without creating a device.
+    /// Empty tokens are rejected, any host would match them.
+    pub fn auth_token(mut self, auth_token: Vec<u8>) -> Result<Self, PicaError> {
+        if auth_token.is_empty() {
+            return Err(PicaError::EmptyAuthToken);
+        }
+        self.auth_token = Some(auth_token);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Pica {
         let (command_tx, command_rx) = mpsc::channel(MAX_SESSION * MAX_DEVICE);
         let (event_tx, _) = broadcast::channel(16);
         Pica {
-            devices: HashMap::new(),
+            devices: BTreeMap::new(),
             anchors: HashMap::new(),
             counter: 0,
             command_rx: Some(command_rx),
             command_tx,
             event_tx,
-            ranging_estimator,
-            pcapng_dir,
+            ranging_estimator: self.ranging_estimator,
+            pcapng_dir: self.pcapng_dir,
+            pcapng_max_size: self.pcapng_max_size,
+            trace_format: self.trace_format,
+            pcapng_combined: self.pcapng_combined,
+            csv_dir: self.csv_dir,
+            device_options: self.device_options,
+            max_devices: self.max_devices,
+            framing: self.framing,
+            mtu: self.mtu,
+            data_mtu: self.data_mtu,
+            max_tlvs_per_packet: self.max_tlvs_per_packet,
+            max_reassembly_size: self.max_reassembly_size,
+            idle_timeout: self.idle_timeout,
+            packet_transform: self.packet_transform,
+            noise_model: self.noise_model,
+            colocated_report: self.colocated_report,
+            client_handles: self.client_handles,
+            auth_token: self.auth_token,
+            rngs: PairRngs::new(self.noise_seed),
+            schedulers: Default::default(),
+            measurement_observer: None,
+            ground_truth: Default::default(),
+            peer_positions: Default::default(),
+            notification_txs: Default::default(),
+            trace_files: Default::default(),
+            clock_paused: false,
+        }
+    }
+}
+
+impl Pica {
+    pub fn new(ranging_estimator: Box<dyn RangingEstimator>, pcapng_dir: Option<PathBuf>) -> Self {
+        let builder = PicaBuilder::new().ranging_estimator(ranging_estimator);
+        match pcapng_dir {
+            Some(pcapng_dir) => builder.pcapng_dir(pcapng_dir).build(),
+            None => builder.build(),
         }
     }
 
+    pub fn builder() -> PicaBuilder {
+        PicaBuilder::new()
+    }
+
+    /// Return the clock used as time source by the devices.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.device_options.clock.clone()
+    }
+
+    /// Return the layout of the UCI packet headers exchanged with the host.
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Install an observer invoked with each measurement generated in
+    /// the ranging rounds, replacing the installed observer if any.
+    /// The observer does not affect the ranging notifications.
+    pub fn on_measurement(&mut self, observer: Box<MeasurementObserver>) {
+        self.measurement_observer = Some(observer);
+    }
+
+    /// Subscribe to the lifecycle events of the UCI connections.
+    /// The events are purely observational: devices are reported as
+    /// connected when added, and as disconnected when removed.
     pub fn events(&self) -> broadcast::Receiver<PicaEvent> {
         self.event_tx.subscribe()
     }
@@ -211,10 +1495,6 @@ impl Pica {
         self.devices.get_mut(&device_handle)
     }
 
-    fn get_device(&self, device_handle: usize) -> Option<&Device> {
-        self.devices.get(&device_handle)
-    }
-
     fn get_category(&self, mac_address: &MacAddress) -> Option<Category> {
         if self.anchors.contains_key(mac_address) {
             Some(Category::Anchor)
@@ -237,28 +1517,120 @@ impl Pica {
 
     /// Handle an incoming stream of UCI packets.
     /// Reassemble control packets when fragmented, data packets are unmodified.
+    /// The connection is closed if no packet is received for the
+    /// duration of `idle_timeout`.
+    /// Control packets whose payload is larger than `max_reassembly_size`
+    /// are dropped, the remaining fragments are discarded and commands are
+    /// rejected with the status INVALID_MESSAGE_SIZE. Packets whose payload
+    /// was dropped by the read adapter are rejected the same way. A Core
+    /// Generic Error notification is sent for the dropped packets that
+    /// cannot be answered, such as data packets, and in addition to the
+    /// response if `generic_error_notifications` is set.
+    #[allow(clippy::too_many_arguments)]
     async fn read_routine(
         mut uci_stream: impl futures::stream::Stream<Item = Vec<u8>> + Unpin,
         cmd_tx: mpsc::Sender<PicaCommand>,
+        packet_tx: mpsc::UnboundedSender<UciPacket>,
         handle: Handle,
-        pcapng_file: Option<&pcapng::File>,
+        max_reassembly_size: usize,
+        generic_error_notifications: bool,
+        idle_timeout: Option<Duration>,
+        idle_timer: Option<&Timer>,
+        packet_transform: Option<&PacketTransform>,
+        trace_file: Option<&dyn TraceWriter>,
     ) -> anyhow::Result<()> {
         use futures::stream::StreamExt;
 
         loop {
             let mut complete_packet: Option<Vec<u8>> = None;
+            let mut discarding = false;
             loop {
-                let packet = uci_stream
-                    .next()
-                    .await
-                    .ok_or(anyhow::anyhow!("input packet stream closed"))?;
-                let header =
-                    packets::uci::CommonPacketHeader::decode_full(&packet[0..COMMON_HEADER_SIZE])?;
-
-                if let Some(file) = pcapng_file {
-                    file.write(&packet, pcapng::Direction::Tx)?;
+                // The idle timeout is measured on the shared clock.
+                let packet = match (idle_timeout, idle_timer) {
+                    (Some(idle_timeout), Some(idle_timer)) => tokio::select! {
+                        packet = uci_stream.next() => packet,
+                        _ = idle_timer.rearm(idle_timeout) => {
+                            log::info!("[{}] Idle timeout, closing connection", handle);
+                            anyhow::bail!("idle timeout")
+                        }
+                    },
+                    _ => uci_stream.next().await,
                 }
-
+                .ok_or(anyhow::anyhow!("input packet stream closed"))?;
+                let packet = match packet_transform {
+                    Some(packet_transform) => match packet_transform(Direction::Inbound, packet) {
+                        Some(packet) => packet,
+                        None => continue,
+                    },
+                    None => packet,
+                };
+                if packet.len() < COMMON_HEADER_SIZE {
+                    log::warn!("[{}] Empty packet, discarding", handle);
+                    continue;
+                }
+                let header =
+                    packets::uci::CommonPacketHeader::decode_full(&packet[0..COMMON_HEADER_SIZE])?;
+
+                if let Some(file) = trace_file {
+                    file.write(&packet, trace::Direction::Tx)?;
+                }
+
+                let complete = header.pbf == packets::uci::PacketBoundaryFlag::Complete;
+
+                if discarding {
+                    if complete {
+                        break;
+                    }
+                    continue;
+                }
+
+                let size = complete_packet
+                    .as_ref()
+                    .map_or(0, |p| p.len() - HEADER_SIZE)
+                    + packet.len().saturating_sub(HEADER_SIZE);
+                let truncated = packets::uci::is_truncated(&packet);
+                if truncated
+                    || (header.mt != packets::uci::MessageType::Data && size > max_reassembly_size)
+                {
+                    if truncated {
+                        log::warn!("[{}] Oversized packet dropped by the transport", handle);
+                    } else {
+                        log::warn!(
+                            "[{}] Reassembled packet exceeds {} bytes, discarding",
+                            handle,
+                            max_reassembly_size
+                        );
+                    }
+                    // The opcode is missing from packets shorter than
+                    // two bytes, which cannot be answered.
+                    let responded = match (header.mt, packet.get(1)) {
+                        (packets::uci::MessageType::Command, Some(opcode)) => {
+                            let _ = packet_tx.send(device::status_response(
+                                packet[0] & 0xf,
+                                opcode & 0x3f,
+                                uci::Status::InvalidMessageSize,
+                            ));
+                            true
+                        }
+                        _ => false,
+                    };
+                    if generic_error_notifications || !responded {
+                        let _ = packet_tx.send(
+                            CoreGenericErrorNtf {
+                                status: uci::Status::InvalidMessageSize,
+                            }
+                            .encode_to_vec()
+                            .unwrap(),
+                        );
+                    }
+                    complete_packet = None;
+                    discarding = !complete;
+                    if complete {
+                        break;
+                    }
+                    continue;
+                }
+
                 match &mut complete_packet {
                     Some(complete_packet) => {
                         complete_packet.extend_from_slice(&packet[HEADER_SIZE..])
@@ -273,19 +1645,31 @@ impl Pica {
                 }
             }
 
-            cmd_tx
-                .send(PicaCommand::UciPacket(handle, complete_packet.unwrap()))
-                .await
-                .unwrap()
+            if let Some(complete_packet) = complete_packet {
+                cmd_tx
+                    .send(PicaCommand::UciPacket(handle, complete_packet))
+                    .await
+                    .unwrap()
+            }
         }
     }
 
     /// Segment a stream of UCI packets.
+    /// Session status and ranging notifications are forwarded to the
+    /// notification sink of the device instead, when one is registered.
+    #[allow(clippy::too_many_arguments)]
     async fn write_routine(
         mut uci_sink: impl futures::sink::Sink<Vec<u8>> + Unpin,
         mut packet_rx: mpsc::UnboundedReceiver<UciPacket>,
         _handle: Handle,
-        pcapng_file: Option<&pcapng::File>,
+        framing: Framing,
+        mtu: usize,
+        data_mtu: usize,
+        max_tlvs_per_packet: Option<usize>,
+        packet_transform: Option<&PacketTransform>,
+        trace_file: Option<&dyn TraceWriter>,
+        mut csv_recorder: Option<&mut csv::Recorder>,
+        notification_tx: Option<&NotificationSender>,
     ) -> anyhow::Result<()> {
         use futures::sink::SinkExt;
 
@@ -294,68 +1678,112 @@ impl Pica {
                 .recv()
                 .await
                 .ok_or(anyhow::anyhow!("output packet stream closed"))?;
-            let mut offset = HEADER_SIZE;
-            let mt = parse_message_type(complete_packet[0]);
-
-            while offset < complete_packet.len() {
-                let remaining_length = complete_packet.len() - offset;
-                let fragment_length = std::cmp::min(
-                    remaining_length,
-                    if mt == MessageType::Data {
-                        MAX_DATA_PACKET_PAYLOAD_SIZE
-                    } else {
-                        MAX_CTRL_PACKET_PAYLOAD_SIZE
-                    },
-                );
-                let pbf = if fragment_length == remaining_length {
-                    PacketBoundaryFlag::Complete
-                } else {
-                    PacketBoundaryFlag::NotComplete
-                };
 
-                let mut packet = Vec::with_capacity(HEADER_SIZE + fragment_length);
-
-                packet.extend_from_slice(&complete_packet[0..HEADER_SIZE]);
-                const PBF_MASK: u8 = 0x10;
-                packet[0] &= !PBF_MASK;
-                packet[0] |= (pbf as u8) << 4;
+            // The ranging results are exported regardless of the
+            // routing of the notifications.
+            if let Some(csv_recorder) = csv_recorder.as_deref_mut() {
+                if let Err(err) = csv_recorder.record(&complete_packet) {
+                    log::error!("Failed to record ranging results: {}", err);
+                }
+            }
 
-                match mt {
-                    MessageType::Data => {
-                        packet[2..4].copy_from_slice(&(fragment_length as u16).to_le_bytes())
+            // Notifications fall back to the device connection when
+            // the notification sink is closed.
+            let complete_packet = match notification_tx {
+                Some(notification_tx) if is_routed_notification(&complete_packet) => {
+                    let mut notification_tx = notification_tx.lock().unwrap();
+                    match notification_tx.as_ref() {
+                        Some(tx) => match tx.send(complete_packet) {
+                            Ok(()) => continue,
+                            Err(mpsc::error::SendError(packet)) => {
+                                *notification_tx = None;
+                                packet
+                            }
+                        },
+                        None => complete_packet,
                     }
-                    _ => packet[3] = fragment_length as u8,
                 }
+                _ => complete_packet,
+            };
+            let max_payload_size = match parse_message_type(complete_packet[0]) {
+                MessageType::Data => data_mtu,
+                _ => mtu,
+            };
 
-                packet.extend_from_slice(&complete_packet[offset..offset + fragment_length]);
-
-                if let Some(file) = pcapng_file {
-                    file.write(&packet, pcapng::Direction::Rx)?;
+            let fragments = match max_tlvs_per_packet {
+                Some(max_tlvs) => {
+                    segment_tlvs(&complete_packet, max_payload_size, max_tlvs, framing)
+                }
+                None => segment(&complete_packet, max_payload_size, framing),
+            };
+            for packet in fragments {
+                let packet = match packet_transform {
+                    Some(packet_transform) => match packet_transform(Direction::Outbound, packet) {
+                        Some(packet) => packet,
+                        None => continue,
+                    },
+                    None => packet,
+                };
+                if let Some(file) = trace_file {
+                    file.write(&packet, trace::Direction::Rx)?;
                 }
 
                 uci_sink
                     .send(packet)
                     .await
                     .map_err(|_| anyhow::anyhow!("output packet sink closed"))?;
-
-                offset += fragment_length;
             }
         }
     }
 
-    pub fn add_device(&mut self, stream: UciStream, sink: UciSink) -> Result<Handle> {
+    pub fn add_device(&mut self, stream: UciStream, sink: UciSink) -> Result<Handle, PicaError> {
+        self.add_device_with_handle(stream, sink, None)
+    }
+
+    /// Register a new device with the selected handle, or
+    /// with an allocated handle if none is selected.
+    fn add_device_with_handle(
+        &mut self,
+        stream: UciStream,
+        sink: UciSink,
+        handle: Option<Handle>,
+    ) -> Result<Handle, PicaError> {
         let (packet_tx, packet_rx) = mpsc::unbounded_channel();
         let pica_tx = self.command_tx.clone();
         let disconnect_tx = self.command_tx.clone();
-        let pcapng_dir = self.pcapng_dir.clone();
+        let csv_dir = self.csv_dir.clone();
+        let clock = self.device_options.clock.clone();
+        let max_reassembly_size = self.max_reassembly_size;
+        let generic_error_notifications = self.device_options.generic_error_notifications;
+        let framing = self.framing;
+        let mtu = self.mtu;
+        let data_mtu = self.data_mtu;
+        let max_tlvs_per_packet = self.max_tlvs_per_packet;
+        let idle_timeout = self.idle_timeout;
+        let timers = self.device_options.timers.clone();
+        let packet_transform = self.packet_transform.clone();
+        let notification_tx = NotificationSender::default();
 
-        let handle = self.counter;
-        self.counter += 1;
+        if self
+            .max_devices
+            .is_some_and(|max_devices| self.devices.len() >= max_devices)
+        {
+            log::warn!("Maximum number of devices reached, rejecting connection");
+            return Err(PicaError::MaxDevicesExceeded);
+        }
+
+        let handle = handle.unwrap_or_else(|| self.allocate_handle());
 
         log::debug!("[{}] Connecting device", handle);
 
         let mac_address = MacAddress::Short((handle as u16).to_be_bytes());
-        let mut device = Device::new(handle, mac_address, packet_tx, self.command_tx.clone());
+        let mut device = Device::new(
+            handle,
+            mac_address,
+            packet_tx.clone(),
+            self.command_tx.clone(),
+            self.device_options.clone(),
+        );
         device.init();
 
         self.send_event(PicaEvent::Connected {
@@ -364,24 +1792,69 @@ impl Pica {
         });
 
         self.devices.insert(handle, device);
+        self.notification_txs
+            .insert(handle, notification_tx.clone());
+        let trace_file = self.create_trace_file(handle);
+        if let Some(trace_file) = &trace_file {
+            self.trace_files.insert(handle, trace_file.clone());
+        }
 
         // Spawn and detach the connection handling task.
         // The task notifies pica when exiting to let it clean
         // the state.
         tokio::task::spawn(async move {
-            let pcapng_file = if let Some(dir) = pcapng_dir {
-                let full_path = dir.join(format!("device-{}.pcapng", handle));
-                log::debug!("Recording pcapng to file {}", full_path.as_path().display());
-                Some(pcapng::File::create(full_path).unwrap())
-            } else {
-                None
-            };
+            let idle_timer = idle_timeout.map(|idle_timeout| {
+                timers.register(
+                    TimerKind::IdleTimeout,
+                    handle,
+                    None,
+                    clock.clone(),
+                    idle_timeout,
+                )
+            });
+            let mut csv_recorder = csv_dir.map(|dir| csv::Recorder::new(&dir, handle, clock));
 
             let _ = tokio::try_join!(
-                async { Self::read_routine(stream, pica_tx, handle, pcapng_file.as_ref()).await },
-                async { Self::write_routine(sink, packet_rx, handle, pcapng_file.as_ref()).await }
+                async {
+                    Self::read_routine(
+                        stream,
+                        pica_tx,
+                        packet_tx,
+                        handle,
+                        max_reassembly_size,
+                        generic_error_notifications,
+                        idle_timeout,
+                        idle_timer.as_ref(),
+                        packet_transform.as_deref(),
+                        trace_file.as_deref().map(|file| file as &dyn TraceWriter),
+                    )
+                    .await
+                },
+                async {
+                    Self::write_routine(
+                        sink,
+                        packet_rx,
+                        handle,
+                        framing,
+                        mtu,
+                        data_mtu,
+                        max_tlvs_per_packet,
+                        packet_transform.as_deref(),
+                        trace_file.as_deref().map(|file| file as &dyn TraceWriter),
+                        csv_recorder.as_mut(),
+                        Some(&notification_tx),
+                    )
+                    .await
+                }
             );
 
+            if let Some(file) = &trace_file {
+                let _ = file.flush();
+            }
+            if let Some(csv_recorder) = &mut csv_recorder {
+                let _ = csv_recorder.flush();
+            }
+
             disconnect_tx
                 .send(PicaCommand::Disconnect(handle))
                 .await
@@ -391,44 +1864,346 @@ impl Pica {
         Ok(handle)
     }
 
+    /// Create the trace files recording the traffic of a device,
+    /// or None if no trace is enabled.
+    fn create_trace_file(&self, handle: Handle) -> Option<Arc<trace::Tee>> {
+        let mut trace_files: Vec<Box<dyn TraceWriter>> = vec![];
+        if let Some(dir) = &self.pcapng_dir {
+            match trace::create(dir, handle, self.trace_format, self.pcapng_max_size) {
+                Ok(file) => trace_files.push(file),
+                Err(err) => log::error!("[{}] Failed to create the trace file: {}", handle, err),
+            }
+        }
+        if let Some(file) = &self.pcapng_combined {
+            match file.add_interface(&format!("device-{}", handle)) {
+                Ok(interface) => trace_files.push(Box::new(interface)),
+                Err(err) => log::error!("[{}] Failed to add the trace interface: {}", handle, err),
+            }
+        }
+        (!trace_files.is_empty()).then(|| Arc::new(trace::Tee(trace_files)))
+    }
+
+    /// Return true if the handle is assigned to a device or anchor.
+    fn is_handle_used(&self, handle: Handle) -> bool {
+        self.devices.contains_key(&handle)
+            || self.anchors.values().any(|anchor| anchor.handle == handle)
+    }
+
+    /// Allocate the handle of a new device or anchor. The handles
+    /// reserved for the clients identified by the connection
+    /// handshake are skipped.
+    fn allocate_handle(&mut self) -> Handle {
+        while self.is_handle_used(self.counter)
+            || self
+                .client_handles
+                .values()
+                .any(|handle| *handle == self.counter)
+        {
+            self.counter += 1;
+        }
+        let handle = self.counter;
+        self.counter += 1;
+        handle
+    }
+
+    /// Connect a new device. When an authentication token is configured,
+    /// the connection is closed unless the first packet received from the
+    /// host authenticates with the token, within the authentication
+    /// timeout. When client handles are
+    /// configured, the next packet is inspected for the client id handshake
+    /// before the device is created, and the connection is closed if no
+    /// packet is received within the handshake timeout.
+    fn connect(&mut self, mut stream: UciStream, mut sink: UciSink) {
+        use futures::sink::SinkExt;
+        use futures::stream::StreamExt;
+
+        if self.auth_token.is_none() && self.client_handles.is_empty() {
+            let _ = self.add_device(stream, sink);
+            return;
+        }
+
+        let auth_token = self.auth_token.clone();
+        let client_handshake = !self.client_handles.is_empty();
+        let framing = self.framing;
+        let command_tx = self.command_tx.clone();
+        tokio::spawn(async move {
+            if let Some(auth_token) = auth_token {
+                let packet = match time::timeout(AUTH_TIMEOUT, stream.next()).await {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) => return,
+                    Err(_) => {
+                        log::warn!("Closing connection not authenticated in time");
+                        return;
+                    }
+                };
+                let status = match PicaAuthenticateCmd::decode_full(&packet) {
+                    Ok(cmd) if tokens_match(&cmd.token, &auth_token) => uci::Status::Ok,
+                    Ok(_) => uci::Status::Rejected,
+                    Err(_) => {
+                        log::warn!("Closing unauthenticated connection");
+                        return;
+                    }
+                };
+                // The response is sent before the device is created,
+                // the payload length is filled in by the segmentation.
+                let response = PicaAuthenticateRsp { status }.encode_to_vec().unwrap();
+                for fragment in segment(&response, MAX_CTRL_PACKET_PAYLOAD_SIZE, framing) {
+                    let _ = sink.send(fragment).await;
+                }
+                if status != uci::Status::Ok {
+                    log::warn!("Closing connection with invalid authentication token");
+                    return;
+                }
+            }
+
+            if !client_handshake {
+                let _ = command_tx
+                    .send(PicaCommand::ConnectClient(None, stream, sink))
+                    .await;
+                return;
+            }
+
+            let packet = match time::timeout(HANDSHAKE_TIMEOUT, stream.next()).await {
+                Ok(Some(packet)) => packet,
+                Ok(None) => return,
+                Err(_) => {
+                    log::warn!("Closing connection without handshake in time");
+                    return;
+                }
+            };
+            // Packets other than the handshake are processed by the device.
+            let (client_id, stream): (_, UciStream) = match PicaSetClientIdCmd::decode_full(&packet)
+            {
+                Ok(cmd) => (Some(cmd.client_id), stream),
+                Err(_) => (
+                    None,
+                    Box::pin(futures::stream::once(async { packet }).chain(stream)),
+                ),
+            };
+            let _ = command_tx
+                .send(PicaCommand::ConnectClient(client_id, stream, sink))
+                .await;
+        });
+    }
+
+    /// Create the device of a connection after the client id handshake.
+    /// The handshake is rejected if the handle mapped to the client id
+    /// is already in use, and the connection is closed.
+    fn connect_client(&mut self, client_id: Option<u32>, stream: UciStream, mut sink: UciSink) {
+        use futures::sink::SinkExt;
+
+        let Some(client_id) = client_id else {
+            let _ = self.add_device(stream, sink);
+            return;
+        };
+
+        let handle = self.client_handles.get(&client_id).copied();
+        let status = match handle {
+            Some(handle) if self.is_handle_used(handle) => {
+                log::warn!(
+                    "[{}] Handle already in use, rejecting client 0x{:x}",
+                    handle,
+                    client_id
+                );
+                uci::Status::Rejected
+            }
+            _ => uci::Status::Ok,
+        };
+
+        let response = PicaSetClientIdRsp { status }.encode_to_vec().unwrap();
+        if status != uci::Status::Ok {
+            // The response is sent without device, the payload length
+            // is filled in by the segmentation.
+            let fragments = segment(&response, MAX_CTRL_PACKET_PAYLOAD_SIZE, self.framing);
+            tokio::spawn(async move {
+                for fragment in fragments {
+                    let _ = sink.send(fragment).await;
+                }
+            });
+            return;
+        }
+
+        if let Ok(handle) = self.add_device_with_handle(stream, sink, handle) {
+            let _ = self.devices[&handle].tx.send(response);
+        }
+    }
+
+    /// Register a new device connected through an in-memory channel.
+    /// Return the host ends of the connection: the sink is used to send
+    /// UCI packets to the device, the stream yields the UCI packets sent
+    /// by the device. The device is disconnected when either end is dropped.
+    pub fn connect_local(&mut self) -> Result<(UciSink, UciStream)> {
+        use futures::sink::SinkExt;
+
+        let (host_tx, device_rx) = futures::channel::mpsc::unbounded();
+        let (device_tx, host_rx) = futures::channel::mpsc::unbounded();
+        self.add_device(
+            Box::pin(device_rx),
+            Box::pin(device_tx.sink_map_err(anyhow::Error::from)),
+        )?;
+        Ok((
+            Box::pin(host_tx.sink_map_err(anyhow::Error::from)),
+            Box::pin(host_rx),
+        ))
+    }
+
     fn disconnect(&mut self, device_handle: usize) {
         log::debug!("[{}] Disconnecting device", device_handle);
 
-        if let Some(device) = self.devices.get(&device_handle) {
+        if let Some(mut device) = self.devices.remove(&device_handle) {
             self.send_event(PicaEvent::Disconnected {
                 handle: device_handle,
                 mac_address: device.mac_address,
             });
-            self.devices.remove(&device_handle);
+            device.stop_ranging_tasks();
+            self.schedulers.remove(&device_handle);
+            self.notification_txs.remove(&device_handle);
+            self.trace_files.remove(&device_handle);
+            self.ground_truth
+                .retain(|(handle, _), _| *handle != device_handle);
+            self.peer_positions
+                .retain(|(handle, _), _| *handle != device_handle);
+            if self.get_category(&device.mac_address).is_none() {
+                self.rngs.remove(&device.mac_address);
+            }
+        }
+    }
+
+    /// Queue a ranging round for the selected device and session.
+    /// Ranging rounds requested while the device is busy are serviced
+    /// by decreasing session priority.
+    fn schedule_ranging(&mut self, device_handle: usize, session_id: u32) {
+        if self.clock_paused {
+            return;
+        }
+
+        let Some(session) = self
+            .devices
+            .get(&device_handle)
+            .and_then(|device| device.session(session_id))
+        else {
+            return;
+        };
+
+        let priority = session.app_config.session_priority;
+        let scheduler = self.schedulers.entry(device_handle).or_default();
+        if scheduler.push(session_id, priority) {
+            self.send_ranging_round(device_handle, Duration::ZERO);
+        }
+    }
+
+    fn send_ranging_round(&self, device_handle: usize, delay: Duration) {
+        let tx = self.command_tx.clone();
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            let _ = tx.send(PicaCommand::RangingRound(device_handle)).await;
+        });
+    }
+
+    /// Execute the next ranging round of the selected device.
+    /// The device is busy for the duration of the ranging round.
+    fn ranging_round(&mut self, device_handle: usize) {
+        // Rounds scheduled before the clock was paused are dropped.
+        if self.clock_paused {
+            if let Some(scheduler) = self.schedulers.get_mut(&device_handle) {
+                scheduler.cancel();
+            }
+            return;
+        }
+
+        if let Some(delay) = self.execute_ranging_round(device_handle) {
+            self.send_ranging_round(device_handle, delay);
         }
     }
 
+    /// Execute the ranging round of the next session selected by the
+    /// scheduler of the device. Returns the delay after which the next
+    /// round can be executed, or None if the device is idle.
+    fn execute_ranging_round(&mut self, device_handle: usize) -> Option<Duration> {
+        let Some(session_id) = self
+            .schedulers
+            .get_mut(&device_handle)
+            .and_then(|scheduler| scheduler.pop())
+        else {
+            return None;
+        };
+
+        // The session may have been stopped while the round was pending.
+        let Some(round_duration) = self
+            .devices
+            .get(&device_handle)
+            .and_then(|device| device.session(session_id))
+            .filter(|session| session.session_state() == SessionState::SessionStateActive)
+            .map(|session| session.app_config.ranging_round_duration())
+        else {
+            return Some(Duration::ZERO);
+        };
+
+        // Hybrid sessions range with the primary session of
+        // each of their phases in turn.
+        let device = self.devices.get_mut(&device_handle).unwrap();
+        let session_id = match device.session_mut(session_id).unwrap().next_phase() {
+            Some(phase_session_id) if device.session(phase_session_id).is_some() => {
+                phase_session_id
+            }
+            Some(phase_session_id) => {
+                log::warn!(
+                    "[{}] Hybrid session phase 0x{:x} not found",
+                    device_handle,
+                    phase_session_id
+                );
+                return Some(round_duration);
+            }
+            None => session_id,
+        };
+
+        self.ranging(device_handle, session_id);
+        Some(round_duration)
+    }
+
     fn ranging(&mut self, device_handle: usize, session_id: u32) {
         log::debug!("[{}] Ranging event", device_handle);
         log::debug!("  session_id={}", session_id);
 
-        let device = self.get_device(device_handle).unwrap();
-        let session = device.session(session_id).unwrap();
+        // The device may have been disconnected, or the session
+        // deinitialized, while the round was pending.
+        let Some(device) = self.devices.get(&device_handle) else {
+            log::warn!("[{}] Device not found", device_handle);
+            return;
+        };
+        let Some(session) = device.session(session_id) else {
+            log::warn!("[{}] Session 0x{:x} not found", device_handle, session_id);
+            return;
+        };
+        // Rounds scheduled before the device was suspended are dropped.
+        if device.is_suspended() {
+            return;
+        }
+        // The figure of merit is low during the warmup phase
+        // of the session.
+        let aoa_fom = if session.warmup_rounds > 0 {
+            self.noise_model.aoa_fom().min(WARMUP_AOA_FOM)
+        } else {
+            self.noise_model.aoa_fom()
+        };
 
         let mut data_transfer = Vec::new();
         let mut measurements = Vec::new();
+        let mut ground_truth = Vec::new();
+        let mut peer_positions = Vec::new();
 
         // Look for compatible anchors.
+        let mut peers = Vec::new();
         for mac_address in session.get_dst_mac_address() {
             if let Some(other) = self.anchors.get(mac_address) {
-                let Some(local) = self
-                    .ranging_estimator
-                    .estimate(&device.handle, &other.handle)
-                else {
-                    continue;
-                };
-                let Some(remote) = self
-                    .ranging_estimator
-                    .estimate(&other.handle, &device.handle)
-                else {
-                    continue;
-                };
-                measurements.push(make_measurement(mac_address, local, remote));
+                peers.push(RangingPeer {
+                    handle: other.handle,
+                    mac_address: *mac_address,
+                    device_mac_address: *mac_address,
+                    ranging_offset: 0,
+                    allow_negative_distance: false,
+                    antenna_config: AntennaConfig::default(),
+                });
             }
         }
 
@@ -439,25 +2214,19 @@ impl Pica {
             }
 
             if peer_device.can_start_ranging(session, session_id) {
-                let peer_mac_address = peer_device
-                    .session(session_id)
-                    .unwrap()
-                    .app_config
-                    .device_mac_address
-                    .unwrap();
-                let Some(local) = self
-                    .ranging_estimator
-                    .estimate(&device.handle, &peer_device.handle)
-                else {
-                    continue;
-                };
-                let Some(remote) = self
-                    .ranging_estimator
-                    .estimate(&peer_device.handle, &device.handle)
-                else {
-                    continue;
-                };
-                measurements.push(make_measurement(&peer_mac_address, local, remote));
+                peers.push(RangingPeer {
+                    handle: peer_device.handle,
+                    mac_address: peer_device
+                        .session(session_id)
+                        .unwrap()
+                        .app_config
+                        .device_mac_address
+                        .unwrap(),
+                    device_mac_address: peer_device.mac_address,
+                    ranging_offset: peer_device.ranging_offset(),
+                    allow_negative_distance: peer_device.allow_negative_distance(),
+                    antenna_config: peer_device.antenna_config(),
+                });
             }
 
             if device.can_start_data_transfer(session_id)
@@ -467,10 +2236,81 @@ impl Pica {
             }
         }
 
+        for peer in peers.iter() {
+            if let Some(ranging) = range_peer(
+                self.ranging_estimator.as_ref(),
+                &self.noise_model,
+                &mut self.rngs,
+                &mut self.measurement_observer,
+                self.colocated_report,
+                device,
+                session_id,
+                aoa_fom,
+                peer,
+            ) {
+                ground_truth.push(ranging.ground_truth);
+                peer_positions.extend(ranging.position);
+                measurements.extend(ranging.measurement);
+            }
+        }
+
+        // The angles of arrival measured by the peers are absent from the
+        // messages exchanged with the other ranging methods.
+        if !session.app_config.reports_destination_aoa() {
+            for measurement in measurements.iter_mut() {
+                measurement.aoa_destination_azimuth = 0;
+                measurement.aoa_destination_azimuth_fom = 0;
+                measurement.aoa_destination_elevation = 0;
+                measurement.aoa_destination_elevation_fom = 0;
+            }
+        }
+
+        // One-to-many sessions report one measurement per controlee,
+        // in the order of the controlee list. Controlees that could not
+        // be ranged with are reported as unavailable.
+        if session.app_config.multi_node_mode == Some(MultiNodeMode::OneToMany) {
+            measurements = session
+                .get_dst_mac_address()
+                .iter()
+                .filter_map(|mac_address| {
+                    measurements
+                        .iter()
+                        .find(|measurement| {
+                            MacAddress::Short(measurement.mac_address.to_le_bytes()) == *mac_address
+                        })
+                        .cloned()
+                        .or_else(|| make_unavailable_measurement(mac_address))
+                })
+                .collect();
+        }
+
+        let one_to_one = session.app_config.multi_node_mode != Some(MultiNodeMode::OneToMany);
+        // Controlees out of the reported distance limits are reported as
+        // unavailable, and do not count as ranged.
+        let controlee_ranged = measurements
+            .iter()
+            .any(|measurement| measurement.status == uci::Status::Ok);
+
+        // The measurements are buffered over the coalescing window of
+        // the device, and reported once per window averaged for each
+        // peer. Windows without valid measurements are not reported.
+        let coalescing_window = device.coalescing_window() as usize;
+        let coalesced_round = (coalescing_window > 1).then(|| measurements.clone());
+        let window_complete = session.coalesced_rounds.len() + 1 >= coalescing_window;
+        if let Some(round) = coalesced_round.as_ref().filter(|_| window_complete) {
+            measurements =
+                coalesce_measurements(session.coalesced_rounds.iter().flatten().chain(round));
+        }
+        let report = coalesced_round.is_none() || (window_complete && !measurements.is_empty());
+
         // TODO: Data transfer should be limited in size for
         // each round of ranging
+        // Devices whose host connection is closed are disconnected
+        // once the ranging round is complete.
+        let mut disconnected = Vec::new();
+        let data_transferred = !data_transfer.is_empty();
         for peer_device in data_transfer.iter() {
-            peer_device
+            if peer_device
                 .tx
                 .send(
                     DataMessageRcv {
@@ -484,25 +2324,147 @@ impl Pica {
                     .encode_to_vec()
                     .unwrap(),
                 )
-                .unwrap();
+                .is_err()
+            {
+                disconnected.push(peer_device.handle);
+            }
         }
-        if session.is_session_info_ntf_enabled() {
-            device
-                .tx
-                .send(
-                    // TODO: support extended address
-                    ShortMacTwoWaySessionInfoNtf {
-                        sequence_number: session.sequence_number,
-                        session_token: session_id,
-                        rcr_indicator: 0,            //TODO
-                        current_ranging_interval: 0, //TODO
-                        two_way_ranging_measurements: measurements,
-                        vendor_data: vec![],
+        let now = time::Instant::now();
+        let mut vendor_data = vec![];
+        let report_type = session.app_config.report_type(session.sequence_number);
+
+        // Radial velocity of the peers, derived from the variation of
+        // the ground truth distances since the previous ranging round.
+        if device.radial_velocity_report() {
+            let previous = self.ground_truth.get(&(device_handle, session_id));
+            vendor_data.extend(make_vendor_data_tlvs(
+                VendorDataTlvType::RadialVelocity,
+                measurements.iter().map(|measurement| {
+                    let peer_mac_address = MacAddress::Short(measurement.mac_address.to_le_bytes());
+                    let velocity = radial_velocity(previous, now, &ground_truth, peer_mac_address);
+                    [
+                        measurement.mac_address.to_le_bytes(),
+                        velocity.to_le_bytes(),
+                    ]
+                    .concat()
+                }),
+            ));
+        }
+
+        // Movement indicator of the peers, set when the speed of the
+        // peer in the world frame over the movement window exceeds the
+        // configured threshold. The peers are stationary until the
+        // window is complete, or when the ranging estimator does not
+        // manage their position.
+        if let Some(threshold) = device.movement_threshold() {
+            let previous = self
+                .peer_positions
+                .get(&(device_handle, session_id))
+                .filter(|window| window.len() == MOVEMENT_WINDOW)
+                .and_then(|window| window.front());
+            vendor_data.extend(make_vendor_data_tlvs(
+                VendorDataTlvType::MovementIndicator,
+                measurements.iter().map(|measurement| {
+                    let peer_mac_address = MacAddress::Short(measurement.mac_address.to_le_bytes());
+                    let speed = peer_speed(previous, now, &peer_positions, peer_mac_address);
+                    let mobile = speed > threshold as f32;
+                    let mut value = measurement.mac_address.to_le_bytes().to_vec();
+                    value.push(mobile.into());
+                    value
+                }),
+            ));
+        }
+
+        // Angle of arrival measured by each receive antenna pair.
+        if device.antenna_pairs() > 1
+            && session.app_config.result_report_config.aoa_azimuth
+            && device.antenna_config().supports_azimuth()
+        {
+            vendor_data.extend(make_vendor_data_tlvs(
+                VendorDataTlvType::AntennaPairAoa,
+                measurements.iter().map(|measurement| {
+                    let mut value = measurement.mac_address.to_le_bytes().to_vec();
+                    for azimuth in antenna_pair_azimuths(
+                        measurement.distance,
+                        measurement.aoa_azimuth as i16,
+                        device.antenna_pairs(),
+                    ) {
+                        value.extend(azimuth.to_le_bytes());
+                        value.extend(measurement.aoa_elevation.to_le_bytes());
                     }
-                    .encode_to_vec()
-                    .unwrap(),
-                )
-                .unwrap();
+                    value
+                }),
+            ));
+        }
+
+        // Timing fields of the two-way ranging exchanges,
+        // consistent with the reported distances, and measured
+        // with the drifting clock of the device. Peers that could
+        // not be ranged with have no timing fields.
+        let clock_rate = device.clock_drift().rate();
+        if let Some(reply_time) = device.twr_reply_time().filter(|_| {
+            session.app_config.result_report_config.tof
+                && report_type == RangingMeasurementType::TwoWay
+        }) {
+            vendor_data.extend(make_vendor_data_tlvs(
+                VendorDataTlvType::TwrTiming,
+                measurements
+                    .iter()
+                    .filter(|measurement| measurement.status != uci::Status::RangingRxTimeout)
+                    .map(|measurement| {
+                        let (round_time, reply_time) = twr_timing(measurement.distance, reply_time);
+                        let round_time = (round_time as f64 * clock_rate).round() as u32;
+                        let reply_time = (reply_time as f64 * clock_rate).round() as u32;
+                        [
+                            measurement.mac_address.to_le_bytes().as_slice(),
+                            &round_time.to_le_bytes(),
+                            &reply_time.to_le_bytes(),
+                        ]
+                        .concat()
+                    }),
+            ));
+        }
+
+        // STS index of the ranging round.
+        if session.app_config.is_dynamic_sts() {
+            vendor_data.extend(make_vendor_data_tlvs(
+                VendorDataTlvType::StsIndex,
+                [session.sts_index.to_le_bytes().to_vec()],
+            ));
+        }
+
+        if report && session.is_session_info_ntf_enabled() {
+            // Hybrid sessions alternate the type of the reported
+            // measurements according to the configured schedule.
+            // TODO: support extended address
+            let ntf = match report_type {
+                RangingMeasurementType::OwrAoa => ShortMacOwrAoaSessionInfoNtf {
+                    sequence_number: session.sequence_number,
+                    session_token: session_id,
+                    rcr_indicator: 0,            //TODO
+                    current_ranging_interval: 0, //TODO
+                    owr_aoa_ranging_measurements: measurements
+                        .iter()
+                        .map(|measurement| {
+                            make_owr_aoa_measurement(measurement, session.sequence_number as u8)
+                        })
+                        .collect(),
+                    vendor_data,
+                }
+                .encode_to_vec(),
+                _ => ShortMacTwoWaySessionInfoNtf {
+                    sequence_number: session.sequence_number,
+                    session_token: session_id,
+                    rcr_indicator: 0,            //TODO
+                    current_ranging_interval: 0, //TODO
+                    two_way_ranging_measurements: measurements,
+                    vendor_data,
+                }
+                .encode_to_vec(),
+            };
+            if device.tx.send(ntf.unwrap()).is_err() {
+                disconnected.push(device_handle);
+            }
 
             let device = self.get_device_mut(device_handle).unwrap();
             let session = device.session_mut(session_id).unwrap();
@@ -510,28 +2472,129 @@ impl Pica {
             session.sequence_number += 1;
         }
 
+        self.ground_truth
+            .insert((device_handle, session_id), (now, ground_truth));
+        let window = self
+            .peer_positions
+            .entry((device_handle, session_id))
+            .or_default();
+        if window.len() == MOVEMENT_WINDOW {
+            window.pop_front();
+        }
+        window.push_back((now, peer_positions));
+
         // TODO: Clean the data only when all the data is transfered
         let device = self.get_device_mut(device_handle).unwrap();
         let session = device.session_mut(session_id).unwrap();
 
-        session.clear_data();
+        session.complete_data_transfer(data_transferred);
+        session.warmup_rounds = session.warmup_rounds.saturating_sub(1);
+        match coalesced_round {
+            Some(round) if !window_complete => session.coalesced_rounds.push(round),
+            _ => session.coalesced_rounds.clear(),
+        }
+
+        // The session key is rotated, and the STS index incremented,
+        // once the key rotation period has elapsed.
+        if let Some(key_rotation_period) = session.app_config.key_rotation_period() {
+            session.key_rotation_rounds += 1;
+            if session.key_rotation_rounds >= key_rotation_period {
+                session.key_rotation_rounds = 0;
+                session.sts_index = session.sts_index.wrapping_add(1);
+                log::debug!(
+                    "[{}:0x{:x}] Session key rotated, STS index {}",
+                    device_handle,
+                    session_id,
+                    session.sts_index
+                );
+            }
+        }
+
+        // One-to-one sessions are stopped after MAX_RR_RETRY consecutive
+        // ranging rounds failed to range with the controlee.
+        let max_rr_retry = session.app_config.max_rr_retry;
+        if one_to_one && max_rr_retry > 0 {
+            session.failed_rounds = if controlee_ranged {
+                0
+            } else {
+                session.failed_rounds.saturating_add(1)
+            };
+            if session.failed_rounds >= max_rr_retry {
+                log::info!(
+                    "[{}:0x{:x}] Controlee lost for {} ranging rounds, stopping the session",
+                    device_handle,
+                    session_id,
+                    max_rr_retry
+                );
+                device.stop_session(session_id, ReasonCode::MaxRangingRoundRetryCountReached);
+            }
+        }
+
+        for handle in disconnected {
+            log::warn!("[{}] Host connection closed", handle);
+            self.disconnect(handle);
+        }
     }
 
     fn uci_packet(&mut self, device_handle: usize, packet: Vec<u8>) {
+        let Some(device) = self.devices.get_mut(&device_handle) else {
+            return log::error!("Device {} not found", device_handle);
+        };
+        device.receive_packet(packet);
+
+        // Forget the sessions deinitialized by the command,
+        // or by the reset of the device.
+        if let Some(scheduler) = self.schedulers.get_mut(&device_handle) {
+            scheduler.retain(|session_id| device.session(session_id).is_some());
+        }
+    }
+
+    fn inject_fault(&mut self, device_handle: usize, fault: Fault) {
+        log::debug!("[{}] Inject fault", device_handle);
+        log::debug!("  fault={:?}", fault);
+
         match self.get_device_mut(device_handle) {
-            Some(device) => device.receive_packet(packet),
+            Some(device) => device.inject_fault(fault),
             None => log::error!("Device {} not found", device_handle),
         }
     }
 
+    fn inject_session_status(
+        &mut self,
+        device_handle: usize,
+        session_id: u32,
+        state: uci::SessionState,
+        reason: uci::ReasonCode,
+    ) {
+        log::debug!(
+            "[{}:0x{:x}] Inject session status",
+            device_handle,
+            session_id
+        );
+        log::debug!("  state={:?}, reason={:?}", state, reason);
+
+        match self.get_device_mut(device_handle) {
+            Some(device) => {
+                if !device.inject_session_status(session_id, state, reason) {
+                    log::warn!(
+                        "[{}:0x{:x}] Session not found, ignoring injected status",
+                        device_handle,
+                        session_id
+                    )
+                }
+            }
+            None => log::warn!("Device {} not found", device_handle),
+        }
+    }
+
     fn pica_command(&mut self, command: PicaCommand) {
         use PicaCommand::*;
         match command {
-            Connect(stream, sink) => {
-                let _ = self.add_device(stream, sink);
-            }
+            Connect(stream, sink) => self.connect(stream, sink),
+            ConnectClient(client_id, stream, sink) => self.connect_client(client_id, stream, sink),
             Disconnect(device_handle) => self.disconnect(device_handle),
-            Ranging(device_handle, session_id) => self.ranging(device_handle, session_id),
+            Ranging(device_handle, session_id) => self.schedule_ranging(device_handle, session_id),
+            RangingRound(device_handle) => self.ranging_round(device_handle),
             StopRanging(mac_address, session_id) => {
                 self.stop_controlee_ranging(&mac_address, session_id)
             }
@@ -542,6 +2605,62 @@ impl Pica {
             DestroyAnchor(mac_address, pica_cmd_rsp_tx) => {
                 self.destroy_anchor(mac_address, pica_cmd_rsp_tx)
             }
+            InjectFault(device_handle, fault) => self.inject_fault(device_handle, fault),
+            SetTemperature(mac_address, temperature, pica_cmd_rsp_tx) => {
+                self.set_temperature(mac_address, temperature, pica_cmd_rsp_tx)
+            }
+            SetCountryCode(mac_address, country_code, pica_cmd_rsp_tx) => {
+                self.set_country_code(mac_address, country_code, pica_cmd_rsp_tx)
+            }
+            GetGroundTruth(session_id, rsp_tx) => self.get_ground_truth(session_id, rsp_tx),
+            MoveSession {
+                session_id,
+                from,
+                to,
+                rsp_tx,
+            } => self.move_session(session_id, from, to, rsp_tx),
+            SetMacAddress {
+                handle,
+                address,
+                rsp_tx,
+            } => self.set_mac_address(handle, address, rsp_tx),
+            OverrideCapability {
+                handle,
+                id,
+                value,
+                rsp_tx,
+            } => self.override_capability(handle, id, value, rsp_tx),
+            GetConfig(rsp_tx) => self.get_config(rsp_tx),
+            SetNotificationSink {
+                handle,
+                sink,
+                rsp_tx,
+            } => self.set_notification_sink(handle, sink, rsp_tx),
+            InjectSessionStatus {
+                handle,
+                session_id,
+                state,
+                reason,
+            } => self.inject_session_status(handle, session_id, state, reason),
+            ResetDevice { handle, rsp_tx } => self.reset_device(handle, rsp_tx),
+            GetDeviceState { handle, rsp_tx } => self.get_device_state(handle, rsp_tx),
+            PauseClock => self.pause_clock(),
+            ResumeClock => self.resume_clock(),
+            StepRounds(count, rsp_tx) => self.step_rounds(count, rsp_tx),
+            StopAllRanging(rsp_tx) => self.stop_all_ranging(rsp_tx),
+            GetTimers(rsp_tx) => self.get_timers(rsp_tx),
+            SetClockDrift {
+                handle,
+                ppm,
+                rsp_tx,
+            } => self.set_clock_drift(handle, ppm, rsp_tx),
+            SetStopReason {
+                handle,
+                session_id,
+                reason,
+                rsp_tx,
+            } => self.set_stop_reason(handle, session_id, reason, rsp_tx),
+            SetPositions(positions, rsp_tx) => self.set_positions(positions, rsp_tx),
         }
     }
 
@@ -571,15 +2690,7 @@ impl Pica {
             }
 
             if session.session_state() == SessionState::SessionStateActive {
-                session.stop_ranging_task();
-                session.set_state(
-                    SessionState::SessionStateIdle,
-                    ReasonCode::SessionStoppedDueToInbandSignal,
-                );
-                device.n_active_sessions = device.n_active_sessions.saturating_sub(1);
-                if device.n_active_sessions == 0 {
-                    device.set_state(DeviceState::DeviceStateReady);
-                }
+                device.stop_session(session_id, ReasonCode::SessionStoppedDueToInbandSignal);
             } else {
                 log::warn!("stop_controlee_ranging: session is not active !");
             }
@@ -590,16 +2701,15 @@ impl Pica {
     fn create_anchor(
         &mut self,
         mac_address: MacAddress,
-        rsp_tx: oneshot::Sender<Result<Handle, PicaCommandError>>,
+        rsp_tx: oneshot::Sender<Result<Handle, PicaError>>,
     ) {
         log::debug!("[_] Create anchor");
         log::debug!("  mac_address: {}", mac_address);
 
         let status = if self.get_category(&mac_address).is_some() {
-            Err(PicaCommandError::DeviceAlreadyExists(mac_address))
+            Err(PicaError::DeviceAlreadyExists(mac_address))
         } else {
-            let handle = self.counter;
-            self.counter += 1;
+            let handle = self.allocate_handle();
 
             assert!(self
                 .anchors
@@ -623,31 +2733,497 @@ impl Pica {
     fn destroy_anchor(
         &mut self,
         mac_address: MacAddress,
-        rsp_tx: oneshot::Sender<Result<Handle, PicaCommandError>>,
+        rsp_tx: oneshot::Sender<Result<Handle, PicaError>>,
     ) {
         log::debug!("[_] Destroy anchor");
         log::debug!("  mac_address: {}", mac_address);
 
         let status = match self.anchors.remove(&mac_address) {
-            None => Err(PicaCommandError::DeviceNotFound(mac_address)),
-            Some(anchor) => Ok(anchor.handle),
+            None => Err(PicaError::DeviceNotFound(mac_address)),
+            Some(anchor) => {
+                if self.get_category(&mac_address).is_none() {
+                    self.rngs.remove(&mac_address);
+                }
+                Ok(anchor.handle)
+            }
         };
 
         rsp_tx.send(status).unwrap_or_else(|err| {
             log::error!("Failed to send destroy-anchor command response: {:?}", err)
         })
     }
-}
 
-/// Run the internal pica event loop.
-/// As opposed to Pica::run, the context is passed under a mutex, which
-/// allows synchronous access to the context for device creation.
-pub async fn run(this: &std::sync::Mutex<Pica>) -> Result<()> {
-    // Extract the mpsc receiver from the Pica context.
-    // The receiver cannot be cloned.
-    let Some(mut command_rx) = this.lock().unwrap().command_rx.take() else {
-        anyhow::bail!("missing pica command receiver");
-    };
+    fn set_temperature(
+        &mut self,
+        mac_address: MacAddress,
+        temperature: i8,
+        rsp_tx: oneshot::Sender<Result<Handle, PicaError>>,
+    ) {
+        log::debug!("[_] Set temperature");
+        log::debug!("  mac_address: {}", mac_address);
+        log::debug!("  temperature: {}", temperature);
+
+        let status = match self
+            .devices
+            .values_mut()
+            .find(|device| device.mac_address == mac_address)
+        {
+            None => Err(PicaError::DeviceNotFound(mac_address)),
+            Some(device) => {
+                device.set_temperature(temperature);
+                Ok(device.handle)
+            }
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send set-temperature command response: {:?}", err)
+        })
+    }
+
+    fn set_country_code(
+        &mut self,
+        mac_address: MacAddress,
+        country_code: [u8; 2],
+        rsp_tx: oneshot::Sender<Result<Handle, PicaError>>,
+    ) {
+        log::debug!("[_] Set country code");
+        log::debug!("  mac_address: {}", mac_address);
+        log::debug!("  country_code: {:?}", country_code);
+
+        let status = match self
+            .devices
+            .values_mut()
+            .find(|device| device.mac_address == mac_address)
+        {
+            None => Err(PicaError::DeviceNotFound(mac_address)),
+            Some(device) => {
+                if device.set_country_code(country_code) {
+                    Ok(device.handle)
+                } else {
+                    Err(PicaError::InvalidCountryCode(country_code))
+                }
+            }
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!(
+                "Failed to send set-country-code command response: {:?}",
+                err
+            )
+        })
+    }
+
+    fn get_ground_truth(&self, session_id: u32, rsp_tx: oneshot::Sender<Vec<GroundTruth>>) {
+        let ground_truth = self
+            .ground_truth
+            .iter()
+            .filter(|((_, id), _)| *id == session_id)
+            .flat_map(|(_, (_, ground_truth))| ground_truth.iter().cloned())
+            .collect();
+
+        rsp_tx.send(ground_truth).unwrap_or_else(|err| {
+            log::error!(
+                "Failed to send get-ground-truth command response: {:?}",
+                err
+            )
+        })
+    }
+
+    fn get_config(&self, rsp_tx: oneshot::Sender<Vec<ConfigSnapshot>>) {
+        let mut device_handles = self.devices.keys().copied().collect::<Vec<_>>();
+        device_handles.sort();
+        let config = device_handles
+            .iter()
+            .map(|device_handle| self.devices[device_handle].config_snapshot())
+            .collect();
+
+        rsp_tx.send(config).unwrap_or_else(|err| {
+            log::error!("Failed to send get-config command response: {:?}", err)
+        })
+    }
+
+    fn get_device_handle(&self, mac_address: &MacAddress) -> Option<Handle> {
+        self.devices
+            .values()
+            .find(|device| device.mac_address == *mac_address)
+            .map(|device| device.handle)
+    }
+
+    fn transfer_session(
+        &mut self,
+        session_id: u32,
+        from: MacAddress,
+        to: MacAddress,
+    ) -> Result<(), PicaError> {
+        let from_handle = self
+            .get_device_handle(&from)
+            .ok_or(PicaError::DeviceNotFound(from))?;
+        let to_handle = self
+            .get_device_handle(&to)
+            .ok_or(PicaError::DeviceNotFound(to))?;
+        if self.devices[&from_handle].session(session_id).is_none() {
+            return Err(PicaError::SessionNotFound(session_id));
+        }
+        if !self.devices[&to_handle].can_attach_session(session_id) {
+            return Err(PicaError::SessionNotAttachable(to));
+        }
+
+        let session = self
+            .get_device_mut(from_handle)
+            .and_then(|device| device.detach_session(session_id))
+            .unwrap();
+        self.get_device_mut(to_handle)
+            .unwrap()
+            .attach_session(session);
+        Ok(())
+    }
+
+    fn move_session(
+        &mut self,
+        session_id: u32,
+        from: MacAddress,
+        to: MacAddress,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    ) {
+        log::debug!("[_] Move session");
+        log::debug!("  session_id: 0x{:x}", session_id);
+        log::debug!("  from: {}", from);
+        log::debug!("  to: {}", to);
+
+        let status = self.transfer_session(session_id, from, to);
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send move-session command response: {:?}", err)
+        })
+    }
+
+    fn set_notification_sink(
+        &mut self,
+        handle: Handle,
+        sink: UciSink,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    ) {
+        log::debug!("[{}] Set notification sink", handle);
+
+        let status = match self.notification_txs.get(&handle) {
+            Some(notification_tx) => {
+                let (packet_tx, packet_rx) = mpsc::unbounded_channel();
+                let framing = self.framing;
+                let mtu = self.mtu;
+                let data_mtu = self.data_mtu;
+                let max_tlvs_per_packet = self.max_tlvs_per_packet;
+                let packet_transform = self.packet_transform.clone();
+                let trace_file = self.trace_files.get(&handle).cloned();
+                // The ranging results are already exported by the writer
+                // of the device connection.
+                tokio::task::spawn(async move {
+                    let _ = Self::write_routine(
+                        sink,
+                        packet_rx,
+                        handle,
+                        framing,
+                        mtu,
+                        data_mtu,
+                        max_tlvs_per_packet,
+                        packet_transform.as_deref(),
+                        trace_file.as_deref().map(|file| file as &dyn TraceWriter),
+                        None,
+                        None,
+                    )
+                    .await;
+                });
+                *notification_tx.lock().unwrap() = Some(packet_tx);
+                Ok(())
+            }
+            None => Err(PicaError::HandleNotFound(handle)),
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!(
+                "Failed to send set-notification-sink command response: {:?}",
+                err
+            )
+        })
+    }
+
+    fn reset_device(
+        &mut self,
+        handle: Handle,
+        rsp_tx: oneshot::Sender<Result<uci::DeviceState, PicaError>>,
+    ) {
+        log::debug!("[{}] Reset device", handle);
+
+        let status = match self.devices.get_mut(&handle) {
+            Some(device) => {
+                let state = device.reset();
+                self.schedulers.remove(&handle);
+                Ok(state)
+            }
+            None => Err(PicaError::HandleNotFound(handle)),
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send reset-device command response: {:?}", err)
+        })
+    }
+
+    fn get_device_state(
+        &self,
+        handle: Handle,
+        rsp_tx: oneshot::Sender<Result<uci::DeviceState, PicaError>>,
+    ) {
+        log::debug!("[{}] Get device state", handle);
+
+        let status = self
+            .devices
+            .get(&handle)
+            .map(|device| device.state())
+            .ok_or(PicaError::HandleNotFound(handle));
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!(
+                "Failed to send get-device-state command response: {:?}",
+                err
+            )
+        })
+    }
+
+    /// Stop the active sessions of all devices on behalf of the UWBS.
+    /// The ranging timers are cancelled, the pending ranging rounds
+    /// dropped, and the sessions reported idle to the hosts.
+    fn stop_all_ranging(&mut self, rsp_tx: oneshot::Sender<Vec<(Handle, u32)>>) {
+        log::debug!("Stop all ranging");
+
+        let device_handles = self.devices.keys().copied().collect::<Vec<_>>();
+        let mut stopped = vec![];
+        for device_handle in device_handles {
+            let device = self.devices.get_mut(&device_handle).unwrap();
+            let session_ids = device.active_session_ids();
+            if session_ids.is_empty() {
+                continue;
+            }
+            for session_id in session_ids {
+                log::debug!("[{}] Stop session 0x{:x}", device_handle, session_id);
+                device.stop_session(
+                    session_id,
+                    ReasonCode::StateChangeWithSessionManagementCommands,
+                );
+                stopped.push((device_handle, session_id));
+            }
+            self.schedulers.remove(&device_handle);
+        }
+
+        rsp_tx.send(stopped).unwrap_or_else(|err| {
+            log::error!(
+                "Failed to send stop-all-ranging command response: {:?}",
+                err
+            )
+        })
+    }
+
+    fn get_timers(&self, rsp_tx: oneshot::Sender<Vec<TimerInfo>>) {
+        log::debug!("Get timers");
+        rsp_tx
+            .send(self.device_options.timers.list())
+            .unwrap_or_else(|err| {
+                log::error!("Failed to send get-timers command response: {:?}", err)
+            })
+    }
+
+    fn pause_clock(&mut self) {
+        log::debug!("Pause clock");
+        self.clock_paused = true;
+        self.device_options.clock.pause();
+    }
+
+    fn resume_clock(&mut self) {
+        log::debug!("Resume clock");
+        self.clock_paused = false;
+        self.device_options.clock.resume();
+    }
+
+    fn step_rounds(&mut self, count: usize, rsp_tx: oneshot::Sender<Result<(), PicaError>>) {
+        log::debug!("Step rounds");
+        log::debug!("  count: {}", count);
+
+        let status = if self.clock_paused {
+            for _ in 0..count {
+                let mut ranging_interval = Duration::ZERO;
+                let device_handles = self.devices.keys().copied().collect::<Vec<_>>();
+                for device_handle in device_handles {
+                    // Devices whose host connection was closed during
+                    // a previous ranging round are disconnected.
+                    let Some(device) = self.devices.get(&device_handle) else {
+                        continue;
+                    };
+                    let scheduler = self.schedulers.entry(device_handle).or_default();
+                    for session_id in device.active_session_ids() {
+                        let session = device.session(session_id).unwrap();
+                        ranging_interval = ranging_interval.max(Duration::from_millis(
+                            session.app_config.ranging_duration as u64,
+                        ));
+                        scheduler.push(session_id, session.app_config.session_priority);
+                    }
+                    // The rounds are executed back to back, until
+                    // the scheduler is idle.
+                    while self.execute_ranging_round(device_handle).is_some() {}
+                }
+                self.device_options.clock.advance(ranging_interval);
+            }
+            Ok(())
+        } else {
+            Err(PicaError::ClockNotPaused)
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send step-rounds command response: {:?}", err)
+        })
+    }
+
+    fn set_mac_address(
+        &mut self,
+        handle: Handle,
+        address: MacAddress,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    ) {
+        log::debug!("[{}] Set MAC address", handle);
+        log::debug!("  address: {}", address);
+
+        let status = if !self.devices.contains_key(&handle) {
+            Err(PicaError::HandleNotFound(handle))
+        } else if self.devices[&handle].mac_address == address {
+            Ok(())
+        } else if self.get_category(&address).is_some() {
+            Err(PicaError::DeviceAlreadyExists(address))
+        } else {
+            let device = self.get_device_mut(handle).unwrap();
+            let previous_mac_address = std::mem::replace(&mut device.mac_address, address);
+            self.send_event(PicaEvent::MacAddressChanged {
+                handle,
+                previous_mac_address,
+                mac_address: address,
+            });
+            Ok(())
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send set-mac-address command response: {:?}", err)
+        })
+    }
+
+    fn override_capability(
+        &mut self,
+        handle: Handle,
+        id: u8,
+        value: Vec<u8>,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    ) {
+        log::debug!("[{}] Override capability", handle);
+        log::debug!("  id: 0x{:x}", id);
+        log::debug!("  value: {:?}", value);
+
+        // The TLV length is encoded on one octet.
+        let status = if value.len() > u8::MAX as usize {
+            Err(PicaError::InvalidCapabilityLength(id))
+        } else if let Some(device) = self.get_device_mut(handle) {
+            device.override_capability(id, value);
+            Ok(())
+        } else {
+            Err(PicaError::HandleNotFound(handle))
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!(
+                "Failed to send override-capability command response: {:?}",
+                err
+            )
+        })
+    }
+
+    fn set_clock_drift(
+        &mut self,
+        handle: Handle,
+        ppm: f64,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    ) {
+        log::debug!("[{}] Set clock drift", handle);
+        log::debug!("  ppm: {}", ppm);
+
+        // The local clock must keep moving forward, at most twice as
+        // fast as the shared clock.
+        let status = if !ppm.is_finite() || ppm.abs() >= MAX_CLOCK_DRIFT_PPM {
+            Err(PicaError::InvalidClockDrift)
+        } else if let Some(device) = self.get_device_mut(handle) {
+            device.set_clock_drift(ppm);
+            Ok(())
+        } else {
+            Err(PicaError::HandleNotFound(handle))
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send set-clock-drift command response: {:?}", err)
+        })
+    }
+
+    fn set_stop_reason(
+        &mut self,
+        handle: Handle,
+        session_id: u32,
+        reason: Option<uci::ReasonCode>,
+        rsp_tx: oneshot::Sender<Result<(), PicaError>>,
+    ) {
+        log::debug!("[{}:0x{:x}] Set stop reason", handle, session_id);
+        log::debug!("  reason={:?}", reason);
+
+        let status = match self.get_device_mut(handle) {
+            Some(device) => {
+                if device.set_stop_reason(session_id, reason) {
+                    Ok(())
+                } else {
+                    Err(PicaError::SessionNotFound(session_id))
+                }
+            }
+            None => Err(PicaError::HandleNotFound(handle)),
+        };
+
+        rsp_tx.send(status).unwrap_or_else(|err| {
+            log::error!("Failed to send set-stop-reason command response: {:?}", err)
+        })
+    }
+
+    fn set_positions(
+        &mut self,
+        mut positions: Vec<(Handle, Position)>,
+        rsp_tx: oneshot::Sender<Vec<PicaError>>,
+    ) {
+        log::debug!("[_] Set positions");
+        log::debug!("  count={}", positions.len());
+
+        let mut errors = vec![];
+        positions.retain(|(handle, _)| {
+            let found = self.devices.contains_key(handle)
+                || self.anchors.values().any(|anchor| anchor.handle == *handle);
+            if !found {
+                errors.push(PicaError::HandleNotFound(*handle));
+            }
+            found
+        });
+        self.ranging_estimator.set_positions(&positions);
+
+        rsp_tx.send(errors).unwrap_or_else(|err| {
+            log::error!("Failed to send set-positions command response: {:?}", err)
+        })
+    }
+}
+
+/// Run the internal pica event loop.
+/// As opposed to Pica::run, the context is passed under a mutex, which
+/// allows synchronous access to the context for device creation.
+pub async fn run(this: &std::sync::Mutex<Pica>) -> Result<()> {
+    // Extract the mpsc receiver from the Pica context.
+    // The receiver cannot be cloned.
+    let Some(mut command_rx) = this.lock().unwrap().command_rx.take() else {
+        anyhow::bail!("missing pica command receiver");
+    };
 
     loop {
         if let Some(command) = command_rx.recv().await {
@@ -655,3 +3231,4358 @@ pub async fn run(this: &std::sync::Mutex<Pica>) -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+
+    /// Wait for the next packet of the selected type,
+    /// discarding the notifications received in between.
+    async fn expect<T: Packet>(stream: &mut UciStream) -> T {
+        loop {
+            let packet = stream.next().await.expect("connection closed");
+            if let Ok(packet) = T::decode_full(&packet) {
+                return packet;
+            }
+        }
+    }
+
+    pub(crate) fn tlv(cfg_id: AppConfigTlvType, v: &[u8]) -> AppConfigTlv {
+        AppConfigTlv {
+            cfg_id,
+            v: v.to_vec(),
+        }
+    }
+
+    /// Ranging estimator returning the same measurement for all
+    /// pairs of devices.
+    pub(crate) struct FixedRangingEstimator(pub(crate) RangingMeasurement);
+
+    impl RangingEstimator for FixedRangingEstimator {
+        fn estimate(&self, _left: &Handle, _right: &Handle) -> Option<RangingMeasurement> {
+            Some(self.0)
+        }
+    }
+
+    /// Initialize a ranging session, and configure the mandatory
+    /// app configuration parameters followed by the selected parameters.
+    async fn init_session(
+        sink: &mut UciSink,
+        stream: &mut UciStream,
+        session_id: u32,
+        tlvs: Vec<AppConfigTlv>,
+    ) {
+        init_session_with_type(
+            sink,
+            stream,
+            session_id,
+            SessionType::FiraRangingSession,
+            tlvs,
+        )
+        .await
+    }
+
+    async fn init_session_with_type(
+        sink: &mut UciSink,
+        stream: &mut UciStream,
+        session_id: u32,
+        session_type: SessionType,
+        tlvs: Vec<AppConfigTlv>,
+    ) {
+        sink.send(
+            SessionInitCmd {
+                session_id,
+                session_type,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<SessionInitRsp>(stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let mut mandatory_tlvs = vec![
+            tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+            tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+            tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+            tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+            tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+        ];
+        mandatory_tlvs.extend(tlvs);
+        sink.send(
+            SessionSetAppConfigCmd {
+                session_token: session_id,
+                tlvs: mandatory_tlvs,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<SessionSetAppConfigRsp>(stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn connect_local() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let mut events = pica.events();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        assert!(matches!(
+            events.recv().await,
+            Ok(PicaEvent::Connected { handle: 0, .. })
+        ));
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreDeviceResetRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<CoreGetDeviceInfoRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        drop(sink);
+        assert!(matches!(
+            events.recv().await,
+            Ok(PicaEvent::Disconnected { handle: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn generic_error_notifications() {
+        for enabled in [false, true] {
+            let mut pica = Pica::builder().generic_error_notifications(enabled).build();
+            let (mut sink, mut stream) = pica.connect_local().unwrap();
+            tokio::spawn(pica.run());
+
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+            // Core Device Reset command missing the reset config field,
+            // and Core command with an unknown opcode identifier.
+            sink.send(vec![0x20, 0x00, 0x00, 0x00]).await.unwrap();
+            sink.send(vec![0x20, 0x3e, 0x00, 0x00]).await.unwrap();
+            sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+
+            // The commands are rejected, and the device is still operational.
+            let mut responses = vec![];
+            let mut notifications = vec![];
+            loop {
+                let packet = stream.next().await.expect("connection closed");
+                if CoreGetDeviceInfoRsp::decode_full(&packet).is_ok() {
+                    break;
+                } else if let Ok(ntf) = CoreGenericErrorNtf::decode_full(&packet) {
+                    notifications.push(ntf.status);
+                } else if parse_message_type(packet[0]) == MessageType::Response {
+                    responses.push(packet);
+                }
+            }
+            assert_eq!(
+                responses,
+                vec![
+                    vec![0x40, 0x00, 0x00, 0x01, 0x03],
+                    vec![0x40, 0x3e, 0x00, 0x01, 0x08]
+                ]
+            );
+            assert_eq!(
+                notifications,
+                if enabled {
+                    vec![uci::Status::SyntaxError, uci::Status::UnknownOid]
+                } else {
+                    vec![]
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reassembly_size_limit() {
+        let mut pica = Pica::builder().max_reassembly_size(512).build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // Stream fragments of a Core Set Config command well past the limit.
+        let mut fragment = vec![0x30, 0x04, 0x00, 0xff];
+        fragment.resize(HEADER_SIZE + 0xff, 0);
+        for _ in 0..64 {
+            sink.send(fragment.clone()).await.unwrap();
+        }
+        fragment[0] = 0x20;
+        sink.send(fragment).await.unwrap();
+
+        // The command is rejected once, the remaining fragments are discarded.
+        let rsp = loop {
+            let packet = stream.next().await.expect("connection closed");
+            if parse_message_type(packet[0]) == MessageType::Response {
+                break packet;
+            }
+        };
+        assert_eq!(rsp, vec![0x40, 0x04, 0x00, 0x01, 0x06]);
+
+        // Data packets and packets too short to carry an opcode cannot
+        // be answered, and are always reported.
+        for packet in [vec![0x02, 0x00, 0xff, 0xff], vec![0x20]] {
+            sink.send(packet).await.unwrap();
+            let ntf = expect::<CoreGenericErrorNtf>(&mut stream).await;
+            assert_eq!(ntf.status, uci::Status::InvalidMessageSize);
+        }
+
+        // Commands whose reassembled payload is exactly at the limit
+        // are accepted.
+        let mut payload = vec![29];
+        for _ in 0..28 {
+            payload.extend([0xf0, 16]);
+            payload.extend([0; 16]);
+        }
+        payload.extend([0xf0, 5, 0, 0, 0, 0, 0]);
+        assert_eq!(payload.len(), 512);
+        let mut chunks = payload.chunks(0xff).peekable();
+        while let Some(chunk) = chunks.next() {
+            let pbf = if chunks.peek().is_some() { 0x10 } else { 0x00 };
+            let mut fragment = vec![0x20 | pbf, 0x04, 0x00, chunk.len() as u8];
+            fragment.extend(chunk);
+            sink.send(fragment).await.unwrap();
+        }
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // The connection is still usable.
+        sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<CoreGetDeviceInfoRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn truncated_packet() {
+        let pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // Header of a Core Set Config command whose payload was
+        // discarded by the read adapter.
+        sink.send(vec![0x20, 0x04, 0x00, 0xff]).await.unwrap();
+        let rsp = loop {
+            let packet = stream.next().await.expect("connection closed");
+            if parse_message_type(packet[0]) == MessageType::Response {
+                break packet;
+            }
+        };
+        assert_eq!(rsp, vec![0x40, 0x04, 0x00, 0x01, 0x06]);
+
+        // Data packets and packets too short to carry an opcode cannot
+        // be answered, and are always reported.
+        for packet in [vec![0x02, 0x00, 0xff, 0xff], vec![0x20]] {
+            sink.send(packet).await.unwrap();
+            let ntf = expect::<CoreGenericErrorNtf>(&mut stream).await;
+            assert_eq!(ntf.status, uci::Status::InvalidMessageSize);
+        }
+
+        // The connection is still usable.
+        sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<CoreGetDeviceInfoRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn loopback() {
+        let mut pica = Pica::builder().mtu(MAX_CTRL_PACKET_PAYLOAD_SIZE).build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // The second payload does not fit in a single control packet,
+        // and is segmented in both directions.
+        for size in [16, 300] {
+            let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let cmd = PicaLoopbackCmd {
+                payload: payload.clone(),
+            }
+            .encode_to_vec()
+            .unwrap();
+            let fragments = segment(&cmd, MAX_CTRL_PACKET_PAYLOAD_SIZE, Framing::V1);
+            assert_eq!(fragments.len(), if size > 255 { 2 } else { 1 });
+            for fragment in fragments {
+                sink.send(fragment).await.unwrap();
+            }
+
+            let mut rsp = loop {
+                let packet = stream.next().await.expect("connection closed");
+                if parse_message_type(packet[0]) == MessageType::Response {
+                    break packet;
+                }
+            };
+            let mut n_fragments = 1;
+            while rsp[0] & 0x10 != 0 {
+                let fragment = stream.next().await.expect("connection closed");
+                rsp.extend_from_slice(&fragment[HEADER_SIZE..]);
+                rsp[0] = fragment[0];
+                n_fragments += 1;
+            }
+            assert_eq!(n_fragments, if size > 255 { 2 } else { 1 });
+
+            // Pica Loopback response, with the status followed by the payload.
+            assert_eq!(&rsp[..2], &[0x4e, 0x06]);
+            assert_eq!(rsp[HEADER_SIZE], u8::from(uci::Status::Ok));
+            assert_eq!(&rsp[HEADER_SIZE + 1..], &payload[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn builder() {
+        let versions = Versions {
+            uci_version: 0x0001,
+            ..Default::default()
+        };
+        let mut pica = Pica::builder()
+            .max_devices(1)
+            .versions(versions)
+            .mtu(4)
+            .build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        assert!(pica.connect_local().is_err());
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // The response is segmented according to the MTU.
+        sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let mut rsp = loop {
+            let packet = stream.next().await.expect("connection closed");
+            if parse_message_type(packet[0]) == MessageType::Response {
+                break packet;
+            }
+        };
+        assert_eq!(rsp[3], 4);
+        while rsp[0] & 0x10 != 0 {
+            let fragment = stream.next().await.expect("connection closed");
+            rsp.extend_from_slice(&fragment[HEADER_SIZE..]);
+            rsp[0] = fragment[0];
+        }
+        rsp[3] = (rsp.len() - HEADER_SIZE) as u8;
+        let rsp = CoreGetDeviceInfoRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.uci_version, 0x0001);
+        assert_eq!(rsp.mac_version, versions.mac_version);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn packet_transform() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        // Drop every other outbound packet, and record the
+        // packets forwarded to the host.
+        let count = Arc::new(AtomicUsize::new(0));
+        let forwarded = Arc::new(Mutex::new(vec![]));
+        let mut pica = Pica::builder()
+            .packet_transform({
+                let count = count.clone();
+                let forwarded = forwarded.clone();
+                Box::new(move |direction, packet| {
+                    if direction == Direction::Inbound {
+                        return Some(packet);
+                    }
+                    if count.fetch_add(1, Ordering::SeqCst) % 2 == 1 {
+                        return None;
+                    }
+                    forwarded.lock().unwrap().push(packet.clone());
+                    Some(packet)
+                })
+            })
+            .build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        for _ in 0..4 {
+            sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+        }
+
+        let mut received = vec![];
+        while let Ok(Some(packet)) = time::timeout(Duration::from_millis(100), stream.next()).await
+        {
+            received.push(packet);
+        }
+
+        // The host receives the forwarded packets only.
+        let forwarded = forwarded.lock().unwrap();
+        assert_eq!(received, *forwarded);
+        assert!(forwarded.len() >= 2);
+        assert_eq!(forwarded.len(), count.load(Ordering::SeqCst).div_ceil(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let mut pica = Pica::builder()
+            .idle_timeout(Duration::from_secs(1))
+            .max_devices(1)
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        assert!(matches!(
+            events.recv().await,
+            Ok(PicaEvent::Connected { handle: 0, .. })
+        ));
+
+        // Inbound packets reset the timeout.
+        for _ in 0..3 {
+            time::sleep(Duration::from_millis(600)).await;
+            sink.send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+        }
+        assert!(events.try_recv().is_err());
+
+        time::sleep(Duration::from_millis(1100)).await;
+        assert!(matches!(
+            events.recv().await,
+            Ok(PicaEvent::Disconnected { handle: 0, .. })
+        ));
+        while stream.next().await.is_some() {}
+
+        // The device slot is freed.
+        let (stream, sink) = {
+            let (host_tx, device_rx) = futures::channel::mpsc::unbounded();
+            let (device_tx, _host_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+            drop(host_tx);
+            (device_rx, device_tx)
+        };
+        commands
+            .send(PicaCommand::Connect(
+                Box::pin(stream),
+                Box::pin(sink.sink_map_err(anyhow::Error::from)),
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            events.recv().await,
+            Ok(PicaEvent::Connected { handle: 1, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn move_session() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink_a, mut stream_a) = pica.connect_local().unwrap();
+        let (mut sink_b, mut stream_b) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let mut mac_addresses = vec![];
+        for _ in 0..2 {
+            let Ok(PicaEvent::Connected { mac_address, .. }) = events.recv().await else {
+                panic!("expected connected event");
+            };
+            mac_addresses.push(mac_address);
+        }
+        let (mac_a, mac_b) = (mac_addresses[0], mac_addresses[1]);
+
+        for (sink, stream) in [(&mut sink_a, &mut stream_a), (&mut sink_b, &mut stream_b)] {
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(stream).await;
+        }
+
+        let session_id = 0x1234;
+        sink_a
+            .send(
+                SessionInitCmd {
+                    session_id,
+                    session_type: SessionType::FiraRangingSession,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        let rsp = expect::<SessionInitRsp>(&mut stream_a).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::MoveSession {
+                session_id,
+                from: mac_a,
+                to: mac_b,
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rsp_rx.await.unwrap(), Ok(()));
+
+        // The source host is notified of the session deinit,
+        // the destination host of the current session state.
+        let ntf = expect::<SessionStatusNtf>(&mut stream_b).await;
+        assert_eq!(ntf.session_token, session_id);
+        assert_eq!(ntf.session_state, SessionState::SessionStateInit);
+        loop {
+            let ntf = expect::<SessionStatusNtf>(&mut stream_a).await;
+            if ntf.session_state == SessionState::SessionStateDeinit {
+                break;
+            }
+        }
+
+        sink_b
+            .send(
+                SessionGetStateCmd {
+                    session_token: session_id,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        let rsp = expect::<SessionGetStateRsp>(&mut stream_b).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(rsp.session_state, SessionState::SessionStateInit);
+
+        sink_a
+            .send(
+                SessionGetStateCmd {
+                    session_token: session_id,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        let rsp = expect::<SessionGetStateRsp>(&mut stream_a).await;
+        assert_eq!(rsp.status, uci::Status::ErrorSessionNotExist);
+
+        // The session is no longer attached to the source device.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::MoveSession {
+                session_id,
+                from: mac_a,
+                to: mac_b,
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            rsp_rx.await.unwrap(),
+            Err(PicaError::SessionNotFound(session_id))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn session_priority() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // The ranging rounds (about 50ms) of the two sessions
+        // are scheduled in the same ranging interval (100ms).
+        for (session_id, priority) in [(1, 10), (2, 90)] {
+            init_session(
+                &mut sink,
+                &mut stream,
+                session_id,
+                vec![
+                    tlv(AppConfigTlvType::RangingDuration, &100u32.to_le_bytes()),
+                    tlv(AppConfigTlvType::SessionPriority, &[priority]),
+                ],
+            )
+            .await;
+        }
+
+        for session_id in [1, 2] {
+            sink.send(SessionStartCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+        }
+        for _ in 0..2 {
+            let rsp = expect::<SessionStartRsp>(&mut stream).await;
+            assert_eq!(rsp.status, uci::Status::Ok);
+        }
+
+        // The higher priority session is serviced first in each
+        // contended block, the lower priority session is delayed
+        // by one ranging round.
+        let mut reports = vec![];
+        for _ in 0..4 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            reports.push((ntf.session_token, time::Instant::now()));
+        }
+        assert_eq!(
+            reports.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![2, 1, 2, 1]
+        );
+        assert!(reports[1].1 - reports[0].1 >= Duration::from_millis(48));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hybrid_session() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // Sessions 1 and 2 are the primary sessions of the phases
+        // of the hybrid session 3.
+        for session_id in [1, 2, 3] {
+            init_session(&mut sink, &mut stream, session_id, vec![]).await;
+        }
+
+        let phase = |session_token| PhaseList {
+            session_token,
+            start_slot_index: 0,
+            end_slot_index: 0,
+        };
+        let set_hybrid_config = |phase_list| {
+            SessionSetHybridControllerConfigCmd {
+                session_token: 3,
+                message_control: 0,
+                update_time: [0; 8],
+                phase_list,
+            }
+            .encode_to_vec()
+            .unwrap()
+        };
+
+        // The phases must reference existing sessions.
+        sink.send(set_hybrid_config(vec![phase(1), phase(4)]))
+            .await
+            .unwrap();
+        let rsp = expect::<SessionSetHybridControllerConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::ErrorSessionNotExist);
+
+        sink.send(set_hybrid_config(vec![phase(1), phase(2)]))
+            .await
+            .unwrap();
+        let rsp = expect::<SessionSetHybridControllerConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        sink.send(SessionStartCmd { session_id: 3 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<SessionStartRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        // The ranging rounds alternate between the two phases.
+        let mut reports = vec![];
+        for _ in 0..4 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            reports.push(ntf.session_token);
+        }
+        assert_eq!(reports, vec![1, 2, 1, 2]);
+    }
+
+    async fn get_ground_truth(
+        commands: &mpsc::Sender<PicaCommand>,
+        session_id: u32,
+    ) -> Vec<GroundTruth> {
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::GetGroundTruth(session_id, rsp_tx))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ground_truth() {
+        let truth = RangingMeasurement {
+            range: 100,
+            azimuth: 30,
+            elevation: 0,
+        };
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(truth)))
+            .noise_model(NoiseModel { aoa_sigma: 20. })
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { mac_address, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        let anchor_mac_address = MacAddress::Short([0x00, 0x02]);
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(anchor_mac_address, rsp_tx))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        // No ranging round was executed.
+        assert!(get_ground_truth(&commands, 1).await.is_empty());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                tlv(AppConfigTlvType::ResultReportConfig, &[0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The reported azimuth is noisy, the ground truth is not.
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        let measurement = &ntf.two_way_ranging_measurements[0];
+        assert_eq!(measurement.distance, 100);
+        assert_ne!(measurement.aoa_azimuth, 30);
+        assert_eq!(
+            get_ground_truth(&commands, 1).await,
+            vec![GroundTruth {
+                mac_address,
+                peer_mac_address: anchor_mac_address,
+                measurement: truth,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn suspend_active_ranging() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+
+        // No ranging notification is reported while suspended,
+        // the session remains active.
+        sink.send(PicaSuspendCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<PicaSuspendRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        while let Ok(Some(packet)) = time::timeout(Duration::from_secs(1), stream.next()).await {
+            assert!(ShortMacTwoWaySessionInfoNtf::decode_full(&packet).is_err());
+            assert!(SessionStatusNtf::decode_full(&packet).is_err());
+        }
+
+        // Ranging continues when resumed.
+        sink.send(PicaResumeCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<PicaResumeRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let ntf = expect::<CoreDeviceStatusNtf>(&mut stream).await;
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateActive);
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(ntf.session_token, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn twr_timing() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 1234,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .twr_reply_time(Duration::from_micros(500))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        // The second controlee cannot be ranged with, and is reported
+        // as unavailable without timing fields.
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::MultiNodeMode, &[0x01]),
+                tlv(AppConfigTlvType::NumberOfControlees, &[0x02]),
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02, 0x00, 0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        let measurement = &ntf.two_way_ranging_measurements[0];
+        assert_eq!(measurement.distance, 1234);
+        assert_eq!(
+            ntf.two_way_ranging_measurements[1].status,
+            uci::Status::RangingRxTimeout
+        );
+        let tlv = VendorDataTlv::decode_full(&ntf.vendor_data).unwrap();
+        assert_eq!(tlv.t, VendorDataTlvType::TwrTiming);
+        assert_eq!(tlv.v.len(), 10);
+        assert_eq!(tlv.v[0..2], [0x00, 0x02]);
+        let round_time = u32::from_le_bytes(tlv.v[2..6].try_into().unwrap());
+        let reply_time = u32::from_le_bytes(tlv.v[6..10].try_into().unwrap());
+        assert_eq!(reply_time, 500_000_000);
+
+        // The distance is derived back from the timing fields.
+        let time_of_flight = (round_time - reply_time) as f64 / 2.;
+        assert_eq!(
+            (time_of_flight * SPEED_OF_LIGHT).round() as u16,
+            measurement.distance
+        );
+        for distance in [0, 1, 100, 1234, u16::MAX] {
+            let (round_time, reply_time) = super::twr_timing(distance, Duration::from_micros(500));
+            let time_of_flight = (round_time - reply_time) as f64 / 2.;
+            assert_eq!((time_of_flight * SPEED_OF_LIGHT).round() as u16, distance);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clock_drift() {
+        let clock = Arc::new(MockClock::default());
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 1234,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .clock(clock.clone())
+            .twr_reply_time(Duration::from_micros(500))
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        let set_clock_drift = |ppm| {
+            let commands = commands.clone();
+            async move {
+                let (rsp_tx, rsp_rx) = oneshot::channel();
+                commands
+                    .send(PicaCommand::SetClockDrift {
+                        handle,
+                        ppm,
+                        rsp_tx,
+                    })
+                    .await
+                    .unwrap();
+                rsp_rx.await.unwrap()
+            }
+        };
+        for ppm in [-1e6, 1e6, 1e12, f64::INFINITY, f64::NAN] {
+            assert_eq!(
+                set_clock_drift(ppm).await,
+                Err(PicaError::InvalidClockDrift)
+            );
+        }
+        assert_eq!(set_clock_drift(200.).await, Ok(()));
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        expect::<SessionStartRsp>(&mut stream).await;
+
+        // The timing fields are measured with the drifting clock.
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(1));
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            let tlv = VendorDataTlv::decode_full(&ntf.vendor_data).unwrap();
+            let round_time = u32::from_le_bytes(tlv.v[2..6].try_into().unwrap());
+            let reply_time = u32::from_le_bytes(tlv.v[6..10].try_into().unwrap());
+            let (expected_round_time, _) = super::twr_timing(1234, Duration::from_micros(500));
+            assert_eq!(reply_time, 500_100_000);
+            assert_eq!(
+                round_time,
+                (expected_round_time as f64 * 1.0002).round() as u32
+            );
+        }
+
+        // The UWBS timestamp diverges from the shared clock
+        // by 200 microseconds per second.
+        sink.send(CoreQueryTimeStampCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<CoreQueryTimeStampRsp>(&mut stream).await;
+        assert_eq!(clock.now(), Duration::from_secs(5));
+        assert_eq!(rsp.timeStamp, 5_001_000);
+    }
+
+    #[tokio::test]
+    async fn removed_device() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let mut events = pica.events();
+        let (_sink, _stream) = pica.connect_local().unwrap();
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+
+        // Ranging round for a session deinitialized in the meantime.
+        pica.ranging(handle, 1);
+
+        // Commands racing the disconnection of the device are dropped.
+        pica.disconnect(handle);
+        pica.schedule_ranging(handle, 1);
+        pica.ranging_round(handle);
+        pica.ranging(handle, 1);
+        pica.uci_packet(handle, CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        pica.pause_clock();
+        pica.step_rounds(1, rsp_tx);
+        assert_eq!(rsp_rx.await.unwrap(), Ok(()));
+        assert!(pica.devices.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn device_order() {
+        /// Connect a device with the selected handle.
+        fn connect(pica: &mut Pica, handle: Handle) -> (UciSink, UciStream) {
+            let (host_tx, device_rx) = futures::channel::mpsc::unbounded();
+            let (device_tx, host_rx) = futures::channel::mpsc::unbounded();
+            pica.add_device_with_handle(
+                Box::pin(device_rx),
+                Box::pin(device_tx.sink_map_err(anyhow::Error::from)),
+                Some(handle),
+            )
+            .unwrap();
+            (
+                Box::pin(host_tx.sink_map_err(anyhow::Error::from)),
+                Box::pin(host_rx),
+            )
+        }
+
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .build();
+
+        // The controlees are connected out of order.
+        let (mut sink, mut stream) = connect(&mut pica, 5);
+        let mut controlees = [9, 2, 7]
+            .into_iter()
+            .map(|handle| (handle, connect(&mut pica, handle)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pica.devices.keys().copied().collect::<Vec<_>>(),
+            vec![2, 5, 7, 9]
+        );
+        tokio::spawn(pica.run());
+
+        for (handle, (sink, stream)) in controlees.iter_mut() {
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(stream).await;
+            init_session(
+                sink,
+                stream,
+                1,
+                vec![
+                    tlv(AppConfigTlvType::DeviceType, &[0x00]),
+                    tlv(AppConfigTlvType::DeviceRole, &[0x00]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, *handle as u8]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x01]),
+                ],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+        }
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DeviceType, &[0x01]),
+                tlv(AppConfigTlvType::NumberOfControlees, &[0x03]),
+                tlv(
+                    AppConfigTlvType::DstMacAddress,
+                    &[0x00, 0x09, 0x00, 0x02, 0x00, 0x07],
+                ),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The peers are reported by ascending handle, whatever
+        // the connection order or the order of the controlee list.
+        let ntf = loop {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            if ntf.two_way_ranging_measurements.len() == 3 {
+                break ntf;
+            }
+        };
+        assert_eq!(
+            ntf.two_way_ranging_measurements
+                .iter()
+                .map(|measurement| measurement.mac_address)
+                .collect::<Vec<_>>(),
+            vec![0x0200, 0x0700, 0x0900]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_all_ranging() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        let (mut other_sink, mut other_stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let mut handles = vec![];
+        for _ in 0..2 {
+            let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+                panic!("expected connected event");
+            };
+            handles.push(handle);
+        }
+
+        for (session_id, sink, stream) in [
+            (1, &mut sink, &mut stream),
+            (2, &mut other_sink, &mut other_stream),
+        ] {
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(stream).await;
+            init_session(sink, stream, session_id, vec![]).await;
+            sink.send(SessionStartCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            expect::<ShortMacTwoWaySessionInfoNtf>(stream).await;
+        }
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::StopAllRanging(rsp_tx))
+            .await
+            .unwrap();
+        assert_eq!(
+            rsp_rx.await.unwrap(),
+            vec![(handles[0], 1), (handles[1], 2)]
+        );
+
+        for (session_id, sink, stream) in [
+            (1, &mut sink, &mut stream),
+            (2, &mut other_sink, &mut other_stream),
+        ] {
+            // The sessions are reported idle, and no longer range.
+            loop {
+                let ntf = expect::<SessionStatusNtf>(stream).await;
+                if ntf.session_state == SessionState::SessionStateIdle {
+                    assert_eq!(ntf.session_token, session_id);
+                    break;
+                }
+            }
+            while let Ok(Some(packet)) = time::timeout(Duration::from_secs(1), stream.next()).await
+            {
+                assert!(ShortMacTwoWaySessionInfoNtf::decode_full(&packet).is_err());
+            }
+            sink.send(
+                SessionGetStateCmd {
+                    session_token: session_id,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            let rsp = expect::<SessionGetStateRsp>(stream).await;
+            assert_eq!(rsp.session_state, SessionState::SessionStateIdle);
+        }
+
+        // Nothing is left to stop.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::StopAllRanging(rsp_tx))
+            .await
+            .unwrap();
+        assert!(rsp_rx.await.unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn on_measurement() {
+        use std::sync::Mutex;
+
+        let truth = RangingMeasurement {
+            range: 100,
+            azimuth: 30,
+            elevation: 0,
+        };
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(truth)))
+            .noise_model(NoiseModel { aoa_sigma: 20. })
+            .build();
+        let observed = Arc::new(Mutex::new(vec![]));
+        let observed_clone = observed.clone();
+        pica.on_measurement(Box::new(move |event| {
+            observed_clone.lock().unwrap().push(event)
+        }));
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { mac_address, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        let anchor_mac_address = MacAddress::Short([0x00, 0x02]);
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(anchor_mac_address, rsp_tx))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                tlv(AppConfigTlvType::ResultReportConfig, &[0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The observer receives the noise-free and the reported
+        // measurement of each ranging round.
+        for round_index in 0..3 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            let reported = &ntf.two_way_ranging_measurements[0];
+            let event = observed.lock().unwrap()[round_index].clone();
+            assert_eq!(event.session_id, 1);
+            assert_eq!(event.round_index, ntf.sequence_number);
+            assert_eq!(event.round_index, round_index as u32);
+            assert_eq!(event.mac_address, mac_address);
+            assert_eq!(event.peer_mac_address, anchor_mac_address);
+            assert_eq!(event.truth, truth);
+            assert_eq!(event.measurement.range, reported.distance);
+            assert_eq!(event.measurement.azimuth, reported.aoa_azimuth as i16);
+        }
+        assert_eq!(observed.lock().unwrap().len(), 3);
+    }
+
+    /// Return the raw ranging notifications reported for a session
+    /// with two anchors, with noise generated from the selected seed.
+    async fn noisy_ranging_reports(noise_seed: u64, rounds: usize) -> Vec<Vec<u8>> {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 30,
+                elevation: 0,
+            })))
+            .noise_model(NoiseModel { aoa_sigma: 20. })
+            .noise_seed(noise_seed)
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        for anchor in [[0x00, 0x02], [0x00, 0x03]] {
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(MacAddress::Short(anchor), rsp_tx))
+                .await
+                .unwrap();
+            rsp_rx.await.unwrap().unwrap();
+        }
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::MultiNodeMode, &[0x01]),
+                tlv(AppConfigTlvType::NumberOfControlees, &[0x02]),
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02, 0x00, 0x03]),
+                tlv(AppConfigTlvType::ResultReportConfig, &[0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        let mut reports = Vec::new();
+        while reports.len() < rounds {
+            let packet = stream.next().await.expect("connection closed");
+            if ShortMacTwoWaySessionInfoNtf::decode_full(&packet).is_ok() {
+                reports.push(packet);
+            }
+        }
+        reports
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn noise_seed() {
+        let reports = noisy_ranging_reports(42, 4).await;
+        // The same scene generates the same noise sequence.
+        assert_eq!(noisy_ranging_reports(42, 4).await, reports);
+        // The noise sequence changes with the global seed.
+        assert_ne!(noisy_ranging_reports(43, 4).await, reports);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn host_connection_closed() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let mut events = pica.events();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        let (mut other_sink, mut other_stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        let Ok(PicaEvent::Connected { .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+
+        for (sink, stream) in [
+            (&mut sink, &mut stream),
+            (&mut other_sink, &mut other_stream),
+        ] {
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(stream).await;
+        }
+        init_session(&mut sink, &mut stream, 1, vec![]).await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+
+        // The next ranging notification cannot be delivered to the host.
+        drop(stream);
+        let Ok(PicaEvent::Disconnected {
+            handle: disconnected_handle,
+            ..
+        }) = events.recv().await
+        else {
+            panic!("expected disconnected event");
+        };
+        assert_eq!(disconnected_handle, handle);
+
+        // The other device is still connected.
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(events.try_recv().is_err());
+        other_sink
+            .send(CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        expect::<CoreGetDeviceInfoRsp>(&mut other_stream).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn one_to_many_measurements() {
+        use std::sync::Mutex;
+
+        /// Ranging estimator producing no measurement for one device.
+        struct OutOfRangeEstimator(Arc<Mutex<Option<Handle>>>);
+
+        impl RangingEstimator for OutOfRangeEstimator {
+            fn estimate(&self, left: &Handle, right: &Handle) -> Option<RangingMeasurement> {
+                let out_of_range = *self.0.lock().unwrap();
+                (out_of_range != Some(*left) && out_of_range != Some(*right)).then_some(
+                    RangingMeasurement {
+                        range: 100,
+                        azimuth: 0,
+                        elevation: 0,
+                    },
+                )
+            }
+        }
+
+        let out_of_range = Arc::new(Mutex::new(None));
+        let mut pica = Pica::new(Box::new(OutOfRangeEstimator(out_of_range.clone())), None);
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let mut anchors = vec![];
+        for address in [0x02, 0x03, 0x04] {
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short([0x00, address]),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            anchors.push(rsp_rx.await.unwrap().unwrap());
+        }
+        *out_of_range.lock().unwrap() = Some(anchors[1]);
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::MultiNodeMode, &[0x01]),
+                tlv(AppConfigTlvType::NumberOfControlees, &[0x03]),
+                tlv(
+                    AppConfigTlvType::DstMacAddress,
+                    &[0x00, 0x02, 0x00, 0x03, 0x00, 0x04],
+                ),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        let measurements = &ntf.two_way_ranging_measurements;
+        assert_eq!(
+            measurements
+                .iter()
+                .map(|measurement| (measurement.mac_address, measurement.status))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x0200, uci::Status::Ok),
+                (0x0300, uci::Status::RangingRxTimeout),
+                (0x0400, uci::Status::Ok),
+            ]
+        );
+        assert_eq!(measurements[0].distance, 100);
+        assert_eq!(measurements[1].distance, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn controlee_loss() {
+        use std::sync::Mutex;
+
+        /// Ranging estimator producing no measurement for one device.
+        struct OutOfRangeEstimator(Arc<Mutex<Option<Handle>>>);
+
+        impl RangingEstimator for OutOfRangeEstimator {
+            fn estimate(&self, left: &Handle, right: &Handle) -> Option<RangingMeasurement> {
+                let out_of_range = *self.0.lock().unwrap();
+                (out_of_range != Some(*left) && out_of_range != Some(*right)).then_some(
+                    RangingMeasurement {
+                        range: 100,
+                        azimuth: 0,
+                        elevation: 0,
+                    },
+                )
+            }
+        }
+
+        let out_of_range = Arc::new(Mutex::new(None));
+        let mut pica = Pica::new(Box::new(OutOfRangeEstimator(out_of_range.clone())), None);
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        let anchor = rsp_rx.await.unwrap().unwrap();
+        *out_of_range.lock().unwrap() = Some(anchor);
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                tlv(AppConfigTlvType::MaxRrRetry, &[0x03, 0x00]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The controlee returning in range before the threshold
+        // resets the count of failed rounds.
+        for _ in 0..2 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            assert!(ntf.two_way_ranging_measurements.is_empty());
+        }
+        *out_of_range.lock().unwrap() = None;
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(ntf.two_way_ranging_measurements.len(), 1);
+        *out_of_range.lock().unwrap() = Some(anchor);
+
+        let mut failed_rounds = 0;
+        let ntf = loop {
+            let packet = stream.next().await.unwrap();
+            if ShortMacTwoWaySessionInfoNtf::decode_full(&packet).is_ok() {
+                failed_rounds += 1;
+            } else if let Ok(ntf) = SessionStatusNtf::decode_full(&packet) {
+                if ntf.session_state == SessionState::SessionStateIdle {
+                    break ntf;
+                }
+            }
+        };
+        assert_eq!(failed_rounds, 3);
+        assert_eq!(
+            ntf.reason_code,
+            u8::from(ReasonCode::MaxRangingRoundRetryCountReached)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn step_rounds() {
+        let mut pica = Pica::new(
+            Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })),
+            None,
+        );
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        // Rounds can only be stepped while the clock is paused.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::StepRounds(1, rsp_tx))
+            .await
+            .unwrap();
+        assert_eq!(rsp_rx.await.unwrap(), Err(PicaError::ClockNotPaused));
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        commands.send(PicaCommand::PauseClock).await.unwrap();
+        for (session_id, priority) in [(1, 50), (2, 100)] {
+            init_session(
+                &mut sink,
+                &mut stream,
+                session_id,
+                vec![tlv(AppConfigTlvType::SessionPriority, &[priority])],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            let rsp = expect::<SessionStartRsp>(&mut stream).await;
+            assert_eq!(rsp.status, uci::Status::Ok);
+        }
+
+        async fn session_info_ntfs(stream: &mut UciStream) -> Vec<u32> {
+            let mut session_ids = vec![];
+            while let Ok(Some(packet)) = time::timeout(Duration::from_secs(1), stream.next()).await
+            {
+                if let Ok(ntf) = SessionInfoNtf::decode_full(&packet) {
+                    session_ids.push(ntf.session_token);
+                }
+            }
+            session_ids
+        }
+
+        // No round is executed while the clock is paused.
+        assert!(session_info_ntfs(&mut stream).await.is_empty());
+
+        // Exactly one round is executed for each active session,
+        // by decreasing session priority.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::StepRounds(1, rsp_tx))
+            .await
+            .unwrap();
+        assert_eq!(rsp_rx.await.unwrap(), Ok(()));
+        assert_eq!(session_info_ntfs(&mut stream).await, vec![2, 1]);
+
+        // Rounds are executed periodically after the clock is resumed.
+        commands.send(PicaCommand::ResumeClock).await.unwrap();
+        expect::<SessionInfoNtf>(&mut stream).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inject_session_status() {
+        let mut pica = Pica::builder().build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(&mut sink, &mut stream, 1, vec![]).await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        while expect::<SessionStatusNtf>(&mut stream).await.session_state
+            != SessionState::SessionStateActive
+        {}
+
+        // Sessions that do not exist are ignored.
+        commands
+            .send(PicaCommand::InjectSessionStatus {
+                handle,
+                session_id: 2,
+                state: SessionState::SessionStateIdle,
+                reason: ReasonCode::SessionStoppedDueToInbandSignal,
+            })
+            .await
+            .unwrap();
+        commands
+            .send(PicaCommand::InjectSessionStatus {
+                handle,
+                session_id: 1,
+                state: SessionState::SessionStateIdle,
+                reason: ReasonCode::SessionStoppedDueToInbandSignal,
+            })
+            .await
+            .unwrap();
+        let ntf = expect::<SessionStatusNtf>(&mut stream).await;
+        assert_eq!(ntf.session_token, 1);
+        assert_eq!(ntf.session_state, SessionState::SessionStateIdle);
+        assert_eq!(
+            ntf.reason_code,
+            u8::from(ReasonCode::SessionStoppedDueToInbandSignal)
+        );
+
+        sink.send(
+            SessionGetStateCmd { session_token: 1 }
+                .encode_to_vec()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<SessionGetStateRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(rsp.session_state, SessionState::SessionStateIdle);
+    }
+
+    #[tokio::test]
+    async fn session_recover() {
+        let mut pica = Pica::builder().build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(
+                AppConfigTlvType::RangingDuration,
+                &[0x2c, 0x01, 0x00, 0x00],
+            )],
+        )
+        .await;
+
+        // Sessions not in the error state cannot be recovered.
+        sink.send(
+            PicaSessionRecoverCmd { session_token: 1 }
+                .encode_to_vec()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<PicaSessionRecoverRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Rejected);
+
+        commands
+            .send(PicaCommand::InjectSessionStatus {
+                handle,
+                session_id: 1,
+                state: SessionState::SessionStateError,
+                reason: ReasonCode::StateChangeWithSessionManagementCommands,
+            })
+            .await
+            .unwrap();
+        while expect::<SessionStatusNtf>(&mut stream).await.session_state
+            != SessionState::SessionStateError
+        {}
+
+        // Sessions in the error state cannot be started.
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<SessionStartRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Rejected);
+
+        sink.send(
+            PicaSessionRecoverCmd { session_token: 1 }
+                .encode_to_vec()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<PicaSessionRecoverRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let ntf = expect::<SessionStatusNtf>(&mut stream).await;
+        assert_eq!(ntf.session_state, SessionState::SessionStateIdle);
+
+        // The application configuration is preserved.
+        sink.send(
+            SessionGetAppConfigCmd {
+                session_token: 1,
+                app_cfg: vec![AppConfigTlvType::RangingDuration],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<SessionGetAppConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(
+            rsp.tlvs,
+            vec![tlv(
+                AppConfigTlvType::RangingDuration,
+                &[0x2c, 0x01, 0x00, 0x00]
+            )]
+        );
+
+        // Sessions that were never configured are recovered
+        // to the initialized state.
+        sink.send(
+            SessionInitCmd {
+                session_id: 2,
+                session_type: SessionType::FiraRangingSession,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<SessionInitRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        commands
+            .send(PicaCommand::InjectSessionStatus {
+                handle,
+                session_id: 2,
+                state: SessionState::SessionStateError,
+                reason: ReasonCode::StateChangeWithSessionManagementCommands,
+            })
+            .await
+            .unwrap();
+        while expect::<SessionStatusNtf>(&mut stream).await.session_state
+            != SessionState::SessionStateError
+        {}
+
+        sink.send(
+            PicaSessionRecoverCmd { session_token: 2 }
+                .encode_to_vec()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<PicaSessionRecoverRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let ntf = expect::<SessionStatusNtf>(&mut stream).await;
+        assert_eq!(ntf.session_token, 2);
+        assert_eq!(ntf.session_state, SessionState::SessionStateInit);
+
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<SessionStartRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn stop_reason() {
+        let mut pica = Pica::builder().build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(&mut sink, &mut stream, 1, vec![]).await;
+
+        async fn start_stop(sink: &mut UciSink, stream: &mut UciStream) -> SessionStatusNtf {
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            while expect::<SessionStatusNtf>(stream).await.session_state
+                != SessionState::SessionStateActive
+            {}
+            sink.send(SessionStopCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            loop {
+                let ntf = expect::<SessionStatusNtf>(stream).await;
+                if ntf.session_state == SessionState::SessionStateIdle {
+                    return ntf;
+                }
+            }
+        }
+
+        // Stops requested by the host report the host-initiated reason.
+        let ntf = start_stop(&mut sink, &mut stream).await;
+        assert_eq!(
+            ntf.reason_code,
+            u8::from(ReasonCode::StateChangeWithSessionManagementCommands)
+        );
+
+        // The reason can be overridden for the selected session only.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::SetStopReason {
+                handle,
+                session_id: 2,
+                reason: Some(ReasonCode::MaxNumberOfMeasurementsReached),
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rsp_rx.await.unwrap(), Err(PicaError::SessionNotFound(2)));
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::SetStopReason {
+                handle,
+                session_id: 1,
+                reason: Some(ReasonCode::MaxNumberOfMeasurementsReached),
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+        let ntf = start_stop(&mut sink, &mut stream).await;
+        assert_eq!(
+            ntf.reason_code,
+            u8::from(ReasonCode::MaxNumberOfMeasurementsReached)
+        );
+
+        // Clearing the override restores the host-initiated reason.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::SetStopReason {
+                handle,
+                session_id: 1,
+                reason: None,
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+        let ntf = start_stop(&mut sink, &mut stream).await;
+        assert_eq!(
+            ntf.reason_code,
+            u8::from(ReasonCode::StateChangeWithSessionManagementCommands)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn radial_velocity() {
+        use std::sync::Mutex;
+
+        /// Ranging estimator moving one device away at 100 cm/s,
+        /// the other devices are static.
+        struct TrajectoryEstimator {
+            start: time::Instant,
+            moving: Arc<Mutex<Option<Handle>>>,
+        }
+
+        impl RangingEstimator for TrajectoryEstimator {
+            fn estimate(&self, left: &Handle, right: &Handle) -> Option<RangingMeasurement> {
+                let moving = *self.moving.lock().unwrap();
+                let range = if moving == Some(*left) || moving == Some(*right) {
+                    100 + (self.start.elapsed().as_millis() / 10) as u16
+                } else {
+                    100
+                };
+                Some(RangingMeasurement {
+                    range,
+                    azimuth: 0,
+                    elevation: 0,
+                })
+            }
+        }
+
+        /// Decode the radial velocities reported in the vendor data.
+        fn radial_velocities(ntf: &ShortMacTwoWaySessionInfoNtf) -> Vec<(u16, i16)> {
+            let tlv = VendorDataTlv::decode_full(&ntf.vendor_data).unwrap();
+            assert_eq!(tlv.t, VendorDataTlvType::RadialVelocity);
+            tlv.v
+                .chunks(4)
+                .map(|chunk| {
+                    (
+                        u16::from_le_bytes([chunk[0], chunk[1]]),
+                        i16::from_le_bytes([chunk[2], chunk[3]]),
+                    )
+                })
+                .collect()
+        }
+
+        let moving = Arc::new(Mutex::new(None));
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(TrajectoryEstimator {
+                start: time::Instant::now(),
+                moving: moving.clone(),
+            }))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let mut anchors = vec![];
+        for address in [0x02, 0x03] {
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short([0x00, address]),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            anchors.push(rsp_rx.await.unwrap().unwrap());
+        }
+        *moving.lock().unwrap() = Some(anchors[0]);
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::RadialVelocityReport,
+                    value: vec![0x01],
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::MultiNodeMode, &[0x01]),
+                tlv(AppConfigTlvType::NumberOfControlees, &[0x02]),
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02, 0x00, 0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The velocity is unknown in the first ranging round.
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(radial_velocities(&ntf), vec![(0x0200, 0), (0x0300, 0)]);
+
+        // The moving device reports a positive radial velocity,
+        // the static device reports zero.
+        for _ in 0..3 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            let velocities = radial_velocities(&ntf);
+            assert_eq!(velocities.len(), 2);
+            assert_eq!(velocities[0].0, 0x0200);
+            assert!((95..=105).contains(&velocities[0].1));
+            assert_eq!(velocities[1], (0x0300, 0));
+        }
+    }
+
+    /// Decode the movement indicators reported in the vendor data.
+    fn movement_indicators(ntf: &ShortMacTwoWaySessionInfoNtf) -> Vec<(u16, u8)> {
+        let tlv = VendorDataTlv::decode_full(&ntf.vendor_data).unwrap();
+        assert_eq!(tlv.t, VendorDataTlvType::MovementIndicator);
+        tlv.v
+            .chunks(3)
+            .map(|chunk| (u16::from_le_bytes([chunk[0], chunk[1]]), chunk[2]))
+            .collect()
+    }
+
+    /// Start ranging with an anchor, with the movement indicator
+    /// reported above 50 cm/s.
+    async fn start_movement_session(
+        ranging_estimator: Box<dyn RangingEstimator>,
+    ) -> (UciSink, UciStream) {
+        let mut pica = Pica::builder().ranging_estimator(ranging_estimator).build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::MovementThreshold,
+                    value: 50u16.to_le_bytes().to_vec(),
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        (sink, stream)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn movement_indicator() {
+        use std::sync::Mutex;
+
+        /// Ranging estimator moving the peers away from the origin
+        /// at 200 cm/s until the trajectory is stopped.
+        struct TrajectoryEstimator {
+            start: time::Instant,
+            stop: Arc<Mutex<Option<time::Instant>>>,
+        }
+
+        impl TrajectoryEstimator {
+            fn distance(&self) -> u16 {
+                let now = self.stop.lock().unwrap().unwrap_or_else(time::Instant::now);
+                100 + (now.duration_since(self.start).as_millis() / 5) as u16
+            }
+        }
+
+        impl RangingEstimator for TrajectoryEstimator {
+            fn estimate(&self, _left: &Handle, _right: &Handle) -> Option<RangingMeasurement> {
+                Some(RangingMeasurement {
+                    range: self.distance(),
+                    azimuth: 0,
+                    elevation: 0,
+                })
+            }
+
+            fn position(&self, _handle: &Handle) -> Option<Position> {
+                Some(Position::new(self.distance() as i16, 0, 0, 0, 0, 0))
+            }
+        }
+
+        let stop = Arc::new(Mutex::new(None));
+        let (_sink, mut stream) = start_movement_session(Box::new(TrajectoryEstimator {
+            start: time::Instant::now(),
+            stop: stop.clone(),
+        }))
+        .await;
+
+        // The peer is stationary until the movement window is complete.
+        for _ in 0..MOVEMENT_WINDOW {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            assert_eq!(movement_indicators(&ntf), vec![(0x0200, 0)]);
+        }
+
+        // The peer moving faster than the threshold is mobile.
+        for _ in 0..2 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            assert_eq!(movement_indicators(&ntf), vec![(0x0200, 1)]);
+        }
+
+        // The peer is stationary once the trajectory is stopped. The round
+        // following the stop still includes part of the trajectory.
+        *stop.lock().unwrap() = Some(time::Instant::now());
+        expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        for _ in 0..2 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            assert_eq!(movement_indicators(&ntf), vec![(0x0200, 0)]);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oscillating_peer() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Ranging estimator swinging the peers from one side of the
+        /// device to the other on every ranging round, at a constant
+        /// distance.
+        struct OscillatingEstimator {
+            rounds: AtomicUsize,
+        }
+
+        impl OscillatingEstimator {
+            fn side(&self) -> i16 {
+                if self.rounds.load(Ordering::SeqCst) % 2 == 0 {
+                    -1
+                } else {
+                    1
+                }
+            }
+        }
+
+        impl RangingEstimator for OscillatingEstimator {
+            fn estimate(&self, _left: &Handle, _right: &Handle) -> Option<RangingMeasurement> {
+                Some(RangingMeasurement {
+                    range: 100,
+                    azimuth: 90 * self.side(),
+                    elevation: 0,
+                })
+            }
+
+            fn position(&self, _handle: &Handle) -> Option<Position> {
+                let position = Position::new(100 * self.side(), 0, 0, 0, 0, 0);
+                self.rounds.fetch_add(1, Ordering::SeqCst);
+                Some(position)
+            }
+        }
+
+        let (_sink, mut stream) = start_movement_session(Box::new(OscillatingEstimator {
+            rounds: AtomicUsize::new(0),
+        }))
+        .await;
+
+        // The peer oscillating at half the ranging rate is back to
+        // the same position at the start of the movement window,
+        // and is stationary.
+        for _ in 0..MOVEMENT_WINDOW + 4 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            assert_eq!(movement_indicators(&ntf), vec![(0x0200, 0)]);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn antenna_pairs() {
+        for antenna_pairs in [1, 2] {
+            let mut pica = Pica::builder()
+                .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                    range: 100,
+                    azimuth: 30,
+                    elevation: 10,
+                })))
+                .antenna_pairs(antenna_pairs)
+                .build();
+            let commands = pica.commands();
+            let (mut sink, mut stream) = pica.connect_local().unwrap();
+            tokio::spawn(pica.run());
+
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short([0x00, 0x02]),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            rsp_rx.await.unwrap().unwrap();
+
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(&mut stream).await;
+            init_session(
+                &mut sink,
+                &mut stream,
+                1,
+                vec![
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::ResultReportConfig, &[0x07]),
+                ],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            assert_eq!(ntf.two_way_ranging_measurements[0].aoa_azimuth, 30);
+
+            // A single antenna pair reports the angle of arrival
+            // in the ranging measurement only.
+            if antenna_pairs == 1 {
+                assert!(ntf.vendor_data.is_empty());
+                continue;
+            }
+
+            // Each antenna pair sees the peer under a slightly different angle.
+            let tlv = VendorDataTlv::decode_full(&ntf.vendor_data).unwrap();
+            assert_eq!(tlv.t, VendorDataTlvType::AntennaPairAoa);
+            assert_eq!(tlv.v.len(), 2 + 4 * antenna_pairs as usize);
+            assert_eq!(tlv.v[0..2], [0x00, 0x02]);
+            let angles = tlv.v[2..]
+                .chunks(4)
+                .map(|chunk| {
+                    (
+                        i16::from_le_bytes([chunk[0], chunk[1]]),
+                        i16::from_le_bytes([chunk[2], chunk[3]]),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_ne!(angles[0].0, angles[1].0);
+            assert!(angles
+                .iter()
+                .all(|(azimuth, elevation)| (*azimuth - 30).abs() <= 3 && *elevation == 10));
+            assert_eq!(angles[0].0 + angles[1].0, 60);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warmup_rounds() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 30,
+                elevation: 0,
+            })))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                tlv(AppConfigTlvType::ResultReportConfig, &[0x0b]),
+                tlv(AppConfigTlvType::WarmupRounds, &[0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The first three reports have a low figure of merit,
+        // the following reports are normal.
+        let mut foms = vec![];
+        for _ in 0..5 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            let measurement = &ntf.two_way_ranging_measurements[0];
+            assert_eq!(measurement.aoa_azimuth, 30);
+            foms.push(measurement.aoa_azimuth_fom);
+        }
+        assert_eq!(foms, vec![10, 10, 10, 100, 100]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hybrid_report_schedule() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 30,
+                elevation: 0,
+            })))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                tlv(AppConfigTlvType::ResultReportConfig, &[0x03]),
+                tlv(AppConfigTlvType::HybridReportSchedule, &[0x01, 0x03]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The reports alternate between two-way ranging measurements
+        // and one-way AoA measurements.
+        for round in 0..4 {
+            let ntf = expect::<SessionInfoNtf>(&mut stream).await;
+            assert_eq!(ntf.sequence_number, round);
+            match ntf.specialize().unwrap() {
+                SessionInfoNtfChild::ShortMacTwoWaySessionInfoNtf(ntf) if round % 2 == 0 => {
+                    let measurement = &ntf.two_way_ranging_measurements[0];
+                    assert_eq!(measurement.distance, 100);
+                    assert_eq!(measurement.aoa_azimuth, 30);
+                }
+                SessionInfoNtfChild::ShortMacOwrAoaSessionInfoNtf(ntf) if round % 2 == 1 => {
+                    let measurement = &ntf.owr_aoa_ranging_measurements[0];
+                    assert_eq!(measurement.mac_address, 0x0200);
+                    assert_eq!(measurement.frame_sequence_number, round as u8);
+                    assert_eq!(measurement.aoa_azimuth, 30);
+                }
+                ntf => panic!("unexpected report in round {}: {:?}", round, ntf),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn key_rotation() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // The key is rotated every other round when enabled,
+        // the STS index is constant otherwise.
+        for (session_id, key_rotation, sts_indexes) in [
+            (1, 0x01, [10, 10, 11, 11, 12, 12]),
+            (2, 0x00, [10, 10, 10, 10, 10, 10]),
+        ] {
+            init_session(
+                &mut sink,
+                &mut stream,
+                session_id,
+                vec![
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::StsConfig, &[0x01]),
+                    tlv(AppConfigTlvType::StsIndex, &[10, 0, 0, 0]),
+                    tlv(AppConfigTlvType::KeyRotation, &[key_rotation]),
+                    tlv(AppConfigTlvType::KeyRotationRate, &[0x01]),
+                ],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+
+            for sts_index in sts_indexes {
+                let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+                assert_eq!(ntf.session_token, session_id);
+                let tlv = VendorDataTlv::decode_full(&ntf.vendor_data).unwrap();
+                assert_eq!(tlv.t, VendorDataTlvType::StsIndex);
+                assert_eq!(tlv.v, u32::to_le_bytes(sts_index));
+            }
+
+            sink.send(SessionStopCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            expect::<SessionStopRsp>(&mut stream).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_active_sessions() {
+        async fn start(sink: &mut UciSink, stream: &mut UciStream, session_id: u32) -> uci::Status {
+            sink.send(SessionStartCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            expect::<SessionStartRsp>(stream).await.status
+        }
+
+        let mut pica = Pica::builder().max_active_sessions(2).build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        for session_id in 1..=3 {
+            init_session(&mut sink, &mut stream, session_id, vec![]).await;
+        }
+
+        assert_eq!(start(&mut sink, &mut stream, 1).await, uci::Status::Ok);
+        assert_eq!(start(&mut sink, &mut stream, 2).await, uci::Status::Ok);
+        assert_eq!(
+            start(&mut sink, &mut stream, 3).await,
+            uci::Status::ErrorMaxSessionsExceeded
+        );
+
+        // Stopping a session frees capacity for another session.
+        sink.send(SessionStopCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<SessionStopRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(start(&mut sink, &mut stream, 3).await, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn raw_trace() {
+        let dir = std::env::temp_dir().join(format!("pica-raw-trace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut pica = Pica::builder()
+            .pcapng_dir(&dir)
+            .trace_format(TraceFormat::Raw)
+            .build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        // Device status notification, reset command, response and
+        // notification.
+        let mut packets = vec![stream.next().await.unwrap()];
+        let cmd = CoreDeviceResetCmd {
+            reset_config: ResetConfig::UwbsReset,
+        }
+        .encode_to_vec()
+        .unwrap();
+        sink.send(cmd.clone()).await.unwrap();
+        packets.push(cmd);
+        packets.push(stream.next().await.unwrap());
+        packets.push(stream.next().await.unwrap());
+
+        let content = std::fs::read(dir.join("device-0.bin")).unwrap();
+        let mut recorded = vec![];
+        let mut offset = 0;
+        while offset < content.len() {
+            let length = u16::from_le_bytes([content[offset], content[offset + 1]]) as usize;
+            recorded.push(content[offset + 2..offset + 2 + length].to_vec());
+            offset += 2 + length;
+        }
+        assert_eq!(recorded, packets);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pcapng_combined() {
+        let path =
+            std::env::temp_dir().join(format!("pica-combined-trace-{}.pcapng", std::process::id()));
+
+        let mut pica = Pica::builder().pcapng_combined(&path).unwrap().build();
+        let (mut sink_0, mut stream_0) = pica.connect_local().unwrap();
+        let (mut sink_1, mut stream_1) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        // Device status notification, reset command, response and
+        // notification of each device.
+        let mut packets = vec![];
+        for (sink, stream) in [(&mut sink_0, &mut stream_0), (&mut sink_1, &mut stream_1)] {
+            let mut device_packets = vec![stream.next().await.unwrap()];
+            let cmd = CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap();
+            sink.send(cmd.clone()).await.unwrap();
+            device_packets.push(cmd);
+            device_packets.push(stream.next().await.unwrap());
+            device_packets.push(stream.next().await.unwrap());
+            packets.push(device_packets);
+        }
+
+        let capture = pcapng::read_capture(&path).unwrap();
+        assert_eq!(capture.interfaces.len(), 2);
+        for (handle, packets) in packets.iter().enumerate() {
+            let interface_id = capture
+                .interfaces
+                .iter()
+                .position(|name| name.as_deref() == Some(&format!("device-{}", handle)))
+                .unwrap() as u32;
+            let recorded = capture
+                .packets
+                .iter()
+                .filter(|(id, _)| *id == interface_id)
+                .map(|(_, packet)| packet.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(&recorded, packets);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn csv_export() {
+        let dir = std::env::temp_dir().join(format!("pica-csv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let clock = Arc::new(MockClock::new(Duration::from_secs(5)));
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 30,
+                elevation: -10,
+            })))
+            .clock(clock.clone())
+            .csv_dir(&dir)
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        for mac_address in [[0x00, 0x02], [0x00, 0x03]] {
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short(mac_address),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            rsp_rx.await.unwrap().unwrap();
+        }
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+
+        // Concurrent sessions ranging with separate anchors.
+        for (session_id, dst_mac_address) in [(1, [0x00, 0x02]), (2, [0x00, 0x03])] {
+            init_session(
+                &mut sink,
+                &mut stream,
+                session_id,
+                vec![
+                    tlv(AppConfigTlvType::DstMacAddress, &dst_mac_address),
+                    tlv(AppConfigTlvType::ResultReportConfig, &[0x07]),
+                ],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+        }
+
+        // Collect the reported rows until both sessions are stopped.
+        let mut rows: HashMap<u32, Vec<String>> = HashMap::new();
+        let mut stopped = 0;
+        while stopped < 2 {
+            // The ranging rounds are triggered by advancing the clock
+            // of the devices whenever the connection is idle.
+            let Ok(packet) = time::timeout(Duration::from_millis(100), stream.next()).await else {
+                clock.advance(Duration::from_millis(200));
+                continue;
+            };
+            let packet = packet.unwrap();
+            if let Ok(ntf) = ShortMacTwoWaySessionInfoNtf::decode_full(&packet) {
+                let session_rows = rows.entry(ntf.session_token).or_default();
+                for measurement in &ntf.two_way_ranging_measurements {
+                    session_rows.push(format!(
+                        "{},{},{},100,30,-10,0,{}",
+                        clock.now().as_micros(),
+                        ntf.sequence_number,
+                        MacAddress::Short(measurement.mac_address.to_le_bytes()),
+                        measurement.rssi
+                    ));
+                }
+                if rows.values().map(Vec::len).sum::<usize>() == 6 {
+                    for session_id in [1, 2] {
+                        sink.send(SessionStopCmd { session_id }.encode_to_vec().unwrap())
+                            .await
+                            .unwrap();
+                    }
+                }
+            } else if let Ok(ntf) = SessionStatusNtf::decode_full(&packet) {
+                if ntf.session_state == SessionState::SessionStateIdle && !rows.is_empty() {
+                    stopped += 1;
+                }
+            }
+        }
+
+        // The files are flushed when the sessions are stopped.
+        for (session_id, peer) in [(1, "00:02"), (2, "00:03")] {
+            let content =
+                std::fs::read_to_string(dir.join(format!("device-0-session-{}.csv", session_id)))
+                    .unwrap();
+            let mut lines = content.lines();
+            assert_eq!(lines.next(), Some(csv::HEADER));
+            let lines = lines.map(str::to_owned).collect::<Vec<_>>();
+            assert!(!lines.is_empty());
+            assert!(lines.iter().all(|line| line.contains(peer)));
+            assert_eq!(lines, rows[&session_id]);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn data_fragmentation() {
+        /// Receive the fragments of the next non-empty data message.
+        async fn next_data_message(stream: &mut UciStream) -> Vec<Vec<u8>> {
+            let mut fragments = vec![];
+            loop {
+                let packet = stream.next().await.unwrap();
+                if uci::parse_message_type(packet[0]) != MessageType::Data {
+                    continue;
+                }
+                let complete = packet[0] & 0x10 == 0;
+                fragments.push(packet);
+                if complete && !reassemble(&fragments).application_data.is_empty() {
+                    return fragments;
+                }
+                if complete {
+                    fragments.clear();
+                }
+            }
+        }
+
+        /// Reassemble the fragments of a data message.
+        fn reassemble(fragments: &[Vec<u8>]) -> DataMessageRcv {
+            let mut packet = fragments[0][..uci::HEADER_SIZE].to_vec();
+            packet[0] &= !0x10;
+            for fragment in fragments {
+                packet.extend_from_slice(&fragment[uci::HEADER_SIZE..]);
+            }
+            let payload_length = (packet.len() - uci::HEADER_SIZE) as u16;
+            packet[2..4].copy_from_slice(&payload_length.to_le_bytes());
+            DataMessageRcv::decode_full(&packet).unwrap()
+        }
+
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .data_mtu(100)
+            .build();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        let (mut peer_sink, mut peer_stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        for (sink, stream, tlvs) in [
+            (
+                &mut sink,
+                &mut stream,
+                vec![
+                    tlv(AppConfigTlvType::DeviceType, &[0x01]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                ],
+            ),
+            (
+                &mut peer_sink,
+                &mut peer_stream,
+                vec![
+                    tlv(AppConfigTlvType::DeviceType, &[0x00]),
+                    tlv(AppConfigTlvType::DeviceRole, &[0x00]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x01]),
+                ],
+            ),
+        ] {
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(stream).await;
+            init_session_with_type(
+                sink,
+                stream,
+                1,
+                SessionType::FiraRangingAndInBandDataSession,
+                tlvs,
+            )
+            .await;
+        }
+
+        // The payload is larger than the data MTU, and is delivered
+        // in three fragments.
+        let application_data = (0..250).map(|n| n as u8).collect::<Vec<_>>();
+        sink.send(
+            DataMessageSnd {
+                session_handle: 1,
+                destination_address: 0x0200,
+                data_sequence_number: 1,
+                pbf: PacketBoundaryFlag::Complete,
+                application_data: application_data.clone(),
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        for sink in [&mut peer_sink, &mut sink] {
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+        }
+
+        let fragments = next_data_message(&mut peer_stream).await;
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments[..2]
+            .iter()
+            .all(|fragment| fragment[0] & 0x10 != 0));
+        assert!(fragments
+            .iter()
+            .all(|fragment| fragment.len() <= uci::HEADER_SIZE + 100));
+        assert_eq!(reassemble(&fragments).application_data, application_data);
+
+        // A payload fitting in a single fragment does not set the
+        // packet boundary flag.
+        sink.send(
+            DataMessageSnd {
+                session_handle: 1,
+                destination_address: 0x0200,
+                data_sequence_number: 2,
+                pbf: PacketBoundaryFlag::Complete,
+                application_data: vec![0xaa; 20],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let fragments = next_data_message(&mut peer_stream).await;
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(fragments[0][0] & 0x10, 0);
+        assert_eq!(reassemble(&fragments).application_data, vec![0xaa; 20]);
+    }
+
+    #[tokio::test]
+    async fn client_handles() {
+        /// Open a connection, and send the client id handshake if selected.
+        async fn connect(
+            commands: &mpsc::Sender<PicaCommand>,
+            client_id: Option<u32>,
+        ) -> (UciSink, UciStream) {
+            let (host_tx, device_rx) = futures::channel::mpsc::unbounded();
+            let (device_tx, host_rx) = futures::channel::mpsc::unbounded();
+            commands
+                .send(PicaCommand::Connect(
+                    Box::pin(device_rx),
+                    Box::pin(device_tx.sink_map_err(anyhow::Error::from)),
+                ))
+                .await
+                .unwrap();
+            let mut sink: UciSink = Box::pin(host_tx.sink_map_err(anyhow::Error::from));
+            if let Some(client_id) = client_id {
+                sink.send(PicaSetClientIdCmd { client_id }.encode_to_vec().unwrap())
+                    .await
+                    .unwrap();
+            }
+            (sink, Box::pin(host_rx))
+        }
+
+        async fn connected(events: &mut broadcast::Receiver<PicaEvent>) -> Handle {
+            match events.recv().await {
+                Ok(PicaEvent::Connected { handle, .. }) => handle,
+                _ => panic!("expected connected event"),
+            }
+        }
+
+        let pica = Pica::builder()
+            .client_handles(HashMap::from([(0x1234, 7), (0x5678, 3), (0x9abc, 7)]))
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        tokio::spawn(pica.run());
+
+        // Handles are assigned from the client id, regardless
+        // of the connection order.
+        let (_sink, mut stream) = connect(&commands, Some(0x5678)).await;
+        let rsp = expect::<PicaSetClientIdRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(connected(&mut events).await, 3);
+
+        let (_other_sink, mut other_stream) = connect(&commands, Some(0x1234)).await;
+        let rsp = expect::<PicaSetClientIdRsp>(&mut other_stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert_eq!(connected(&mut events).await, 7);
+
+        // Clients without handshake are allocated the first
+        // handle not reserved by the mapping.
+        let (mut sink, mut stream) = connect(&commands, None).await;
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        assert_eq!(connected(&mut events).await, 0);
+
+        // A client id mapped to a handle in use is rejected.
+        let (_sink, mut stream) = connect(&commands, Some(0x9abc)).await;
+        let rsp = stream.next().await.unwrap();
+        assert_eq!(rsp[3] as usize, rsp.len() - HEADER_SIZE);
+        let rsp = PicaSetClientIdRsp::decode_full(&rsp).unwrap();
+        assert_eq!(rsp.status, uci::Status::Rejected);
+        assert!(stream.next().await.is_none());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handshake_timeout() {
+        let pica = Pica::builder()
+            .client_handles(HashMap::from([(0x1234, 7)]))
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        tokio::spawn(pica.run());
+
+        // Connections left silent are closed after the timeout.
+        let (host_tx, device_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+        let (device_tx, host_rx) = futures::channel::mpsc::unbounded();
+        commands
+            .send(PicaCommand::Connect(
+                Box::pin(device_rx),
+                Box::pin(device_tx.sink_map_err(anyhow::Error::from)),
+            ))
+            .await
+            .unwrap();
+        let mut stream: UciStream = Box::pin(host_rx);
+        let start = time::Instant::now();
+        assert!(stream.next().await.is_none());
+        assert!(start.elapsed() >= HANDSHAKE_TIMEOUT);
+        assert!(events.try_recv().is_err());
+        drop(host_tx);
+    }
+
+    #[tokio::test]
+    async fn auth_token() {
+        /// Open a connection, and send the selected packet.
+        async fn connect(
+            commands: &mpsc::Sender<PicaCommand>,
+            packet: Vec<u8>,
+        ) -> (UciSink, UciStream) {
+            let (host_tx, device_rx) = futures::channel::mpsc::unbounded();
+            let (device_tx, host_rx) = futures::channel::mpsc::unbounded();
+            commands
+                .send(PicaCommand::Connect(
+                    Box::pin(device_rx),
+                    Box::pin(device_tx.sink_map_err(anyhow::Error::from)),
+                ))
+                .await
+                .unwrap();
+            let mut sink: UciSink = Box::pin(host_tx.sink_map_err(anyhow::Error::from));
+            sink.send(packet).await.unwrap();
+            (sink, Box::pin(host_rx))
+        }
+
+        // Empty tokens would be matched by any host.
+        assert_eq!(
+            Pica::builder().auth_token(vec![]).err(),
+            Some(PicaError::EmptyAuthToken)
+        );
+
+        let pica = Pica::builder()
+            .auth_token(b"secret".to_vec())
+            .unwrap()
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        tokio::spawn(pica.run());
+
+        let authenticate = |token: &[u8]| {
+            PicaAuthenticateCmd {
+                token: token.to_vec(),
+            }
+            .encode_to_vec()
+            .unwrap()
+        };
+        let reset = CoreDeviceResetCmd {
+            reset_config: ResetConfig::UwbsReset,
+        }
+        .encode_to_vec()
+        .unwrap();
+
+        // Connections with an invalid token are rejected.
+        for token in [&b"secreT"[..], b"secrets", b""] {
+            let (_sink, mut stream) = connect(&commands, authenticate(token)).await;
+            let rsp = expect::<PicaAuthenticateRsp>(&mut stream).await;
+            assert_eq!(rsp.status, uci::Status::Rejected);
+            assert!(stream.next().await.is_none());
+        }
+
+        // Connections without authentication are closed.
+        let (_sink, mut stream) = connect(&commands, reset.clone()).await;
+        assert!(stream.next().await.is_none());
+        assert!(events.try_recv().is_err());
+
+        // The device is created once the host is authenticated.
+        let (mut sink, mut stream) = connect(&commands, authenticate(b"secret")).await;
+        let rsp = expect::<PicaAuthenticateRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+        sink.send(reset).await.unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        assert!(matches!(
+            events.recv().await,
+            Ok(PicaEvent::Connected { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn auth_timeout() {
+        let pica = Pica::builder()
+            .auth_token(b"secret".to_vec())
+            .unwrap()
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        tokio::spawn(pica.run());
+
+        // Connections left silent are closed after the timeout.
+        let (host_tx, device_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+        let (device_tx, host_rx) = futures::channel::mpsc::unbounded();
+        commands
+            .send(PicaCommand::Connect(
+                Box::pin(device_rx),
+                Box::pin(device_tx.sink_map_err(anyhow::Error::from)),
+            ))
+            .await
+            .unwrap();
+        let mut stream: UciStream = Box::pin(host_rx);
+        let start = time::Instant::now();
+        assert!(stream.next().await.is_none());
+        assert!(start.elapsed() >= AUTH_TIMEOUT);
+        assert!(events.try_recv().is_err());
+        drop(host_tx);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notification_sink() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        let (notification_tx, notification_rx) = futures::channel::mpsc::unbounded();
+        let mut notifications: UciStream = Box::pin(notification_rx);
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::SetNotificationSink {
+                handle,
+                sink: Box::pin(notification_tx.sink_map_err(anyhow::Error::from)),
+                rsp_tx,
+            })
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        expect::<SessionStartRsp>(&mut stream).await;
+
+        // Ranging notifications are delivered to the notification sink.
+        for _ in 0..2 {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut notifications).await;
+            assert_eq!(ntf.two_way_ranging_measurements.len(), 1);
+        }
+
+        // Command responses remain on the device connection.
+        sink.send(SessionStopCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        loop {
+            let packet = stream.next().await.unwrap();
+            assert!(!is_routed_notification(&packet));
+            if SessionStopRsp::decode_full(&packet).is_ok() {
+                break;
+            }
+        }
+        loop {
+            let ntf = expect::<SessionStatusNtf>(&mut notifications).await;
+            if ntf.session_state == SessionState::SessionStateIdle {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_mac_address() {
+        let truth = RangingMeasurement {
+            range: 100,
+            azimuth: 0,
+            elevation: 0,
+        };
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(truth)))
+            .build();
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink_a, mut stream_a) = pica.connect_local().unwrap();
+        let (mut sink_b, mut stream_b) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let mut handles = vec![];
+        for _ in 0..2 {
+            let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+                panic!("expected connected event");
+            };
+            handles.push(handle);
+        }
+        let (handle_a, handle_b) = (handles[0], handles[1]);
+
+        let set_mac_address = |handle, address| {
+            let commands = commands.clone();
+            async move {
+                let (rsp_tx, rsp_rx) = oneshot::channel();
+                commands
+                    .send(PicaCommand::SetMacAddress {
+                        handle,
+                        address,
+                        rsp_tx,
+                    })
+                    .await
+                    .unwrap();
+                rsp_rx.await.unwrap()
+            }
+        };
+
+        let mac_a = MacAddress::Short([0x12, 0x34]);
+        assert_eq!(set_mac_address(handle_a, mac_a).await, Ok(()));
+        assert_eq!(
+            set_mac_address(handle_b, mac_a).await,
+            Err(PicaError::DeviceAlreadyExists(mac_a))
+        );
+        assert_eq!(
+            set_mac_address(42, MacAddress::Short([0x00, 0x42])).await,
+            Err(PicaError::HandleNotFound(42))
+        );
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            PicaEvent::MacAddressChanged { handle, mac_address, .. }
+                if handle == handle_a && mac_address == mac_a
+        ));
+
+        // The device is looked up by its assigned address.
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::SetTemperature(mac_a, 30, rsp_tx))
+            .await
+            .unwrap();
+        assert_eq!(rsp_rx.await.unwrap(), Ok(handle_a));
+
+        // The host of device A configures the assigned address,
+        // device B ranges with device A.
+        for (sink, stream, tlvs) in [
+            (
+                &mut sink_a,
+                &mut stream_a,
+                vec![
+                    tlv(AppConfigTlvType::DeviceType, &[0x00]),
+                    tlv(AppConfigTlvType::DeviceRole, &[0x00]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x12, 0x34]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                ],
+            ),
+            (
+                &mut sink_b,
+                &mut stream_b,
+                vec![
+                    tlv(AppConfigTlvType::DeviceType, &[0x01]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x12, 0x34]),
+                ],
+            ),
+        ] {
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(stream).await;
+            init_session(sink, stream, 1, tlvs).await;
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            let rsp = expect::<SessionStartRsp>(stream).await;
+            assert_eq!(rsp.status, uci::Status::Ok);
+        }
+
+        let ntf = loop {
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream_b).await;
+            if !ntf.two_way_ranging_measurements.is_empty() {
+                break ntf;
+            }
+        };
+        assert_eq!(
+            ntf.two_way_ranging_measurements[0].mac_address,
+            u16::from_le_bytes([0x12, 0x34])
+        );
+        assert!(get_ground_truth(&commands, 1)
+            .await
+            .iter()
+            .any(|ground_truth| ground_truth.mac_address == mac_a));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ranging_offset() {
+        let truth = RangingMeasurement {
+            range: 100,
+            azimuth: 0,
+            elevation: 0,
+        };
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(truth)))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::RangingOffset,
+                    value: 50i16.to_le_bytes().to_vec(),
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The offset is added to the reported distance,
+        // the ground truth is the geometric distance.
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(ntf.two_way_ranging_measurements[0].distance, 150);
+        assert_eq!(get_ground_truth(&commands, 1).await[0].measurement, truth);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn negative_distance() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .allow_negative_distance(true)
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::RangingOffset,
+                    value: (-150i16).to_le_bytes().to_vec(),
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The raw negative distance is reported in two's complement.
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(ntf.two_way_ranging_measurements[0].distance as i16, -50);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn colocated_devices() {
+        // Co-location is decided on the ground truth distance, regardless
+        // of the ranging offset added to the reported distance.
+        for offset in [0i16, 50] {
+            // Ranging estimation of two devices sharing the same position.
+            let mut pica = Pica::builder()
+                .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                    range: 0,
+                    azimuth: 0,
+                    elevation: 0,
+                })))
+                .noise_model(NoiseModel { aoa_sigma: 20. })
+                .build();
+            let commands = pica.commands();
+            let (mut sink, mut stream) = pica.connect_local().unwrap();
+            tokio::spawn(pica.run());
+
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short([0x00, 0x02]),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            rsp_rx.await.unwrap().unwrap();
+
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(&mut stream).await;
+            sink.send(
+                CoreSetConfigCmd {
+                    parameters: vec![ConfigParameter {
+                        id: ConfigParameterId::RangingOffset,
+                        value: offset.to_le_bytes().to_vec(),
+                    }],
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+            assert_eq!(rsp.status, uci::Status::Ok);
+            init_session(
+                &mut sink,
+                &mut stream,
+                1,
+                vec![
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::ResultReportConfig, &[0x0f]),
+                ],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+
+            // The distance is valid, the angles of arrival are failed.
+            for _ in 0..3 {
+                let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+                let measurement = &ntf.two_way_ranging_measurements[0];
+                assert_eq!(measurement.status, uci::Status::Ok);
+                assert_eq!(measurement.distance, offset as u16);
+                assert_eq!(measurement.aoa_azimuth, 0);
+                assert_eq!(measurement.aoa_azimuth_fom, 0);
+                assert_eq!(measurement.aoa_elevation, 0);
+                assert_eq!(measurement.aoa_elevation_fom, 0);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn destination_aoa_ranging_method() {
+        // Return the first measurement reported with the selected
+        // ranging round usage.
+        async fn measurement(ranging_round_usage: u8) -> ShortAddressTwoWayRangingMeasurement {
+            let mut pica = Pica::builder()
+                .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                    range: 100,
+                    azimuth: 45,
+                    elevation: -10,
+                })))
+                .build();
+            let commands = pica.commands();
+            let (mut sink, mut stream) = pica.connect_local().unwrap();
+            tokio::spawn(pica.run());
+
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short([0x00, 0x02]),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            rsp_rx.await.unwrap().unwrap();
+
+            sink.send(
+                CoreDeviceResetCmd {
+                    reset_config: ResetConfig::UwbsReset,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            expect::<CoreDeviceResetRsp>(&mut stream).await;
+            init_session(
+                &mut sink,
+                &mut stream,
+                1,
+                vec![
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[ranging_round_usage]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::ResultReportConfig, &[0x0f]),
+                ],
+            )
+            .await;
+            sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+                .await
+                .unwrap();
+            let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+            ntf.two_way_ranging_measurements[0].clone()
+        }
+
+        // The peer angles of arrival are sent in the measurement report
+        // phase with DS-TWR deferred.
+        let deferred = measurement(0x02).await;
+        assert_eq!(deferred.aoa_destination_azimuth, 45);
+        assert_ne!(deferred.aoa_destination_azimuth_fom, 0);
+
+        // The peer angles of arrival are sent with the final message
+        // with DS-TWR non-deferred.
+        let non_deferred = measurement(0x04).await;
+        assert_eq!(non_deferred.aoa_destination_azimuth, 45);
+        assert_ne!(non_deferred.aoa_destination_azimuth_fom, 0);
+
+        // The response message of SS-TWR non-deferred does not carry
+        // the peer angles of arrival.
+        let single_sided = measurement(0x03).await;
+        assert_eq!(single_sided.distance, 100);
+        assert_eq!(single_sided.aoa_destination_azimuth, 0);
+        assert_eq!(single_sided.aoa_destination_azimuth_fom, 0);
+        assert_eq!(single_sided.aoa_destination_elevation, 0);
+        assert_eq!(single_sided.aoa_destination_elevation_fom, 0);
+    }
+
+    #[tokio::test]
+    async fn set_positions() {
+        // Ranging estimator recording the batches of positions.
+        #[derive(Clone, Default)]
+        struct PositionRecorder(Arc<std::sync::Mutex<Vec<Vec<(Handle, Position)>>>>);
+
+        impl RangingEstimator for PositionRecorder {
+            fn estimate(&self, _left: &Handle, _right: &Handle) -> Option<RangingMeasurement> {
+                None
+            }
+
+            fn set_positions(&self, positions: &[(Handle, Position)]) {
+                self.0.lock().unwrap().push(positions.to_vec());
+            }
+        }
+
+        let recorder = PositionRecorder::default();
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(recorder.clone()))
+            .build();
+        let commands = pica.commands();
+        tokio::spawn(pica.run());
+
+        let mut positions = vec![];
+        for index in 0..10 {
+            let (rsp_tx, rsp_rx) = oneshot::channel();
+            commands
+                .send(PicaCommand::CreateAnchor(
+                    MacAddress::Short([0x01, index]),
+                    rsp_tx,
+                ))
+                .await
+                .unwrap();
+            let handle = rsp_rx.await.unwrap().unwrap();
+            positions.push((handle, Position::new(100 * index as i16, 0, 0, 0, 0, 0)));
+        }
+
+        // The unknown handle is reported, the other positions are
+        // applied in a single batch.
+        let mut batch = positions.clone();
+        batch.insert(5, (1000, Position::default()));
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::SetPositions(batch, rsp_tx))
+            .await
+            .unwrap();
+        assert_eq!(rsp_rx.await.unwrap(), vec![PicaError::HandleNotFound(1000)]);
+        assert_eq!(*recorder.0.lock().unwrap(), vec![positions]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn get_timers() {
+        // The ranging and idle timeout timers of an active device are
+        // listed with their deadlines read from the clock of the devices.
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1)));
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(NullRangingEstimator()))
+            .idle_timeout(Duration::from_secs(10))
+            .clock(clock.clone())
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        expect::<SessionStartRsp>(&mut stream).await;
+        // The ranging rounds are triggered by the clock of the devices.
+        clock.advance(Duration::from_millis(200));
+        expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands.send(PicaCommand::GetTimers(rsp_tx)).await.unwrap();
+        let timers = rsp_rx.await.unwrap();
+        assert_eq!(timers.len(), 2);
+        assert!(timers.contains(&TimerInfo {
+            kind: TimerKind::IdleTimeout,
+            handle: 0,
+            session_id: None,
+            deadline: Duration::from_secs(11),
+        }));
+        assert!(timers.contains(&TimerInfo {
+            kind: TimerKind::Ranging,
+            handle: 0,
+            session_id: Some(1),
+            deadline: Duration::from_millis(1400),
+        }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_reported_distance() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 1500,
+                azimuth: 0,
+                elevation: 0,
+            })))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::MaxReportedDistance,
+                    value: 1000u16.to_le_bytes().to_vec(),
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![
+                tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                tlv(AppConfigTlvType::MaxRrRetry, &[0x03, 0x00]),
+            ],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // The peer is out of the reported range.
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        let measurement = &ntf.two_way_ranging_measurements[0];
+        assert_eq!(measurement.mac_address, 0x0200);
+        assert_eq!(measurement.status, uci::Status::RangingRxTimeout);
+        assert_eq!(measurement.distance, 0);
+
+        // The rounds with the peer out of the reported range failed,
+        // and the session is stopped after MAX_RR_RETRY rounds.
+        let mut failed_rounds = 1;
+        let ntf = loop {
+            let packet = stream.next().await.unwrap();
+            if ShortMacTwoWaySessionInfoNtf::decode_full(&packet).is_ok() {
+                failed_rounds += 1;
+            } else if let Ok(ntf) = SessionStatusNtf::decode_full(&packet) {
+                if ntf.session_state == SessionState::SessionStateIdle {
+                    break ntf;
+                }
+            }
+        };
+        assert_eq!(failed_rounds, 3);
+        assert_eq!(
+            ntf.reason_code,
+            u8::from(ReasonCode::MaxRangingRoundRetryCountReached)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalescing_window() {
+        use std::sync::atomic::{AtomicU16, Ordering};
+
+        /// Ranging estimator moving the peer away by 100 cm each round,
+        /// starting from 100 cm.
+        struct MovingEstimator(AtomicU16);
+
+        impl RangingEstimator for MovingEstimator {
+            fn estimate(&self, _left: &Handle, _right: &Handle) -> Option<RangingMeasurement> {
+                // The local and remote measurements are estimated
+                // for each round.
+                let count = self.0.fetch_add(1, Ordering::Relaxed);
+                Some(RangingMeasurement {
+                    range: 100 * (count / 2 + 1),
+                    azimuth: 0,
+                    elevation: 0,
+                })
+            }
+        }
+
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(MovingEstimator(AtomicU16::new(0))))
+            .build();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(
+            CoreSetConfigCmd {
+                parameters: vec![ConfigParameter {
+                    id: ConfigParameterId::CoalescingWindow,
+                    value: vec![3],
+                }],
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        let rsp = expect::<CoreSetConfigRsp>(&mut stream).await;
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        init_session(
+            &mut sink,
+            &mut stream,
+            1,
+            vec![tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02])],
+        )
+        .await;
+        sink.send(SessionStartCmd { session_id: 1 }.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+
+        // One report is sent every three rounds, with the mean distance
+        // of the rounds of the window.
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(ntf.sequence_number, 0);
+        assert_eq!(ntf.two_way_ranging_measurements.len(), 1);
+        assert_eq!(ntf.two_way_ranging_measurements[0].distance, 200);
+        let ntf = expect::<ShortMacTwoWaySessionInfoNtf>(&mut stream).await;
+        assert_eq!(ntf.sequence_number, 1);
+        assert_eq!(ntf.two_way_ranging_measurements[0].distance, 500);
+    }
+
+    #[tokio::test]
+    async fn ranging_jitter_not_finite() {
+        for jitter in [f32::NAN, f32::INFINITY] {
+            assert_eq!(
+                Pica::builder().ranging_jitter(jitter, 0).err(),
+                Some(PicaError::InvalidRangingJitter)
+            );
+        }
+        let pica = Pica::builder().ranging_jitter(0.1, 0).unwrap().build();
+        assert_eq!(pica.device_options.ranging_jitter, 0.1);
+    }
+
+    #[test]
+    fn vendor_data_tlvs_split() {
+        // 15 peers with 4 antenna pairs exceed the maximum length
+        // of a TLV value: the records are split across two TLVs.
+        let records = (0..15u8).map(|peer| vec![peer; 18]);
+        let vendor_data = make_vendor_data_tlvs(VendorDataTlvType::AntennaPairAoa, records);
+        let mut buf = vendor_data.as_slice();
+        let first = VendorDataTlv::decode_mut(&mut buf).unwrap();
+        let second = VendorDataTlv::decode_full(buf).unwrap();
+        assert_eq!(first.t, VendorDataTlvType::AntennaPairAoa);
+        assert_eq!(second.t, VendorDataTlvType::AntennaPairAoa);
+        assert_eq!(first.v.len(), 14 * 18);
+        assert_eq!(second.v.len(), 18);
+        assert_eq!(second.v, [14; 18]);
+
+        // The TLV is reported with an empty value without records.
+        let vendor_data = make_vendor_data_tlvs(VendorDataTlvType::TwrTiming, []);
+        assert_eq!(vendor_data, [VendorDataTlvType::TwrTiming.into(), 0x00]);
+    }
+
+    #[test]
+    fn unavailable_measurement_extended_address() {
+        let measurement = make_unavailable_measurement(&MacAddress::Short([1, 2])).unwrap();
+        assert_eq!(measurement.mac_address, 0x0201);
+        assert_eq!(measurement.status, uci::Status::RangingRxTimeout);
+        // Extended addresses cannot be reported in the short
+        // address measurement list, the peer is skipped.
+        assert!(make_unavailable_measurement(&MacAddress::Extended([0; 8])).is_none());
+    }
+
+    #[test]
+    fn coalesce_measurements() {
+        let measurement =
+            |mac_address: u16, distance: u16, azimuth: i16| ShortAddressTwoWayRangingMeasurement {
+                distance,
+                aoa_azimuth: azimuth as u16,
+                aoa_azimuth_fom: 100,
+                ..make_unavailable_measurement(&MacAddress::Short(mac_address.to_le_bytes()))
+                    .unwrap()
+            };
+        let valid = |mac_address, distance, azimuth| ShortAddressTwoWayRangingMeasurement {
+            status: uci::Status::Ok,
+            ..measurement(mac_address, distance, azimuth)
+        };
+
+        // Angles are averaged on the circle, invalid measurements
+        // are ignored.
+        let measurements = [
+            valid(1, 100, 170),
+            valid(2, 300, 10),
+            measurement(1, 1000, 0),
+            valid(1, 200, -170),
+            valid(2, 400, 30),
+        ];
+        let coalesced = super::coalesce_measurements(measurements.iter());
+        assert_eq!(coalesced.len(), 2);
+        assert_eq!(coalesced[0].mac_address, 1);
+        assert_eq!(coalesced[0].distance, 150);
+        assert_eq!(coalesced[0].aoa_azimuth as i16, 180);
+        assert_eq!(coalesced[0].aoa_azimuth_fom, 100);
+        assert_eq!(coalesced[1].mac_address, 2);
+        assert_eq!(coalesced[1].distance, 350);
+        assert_eq!(coalesced[1].aoa_azimuth as i16, 20);
+
+        // Windows without valid measurements are not reported.
+        let measurements = [measurement(1, 100, 0), measurement(2, 200, 0)];
+        assert!(super::coalesce_measurements(measurements.iter()).is_empty());
+    }
+
+    #[test]
+    fn reported_distance_limits() {
+        let measurement = |range| RangingMeasurement {
+            range,
+            azimuth: 0,
+            elevation: 0,
+        };
+        let limits = (Some(20), Some(1000));
+        assert_eq!(
+            with_reported_distance_limits(measurement(500), limits, false),
+            Some(measurement(500))
+        );
+        assert_eq!(
+            with_reported_distance_limits(measurement(10), limits, false),
+            Some(measurement(20))
+        );
+        assert_eq!(
+            with_reported_distance_limits(measurement(1500), limits, false),
+            None
+        );
+        // Negative distances are below the minimum.
+        assert_eq!(
+            with_reported_distance_limits(measurement(-50i16 as u16), limits, true),
+            Some(measurement(20))
+        );
+        assert_eq!(
+            with_reported_distance_limits(measurement(-50i16 as u16), (None, Some(1000)), true),
+            Some(measurement(-50i16 as u16))
+        );
+    }
+
+    #[test]
+    fn ranging_offset_saturation() {
+        let measurement = RangingMeasurement {
+            range: 100,
+            azimuth: 0,
+            elevation: 0,
+        };
+        assert_eq!(with_ranging_offset(measurement, 50, false).range, 150);
+        assert_eq!(with_ranging_offset(measurement, -200, false).range, 0);
+        assert_eq!(
+            with_ranging_offset(
+                RangingMeasurement {
+                    range: u16::MAX,
+                    ..measurement
+                },
+                1,
+                false
+            )
+            .range,
+            u16::MAX
+        );
+        // Negative distances are encoded in two's complement when allowed.
+        assert_eq!(
+            with_ranging_offset(measurement, -200, true).range as i16,
+            -100
+        );
+    }
+
+    #[tokio::test]
+    async fn override_capability() {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), None);
+        let mut events = pica.events();
+        let commands = pica.commands();
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let Ok(PicaEvent::Connected { handle, .. }) = events.recv().await else {
+            panic!("expected connected event");
+        };
+        let override_capability = |id, value| {
+            let commands = commands.clone();
+            async move {
+                let (rsp_tx, rsp_rx) = oneshot::channel();
+                commands
+                    .send(PicaCommand::OverrideCapability {
+                        handle,
+                        id,
+                        value,
+                        rsp_tx,
+                    })
+                    .await
+                    .unwrap();
+                rsp_rx.await.unwrap()
+            }
+        };
+
+        assert_eq!(
+            override_capability(0x50, vec![0x01, 0x02, 0x03]).await,
+            Ok(())
+        );
+        assert_eq!(override_capability(0x0b, vec![0x20]).await, Ok(()));
+        assert_eq!(
+            override_capability(0x51, vec![0; 256]).await,
+            Err(PicaError::InvalidCapabilityLength(0x51))
+        );
+
+        // The overrides are preserved across resets.
+        sink.send(
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+        expect::<CoreDeviceResetRsp>(&mut stream).await;
+        sink.send(CoreGetCapsInfoCmd {}.encode_to_vec().unwrap())
+            .await
+            .unwrap();
+        let rsp = expect::<CoreGetCapsInfoRsp>(&mut stream).await;
+        let caps = rsp
+            .tlvs
+            .iter()
+            .map(|tlv| (u8::from(tlv.t), tlv.v.clone()))
+            .collect::<Vec<_>>();
+
+        // The unknown type is reported verbatim, the known type
+        // replaces the default value.
+        assert!(caps.contains(&(0x50, vec![0x01, 0x02, 0x03])));
+        assert!(caps.contains(&(0x0b, vec![0x20])));
+        assert_eq!(caps.iter().filter(|(id, _)| *id == 0x0b).count(), 1);
+        assert!(!caps.iter().any(|(id, _)| *id == 0x51));
+    }
+
+    #[test]
+    fn measurement_without_aoa() {
+        let measurement = RangingMeasurement {
+            range: 100,
+            azimuth: 45,
+            elevation: -10,
+        };
+        let report_config = ResultReportConfig::try_from(0x01).unwrap();
+        let report = make_measurement(
+            &MacAddress::Short([0, 1]),
+            measurement,
+            measurement,
+            false,
+            &report_config,
+            100,
+            AntennaConfig::default(),
+            AntennaConfig::default(),
+            ColocatedReport::default(),
+        );
+
+        assert_eq!(report.distance, 100);
+        assert_eq!(report.aoa_azimuth, 0);
+        assert_eq!(report.aoa_azimuth_fom, 0);
+        assert_eq!(report.aoa_elevation, 0);
+        assert_eq!(report.aoa_elevation_fom, 0);
+        assert_eq!(report.aoa_destination_azimuth, 0);
+        assert_eq!(report.aoa_destination_elevation, 0);
+    }
+
+    #[test]
+    fn aoa_fom() {
+        let measurement = RangingMeasurement {
+            range: 100,
+            azimuth: 45,
+            elevation: -10,
+        };
+        let report_config = ResultReportConfig::try_from(0x0f).unwrap();
+        let report = |aoa_sigma| {
+            make_measurement(
+                &MacAddress::Short([0, 1]),
+                measurement,
+                measurement,
+                false,
+                &report_config,
+                NoiseModel { aoa_sigma }.aoa_fom(),
+                AntennaConfig::default(),
+                AntennaConfig::default(),
+                ColocatedReport::default(),
+            )
+        };
+
+        let low_noise = report(1.);
+        let high_noise = report(20.);
+        assert_eq!(low_noise.status, uci::Status::Ok);
+        assert_eq!(high_noise.status, uci::Status::Ok);
+        assert!(high_noise.aoa_azimuth_fom < low_noise.aoa_azimuth_fom);
+        assert!(high_noise.aoa_elevation_fom < low_noise.aoa_elevation_fom);
+    }
+
+    #[test]
+    fn aoa_unavailable() {
+        let measurement = RangingMeasurement {
+            range: 0,
+            azimuth: 0,
+            elevation: 0,
+        };
+        let report_config = ResultReportConfig::try_from(0x0f).unwrap();
+        let report = |colocated_report| {
+            make_measurement(
+                &MacAddress::Short([0, 1]),
+                measurement,
+                measurement,
+                true,
+                &report_config,
+                100,
+                AntennaConfig::default(),
+                AntennaConfig::default(),
+                colocated_report,
+            )
+        };
+
+        // The whole measurement is reported as failed.
+        let failed = report(ColocatedReport::Failed);
+        assert_eq!(failed.status, uci::Status::Failed);
+        assert_eq!(failed.aoa_azimuth_fom, 0);
+        assert_eq!(failed.aoa_elevation_fom, 0);
+        assert_eq!(failed.aoa_destination_azimuth_fom, 0);
+        assert_eq!(failed.aoa_destination_elevation_fom, 0);
+
+        // Only the angles of arrival are reported as failed.
+        let aoa_failed = report(ColocatedReport::AoaFailed);
+        assert_eq!(aoa_failed.status, uci::Status::Ok);
+        assert_eq!(aoa_failed.distance, 0);
+        assert_eq!(aoa_failed.aoa_azimuth_fom, 0);
+        assert_eq!(aoa_failed.aoa_elevation_fom, 0);
+        assert_eq!(aoa_failed.aoa_destination_azimuth_fom, 0);
+        assert_eq!(aoa_failed.aoa_destination_elevation_fom, 0);
+    }
+
+    #[test]
+    fn aoa_antenna_config() {
+        let measurement = RangingMeasurement {
+            range: 100,
+            azimuth: 45,
+            elevation: -10,
+        };
+        // Request both azimuth and elevation from an azimuth-only device.
+        let report_config = ResultReportConfig::try_from(0x0f).unwrap();
+        let report = make_measurement(
+            &MacAddress::Short([0, 1]),
+            measurement,
+            measurement,
+            false,
+            &report_config,
+            100,
+            AntennaConfig::Azimuth,
+            AntennaConfig::AzimuthElevation,
+            ColocatedReport::default(),
+        );
+
+        assert_eq!(report.status, uci::Status::Ok);
+        assert_eq!(report.aoa_azimuth, 45);
+        assert_eq!(report.aoa_azimuth_fom, 100);
+        assert_eq!(report.aoa_elevation, 0);
+        assert_eq!(report.aoa_elevation_fom, 0);
+        assert_eq!(report.aoa_destination_azimuth, 45);
+        assert_eq!(report.aoa_destination_elevation, -10i8 as u16);
+    }
+}
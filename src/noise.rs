@@ -0,0 +1,181 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use crate::{MacAddress, RangingMeasurement};
+
+/// Noise applied to the ranging measurements generated
+/// by the ranging estimator.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NoiseModel {
+    /// Standard deviation of the Gaussian noise applied to the
+    /// azimuth and elevation angles, in degrees.
+    pub aoa_sigma: f32,
+}
+
+impl NoiseModel {
+    /// Figure of merit of the AoA measurements, in the range 0-100.
+    /// The figure of merit is 100 for noise-free measurements, and
+    /// decreases as the noise increases: it is halved for a standard
+    /// deviation of 10 degrees.
+    pub fn aoa_fom(&self) -> u8 {
+        (100. / (1. + self.aoa_sigma.max(0.) / 10.)).round() as u8
+    }
+
+    /// Apply the noise to the angles of the input measurement.
+    pub fn apply(&self, rng: &mut Rng, measurement: RangingMeasurement) -> RangingMeasurement {
+        if self.aoa_sigma <= 0. {
+            return measurement;
+        }
+
+        let azimuth = measurement.azimuth as f32 + self.aoa_sigma * rng.gaussian();
+        let elevation = measurement.elevation as f32 + self.aoa_sigma * rng.gaussian();
+        RangingMeasurement {
+            range: measurement.range,
+            azimuth: ((azimuth + 180.).rem_euclid(360.) - 180.).round() as i16,
+            elevation: elevation.clamp(-90., 90.).round() as i8,
+        }
+    }
+}
+
+/// Pseudo random number generator (SplitMix64).
+/// The generator is deterministic to make the noise reproducible.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Return a sample uniformly distributed in (0, 1].
+    pub fn uniform(&mut self) -> f32 {
+        ((self.next_u64() >> 40) + 1) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Return a sample of the standard normal distribution,
+    /// generated with the Box-Muller transform.
+    pub fn gaussian(&mut self) -> f32 {
+        let u1 = self.uniform();
+        let u2 = self.uniform();
+        (-2. * u1.ln()).sqrt() * (2. * std::f32::consts::PI * u2).cos()
+    }
+}
+
+/// Random number generators of the device pairs. The generator of a pair
+/// is seeded from the global seed and the sorted pair of mac addresses,
+/// so that the noise sequence of a pair does not depend on the order
+/// in which the pairs are ranged.
+#[derive(Debug, Clone, Default)]
+pub struct PairRngs {
+    seed: u64,
+    rngs: HashMap<(u64, u64), Rng>,
+}
+
+impl PairRngs {
+    pub fn new(seed: u64) -> Self {
+        PairRngs {
+            seed,
+            rngs: Default::default(),
+        }
+    }
+
+    /// Return the generator of the pair of devices identified
+    /// by their mac addresses.
+    pub fn get(&mut self, left: &MacAddress, right: &MacAddress) -> &mut Rng {
+        let (left, right) = (u64::from(left), u64::from(right));
+        let key = (left.min(right), left.max(right));
+        let seed = self.seed;
+        self.rngs.entry(key).or_insert_with(|| {
+            let mut rng = Rng::new(seed ^ key.0);
+            Rng::new(rng.next_u64() ^ key.1)
+        })
+    }
+
+    /// Drop the generators of the pairs including the device identified
+    /// by its mac address. The generators are seeded anew if the device
+    /// is added back.
+    pub fn remove(&mut self, mac_address: &MacAddress) {
+        let mac_address = u64::from(mac_address);
+        self.rngs
+            .retain(|(left, right), _| *left != mac_address && *right != mac_address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aoa_fom() {
+        let fom = |aoa_sigma| NoiseModel { aoa_sigma }.aoa_fom();
+        assert_eq!(fom(0.), 100);
+        assert!(fom(5.) < fom(0.));
+        assert!(fom(20.) < fom(5.));
+        assert!(fom(1000.) < 2);
+    }
+
+    #[test]
+    fn apply_noise() {
+        let mut rng = Rng::new(0);
+        let measurement = RangingMeasurement {
+            range: 100,
+            azimuth: 179,
+            elevation: 89,
+        };
+
+        assert_eq!(
+            NoiseModel::default().apply(&mut rng, measurement),
+            measurement
+        );
+
+        let noise_model = NoiseModel { aoa_sigma: 30. };
+        for _ in 0..100 {
+            let noisy = noise_model.apply(&mut rng, measurement);
+            assert_eq!(noisy.range, 100);
+            assert!((-180..=180).contains(&noisy.azimuth));
+            assert!((-90..=90).contains(&noisy.elevation));
+        }
+    }
+
+    #[test]
+    fn remove_pair_rngs() {
+        let (a, b, c) = (
+            MacAddress::Short([0, 1]),
+            MacAddress::Short([0, 2]),
+            MacAddress::Short([0, 3]),
+        );
+        let mut rngs = PairRngs::new(0);
+        let first = rngs.get(&a, &b).next_u64();
+        rngs.get(&a, &c).next_u64();
+        rngs.get(&b, &c).next_u64();
+        assert_eq!(rngs.rngs.len(), 3);
+
+        rngs.remove(&a);
+        assert_eq!(rngs.rngs.len(), 1);
+        // The generator of a removed pair restarts from its seed.
+        assert_eq!(rngs.get(&b, &a).next_u64(), first);
+    }
+}
@@ -42,16 +42,218 @@ pub mod uci {
     pub const MAX_CTRL_PACKET_PAYLOAD_SIZE: usize = 255;
     /// Maximum size of an UCI data packet payload.
     pub const MAX_DATA_PACKET_PAYLOAD_SIZE: usize = 1024;
+    /// Maximum size of an UCI control packet payload with the
+    /// extended payload length of the UCI 2.0 framing.
+    pub const MAX_EXTENDED_CTRL_PACKET_PAYLOAD_SIZE: usize = u16::MAX as usize;
+    /// Extended payload length indicator, in the second octet
+    /// of the control packet header.
+    const EXTENDED_LENGTH_MASK: u8 = 0x80;
+
+    /// Layout of the UCI packet headers.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum Framing {
+        /// UCI 1.x framing: the payload length of control packets
+        /// is encoded on the fourth octet of the header.
+        V1,
+        /// UCI 2.0 framing: control packets whose extended payload length
+        /// indicator is set encode the payload length on the third and
+        /// fourth octets of the header, in little-endian order.
+        #[default]
+        V2,
+    }
+
+    impl Framing {
+        /// Return the UCI version matching the framing, as reported
+        /// in the Core Get Device Info response.
+        pub fn uci_version(self) -> u16 {
+            match self {
+                Framing::V1 => 0x1001, // Version 1.1
+                Framing::V2 => 0x0002, // Version 2.0
+            }
+        }
+
+        /// Return the maximum payload size of a control packet.
+        pub fn max_ctrl_payload_size(self) -> usize {
+            match self {
+                Framing::V1 => MAX_CTRL_PACKET_PAYLOAD_SIZE,
+                Framing::V2 => MAX_EXTENDED_CTRL_PACKET_PAYLOAD_SIZE,
+            }
+        }
+    }
 
     // Extract the message type from the first 3 bits of the passed (header) byte
     pub fn parse_message_type(byte: u8) -> MessageType {
         MessageType::try_from((byte >> 5) & 0x7).unwrap_or(MessageType::Command)
     }
 
-    /// Read a single UCI packet from a TCP read half.
+    /// Return true if the payload of the packet is shorter than the
+    /// payload length declared in its header. The read adapters forward
+    /// the header alone of the packets exceeding the maximum packet size.
+    pub fn is_truncated(packet: &[u8]) -> bool {
+        if packet.len() < HEADER_SIZE {
+            return true;
+        }
+        let payload_length = match parse_message_type(packet[0]) {
+            MessageType::Data => u16::from_le_bytes([packet[2], packet[3]]) as usize,
+            _ if packet[1] & EXTENDED_LENGTH_MASK != 0 => {
+                u16::from_le_bytes([packet[2], packet[3]]) as usize
+            }
+            _ => packet[3] as usize,
+        };
+        packet.len() - HEADER_SIZE < payload_length
+    }
+
+    /// Segment a complete UCI packet into fragments carrying at most
+    /// `max_payload_size` bytes of payload each. The packet boundary flag
+    /// and payload length fields of every fragment header are rewritten
+    /// to match the fragment and the selected framing. A packet with an
+    /// empty payload is emitted as a single complete fragment.
+    pub fn segment(packet: &[u8], max_payload_size: usize, framing: Framing) -> Vec<Vec<u8>> {
+        let max_payload_size = max_fragment_payload_size(packet, max_payload_size, framing);
+        let payload = &packet[HEADER_SIZE..];
+        make_fragments(packet, payload.chunks(max_payload_size))
+    }
+
+    /// Segment a complete UCI packet as [segment], additionally limiting
+    /// the number of TLVs carried by each fragment to `max_tlvs` for the
+    /// Core Get Caps Info, Core Get Config, and Session Get App Config
+    /// responses. Fragments are split on TLV boundaries, TLVs exceeding
+    /// `max_payload_size` are split further.
+    pub fn segment_tlvs(
+        packet: &[u8],
+        max_payload_size: usize,
+        max_tlvs: usize,
+        framing: Framing,
+    ) -> Vec<Vec<u8>> {
+        assert!(max_tlvs > 0);
+        let Some(offsets) = tlv_offsets(packet) else {
+            return segment(packet, max_payload_size, framing);
+        };
+
+        let max_payload_size = max_fragment_payload_size(packet, max_payload_size, framing);
+        let payload = &packet[HEADER_SIZE..];
+        let mut boundaries = vec![0];
+        boundaries.extend(offsets.iter().skip(max_tlvs).step_by(max_tlvs));
+        boundaries.push(payload.len());
+        let chunks = boundaries
+            .windows(2)
+            .flat_map(|window| payload[window[0]..window[1]].chunks(max_payload_size));
+        make_fragments(packet, chunks)
+    }
+
+    /// Return the offsets in the payload of the TLVs listed in the
+    /// Core Get Caps Info, Core Get Config, and Session Get App Config
+    /// responses. The TLVs follow the status and TLV count octets.
+    /// Returns None for other packets, or if the TLV list is malformed.
+    fn tlv_offsets(packet: &[u8]) -> Option<Vec<usize>> {
+        const CORE_GET_CAPS_INFO: (u8, u8) = (0x0, 0x03);
+        const CORE_GET_CONFIG: (u8, u8) = (0x0, 0x05);
+        const SESSION_GET_APP_CONFIG: (u8, u8) = (0x1, 0x04);
+
+        if parse_message_type(packet[0]) != MessageType::Response
+            || ![CORE_GET_CAPS_INFO, CORE_GET_CONFIG, SESSION_GET_APP_CONFIG]
+                .contains(&(packet[0] & 0xf, packet[1] & 0x3f))
+        {
+            return None;
+        }
+
+        let payload = &packet[HEADER_SIZE..];
+        let count = *payload.get(1)?;
+        let mut offsets = Vec::with_capacity(count as usize);
+        let mut offset = 2;
+        for _ in 0..count {
+            offsets.push(offset);
+            offset += 2 + *payload.get(offset + 1)? as usize;
+        }
+        (offset == payload.len()).then_some(offsets)
+    }
+
+    /// Return the maximum payload size of the fragments of the packet,
+    /// capped by the maximum control packet payload size of the framing.
+    fn max_fragment_payload_size(
+        packet: &[u8],
+        max_payload_size: usize,
+        framing: Framing,
+    ) -> usize {
+        assert!(max_payload_size > 0);
+        match parse_message_type(packet[0]) {
+            MessageType::Data => max_payload_size,
+            _ => max_payload_size.min(framing.max_ctrl_payload_size()),
+        }
+    }
+
+    /// Build the fragments of the packet carrying the selected payload
+    /// chunks. An empty list of chunks produces a single complete fragment.
+    fn make_fragments<'a>(packet: &[u8], chunks: impl Iterator<Item = &'a [u8]>) -> Vec<Vec<u8>> {
+        const PBF_MASK: u8 = 0x10;
+
+        let mt = parse_message_type(packet[0]);
+        let mut chunks = chunks.peekable();
+        let mut fragments = vec![];
+
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let pbf = if chunks.peek().is_none() {
+                PacketBoundaryFlag::Complete
+            } else {
+                PacketBoundaryFlag::NotComplete
+            };
+
+            let mut fragment = Vec::with_capacity(HEADER_SIZE + chunk.len());
+            fragment.extend_from_slice(&packet[0..HEADER_SIZE]);
+            fragment[0] &= !PBF_MASK;
+            fragment[0] |= (pbf as u8) << 4;
+            match mt {
+                MessageType::Data => {
+                    fragment[2..4].copy_from_slice(&(chunk.len() as u16).to_le_bytes())
+                }
+                _ if chunk.len() > MAX_CTRL_PACKET_PAYLOAD_SIZE => {
+                    fragment[1] |= EXTENDED_LENGTH_MASK;
+                    fragment[2..4].copy_from_slice(&(chunk.len() as u16).to_le_bytes())
+                }
+                _ => {
+                    fragment[1] &= !EXTENDED_LENGTH_MASK;
+                    fragment[3] = chunk.len() as u8
+                }
+            }
+            fragment.extend_from_slice(chunk);
+            fragments.push(fragment);
+
+            if pbf == PacketBoundaryFlag::Complete {
+                return fragments;
+            }
+        }
+    }
+
+    /// Read a single UCI packet from a TCP read half, with the
+    /// UCI 2.0 framing.
     /// This function does not reassemble segmented packets.
     pub async fn read(
+        socket: tokio::net::tcp::OwnedReadHalf,
+    ) -> Option<(Vec<u8>, tokio::net::tcp::OwnedReadHalf)> {
+        read_framed(socket, Framing::V2).await
+    }
+
+    /// Read a single UCI packet from a TCP read half, with the selected
+    /// framing. The connection is closed when a control packet uses the
+    /// extended payload length with the UCI 1.x framing.
+    /// This function does not reassemble segmented packets.
+    pub async fn read_framed(
+        socket: tokio::net::tcp::OwnedReadHalf,
+        framing: Framing,
+    ) -> Option<(Vec<u8>, tokio::net::tcp::OwnedReadHalf)> {
+        read_limited(socket, framing, usize::MAX).await
+    }
+
+    /// Read a single UCI packet from a TCP read half, with the selected
+    /// framing, as [read_framed]. The payload of the packets declaring
+    /// a payload length larger than `max_payload_size` is discarded
+    /// without being buffered, and the header is returned alone so that
+    /// the packet is rejected by the device. The connection is preserved.
+    pub async fn read_limited(
         mut socket: tokio::net::tcp::OwnedReadHalf,
+        framing: Framing,
+        max_payload_size: usize,
     ) -> Option<(Vec<u8>, tokio::net::tcp::OwnedReadHalf)> {
         use tokio::io::AsyncReadExt;
 
@@ -69,6 +271,13 @@ pub mod uci {
                     DataPacketHeader::decode_full(&packet[0..HEADER_SIZE]).ok()?;
                 data_packet_header.payload_length as usize
             }
+            _ if packet[1] & EXTENDED_LENGTH_MASK != 0 => {
+                if framing == Framing::V1 {
+                    log::error!("Extended payload length received with the UCI 1.x framing");
+                    return None;
+                }
+                u16::from_le_bytes([packet[2], packet[3]]) as usize
+            }
             _ => {
                 let control_packet_header =
                     ControlPacketHeader::decode_full(&packet[0..HEADER_SIZE]).ok()?;
@@ -76,6 +285,19 @@ pub mod uci {
             }
         };
 
+        if payload_length > max_payload_size {
+            log::warn!(
+                "Packet payload length {} exceeds {} bytes, discarding",
+                payload_length,
+                max_payload_size
+            );
+            let mut payload = (&mut socket).take(payload_length as u64);
+            let discarded = tokio::io::copy(&mut payload, &mut tokio::io::sink())
+                .await
+                .ok()?;
+            return (discarded == payload_length as u64).then_some((packet, socket));
+        }
+
         // Read the packet payload.
         packet.resize(payload_length + HEADER_SIZE, 0);
         socket.read_exact(&mut packet[HEADER_SIZE..]).await.ok()?;
@@ -96,3 +318,177 @@ pub mod uci {
         Ok(socket)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::uci::*;
+
+    #[test]
+    fn segment_control_packet() {
+        // Core Get Device Info response with a 10 byte payload.
+        let mut packet = vec![0x40, 0x02, 0x00, 0x0a];
+        packet.extend(0..10);
+
+        let fragments = segment(&packet, 4, Framing::V1);
+        assert_eq!(
+            fragments,
+            vec![
+                vec![0x50, 0x02, 0x00, 0x04, 0, 1, 2, 3],
+                vec![0x50, 0x02, 0x00, 0x04, 4, 5, 6, 7],
+                vec![0x40, 0x02, 0x00, 0x02, 8, 9],
+            ]
+        );
+    }
+
+    #[test]
+    fn segment_data_packet() {
+        let mut packet = vec![0x02, 0x00, 0x00, 0x00];
+        packet.resize(HEADER_SIZE + MAX_DATA_PACKET_PAYLOAD_SIZE + 1, 0xaa);
+        packet[2..4].copy_from_slice(&(MAX_DATA_PACKET_PAYLOAD_SIZE as u16 + 1).to_le_bytes());
+
+        let fragments = segment(&packet, MAX_DATA_PACKET_PAYLOAD_SIZE, Framing::V1);
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0][0..4], [0x12, 0x00, 0x00, 0x04]);
+        assert_eq!(
+            fragments[0].len(),
+            HEADER_SIZE + MAX_DATA_PACKET_PAYLOAD_SIZE
+        );
+        assert_eq!(fragments[1], vec![0x02, 0x00, 0x01, 0x00, 0xaa]);
+    }
+
+    #[test]
+    fn segment_empty_payload() {
+        let packet = vec![0x50, 0x02, 0x00, 0x00];
+        assert_eq!(
+            segment(&packet, 4, Framing::V1),
+            vec![vec![0x40, 0x02, 0x00, 0x00]]
+        );
+    }
+
+    #[test]
+    fn segment_extended_payload_length() {
+        let mut packet = vec![0x40, 0x02, 0x00, 0x00];
+        packet.resize(HEADER_SIZE + 300, 0xaa);
+
+        // The UCI 1.x framing caps the fragments to 255 bytes.
+        let fragments = segment(&packet, 1024, Framing::V1);
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0][0..4], [0x50, 0x02, 0x00, 0xff]);
+        assert_eq!(fragments[1][0..4], [0x40, 0x02, 0x00, 0x2d]);
+
+        let fragments = segment(&packet, 1024, Framing::V2);
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(fragments[0][0..4], [0x40, 0x82, 0x2c, 0x01]);
+    }
+
+    #[test]
+    fn segment_max_tlvs() {
+        use pdl_runtime::Packet;
+
+        let packet = CoreGetCapsInfoRsp {
+            status: Status::Ok,
+            tlvs: (0..5)
+                .map(|n| CapTlv {
+                    t: CapTlvType::SupportedChannels,
+                    v: vec![n; 3],
+                })
+                .collect(),
+        }
+        .encode_to_vec()
+        .unwrap();
+
+        // The first fragment carries the status and the TLV count.
+        let fragments = segment_tlvs(&packet, 255, 2, Framing::V2);
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0][0..4], [0x50, 0x03, 0x00, 12]);
+        assert_eq!(fragments[1][0..4], [0x50, 0x03, 0x00, 10]);
+        assert_eq!(fragments[2][0..4], [0x40, 0x03, 0x00, 5]);
+        let payload = fragments
+            .iter()
+            .flat_map(|fragment| fragment[HEADER_SIZE..].to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(payload, packet[HEADER_SIZE..]);
+
+        // TLVs are split further when exceeding the MTU.
+        let fragments = segment_tlvs(&packet, 4, 2, Framing::V2);
+        let sizes = fragments
+            .iter()
+            .map(|fragment| fragment.len() - HEADER_SIZE)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![4, 4, 4, 4, 4, 2, 4, 1]);
+
+        // Other packets are segmented on the MTU only.
+        let packet = [0x40, 0x02, 0x00, 0x03, 0x00, 0x01, 0x02];
+        assert_eq!(segment_tlvs(&packet, 255, 1, Framing::V2).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_extended_payload_length() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::{TcpListener, TcpStream};
+
+        async fn round_trip(packet: &[u8], framing: Framing) -> Option<Vec<u8>> {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            let (read_half, _write_half) = socket.into_split();
+            client.write_all(packet).await.unwrap();
+            read_framed(read_half, framing)
+                .await
+                .map(|(packet, _)| packet)
+        }
+
+        let mut packet = vec![0x40, 0x02, 0x00, 0x00];
+        packet.resize(HEADER_SIZE + 300, 0xaa);
+        let fragment = segment(&packet, 1024, Framing::V2).remove(0);
+
+        // The packet keeps the UCI 2.0 header layout.
+        let received = round_trip(&fragment, Framing::V2).await.unwrap();
+        assert_eq!(received[0..4], [0x40, 0x82, 0x2c, 0x01]);
+        assert_eq!(received, fragment);
+
+        // The UCI 2.0 layout is rejected with the UCI 1.x framing.
+        assert_eq!(round_trip(&fragment, Framing::V1).await, None);
+    }
+
+    #[tokio::test]
+    async fn read_oversized_packet() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (read_half, _write_half) = socket.into_split();
+
+        // Data packet declaring the largest payload length.
+        let mut oversized = vec![0x02, 0x00, 0xff, 0xff];
+        oversized.resize(HEADER_SIZE + 0xffff, 0xaa);
+        let valid = vec![0x20, 0x02, 0x00, 0x00];
+        // The oversized packet exceeds the socket buffers, the writer
+        // must run concurrently with the reader.
+        let writer = tokio::spawn({
+            let valid = valid.clone();
+            async move {
+                client.write_all(&oversized).await.unwrap();
+                client.write_all(&valid).await.unwrap();
+                client
+            }
+        });
+
+        // The header is returned alone.
+        let (packet, read_half) = read_limited(read_half, Framing::V2, 1024).await.unwrap();
+        assert_eq!(packet, vec![0x02, 0x00, 0xff, 0xff]);
+        assert!(is_truncated(&packet));
+
+        // The following packet is read from the same connection.
+        let (packet, _) = read_limited(read_half, Framing::V2, 1024).await.unwrap();
+        assert_eq!(packet, valid);
+        assert!(!is_truncated(&packet));
+        writer.await.unwrap();
+    }
+}
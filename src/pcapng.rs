@@ -15,64 +15,373 @@
 #![allow(clippy::unused_io_amount)]
 
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
 
+use crate::trace::{Direction, TraceWriter};
+
+/// Size of the Section Header Block and Interface Description Block
+/// written at the start of each file.
+const HEADER_SIZE: u64 = 48;
+
+struct Output {
+    file: std::fs::File,
+    size: u64,
+    index: usize,
+}
+
 pub struct File {
-    file: std::sync::Mutex<std::fs::File>,
+    output: std::sync::Mutex<Output>,
+    path: PathBuf,
+    max_size: Option<u64>,
     start_time: Instant,
 }
 
-pub enum Direction {
-    Rx,
-    Tx,
+fn write_section_header(file: &mut std::fs::File) -> std::io::Result<()> {
+    // PCAPng files must start with a Section Header Block.
+    file.write(&u32::to_le_bytes(0x0A0D0D0A))?; // Block Type
+    file.write(&u32::to_le_bytes(28))?; // Block Total Length
+    file.write(&u32::to_le_bytes(0x1A2B3C4D))?; // Byte-Order Magic
+    file.write(&u16::to_le_bytes(1))?; // Major Version
+    file.write(&u16::to_le_bytes(0))?; // Minor Version
+    file.write(&u64::to_le_bytes(0xFFFFFFFFFFFFFFFF))?; // Section Length (not specified)
+    file.write(&u32::to_le_bytes(28))?; // Block Total Length
+    Ok(())
 }
 
-impl File {
-    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
-        let mut file = std::fs::File::create(path)?;
+/// Write an Interface Description Block for UCI records,
+/// with the interface name option if provided.
+fn write_interface_description(
+    file: &mut std::fs::File,
+    name: Option<&str>,
+) -> std::io::Result<()> {
+    let name = name.map(str::as_bytes);
+    let name_padding = name.map_or(0, |name| (4 - name.len() % 4) % 4);
+    // The options are terminated by an End of Options option.
+    let options_length = name.map_or(0, |name| 4 + name.len() + name_padding + 4);
+    let block_total_length = 20 + options_length as u32;
+
+    file.write(&u32::to_le_bytes(0x00000001))?; // Block Type
+    file.write(&u32::to_le_bytes(block_total_length))?; // Block Total Length
+    file.write(&u16::to_le_bytes(293))?; // LinkType
+    file.write(&u16::to_le_bytes(0))?; // Reserved
+    file.write(&u32::to_le_bytes(0))?; // SnapLen (no limit)
+    if let Some(name) = name {
+        file.write(&u16::to_le_bytes(2))?; // Option Code (if_name)
+        file.write(&u16::to_le_bytes(name.len() as u16))?; // Option Length
+        file.write(name)?;
+        file.write(&vec![0; name_padding])?;
+        file.write(&u32::to_le_bytes(0))?; // End of Options
+    }
+    file.write(&u32::to_le_bytes(block_total_length))?; // Block Total Length
+    Ok(())
+}
 
-        // PCAPng files must start with a Section Header Block.
-        file.write(&u32::to_le_bytes(0x0A0D0D0A))?; // Block Type
-        file.write(&u32::to_le_bytes(28))?; // Block Total Length
-        file.write(&u32::to_le_bytes(0x1A2B3C4D))?; // Byte-Order Magic
-        file.write(&u16::to_le_bytes(1))?; // Major Version
-        file.write(&u16::to_le_bytes(0))?; // Minor Version
-        file.write(&u64::to_le_bytes(0xFFFFFFFFFFFFFFFF))?; // Section Length (not specified)
-        file.write(&u32::to_le_bytes(28))?; // Block Total Length
-
-        // Write the Interface Description Block used for all
-        // UCI records.
-        file.write(&u32::to_le_bytes(0x00000001))?; // Block Type
-        file.write(&u32::to_le_bytes(20))?; // Block Total Length
-        file.write(&u16::to_le_bytes(293))?; // LinkType
-        file.write(&u16::to_le_bytes(0))?; // Reserved
-        file.write(&u32::to_le_bytes(0))?; // SnapLen (no limit)
-        file.write(&u32::to_le_bytes(20))?; // Block Total Length
+/// Size of the Enhanced Packet Block recording `packet`.
+fn packet_block_length(packet: &[u8]) -> u32 {
+    packet.len() as u32 + packet_data_padding(packet) as u32 + 32
+}
+
+/// Size of the padding following the packet data in the
+/// Enhanced Packet Block.
+fn packet_data_padding(packet: &[u8]) -> usize {
+    4 - packet.len() % 4
+}
+
+/// Wrap the packet inside an Enhanced Packet Block, and return
+/// the size of the block.
+fn write_packet(
+    file: &mut std::fs::File,
+    interface_id: u32,
+    timestamp: u128,
+    packet: &[u8],
+) -> std::io::Result<u32> {
+    let packet_data_padding = packet_data_padding(packet);
+    let block_total_length = packet_block_length(packet);
+
+    file.write(&u32::to_le_bytes(0x00000006))?; // Block Type
+    file.write(&u32::to_le_bytes(block_total_length))?;
+    file.write(&u32::to_le_bytes(interface_id))?; // Interface ID
+    file.write(&u32::to_le_bytes((timestamp >> 32) as u32))?; // Timestamp (High)
+    file.write(&u32::to_le_bytes(timestamp as u32))?; // Timestamp (Low)
+    file.write(&u32::to_le_bytes(packet.len() as u32))?; // Captured Packet Length
+    file.write(&u32::to_le_bytes(packet.len() as u32))?; // Original Packet Length
+    file.write(packet)?;
+    file.write(&vec![0; packet_data_padding])?;
+    file.write(&u32::to_le_bytes(block_total_length))?; // Block Total Length
+    Ok(block_total_length)
+}
+
+fn create_file(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut file = std::fs::File::create(path)?;
+    write_section_header(&mut file)?;
+    // Write the Interface Description Block used for all
+    // UCI records.
+    write_interface_description(&mut file, None)?;
+    Ok(file)
+}
+
+impl File {
+    /// Create a pcapng file, rotated when its size exceeds `max_size`.
+    /// The records are written to `{name}.pcapng` first, then to
+    /// `{name}.1.pcapng`, `{name}.2.pcapng`, etc. Packets are never
+    /// split across files, and each file is independently readable.
+    pub fn create<P: AsRef<Path>>(path: P, max_size: Option<u64>) -> std::io::Result<File> {
+        let path = path.as_ref().to_path_buf();
+        let file = create_file(&path)?;
 
         Ok(File {
-            file: std::sync::Mutex::new(file),
+            output: std::sync::Mutex::new(Output {
+                file,
+                size: HEADER_SIZE,
+                index: 0,
+            }),
+            path,
+            max_size,
             start_time: Instant::now(),
         })
     }
+}
 
-    pub fn write(&self, packet: &[u8], _dir: Direction) -> std::io::Result<()> {
-        let packet_data_padding: usize = 4 - packet.len() % 4;
-        let block_total_length: u32 = packet.len() as u32 + packet_data_padding as u32 + 32;
+impl TraceWriter for File {
+    fn write(&self, packet: &[u8], _dir: Direction) -> std::io::Result<()> {
+        let block_total_length = packet_block_length(packet);
         let timestamp = self.start_time.elapsed().as_micros();
-        let mut file = self.file.lock().unwrap();
-
-        // Wrap the packet inside an Enhanced Packet Block.
-        file.write(&u32::to_le_bytes(0x00000006))?; // Block Type
-        file.write(&u32::to_le_bytes(block_total_length))?;
-        file.write(&u32::to_le_bytes(0))?; // Interface ID
-        file.write(&u32::to_le_bytes((timestamp >> 32) as u32))?; // Timestamp (High)
-        file.write(&u32::to_le_bytes(timestamp as u32))?; // Timestamp (Low)
-        file.write(&u32::to_le_bytes(packet.len() as u32))?; // Captured Packet Length
-        file.write(&u32::to_le_bytes(packet.len() as u32))?; // Original Packet Length
-        file.write(packet)?;
-        file.write(&vec![0; packet_data_padding])?;
-        file.write(&u32::to_le_bytes(block_total_length))?; // Block Total Length
+        let mut output = self.output.lock().unwrap();
+
+        // Start a new file if the packet would exceed the size limit.
+        // Files always contain at least one packet.
+        if let Some(max_size) = self.max_size {
+            if output.size > HEADER_SIZE && output.size + block_total_length as u64 > max_size {
+                let index = output.index + 1;
+                let path = self.path.with_extension(format!("{}.pcapng", index));
+                log::debug!("Recording pcapng to file {}", path.display());
+                *output = Output {
+                    file: create_file(&path)?,
+                    size: HEADER_SIZE,
+                    index,
+                };
+            }
+        }
+
+        output.size += write_packet(&mut output.file, 0, timestamp, packet)? as u64;
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.output.lock().unwrap().file.flush()
+    }
+}
+
+struct CombinedOutput {
+    file: std::fs::File,
+    interface_count: u32,
+}
+
+/// pcapng file recording the traffic of all devices, with one
+/// interface per device connection. The file is never rotated.
+pub struct CombinedFile {
+    output: std::sync::Mutex<CombinedOutput>,
+    start_time: Instant,
+}
+
+impl CombinedFile {
+    /// Create a pcapng file with no interfaces. The interfaces are
+    /// appended as the devices are connected.
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<CombinedFile> {
+        let mut file = std::fs::File::create(path)?;
+        write_section_header(&mut file)?;
+        Ok(CombinedFile {
+            output: std::sync::Mutex::new(CombinedOutput {
+                file,
+                interface_count: 0,
+            }),
+            start_time: Instant::now(),
+        })
+    }
+
+    /// Append the Interface Description Block of a new device
+    /// connection, and return the writer recording the packets
+    /// of the connection to the interface.
+    pub fn add_interface(self: &Arc<Self>, name: &str) -> std::io::Result<Interface> {
+        let mut output = self.output.lock().unwrap();
+        write_interface_description(&mut output.file, Some(name))?;
+        let id = output.interface_count;
+        output.interface_count += 1;
+        Ok(Interface {
+            file: self.clone(),
+            id,
+        })
+    }
+}
+
+/// Interface of a device connection in a [CombinedFile].
+pub struct Interface {
+    file: Arc<CombinedFile>,
+    id: u32,
+}
+
+impl TraceWriter for Interface {
+    fn write(&self, packet: &[u8], _dir: Direction) -> std::io::Result<()> {
+        let timestamp = self.file.start_time.elapsed().as_micros();
+        let mut output = self.file.output.lock().unwrap();
+        write_packet(&mut output.file, self.id, timestamp, packet)?;
         Ok(())
     }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.file.output.lock().unwrap().file.flush()
+    }
+}
+
+/// Content of a pcapng file written by [File] or [CombinedFile].
+#[derive(Debug, Default)]
+pub struct Capture {
+    /// Names of the interfaces, in order of declaration.
+    pub interfaces: Vec<Option<String>>,
+    /// Packets of the Enhanced Packet Blocks in recording order,
+    /// with their interface identifier.
+    pub packets: Vec<(u32, Vec<u8>)>,
+}
+
+/// Parse the blocks of a pcapng file written by [File], and return
+/// the packets of the Enhanced Packet Blocks in recording order.
+/// Files recording several interfaces are rejected, the packets
+/// of distinct devices cannot be told apart by the caller.
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Vec<u8>>> {
+    let capture = read_capture(path)?;
+    if capture.interfaces.len() > 1 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "multiple interfaces",
+        ));
+    }
+    Ok(capture
+        .packets
+        .into_iter()
+        .map(|(_, packet)| packet)
+        .collect())
+}
+
+/// Parse the blocks of a pcapng file written by [File] or
+/// [CombinedFile], and return the interfaces and packets.
+pub fn read_capture<P: AsRef<Path>>(path: P) -> std::io::Result<Capture> {
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+    let content = std::fs::read(path)?;
+    let u32_at = |offset: usize| {
+        content
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| invalid("truncated block"))
+    };
+    if u32_at(0)? != 0x0A0D0D0A || u32_at(8)? != 0x1A2B3C4D {
+        return Err(invalid("invalid section header block"));
+    }
+
+    let mut capture = Capture::default();
+    let mut offset = 0;
+    while offset < content.len() {
+        let block_type = u32_at(offset)?;
+        let block_total_length = u32_at(offset + 4)? as usize;
+        if block_total_length < 12
+            || u32_at(offset + block_total_length - 4)? as usize != block_total_length
+        {
+            return Err(invalid("invalid block length"));
+        }
+        if block_type == 0x00000001 {
+            // Only the interface name option is decoded.
+            let mut name = None;
+            let mut option_offset = offset + 16;
+            while option_offset + 4 <= offset + block_total_length - 4 {
+                let option = u32_at(option_offset)?;
+                let (code, length) = (option as u16, (option >> 16) as usize);
+                if code == 0 {
+                    break;
+                }
+                let value = content
+                    .get(option_offset + 4..option_offset + 4 + length)
+                    .ok_or_else(|| invalid("truncated option"))?;
+                if code == 2 {
+                    name = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                option_offset += 4 + length.div_ceil(4) * 4;
+            }
+            capture.interfaces.push(name);
+        }
+        if block_type == 0x00000006 {
+            let interface_id = u32_at(offset + 8)?;
+            let packet_length = u32_at(offset + 20)? as usize;
+            let packet = content
+                .get(offset + 28..offset + 28 + packet_length)
+                .ok_or_else(|| invalid("truncated packet"))?;
+            capture.packets.push((interface_id, packet.to_vec()));
+        }
+        offset += block_total_length;
+    }
+    Ok(capture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_packets(path: &Path) -> Vec<Vec<u8>> {
+        read(path).unwrap()
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("pica-pcapng-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("device-0.pcapng");
+
+        let file = File::create(&path, Some(128)).unwrap();
+        let packets = (0..5u8).map(|n| vec![n; 40]).collect::<Vec<_>>();
+        for packet in packets.iter() {
+            file.write(packet, Direction::Tx).unwrap();
+        }
+        drop(file);
+
+        // Each 40 byte packet is recorded in a 76 byte block, with
+        // 4 bytes of padding: only one packet fits in each file.
+        let mut recorded = read_packets(&path);
+        for index in 1..5 {
+            let path = dir.join(format!("device-0.{}.pcapng", index));
+            let file_packets = read_packets(&path);
+            assert_eq!(file_packets.len(), 1);
+            recorded.extend(file_packets);
+        }
+        assert!(!dir.join("device-0.5.pcapng").exists());
+        assert_eq!(recorded, packets);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn combined_interfaces() {
+        let path =
+            std::env::temp_dir().join(format!("pica-combined-{}.pcapng", std::process::id()));
+
+        let file = Arc::new(CombinedFile::create(&path).unwrap());
+        let first = file.add_interface("device-0").unwrap();
+        first.write(&[1, 2, 3], Direction::Tx).unwrap();
+        // Interfaces added after packets were recorded.
+        let second = file.add_interface("device-12").unwrap();
+        second.write(&[4, 5, 6, 7], Direction::Rx).unwrap();
+        first.write(&[8], Direction::Rx).unwrap();
+        drop((first, second, file));
+
+        let capture = read_capture(&path).unwrap();
+        assert_eq!(
+            capture.interfaces,
+            vec![Some("device-0".to_owned()), Some("device-12".to_owned())]
+        );
+        assert_eq!(
+            capture.packets,
+            vec![(0, vec![1, 2, 3]), (1, vec![4, 5, 6, 7]), (0, vec![8])]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use glam::{EulerRot, Quat, Vec3};
+use glam::{EulerRot, Quat, Vec2, Vec3};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::default::Default;
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy)]
+/// Position and orientation of a device in the world frame.
+/// The coordinates are expressed in centimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
     position: Vec3,
     rotation: Quat,
@@ -82,25 +84,59 @@ fn azimuth(delta: Vec3) -> f32 {
 }
 
 fn elevation(delta: Vec3) -> f32 {
-    checked_div(delta.y, f32::sqrt(delta.x.powi(2) + delta.z.powi(2)))
-        .map_or(delta.y.signum() * std::f32::consts::FRAC_PI_2, f32::atan)
+    checked_div(delta.y, f32::sqrt(delta.x.powi(2) + delta.z.powi(2))).map_or(
+        if delta.y == 0. {
+            0.
+        } else {
+            delta.y.signum() * std::f32::consts::FRAC_PI_2
+        },
+        f32::atan,
+    )
+}
+
+fn rotation(yaw: i16, pitch: i8, roll: i16) -> Quat {
+    Quat::from_euler(
+        EulerRot::ZXY, // Rotation performed from right to left order
+        (roll as f32).to_radians(),
+        (pitch as f32).to_radians(),
+        (yaw as f32).to_radians(),
+    )
 }
 
 impl Position {
     pub fn new(x: i16, y: i16, z: i16, yaw: i16, pitch: i8, roll: i16) -> Self {
         Self {
             position: Vec3::new(x as f32, y as f32, z as f32),
-            rotation: Quat::from_euler(
-                EulerRot::ZXY, // Rotation performed from right to left order
-                (roll as f32).to_radians(),
-                (pitch as f32).to_radians(),
-                (yaw as f32).to_radians(),
-            ),
+            rotation: rotation(yaw, pitch, roll),
         }
     }
 
+    /// Replace the yaw, pitch and roll angles of the position,
+    /// the coordinates are unchanged.
+    pub fn set_orientation(&mut self, yaw: i16, pitch: i8, roll: i16) {
+        self.rotation = rotation(yaw, pitch, roll);
+    }
+
+    /// Return the coordinates of the position projected
+    /// on the horizontal plane (x, z).
+    pub fn ground_coordinates(&self) -> Vec2 {
+        Vec2::new(self.position.x, self.position.z)
+    }
+
+    /// Return the distance between the two positions in the world
+    /// frame, independently of their orientation.
+    pub fn distance(&self, other: &Position) -> f32 {
+        self.position.distance(other.position)
+    }
+
+    /// Compute the distance, azimuth and elevation of the other position
+    /// relative to this position. The angles are undefined when the
+    /// positions are the same, and reported as zero.
     pub fn compute_range_azimuth_elevation(&self, other: &Position) -> (u16, i16, i8) {
         let delta = other.position - self.position;
+        if delta == Vec3::ZERO {
+            return (0, 0, 0);
+        }
 
         let distance = delta.length();
         let direction = self.rotation.mul_vec3(delta);
@@ -154,6 +190,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn same_position() {
+        let position_a = Position::new(10, -20, 30, 45, 10, 0);
+        let position_b = Position::new(10, -20, 30, 0, 0, 0);
+        assert_eq!(
+            position_a.compute_range_azimuth_elevation(&position_b),
+            (0, 0, 0)
+        );
+        assert_eq!(
+            position_b.compute_range_azimuth_elevation(&position_a),
+            (0, 0, 0)
+        );
+    }
+
     #[test]
     fn azimuth_without_rotation() {
         let position_a = Position::new(0, 0, 0, 0, 0, 0);
@@ -0,0 +1,251 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replay of the UCI traces recorded with [crate::PicaBuilder::pcapng_dir].
+//!
+//! The commands recorded in the trace are sent again to a new device,
+//! and the responses generated by pica are compared against the recorded
+//! responses. Data and notification packets are not replayed.
+
+use crate::packets::uci::{self, MessageType};
+use crate::{pcapng, Pica};
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time;
+
+/// Default delay for receiving the response to a replayed command.
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Range of bytes excluded from the comparison of the responses
+/// with the selected group and opcode identifiers, used to mask
+/// non-deterministic fields such as timestamps. The offset is
+/// relative to the start of the packet payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mask {
+    pub gid: u8,
+    pub oid: u8,
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Mask {
+    fn apply(&self, packet: &mut [u8]) {
+        if packet[0] & 0xf != self.gid || packet[1] & 0x3f != self.oid {
+            return;
+        }
+        let start = (uci::HEADER_SIZE + self.offset).min(packet.len());
+        let end = (start + self.length).min(packet.len());
+        packet[start..end].fill(0);
+    }
+}
+
+pub(crate) fn parse_integer<T: num_traits::Num>(value: &str) -> Result<T> {
+    match value.strip_prefix("0x") {
+        Some(value) => T::from_str_radix(value, 16),
+        None => T::from_str_radix(value, 10),
+    }
+    .map_err(|_| anyhow::anyhow!("invalid integer: {}", value))
+}
+
+/// Parse a mask from the format `gid:oid:offset:length`.
+/// The fields are decimal integers, or hexadecimal integers
+/// prefixed with `0x`.
+impl FromStr for Mask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields = s.split(':').collect::<Vec<_>>();
+        let [gid, oid, offset, length] = fields[..] else {
+            anyhow::bail!("expected gid:oid:offset:length, got {}", s);
+        };
+        Ok(Mask {
+            gid: parse_integer(gid)?,
+            oid: parse_integer(oid)?,
+            offset: parse_integer(offset)?,
+            length: parse_integer(length)?,
+        })
+    }
+}
+
+/// Recorded response not reproduced by the replay.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the response packet in the trace.
+    pub index: usize,
+    pub expected: Vec<u8>,
+    /// Response generated by pica, or None if no response was
+    /// received before the timeout.
+    pub actual: Option<Vec<u8>>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "packet #{}:", self.index)?;
+        writeln!(f, "  expected: {}", hex::encode(&self.expected))?;
+        match &self.actual {
+            Some(actual) => write!(f, "  actual:   {}", hex::encode(actual)),
+            None => write!(f, "  actual:   no response"),
+        }
+    }
+}
+
+fn message_type(packet: &[u8]) -> Option<MessageType> {
+    MessageType::try_from((packet.first()? >> 5) & 0x7).ok()
+}
+
+/// Replay the commands recorded in a pcapng trace on a new device of
+/// the selected pica instance, and return the recorded responses that
+/// are not reproduced. Each response is expected to be received before
+/// `timeout`, the masked bytes are ignored in the comparison.
+pub async fn verify(
+    mut pica: Pica,
+    path: impl AsRef<Path>,
+    masks: &[Mask],
+    timeout: Duration,
+) -> Result<Vec<Mismatch>> {
+    let packets = pcapng::read(path)?;
+    let (mut sink, mut stream) = pica.connect_local()?;
+    let pica = tokio::spawn(pica.run());
+
+    let mask = |packet: &[u8]| {
+        let mut packet = packet.to_vec();
+        masks.iter().for_each(|mask| mask.apply(&mut packet));
+        packet
+    };
+
+    let mut mismatches = vec![];
+    for (index, packet) in packets.iter().enumerate() {
+        match message_type(packet) {
+            Some(MessageType::Command) => sink.send(packet.clone()).await?,
+            Some(MessageType::Response) => {
+                let response = time::timeout(timeout, async {
+                    while let Some(packet) = stream.next().await {
+                        if message_type(&packet) == Some(MessageType::Response) {
+                            return Some(packet);
+                        }
+                    }
+                    None
+                })
+                .await
+                .ok()
+                .flatten();
+
+                if response.as_deref().map(mask) != Some(mask(packet)) {
+                    mismatches.push(Mismatch {
+                        index,
+                        expected: packet.clone(),
+                        actual: response,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pica.abort();
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::uci::*;
+    use crate::{NullRangingEstimator, Versions};
+    use pdl_runtime::Packet;
+
+    async fn capture(dir: &Path) {
+        let mut pica = Pica::new(Box::new(NullRangingEstimator()), Some(dir.to_path_buf()));
+        let (mut sink, mut stream) = pica.connect_local().unwrap();
+        tokio::spawn(pica.run());
+
+        let commands = vec![
+            CoreDeviceResetCmd {
+                reset_config: ResetConfig::UwbsReset,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            CoreGetDeviceInfoCmd {}.encode_to_vec().unwrap(),
+            CoreQueryTimeStampCmd {}.encode_to_vec().unwrap(),
+            SessionInitCmd {
+                session_id: 1,
+                session_type: SessionType::FiraRangingSession,
+            }
+            .encode_to_vec()
+            .unwrap(),
+            SessionGetStateCmd { session_token: 1 }
+                .encode_to_vec()
+                .unwrap(),
+        ];
+        for command in commands {
+            sink.send(command).await.unwrap();
+            while message_type(&stream.next().await.unwrap()) != Some(MessageType::Response) {}
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_verify() {
+        let dir = std::env::temp_dir().join(format!("pica-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        capture(&dir).await;
+        let path = dir.join("device-0.pcapng");
+
+        // The timestamp of the Core Query Timestamp response differs
+        // between the capture and the replay.
+        let timestamp: Mask = "0x0:0x8:1:8".parse().unwrap();
+        let mismatches = verify(
+            Pica::new(Box::new(NullRangingEstimator()), None),
+            &path,
+            &[timestamp],
+            DEFAULT_RESPONSE_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(mismatches, vec![]);
+
+        // The Core Get Device Info response is not reproduced
+        // by a device reporting different versions.
+        let pica = Pica::builder()
+            .versions(Versions {
+                uci_version: 0x1001,
+                ..Default::default()
+            })
+            .build();
+        let mismatches = verify(pica, &path, &[timestamp], DEFAULT_RESPONSE_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].expected[1], 0x02);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_mask() {
+        assert_eq!(
+            "0x0:0x8:1:8".parse::<Mask>().unwrap(),
+            Mask {
+                gid: 0,
+                oid: 8,
+                offset: 1,
+                length: 8
+            }
+        );
+        assert!("0:8:1".parse::<Mask>().is_err());
+        assert!("0:8:1:x".parse::<Mask>().is_err());
+    }
+}
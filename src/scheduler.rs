@@ -0,0 +1,129 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Scheduler of the ranging rounds of a device.
+/// The device performs a single ranging round at a time: rounds
+/// requested while the device is busy are queued, and serviced by
+/// decreasing session priority once the device is available.
+/// Sessions with equal priority are serviced in round-robin order.
+#[derive(Default)]
+pub struct Scheduler {
+    /// Sessions with a pending ranging round, and their priority.
+    pending: Vec<(u32, u8)>,
+    /// Set while a ranging round is scheduled or in progress.
+    busy: bool,
+    /// Number of ranging rounds serviced.
+    rounds: u64,
+    /// Index of the last round serviced for each session.
+    last_round: HashMap<u32, u64>,
+}
+
+impl Scheduler {
+    /// Queue a ranging round for the selected session. A session has
+    /// at most one pending round. Returns true if the device was idle,
+    /// in which case the caller is responsible for scheduling the round.
+    pub fn push(&mut self, session_id: u32, priority: u8) -> bool {
+        if !self.pending.iter().any(|(id, _)| *id == session_id) {
+            self.pending.push((session_id, priority));
+        }
+        !std::mem::replace(&mut self.busy, true)
+    }
+
+    /// Select the session serviced in the next ranging round.
+    /// The device becomes idle when no round is pending.
+    pub fn pop(&mut self) -> Option<u32> {
+        let Some(index) = self
+            .pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (session_id, priority))| {
+                (
+                    Reverse(*priority),
+                    self.last_round.get(session_id).copied().unwrap_or(0),
+                )
+            })
+            .map(|(index, _)| index)
+        else {
+            self.busy = false;
+            return None;
+        };
+
+        let (session_id, _) = self.pending.remove(index);
+        self.rounds += 1;
+        self.last_round.insert(session_id, self.rounds);
+        Some(session_id)
+    }
+
+    /// Drop the pending ranging rounds. The device becomes idle.
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+        self.busy = false;
+    }
+
+    /// Forget the sessions for which the predicate returns false,
+    /// dropping their pending ranging rounds.
+    pub fn retain(&mut self, mut f: impl FnMut(u32) -> bool) {
+        self.pending.retain(|(session_id, _)| f(*session_id));
+        self.last_round.retain(|session_id, _| f(*session_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+
+    #[test]
+    fn priority() {
+        let mut scheduler = Scheduler::default();
+        assert!(scheduler.push(1, 10));
+        assert!(!scheduler.push(2, 90));
+        assert!(!scheduler.push(1, 10));
+
+        assert_eq!(scheduler.pop(), Some(2));
+        assert_eq!(scheduler.pop(), Some(1));
+        assert_eq!(scheduler.pop(), None);
+
+        // The device is idle again.
+        assert!(scheduler.push(1, 10));
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut scheduler = Scheduler::default();
+        let mut serviced = vec![];
+        for _ in 0..3 {
+            scheduler.push(1, 50);
+            scheduler.push(2, 50);
+            serviced.push(scheduler.pop().unwrap());
+        }
+        assert_eq!(serviced, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn retain() {
+        let mut scheduler = Scheduler::default();
+        scheduler.push(1, 50);
+        scheduler.push(2, 50);
+        assert_eq!(scheduler.pop(), Some(1));
+        scheduler.push(1, 50);
+
+        scheduler.retain(|session_id| session_id != 1);
+        assert!(!scheduler.last_round.contains_key(&1));
+        assert_eq!(scheduler.pop(), Some(2));
+        assert_eq!(scheduler.pop(), None);
+    }
+}
@@ -27,6 +27,52 @@ use tokio::time;
 
 use super::UciPacket;
 
+/// Session management commands subject to the session state machine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionCommand {
+    SetAppConfig,
+    UpdateControllerMulticastList,
+    SetHybridConfig,
+    Start,
+    Stop,
+    Recover,
+}
+
+/// [UCI] 7.1 Session State Machine
+/// Return the status of the response to a session command received
+/// in the selected session state, or `Ok` if the command is permitted
+/// and should be executed.
+fn check_session_state(state: SessionState, command: SessionCommand) -> Result<(), uci::Status> {
+    use SessionCommand::*;
+    use SessionState::*;
+
+    match (command, state) {
+        (SetAppConfig, SessionStateInit | SessionStateActive) => Ok(()),
+        (SetAppConfig, _) => Err(uci::Status::Rejected),
+        (UpdateControllerMulticastList, SessionStateIdle | SessionStateActive) => Ok(()),
+        (UpdateControllerMulticastList, _) => Err(uci::Status::Rejected),
+        (SetHybridConfig, SessionStateIdle) => Ok(()),
+        (SetHybridConfig, _) => Err(uci::Status::Rejected),
+        (Start, SessionStateIdle) => Ok(()),
+        (Start, SessionStateInit) => Err(uci::Status::ErrorSessionNotConfigured),
+        (Start, SessionStateActive) => Err(uci::Status::ErrorSessionActive),
+        (Start, _) => Err(uci::Status::Rejected),
+        (Stop, SessionStateActive) => Ok(()),
+        (Stop, _) => Err(uci::Status::Rejected),
+        (Recover, SessionStateError) => Ok(()),
+        (Recover, _) => Err(uci::Status::Rejected),
+    }
+}
+
+/// Number of data messages the host can send before the data is
+/// transmitted at the next ranging round.
+const DATA_CREDIT: u8 = 1;
+
+/// Maximum size of the application data of a data message.
+/// Data messages larger than a data packet are segmented by the host,
+/// the limit applies to the reassembled application data.
+pub const MAX_DATA_SIZE: usize = 8192;
+
 pub struct Session {
     /// cf. [UCI] 7.1
     pub state: SessionState,
@@ -34,11 +80,39 @@ pub struct Session {
     id: u32,
     device_handle: usize,
     data: BytesMut,
+    /// Application data of the data message being received,
+    /// until the last data packet of the message.
+    partial_data: BytesMut,
+    /// Credit available for sending data messages.
+    data_credit: u8,
+    /// UCI sequence numbers of the data messages pending transmission.
+    data_sequence_numbers: Vec<u8>,
 
     pub session_type: SessionType,
     pub sequence_number: u32,
+    /// Ranging rounds remaining in the warmup phase of the session.
+    pub warmup_rounds: u8,
+    /// Consecutive ranging rounds that failed to range with the
+    /// controlee of a one-to-one session.
+    pub failed_rounds: u16,
+    /// STS index of dynamic STS sessions, incremented on each
+    /// rotation of the session key.
+    pub sts_index: u32,
+    /// Ranging rounds since the last rotation of the session key.
+    pub key_rotation_rounds: u32,
     pub app_config: AppConfig,
     pub ranging_task: Option<JoinHandle<()>>,
+    /// Phases of the hybrid session, configured with
+    /// SESSION_SET_HUS_CONTROLLER_CONFIG.
+    pub phases: Vec<PhaseList>,
+    /// Index of the phase serviced in the next ranging round.
+    next_phase: usize,
+    /// Reason code reported when the host stops the session, replacing
+    /// the reason of the state changes requested by the host.
+    pub stop_reason: Option<ReasonCode>,
+    /// Measurements of the ranging rounds buffered in the current
+    /// coalescing window, one entry per round.
+    pub coalesced_rounds: Vec<Vec<ShortAddressTwoWayRangingMeasurement>>,
     tx: mpsc::UnboundedSender<UciPacket>,
 }
 
@@ -54,10 +128,21 @@ impl Session {
             id,
             device_handle,
             data: BytesMut::new(),
+            partial_data: BytesMut::new(),
+            data_credit: DATA_CREDIT,
+            data_sequence_numbers: vec![],
             session_type,
             sequence_number: 0,
+            warmup_rounds: 0,
+            failed_rounds: 0,
+            sts_index: 0,
+            key_rotation_rounds: 0,
             app_config: AppConfig::default(),
             ranging_task: None,
+            phases: vec![],
+            next_phase: 0,
+            stop_reason: None,
+            coalesced_rounds: vec![],
             tx,
         }
     }
@@ -68,13 +153,24 @@ impl Session {
             return;
         }
 
-        // Send status notification
         self.state = session_state;
+        self.send_status_ntf(session_state, reason_code);
+    }
+
+    /// Update the session state and notify the host, even when the
+    /// state is unchanged. The state machine is not enforced.
+    pub fn inject_state(&mut self, session_state: SessionState, reason_code: ReasonCode) {
+        self.state = session_state;
+        self.send_status_ntf(session_state, reason_code);
+    }
+
+    /// Send a session status notification to the host.
+    fn send_status_ntf(&self, session_state: SessionState, reason_code: ReasonCode) {
         let tx = self.tx.clone();
         let session_id = self.id;
         tokio::spawn(async move {
             time::sleep(Duration::from_millis(1)).await;
-            tx.send(
+            let _ = tx.send(
                 SessionStatusNtf {
                     session_token: session_id,
                     session_state,
@@ -82,11 +178,26 @@ impl Session {
                 }
                 .encode_to_vec()
                 .unwrap(),
-            )
-            .unwrap()
+            );
         });
     }
 
+    /// Validate the session command against the current session state.
+    pub fn check_state(&self, command: SessionCommand) -> Result<(), uci::Status> {
+        let result = check_session_state(self.state, command);
+        if let Err(status) = result {
+            log::warn!(
+                "[{}:0x{:x}] {:?} rejected in state {:?} with status {:?}",
+                self.device_handle,
+                self.id,
+                command,
+                self.state,
+                status
+            );
+        }
+        result
+    }
+
     pub fn get_dst_mac_address(&self) -> &[MacAddress] {
         &self.app_config.dst_mac_address
     }
@@ -95,7 +206,6 @@ impl Session {
         self.app_config.session_info_ntf_config != uci::SessionInfoNtfConfig::Disable
     }
 
-    #[allow(unused)]
     pub fn is_session_data_transfer_status_ntf_enabled(&self) -> bool {
         self.app_config.session_data_transfer_status_ntf_config
             != uci::SessionDataTransferStatusNtfConfig::Disable
@@ -105,8 +215,45 @@ impl Session {
         &self.data
     }
 
-    pub fn clear_data(&mut self) {
-        self.data.clear()
+    /// Complete the transmission of the pending data messages.
+    /// A data transfer status notification is sent for each message
+    /// if enabled, followed by a data credit notification if the
+    /// credit was exhausted.
+    pub fn complete_data_transfer(&mut self, success: bool) {
+        let status = if success {
+            DataTransferNtfStatusCode::UciDataTransferStatusOk
+        } else {
+            DataTransferNtfStatusCode::UciDataTransferStatusErrorDataTransfer
+        };
+
+        let data_sequence_numbers = std::mem::take(&mut self.data_sequence_numbers);
+        if self.is_session_data_transfer_status_ntf_enabled() {
+            for uci_sequence_number in data_sequence_numbers {
+                let _ = self.tx.send(
+                    SessionDataTransferStatusNtf {
+                        session_token: self.id,
+                        status,
+                        tx_count: 1,
+                        uci_sequence_number,
+                    }
+                    .encode_to_vec()
+                    .unwrap(),
+                );
+            }
+        }
+
+        self.data.clear();
+        if self.data_credit == 0 {
+            self.data_credit = DATA_CREDIT;
+            let _ = self.tx.send(
+                SessionDataCreditNtf {
+                    session_token: self.id,
+                    credit_availability: CreditAvailability::CreditAvailable,
+                }
+                .encode_to_vec()
+                .unwrap(),
+            );
+        }
     }
 
     pub fn session_type(&self) -> SessionType {
@@ -117,6 +264,15 @@ impl Session {
         self.state
     }
 
+    /// Return the primary session of the phase serviced in the next
+    /// ranging round, cycling through the phases of the hybrid session.
+    /// Returns None if the session is not a hybrid session.
+    pub fn next_phase(&mut self) -> Option<u32> {
+        let phase = self.phases.get(self.next_phase)?;
+        self.next_phase = (self.next_phase + 1) % self.phases.len();
+        Some(phase.session_token)
+    }
+
     pub fn init(&mut self) {
         self.set_state(
             SessionState::SessionStateInit,
@@ -124,11 +280,33 @@ impl Session {
         );
     }
 
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Transfer the session to the selected device.
+    /// The previous host is notified of the session deinit,
+    /// the new host of the current session state.
+    pub fn rebind(&mut self, device_handle: usize, tx: mpsc::UnboundedSender<UciPacket>) {
+        self.send_status_ntf(
+            SessionState::SessionStateDeinit,
+            ReasonCode::StateChangeWithSessionManagementCommands,
+        );
+        self.device_handle = device_handle;
+        self.tx = tx;
+        self.send_status_ntf(
+            self.state,
+            ReasonCode::StateChangeWithSessionManagementCommands,
+        );
+    }
+
     pub fn stop_ranging_task(&mut self) {
         if let Some(handle) = &self.ranging_task {
             handle.abort();
             self.ranging_task = None;
         }
+        // The rounds of an incomplete coalescing window are dropped.
+        self.coalesced_rounds.clear();
     }
 
     pub fn data_message_snd(&mut self, data: DataMessageSnd) -> ControlPacket {
@@ -149,10 +327,65 @@ impl Session {
 
         assert_eq!(self.id, session_token);
 
-        self.data.extend_from_slice(&data.application_data);
+        let data_size = self.partial_data.len() + data.application_data.len();
+        if data_size > MAX_DATA_SIZE {
+            log::warn!(
+                "[{}:0x{:x}] data message rejected: {} bytes exceed the maximum data size",
+                self.device_handle,
+                self.id,
+                data_size
+            );
+            self.partial_data.clear();
+            return SessionDataTransferStatusNtf {
+                session_token,
+                status: DataTransferNtfStatusCode::UciDataTransferStatusErrorRejected,
+                tx_count: 0,
+                uci_sequence_number,
+            }
+            .try_into()
+            .unwrap();
+        }
+
+        if self.data_credit == 0 {
+            log::warn!(
+                "[{}:0x{:x}] data message rejected: no credit available",
+                self.device_handle,
+                self.id
+            );
+            self.partial_data.clear();
+            return SessionDataTransferStatusNtf {
+                session_token,
+                status: DataTransferNtfStatusCode::UciDataTransferStatusErrorNoCreditAvailable,
+                tx_count: 0,
+                uci_sequence_number,
+            }
+            .try_into()
+            .unwrap();
+        }
+
+        // The credit is taken once per data message, when the last
+        // data packet of the message is received.
+        self.partial_data.extend_from_slice(&data.application_data);
+        if data.pbf == PacketBoundaryFlag::NotComplete {
+            return SessionDataCreditNtf {
+                credit_availability: CreditAvailability::CreditAvailable,
+                session_token,
+            }
+            .try_into()
+            .unwrap();
+        }
+
+        self.data_credit -= 1;
+        self.data_sequence_numbers.push(uci_sequence_number);
+        let message = std::mem::take(&mut self.partial_data);
+        self.data.extend_from_slice(&message);
 
         SessionDataCreditNtf {
-            credit_availability: CreditAvailability::CreditAvailable,
+            credit_availability: if self.data_credit > 0 {
+                CreditAvailability::CreditAvailable
+            } else {
+                CreditAvailability::CreditNotAvailable
+            },
             session_token,
         }
         .try_into()
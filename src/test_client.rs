@@ -0,0 +1,374 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! UCI host harness for writing tests against pica.
+//!
+//! The [Client] sends the commands built with the [crate::packets::uci]
+//! packet builders on a device connection, and returns the parsed
+//! responses. The notifications received while waiting for a response
+//! are queued, and returned by [Client::next_notification].
+
+use crate::packets::uci::{self, *};
+use crate::{Pica, UciPacket, UciSink, UciStream};
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use pdl_runtime::Packet;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time;
+
+/// Default delay for receiving a response or a notification.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// UCI host connected to a pica device.
+pub struct Client {
+    sink: UciSink,
+    stream: UciStream,
+    framing: Framing,
+    timeout: Duration,
+    notifications: VecDeque<UciPacket>,
+}
+
+impl Client {
+    /// Create a client from the halves of a device connection.
+    pub fn new(sink: UciSink, stream: UciStream, framing: Framing) -> Self {
+        Client {
+            sink,
+            stream,
+            framing,
+            timeout: DEFAULT_TIMEOUT,
+            notifications: VecDeque::new(),
+        }
+    }
+
+    /// Connect a client to a new device of the pica instance, with the
+    /// in-memory transport and the framing of the instance.
+    pub fn connect_local(pica: &mut Pica) -> Result<Self> {
+        let (sink, stream) = pica.connect_local()?;
+        Ok(Client::new(sink, stream, pica.framing()))
+    }
+
+    /// Connect a client to the UCI server listening on the selected
+    /// address, with the selected framing.
+    pub async fn connect(addr: impl ToSocketAddrs, framing: Framing) -> Result<Self> {
+        let (read_half, write_half) = TcpStream::connect(addr).await?.into_split();
+        let stream = Box::pin(futures::stream::unfold(read_half, move |read_half| {
+            uci::read_framed(read_half, framing)
+        }));
+        let sink = Box::pin(futures::sink::unfold(write_half, uci::write));
+        Ok(Client::new(sink, stream, framing))
+    }
+
+    /// Select the delay for receiving a response or a notification.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Send a command, and return the parsed response.
+    /// The command is segmented according to the framing of
+    /// the connection.
+    pub async fn command<C: Packet, R: Packet>(&mut self, cmd: C) -> Result<R> {
+        let cmd = cmd
+            .encode_to_vec()
+            .map_err(|err| anyhow::anyhow!("failed to encode command: {:?}", err))?;
+        let (gid, oid) = (cmd[0] & 0xf, cmd[1] & 0x3f);
+        for fragment in segment(&cmd, self.framing.max_ctrl_payload_size(), self.framing) {
+            self.sink.send(fragment).await?;
+        }
+
+        loop {
+            let packet = self.next_packet().await?;
+            if parse_message_type(packet[0]) != MessageType::Response {
+                self.notifications.push_back(packet);
+                continue;
+            }
+            if (packet[0] & 0xf, packet[1] & 0x3f) != (gid, oid) {
+                anyhow::bail!(
+                    "unexpected response {} to command {:02x}:{:02x}",
+                    hex::encode(&packet),
+                    gid,
+                    oid
+                );
+            }
+            return R::decode_full(&packet).map_err(|err| {
+                anyhow::anyhow!(
+                    "failed to decode response {}: {:?}",
+                    hex::encode(&packet),
+                    err
+                )
+            });
+        }
+    }
+
+    /// Return the next notification received from the device.
+    pub async fn next_notification(&mut self) -> Result<UciPacket> {
+        loop {
+            if let Some(packet) = self.notifications.pop_front() {
+                return Ok(packet);
+            }
+            let packet = self.next_packet().await?;
+            if parse_message_type(packet[0]) == MessageType::Notification {
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Return the next notification of the selected type,
+    /// other notifications are discarded.
+    pub async fn expect_notification<T: Packet>(&mut self) -> Result<T> {
+        loop {
+            if let Ok(ntf) = T::decode_full(&self.next_notification().await?) {
+                return Ok(ntf);
+            }
+        }
+    }
+
+    pub async fn device_reset(&mut self) -> Result<CoreDeviceResetRsp> {
+        self.command(CoreDeviceResetCmd {
+            reset_config: ResetConfig::UwbsReset,
+        })
+        .await
+    }
+
+    pub async fn get_device_info(&mut self) -> Result<CoreGetDeviceInfoRsp> {
+        self.command(CoreGetDeviceInfoCmd {}).await
+    }
+
+    pub async fn get_caps_info(&mut self) -> Result<CoreGetCapsInfoRsp> {
+        self.command(CoreGetCapsInfoCmd {}).await
+    }
+
+    pub async fn set_config(
+        &mut self,
+        parameters: Vec<ConfigParameter>,
+    ) -> Result<CoreSetConfigRsp> {
+        self.command(CoreSetConfigCmd { parameters }).await
+    }
+
+    pub async fn get_config(
+        &mut self,
+        parameter_ids: Vec<ConfigParameterId>,
+    ) -> Result<CoreGetConfigRsp> {
+        self.command(CoreGetConfigCmd { parameter_ids }).await
+    }
+
+    pub async fn session_init(
+        &mut self,
+        session_id: u32,
+        session_type: SessionType,
+    ) -> Result<SessionInitRsp> {
+        self.command(SessionInitCmd {
+            session_id,
+            session_type,
+        })
+        .await
+    }
+
+    pub async fn session_deinit(&mut self, session_token: u32) -> Result<SessionDeinitRsp> {
+        self.command(SessionDeinitCmd { session_token }).await
+    }
+
+    pub async fn session_set_app_config(
+        &mut self,
+        session_token: u32,
+        tlvs: Vec<AppConfigTlv>,
+    ) -> Result<SessionSetAppConfigRsp> {
+        self.command(SessionSetAppConfigCmd {
+            session_token,
+            tlvs,
+        })
+        .await
+    }
+
+    pub async fn session_get_state(&mut self, session_token: u32) -> Result<SessionGetStateRsp> {
+        self.command(SessionGetStateCmd { session_token }).await
+    }
+
+    pub async fn session_start(&mut self, session_id: u32) -> Result<SessionStartRsp> {
+        self.command(SessionStartCmd { session_id }).await
+    }
+
+    pub async fn session_stop(&mut self, session_id: u32) -> Result<SessionStopRsp> {
+        self.command(SessionStopCmd { session_id }).await
+    }
+
+    /// Receive the next packet from the device, reassembling
+    /// the fragments of segmented control packets.
+    async fn next_packet(&mut self) -> Result<UciPacket> {
+        let mut complete_packet: Option<UciPacket> = None;
+        loop {
+            let packet = time::timeout(self.timeout, self.stream.next())
+                .await
+                .map_err(|_| anyhow::anyhow!("timeout waiting for a packet"))?
+                .ok_or(anyhow::anyhow!("connection closed"))?;
+            let complete = packet[0] & 0x10 == 0;
+            match &mut complete_packet {
+                Some(complete_packet) => complete_packet.extend_from_slice(&packet[HEADER_SIZE..]),
+                None => complete_packet = Some(packet),
+            }
+            if complete {
+                let mut packet = complete_packet.unwrap();
+                packet[0] &= !0x10;
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{tlv, FixedRangingEstimator};
+    use crate::{MacAddress, PicaCommand, RangingMeasurement};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    #[tokio::test(start_paused = true)]
+    async fn ranging_flow() {
+        let mut pica = Pica::builder()
+            .ranging_estimator(Box::new(FixedRangingEstimator(RangingMeasurement {
+                range: 100,
+                azimuth: 30,
+                elevation: 0,
+            })))
+            .build();
+        let commands = pica.commands();
+        let mut client = Client::connect_local(&mut pica).unwrap();
+        tokio::spawn(pica.run());
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        commands
+            .send(PicaCommand::CreateAnchor(
+                MacAddress::Short([0x00, 0x02]),
+                rsp_tx,
+            ))
+            .await
+            .unwrap();
+        rsp_rx.await.unwrap().unwrap();
+
+        assert_eq!(client.device_reset().await.unwrap().status, uci::Status::Ok);
+        let ntf = client
+            .expect_notification::<CoreDeviceStatusNtf>()
+            .await
+            .unwrap();
+        assert_eq!(ntf.device_state, DeviceState::DeviceStateReady);
+
+        let rsp = client.get_device_info().await.unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+
+        let rsp = client
+            .set_config(vec![ConfigParameter {
+                id: ConfigParameterId::LowPowerMode,
+                value: vec![0],
+            }])
+            .await
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let rsp = client
+            .get_config(vec![ConfigParameterId::LowPowerMode])
+            .await
+            .unwrap();
+        assert_eq!(rsp.parameters[0].value, vec![0]);
+
+        let rsp = client
+            .session_init(1, SessionType::FiraRangingSession)
+            .await
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let rsp = client
+            .session_set_app_config(
+                1,
+                vec![
+                    tlv(AppConfigTlvType::DeviceRole, &[0x01]),
+                    tlv(AppConfigTlvType::MultiNodeMode, &[0x00]),
+                    tlv(AppConfigTlvType::RangingRoundUsage, &[0x04]),
+                    tlv(AppConfigTlvType::DeviceMacAddress, &[0x00, 0x01]),
+                    tlv(AppConfigTlvType::ScheduleMode, &[0x01]),
+                    tlv(AppConfigTlvType::DstMacAddress, &[0x00, 0x02]),
+                    tlv(AppConfigTlvType::ResultReportConfig, &[0x03]),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let rsp = client.session_get_state(1).await.unwrap();
+        assert_eq!(rsp.session_state, SessionState::SessionStateIdle);
+
+        assert_eq!(
+            client.session_start(1).await.unwrap().status,
+            uci::Status::Ok
+        );
+        let ntf = client
+            .expect_notification::<ShortMacTwoWaySessionInfoNtf>()
+            .await
+            .unwrap();
+        assert_eq!(ntf.session_token, 1);
+        assert_eq!(ntf.two_way_ranging_measurements.len(), 1);
+        assert_eq!(ntf.two_way_ranging_measurements[0].mac_address, 0x0200);
+        assert_eq!(ntf.two_way_ranging_measurements[0].distance, 100);
+        assert_eq!(ntf.two_way_ranging_measurements[0].aoa_azimuth, 30);
+
+        assert_eq!(
+            client.session_stop(1).await.unwrap().status,
+            uci::Status::Ok
+        );
+        assert_eq!(
+            client.session_deinit(1).await.unwrap().status,
+            uci::Status::Ok
+        );
+    }
+
+    #[tokio::test]
+    async fn local_framing() {
+        let mut pica = Pica::builder().framing(Framing::V2).build();
+        let mut client = Client::connect_local(&mut pica).unwrap();
+        tokio::spawn(pica.run());
+
+        assert_eq!(client.framing, Framing::V2);
+        assert_eq!(client.device_reset().await.unwrap().status, uci::Status::Ok);
+        let rsp = client.get_caps_info().await.unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn tcp_transport() {
+        let pica = Pica::builder().framing(Framing::V2).build();
+        let commands = pica.commands();
+        tokio::spawn(pica.run());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (read_half, write_half) = socket.into_split();
+            let stream = Box::pin(futures::stream::unfold(read_half, |read_half| {
+                uci::read_framed(read_half, Framing::V2)
+            }));
+            let sink = Box::pin(futures::sink::unfold(write_half, uci::write));
+            commands
+                .send(PicaCommand::Connect(stream, sink))
+                .await
+                .unwrap();
+        });
+
+        let mut client = Client::connect(addr, Framing::V2).await.unwrap();
+        assert_eq!(client.device_reset().await.unwrap().status, uci::Status::Ok);
+        let rsp = client.get_device_info().await.unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        let rsp = client.get_caps_info().await.unwrap();
+        assert_eq!(rsp.status, uci::Status::Ok);
+        assert!(!rsp.tlvs.is_empty());
+    }
+}
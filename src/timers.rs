@@ -0,0 +1,188 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of the timers armed by the device tasks, for debugging.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{Clock, Handle, Sleep};
+
+/// Kind of the timers armed by the device tasks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerKind {
+    /// Periodic trigger of the ranging rounds of a session.
+    Ranging,
+    /// Closing of an inactive host connection.
+    IdleTimeout,
+    /// Device status notification delayed after a reset,
+    /// or during the boot of the device.
+    ResetDelay,
+}
+
+/// Description of an active timer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimerInfo {
+    pub kind: TimerKind,
+    pub handle: Handle,
+    /// Session associated with the ranging timers.
+    pub session_id: Option<u32>,
+    /// Time of the next expiration, read from the clock
+    /// of the devices.
+    pub deadline: Duration,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    timers: BTreeMap<u64, TimerInfo>,
+}
+
+/// Registry of the active timers, shared with the tasks arming them.
+/// Timers are listed in registration order.
+#[derive(Clone, Default)]
+pub struct Timers(Arc<Mutex<Registry>>);
+
+impl Timers {
+    /// Register a timer expiring after `delay`. The timer is listed
+    /// until the returned guard is dropped, i.e. until the task
+    /// owning the timer completes or is aborted.
+    pub fn register(
+        &self,
+        kind: TimerKind,
+        handle: Handle,
+        session_id: Option<u32>,
+        clock: Arc<dyn Clock>,
+        delay: Duration,
+    ) -> Timer {
+        let mut registry = self.0.lock().unwrap();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.timers.insert(
+            id,
+            TimerInfo {
+                kind,
+                handle,
+                session_id,
+                deadline: clock.now() + delay,
+            },
+        );
+        Timer {
+            timers: self.clone(),
+            clock,
+            id,
+        }
+    }
+
+    /// Return the description of the active timers.
+    pub fn list(&self) -> Vec<TimerInfo> {
+        self.0.lock().unwrap().timers.values().cloned().collect()
+    }
+}
+
+/// Guard of a registered timer.
+pub struct Timer {
+    timers: Timers,
+    clock: Arc<dyn Clock>,
+    id: u64,
+}
+
+impl Timer {
+    /// Returns a future completing at the next expiration of the timer,
+    /// measured on the clock of the timer.
+    pub fn sleep(&self) -> Sleep {
+        let deadline = self
+            .timers
+            .0
+            .lock()
+            .unwrap()
+            .timers
+            .get(&self.id)
+            .map(|timer| timer.deadline)
+            .unwrap_or_else(|| self.clock.now());
+        self.clock.sleep_until(deadline)
+    }
+
+    /// Move the next expiration of the timer to `delay` from now.
+    /// Returns a future completing at the expiration, measured
+    /// on the clock of the timer.
+    pub fn rearm(&self, delay: Duration) -> Sleep {
+        let deadline = self.clock.now() + delay;
+        if let Some(timer) = self.timers.0.lock().unwrap().timers.get_mut(&self.id) {
+            timer.deadline = deadline;
+        }
+        self.clock.sleep_until(deadline)
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.timers.0.lock().unwrap().timers.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+
+    #[test]
+    fn register() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1)));
+        let timers = Timers::default();
+
+        let ranging = timers.register(
+            TimerKind::Ranging,
+            1,
+            Some(42),
+            clock.clone(),
+            Duration::from_millis(200),
+        );
+        let idle_timeout = timers.register(
+            TimerKind::IdleTimeout,
+            2,
+            None,
+            clock.clone(),
+            Duration::from_secs(5),
+        );
+        assert_eq!(
+            timers.list(),
+            vec![
+                TimerInfo {
+                    kind: TimerKind::Ranging,
+                    handle: 1,
+                    session_id: Some(42),
+                    deadline: Duration::from_millis(1200),
+                },
+                TimerInfo {
+                    kind: TimerKind::IdleTimeout,
+                    handle: 2,
+                    session_id: None,
+                    deadline: Duration::from_secs(6),
+                },
+            ]
+        );
+
+        clock.advance(Duration::from_millis(200));
+        let _ = ranging.rearm(Duration::from_millis(200));
+        assert_eq!(timers.list()[0].deadline, Duration::from_millis(1400));
+
+        // Timers are unregistered when the guards are dropped.
+        drop(ranging);
+        assert_eq!(timers.list().len(), 1);
+        drop(idle_timeout);
+        assert!(timers.list().is_empty());
+    }
+}
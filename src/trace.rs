@@ -0,0 +1,114 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of the UCI packets exchanged on the device connections.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::pcapng;
+use crate::Handle;
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Format of the traces recorded for the device connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// pcapng file with one Enhanced Packet Block per UCI packet,
+    /// saved as `device-{handle}.pcapng`.
+    #[default]
+    Pcapng,
+    /// Concatenation of the UCI packets, each prefixed with its length
+    /// as a little-endian u16, saved as `device-{handle}.bin`.
+    Raw,
+}
+
+/// Writer for the trace of a device connection. The trace is opened
+/// when the device is connected, and closed when the writer is dropped.
+pub trait TraceWriter: Send + Sync {
+    /// Record a packet exchanged on the connection.
+    fn write(&self, packet: &[u8], dir: Direction) -> std::io::Result<()>;
+    /// Flush the recorded packets to the trace file.
+    fn flush(&self) -> std::io::Result<()>;
+}
+
+/// Open the trace of the device with the selected handle in `dir`.
+/// The size limit only applies to pcapng traces.
+pub fn create(
+    dir: &Path,
+    handle: Handle,
+    format: TraceFormat,
+    max_size: Option<u64>,
+) -> std::io::Result<Box<dyn TraceWriter>> {
+    Ok(match format {
+        TraceFormat::Pcapng => {
+            let path = dir.join(format!("device-{}.pcapng", handle));
+            log::debug!("Recording pcapng to file {}", path.display());
+            Box::new(pcapng::File::create(path, max_size)?)
+        }
+        TraceFormat::Raw => {
+            let path = dir.join(format!("device-{}.bin", handle));
+            log::debug!("Recording raw trace to file {}", path.display());
+            Box::new(RawFile::create(path)?)
+        }
+    })
+}
+
+/// Trace recording the packets to several traces at once.
+pub struct Tee(pub Vec<Box<dyn TraceWriter>>);
+
+impl TraceWriter for Tee {
+    fn write(&self, packet: &[u8], dir: Direction) -> std::io::Result<()> {
+        for writer in self.0.iter() {
+            writer.write(packet, dir)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        for writer in self.0.iter() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Trace recording the UCI packets with a u16 length prefix.
+pub struct RawFile {
+    file: std::sync::Mutex<std::fs::File>,
+}
+
+impl RawFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<RawFile> {
+        Ok(RawFile {
+            file: std::sync::Mutex::new(std::fs::File::create(path)?),
+        })
+    }
+}
+
+impl TraceWriter for RawFile {
+    fn write(&self, packet: &[u8], _dir: Direction) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&u16::to_le_bytes(packet.len() as u16))?;
+        file.write_all(packet)
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.file.lock().unwrap().flush()
+    }
+}
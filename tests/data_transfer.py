@@ -126,6 +126,14 @@ async def controller(host: Host, peer: Host, file: Path):
     event = await host.expect_control(uci.ShortMacTwoWaySessionInfoNtf, timeout=2.0)
     event.show()
 
+    # The credit is restored once the data is transmitted.
+    await host.expect_control(
+        uci.SessionDataCreditNtf(
+            session_token=0,
+            credit_availability=uci.CreditAvailability.CREDIT_AVAILABLE,
+        )
+    )
+
     event = await host.expect_control(uci.ShortMacTwoWaySessionInfoNtf, timeout=2.0)
     event.show()
 
@@ -321,10 +329,15 @@ async def data_transfer(
                     if seq_num >= 65535:
                         seq_num = 0
 
+                    # The credit is taken by the last packet of the message.
                     event = await host.expect_control(
                         uci.SessionDataCreditNtf(
                             session_token=int(session_id),
-                            credit_availability=uci.CreditAvailability.CREDIT_AVAILABLE,
+                            credit_availability=(
+                                uci.CreditAvailability.CREDIT_NOT_AVAILABLE
+                                if i + MAX_DATA_PACKET_PAYLOAD_SIZE >= len(b)
+                                else uci.CreditAvailability.CREDIT_AVAILABLE
+                            ),
                         )
                     )
                     event.show()
@@ -340,7 +353,7 @@ async def data_transfer(
                 event = await host.expect_control(
                     uci.SessionDataCreditNtf(
                         session_token=int(session_id),
-                        credit_availability=uci.CreditAvailability.CREDIT_AVAILABLE,
+                        credit_availability=uci.CreditAvailability.CREDIT_NOT_AVAILABLE,
                     )
                 )
                 event.show()